@@ -7,16 +7,14 @@
 //! that relies on the first argument to determine the behavior, then it
 //! won't work as expected, because the sandbox uses a placeholder name.
 
-use std::collections::HashMap;
 use std::ffi::OsString;
 use std::path::PathBuf;
 
-use gracklezero::{self, FdMode, strict_restrictions};
+use gracklezero::{self, EnvMap, FdMode, strict_restrictions};
 
 pub fn main() {
     let res = gracklezero::sandbox_child(
         gracklezero::LaunchEnv {
-            cmd: get_cmd(),
             args: get_args(),
             cwd: std::env::current_dir().expect("failed to get current directory"),
             env: get_env(),
@@ -25,21 +23,21 @@ pub fn main() {
                 FdMode::KeepInChild,
                 FdMode::KeepInChild,
             ]),
-            restrictions: strict_restrictions!("piped"),
+            ..gracklezero::LaunchEnv::new(get_cmd(), strict_restrictions!("piped"))
         },
         WaitHandler {},
     )
     .expect("Failed to run the child");
-    match res {
-        gracklezero::runtime::ExitCode::Exited(code) => {
+    match res.exit {
+        gracklezero::runtime::ExitStatus::Code(code) => {
             std::process::exit(code);
         }
-        gracklezero::runtime::ExitCode::OsError(s) => {
-            println!("Child exited with OS error: {} (0x{:X})", s.message, s.code);
+        gracklezero::runtime::ExitStatus::Signaled(s) => {
+            println!("Child ended by the OS: 0x{:X}", s);
             std::process::exit(100);
         }
-        gracklezero::runtime::ExitCode::Running => {
-            println!("Child is still running (this should not happen)");
+        gracklezero::runtime::ExitStatus::SandboxKilled => {
+            println!("Child was killed by the sandbox");
             std::process::exit(101);
         }
     }
@@ -56,25 +54,28 @@ fn get_args() -> Vec<OsString> {
     std::env::args_os().skip(2).collect()
 }
 
-fn get_env() -> HashMap<OsString, OsString> {
-    std::env::vars_os().collect()
+fn get_env() -> EnvMap {
+    EnvMap::from_current()
 }
 
 struct WaitHandler {}
 
 impl gracklezero::CommHandler for WaitHandler {
-    fn handle(self, child: Box<dyn gracklezero::Child>) -> Result<(), std::io::Error> {
+    type Output = ();
+    type Error = std::io::Error;
+
+    fn handle(&mut self, child: Box<dyn gracklezero::Child>) -> Result<(), std::io::Error> {
         loop {
-            match child.exit_status() {
-                gracklezero::runtime::ExitCode::Exited(code) => {
+            match child.exit_status()? {
+                Some(gracklezero::runtime::ExitStatus::Code(code)) => {
                     println!("Child exited with code: {}", code);
                     return Ok(());
                 }
-                gracklezero::runtime::ExitCode::OsError(s) => {
-                    println!("Child exited with OS error: {} (0x{:X})", s.message, s.code);
+                Some(status) => {
+                    println!("Child ended with {:?}", status);
                     return Ok(());
                 }
-                gracklezero::runtime::ExitCode::Running => {
+                None => {
                     std::thread::sleep(std::time::Duration::from_millis(100));
                 }
             }
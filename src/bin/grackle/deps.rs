@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: MIT
+
+//! `grackle deps`: show the shared libraries a program loads, and what the sandbox would
+//! let it read.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use clap::Args;
+use gracklezero::{
+    DependencySearch, LaunchEnv, create_compat_restrictions,
+    runtime::{
+        deps::{Dependency, dependencies},
+        error::SandboxError,
+    },
+};
+
+/// Print the program's shared library tree, and the paths the sandbox would let it read,
+/// to track down "missing library dependencies" errors.
+///
+/// grackle exits with 0 when every required library is found, and 1 when some are
+/// missing.
+#[derive(Args)]
+pub struct DepsArgs {
+    /// A library the program loads at runtime with `dlopen`.  The file name may contain
+    /// `*` and `?` wildcards.
+    #[arg(long, value_name = "PATH")]
+    library: Vec<PathBuf>,
+
+    /// A directory to search for libraries before the standard locations.
+    #[arg(long, value_name = "DIR")]
+    library_path: Vec<PathBuf>,
+
+    /// The root directory to resolve library paths in.
+    #[arg(long, value_name = "DIR", default_value = "/")]
+    sysroot: PathBuf,
+
+    /// Print the report as JSON.
+    #[arg(long)]
+    json: bool,
+
+    /// The program to inspect.
+    binary: PathBuf,
+}
+
+pub fn run(args: DepsArgs) -> Result<i32, SandboxError> {
+    let env = LaunchEnv {
+        extra_libraries: args.library,
+        dependency_search: DependencySearch {
+            sysroot: args.sysroot,
+            library_paths: args.library_path,
+            cache: None,
+        },
+        ..LaunchEnv::new(
+            args.binary,
+            create_compat_restrictions(&"grackle".to_string()),
+        )
+    };
+    let report = dependencies(&env)?;
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&report).map_err(std::io::Error::other)?;
+        println!("{}", json);
+    } else {
+        if let Some(interpreter) = &report.interpreter {
+            println!(
+                "{} runs through {}",
+                report.exec.display(),
+                interpreter.display()
+            );
+        }
+        let all: Vec<&Dependency> = report
+            .dependencies
+            .iter()
+            .chain(&report.extra_libraries)
+            .collect();
+        let by_name: HashMap<&str, &Dependency> =
+            all.iter().skip(1).map(|d| (d.name(), *d)).collect();
+        print_tree(all[0], &by_name);
+        // The extra libraries that no other file loads were named by the caller.
+        for extra in &report.extra_libraries {
+            if !all
+                .iter()
+                .any(|d| d.needed().iter().any(|n| n == extra.name()))
+            {
+                print_tree(extra, &by_name);
+            }
+        }
+        println!();
+        println!("readable paths:");
+        for path in &report.readable_paths {
+            println!("    {}", path.display());
+        }
+        if !report.writable_paths.is_empty() {
+            println!("writable paths:");
+            for path in &report.writable_paths {
+                println!("    {}", path.display());
+            }
+        }
+    }
+
+    let mut missing = report.missing().peekable();
+    if missing.peek().is_none() {
+        return Ok(0);
+    }
+    for dep in missing {
+        eprintln!("missing library: {}", dep.best_path().display());
+    }
+    Ok(1)
+}
+
+/// Print the root, and everything it needs, indented by depth.  A library shown once
+/// already isn't expanded again.
+fn print_tree<'a>(root: &'a Dependency, by_name: &HashMap<&'a str, &'a Dependency>) {
+    if !root.exists() {
+        println!("{} => not found", root.best_path().display());
+        return;
+    }
+    println!("{}", root.best_path().display());
+    let mut shown = Vec::new();
+    print_needed(root.needed(), by_name, 1, &mut shown);
+}
+
+fn print_needed<'a>(
+    needed: &'a [String],
+    by_name: &HashMap<&'a str, &'a Dependency>,
+    depth: usize,
+    shown: &mut Vec<&'a str>,
+) {
+    for name in needed {
+        let indent = "    ".repeat(depth);
+        match by_name.get(name.as_str()) {
+            Some(dep) if dep.exists() => {
+                println!("{}{} => {}", indent, name, dep.best_path().display());
+                if !shown.contains(&name.as_str()) {
+                    shown.push(name);
+                    print_needed(dep.needed(), by_name, depth + 1, shown);
+                }
+            }
+            _ => println!("{}{} => not found", indent, name),
+        }
+    }
+}
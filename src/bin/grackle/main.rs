@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: MIT
+
+//! `grackle`, the command line front end to the sandbox, for shell scripts and programs
+//! not written in Rust.
+//!
+//! ```text
+//! grackle run --policy policy.toml -- /usr/bin/fetcher --quiet
+//! grackle probe --json
+//! grackle deps /usr/bin/fetcher
+//! ```
+//!
+//! Build it with the `cli` feature.
+
+use clap::{Parser, Subcommand};
+
+mod deps;
+mod probe;
+mod run;
+
+/// The exit code when `grackle` itself fails, such as when the child can't be launched.
+const FAILURE_CODE: i32 = 125;
+
+#[derive(Parser)]
+#[command(
+    name = "grackle",
+    version,
+    about = "Run programs inside the grackle-zero sandbox"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Run(run::RunArgs),
+    Probe(probe::ProbeArgs),
+    Deps(deps::DepsArgs),
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let ret = match cli.command {
+        Command::Run(args) => run::run(args),
+        Command::Probe(args) => probe::run(args),
+        Command::Deps(args) => deps::run(args),
+    };
+    std::process::exit(ret.unwrap_or_else(|e| {
+        // Show the whole chain, such as the OS error behind a failed exec.
+        eprintln!("grackle: {:#}", e);
+        FAILURE_CODE
+    }))
+}
@@ -0,0 +1,28 @@
+// SPDX-License-Identifier: MIT
+
+//! `grackle probe`: report which sandbox mechanisms the host supports.
+
+use clap::Args;
+use gracklezero::runtime::{error::SandboxError, probe};
+
+/// Print the sandbox mechanisms the host supports, such as the Landlock ABI version or
+/// AppContainer support.
+///
+/// grackle exits with 0 when the host can run the sandbox, and 1 when it can't.
+#[derive(Args)]
+pub struct ProbeArgs {
+    /// Print the capabilities as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+pub fn run(args: ProbeArgs) -> Result<i32, SandboxError> {
+    let host = probe();
+    if args.json {
+        let json = serde_json::to_string_pretty(&host).map_err(std::io::Error::other)?;
+        println!("{}", json);
+    } else {
+        print!("{}", host);
+    }
+    Ok(if host.can_sandbox() { 0 } else { 1 })
+}
@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: MIT
+
+//! `grackle run`: launch a command in the sandbox, attached to the terminal.
+
+use std::{ffi::OsString, path::PathBuf};
+
+use clap::{Args, ValueEnum};
+use gracklezero::{
+    LaunchEnv, SandboxPolicy, create_compat_restrictions, create_strict_restrictions,
+    profile::load_policy,
+    runtime::{ExitStatus, PassthroughHandler, error::SandboxError},
+    sandbox_child,
+};
+
+/// The exit code when the sandbox killed the child, as a shell reports `SIGKILL`.
+const KILLED_CODE: i32 = 128 + 9;
+
+/// Run a command in the sandbox, with its STDIN, STDOUT, and STDERR attached to grackle's
+/// own.
+///
+/// grackle exits with the command's exit code, 128 plus the signal number when the OS
+/// ended it (the NTSTATUS code on Windows), 137 when the sandbox killed it, or 125 when
+/// grackle couldn't run it.
+#[derive(Args)]
+pub struct RunArgs {
+    /// A TOML or JSON file with the `SandboxPolicy`, such as `[filesystem]` and
+    /// `[network]` tables.
+    #[arg(long, value_name = "FILE", conflicts_with = "preset")]
+    policy: Option<PathBuf>,
+
+    /// The built-in policy to start from, when there's no policy file.
+    #[arg(long, value_enum, default_value_t = Preset::ComputeOnly)]
+    preset: Preset,
+
+    /// The standard restrictions the policy applies to.
+    #[arg(long, value_enum, default_value_t = Base::Compat)]
+    restrictions: Base,
+
+    /// The application name, used for the Windows AppContainer profile.
+    #[arg(long, default_value = "grackle")]
+    name: String,
+
+    /// Let the command read the file, or everything beneath the directory.
+    #[arg(long, value_name = "PATH")]
+    read: Vec<PathBuf>,
+
+    /// Let the command read and write the file, or everything beneath the directory.
+    #[arg(long, value_name = "PATH")]
+    write: Vec<PathBuf>,
+
+    /// Set an environment variable in the command, as `NAME=VALUE`.
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_env)]
+    env: Vec<(String, String)>,
+
+    /// Pass grackle's own value for the environment variable to the command.
+    #[arg(long, value_name = "NAME")]
+    pass_env: Vec<String>,
+
+    /// The directory the command runs in.
+    #[arg(long, value_name = "DIR")]
+    cwd: Option<PathBuf>,
+
+    /// Run the command even when grackle runs as root, or as an elevated Administrator,
+    /// which weakens the sandbox.
+    #[arg(long)]
+    allow_privileged: bool,
+
+    /// The command to run, and its arguments.
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<OsString>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Preset {
+    Strict,
+    ComputeOnly,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Base {
+    Compat,
+    Strict,
+}
+
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected NAME=VALUE, found {:?}", s))
+}
+
+pub fn run(args: RunArgs) -> Result<i32, SandboxError> {
+    let mut policy = match &args.policy {
+        Some(path) => load_policy(path)?,
+        None => match args.preset {
+            Preset::Strict => SandboxPolicy::strict(),
+            Preset::ComputeOnly => SandboxPolicy::compute_only(),
+        },
+    };
+    policy.filesystem.read.extend(args.read);
+    policy.filesystem.write.extend(args.write);
+
+    let restrictions = match args.restrictions {
+        Base::Compat => create_compat_restrictions(&args.name),
+        Base::Strict => create_strict_restrictions(&args.name),
+    };
+    let mut command = args.command.into_iter();
+    let cmd = PathBuf::from(command.next().expect("clap requires the command"));
+    let mut env = LaunchEnv {
+        args: command.collect(),
+        allow_privileged_parent: args.allow_privileged,
+        ..LaunchEnv::new(cmd, restrictions)
+    }
+    .with_policy(&policy);
+    if let Some(cwd) = args.cwd {
+        env.cwd = cwd;
+    }
+    env.env.extend(args.env);
+    for name in &args.pass_env {
+        env.env.insert_from_current(name);
+    }
+    // Windows has no selector yet, so the handler polls the pipes.
+    #[cfg(target_os = "windows")]
+    let env = LaunchEnv {
+        fds: env.fds.clone().with_nonblocking(),
+        ..env
+    };
+
+    let ((), status) = sandbox_child(env, PassthroughHandler::new())?.into_result()?;
+    Ok(match status {
+        ExitStatus::Code(code) => code,
+        #[cfg(target_os = "windows")]
+        ExitStatus::Signaled(status) => status,
+        #[cfg(not(target_os = "windows"))]
+        ExitStatus::Signaled(signal) => 128 + signal,
+        ExitStatus::SandboxKilled => KILLED_CODE,
+    })
+}
@@ -0,0 +1,344 @@
+// SPDX-License-Identifier: MIT
+
+//! # Child Side Streams
+//!
+//! Helpers for the sandboxed child program, to open the extra streams that the parent
+//! declared in its `FdSet`.
+//!
+//! On Linux, the parent places each stream at its declared file descriptor number, and
+//! lists those numbers in the `SANDBOX_FDS` environment variable, formatted as
+//! `FD_NUMBER;FD_NUMBER;...`.  Only the listed numbers can be taken.  On Windows, the
+//! parent passes the inherited handles in the `SANDBOX_HANDLES` environment variable,
+//! formatted as `FD_NUMBER:0xHANDLE_ADDRESS;FD_NUMBER:0xHANDLE_ADDRESS;...`.
+//! Either way, the child asks for the stream by its logical FD number.
+//!
+//! The standard streams (0, 1, and 2) are not included here; use `std::io::stdin`,
+//! `std::io::stdout`, and `std::io::stderr` for those.
+//!
+//! Streams the parent declared as named channels, with `FdSet::with_channel`, can be
+//! taken by name instead.  The parent passes the names in the `SANDBOX_CHANNELS`
+//! environment variable, formatted as `NAME:FD_NUMBER;NAME:FD_NUMBER;...`.
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! let mut streams = gracklezero::child::ChildStreams::from_env().expect("bad SANDBOX_HANDLES");
+//! let mut events = streams.take_writer(3).expect("no stream at fd 3");
+//! events.write_all(b"hello").expect("write failed");
+//! ```
+
+use std::{collections::HashMap, fs::File};
+
+/// Environment variable that holds the extra stream handles on Windows.
+pub const SANDBOX_HANDLES_ENV: &str = "SANDBOX_HANDLES";
+
+/// Environment variable that lists the extra stream FD numbers on Linux.
+pub const SANDBOX_FDS_ENV: &str = "SANDBOX_FDS";
+
+/// Environment variable that maps the channel names to their FD numbers.
+pub const SANDBOX_CHANNELS_ENV: &str = "SANDBOX_CHANNELS";
+
+/// The extra streams passed from the parent, keyed by the logical FD number.
+///
+/// Each stream can only be taken once, so that two owners never close the same
+/// descriptor.
+pub struct ChildStreams {
+    /// Maps the logical FD to the OS descriptor; None once taken.
+    streams: HashMap<u32, Option<usize>>,
+    /// Maps the channel names to the logical FD.
+    channels: HashMap<String, u32>,
+}
+
+impl ChildStreams {
+    /// Find the streams passed by the parent.
+    ///
+    /// On Windows, this decodes the `SANDBOX_HANDLES` environment variable, and fails if
+    /// it is malformed.  A missing variable means there are no extra streams.
+    #[cfg(target_os = "windows")]
+    pub fn from_env() -> Result<Self, std::io::Error> {
+        let handles = match env_string(SANDBOX_HANDLES_ENV)? {
+            None => HashMap::new(),
+            Some(v) => parse_handles(&v)?,
+        };
+        ChildStreams::from_map(handles).with_channels_from_env()
+    }
+
+    /// Find the streams passed by the parent.
+    ///
+    /// On Linux, the streams are already at their FD number, so this decodes the
+    /// `SANDBOX_FDS` environment variable that lists them, and fails if it or the channel
+    /// names are malformed.  A missing variable means there are no extra streams.
+    #[cfg(not(target_os = "windows"))]
+    pub fn from_env() -> Result<Self, std::io::Error> {
+        let fds = match env_string(SANDBOX_FDS_ENV)? {
+            None => HashMap::new(),
+            Some(v) => parse_fds(&v)?,
+        };
+        ChildStreams::from_map(fds).with_channels_from_env()
+    }
+
+    fn from_map(map: HashMap<u32, usize>) -> Self {
+        ChildStreams {
+            streams: map.into_iter().map(|(k, v)| (k, Some(v))).collect(),
+            channels: HashMap::new(),
+        }
+    }
+
+    fn with_channels_from_env(mut self) -> Result<Self, std::io::Error> {
+        if let Some(v) = env_string(SANDBOX_CHANNELS_ENV)? {
+            self.channels = parse_channels(&v)?;
+        }
+        Ok(self)
+    }
+
+    /// The logical FD for the named channel, if the parent declared it.
+    pub fn channel_fd(&self, name: &str) -> Option<u32> {
+        self.channels.get(name).copied()
+    }
+
+    /// Take the named channel, to read the data the parent sends.
+    pub fn take_channel_reader(&mut self, name: &str) -> Option<Box<dyn std::io::Read>> {
+        let fd = self.channel_fd(name)?;
+        self.take_reader(fd)
+    }
+
+    /// Take the named channel, to send data to the parent.
+    pub fn take_channel_writer(&mut self, name: &str) -> Option<Box<dyn std::io::Write>> {
+        let fd = self.channel_fd(name)?;
+        self.take_writer(fd)
+    }
+
+    /// Take ownership of the stream at the logical FD as a file.
+    /// Returns None if the parent didn't pass the FD, or it was already taken.
+    pub fn take_file(&mut self, fd: u32) -> Option<File> {
+        if fd <= 2 {
+            // The standard streams belong to std::io.
+            return None;
+        }
+        self.take_os_file(fd)
+    }
+
+    /// Take the stream at the logical FD, to read the data the parent sends.
+    pub fn take_reader(&mut self, fd: u32) -> Option<Box<dyn std::io::Read>> {
+        self.take_file(fd)
+            .map(|f| Box::new(f) as Box<dyn std::io::Read>)
+    }
+
+    /// Take the stream at the logical FD, to send data to the parent.
+    pub fn take_writer(&mut self, fd: u32) -> Option<Box<dyn std::io::Write>> {
+        self.take_file(fd)
+            .map(|f| Box::new(f) as Box<dyn std::io::Write>)
+    }
+
+    #[cfg(target_os = "windows")]
+    fn take_os_file(&mut self, fd: u32) -> Option<File> {
+        use std::os::windows::io::FromRawHandle;
+
+        let handle = self.streams.get_mut(&fd)?.take()?;
+        Some(unsafe { File::from_raw_handle(handle as std::os::windows::io::RawHandle) })
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn take_os_file(&mut self, fd: u32) -> Option<File> {
+        use std::os::fd::FromRawFd;
+
+        let raw = self.streams.get_mut(&fd)?.take()?;
+        if !is_open(raw as i32) {
+            return None;
+        }
+        // The parent listed the FD as a stream for this process, and it's only taken
+        // once, so nothing else owns it.
+        Some(unsafe { File::from_raw_fd(raw as i32) })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_open(fd: i32) -> bool {
+    nix::fcntl::fcntl(
+        unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) },
+        nix::fcntl::FcntlArg::F_GETFD,
+    )
+    .is_ok()
+}
+
+#[cfg(all(not(target_os = "linux"), not(target_os = "windows")))]
+fn is_open(_fd: i32) -> bool {
+    true
+}
+
+/// Read the environment variable, or None if it isn't set.
+fn env_string(name: &str) -> Result<Option<String>, std::io::Error> {
+    match std::env::var_os(name) {
+        None => Ok(None),
+        Some(v) => v.into_string().map(Some).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not valid unicode", name),
+            )
+        }),
+    }
+}
+
+/// Encode the extra stream FD numbers for the `SANDBOX_FDS` value.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn encode_fds(fds: &[u32]) -> String {
+    let mut ret = String::new();
+    for fd in fds {
+        ret.push_str(&format!("{};", fd));
+    }
+    ret
+}
+
+/// Decode the `SANDBOX_FDS` value into a map of logical FD to the OS descriptor, which
+/// is the same number.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+fn parse_fds(value: &str) -> Result<HashMap<u32, usize>, std::io::Error> {
+    let mut ret = HashMap::new();
+    for entry in value.split(';') {
+        if entry.is_empty() {
+            continue;
+        }
+        let fd: u32 = entry.parse().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("malformed SANDBOX_FDS entry '{}'", entry),
+            )
+        })?;
+        ret.insert(fd, fd as usize);
+    }
+    Ok(ret)
+}
+
+/// Encode the channel names and FD numbers for the `SANDBOX_CHANNELS` value.
+pub(crate) fn encode_channels(channels: &[(String, u32)]) -> String {
+    let mut ret = String::new();
+    for (name, fd) in channels {
+        ret.push_str(&format!("{}:{};", name, fd));
+    }
+    ret
+}
+
+/// Decode the `SANDBOX_CHANNELS` value into a map of channel name to logical FD.
+fn parse_channels(value: &str) -> Result<HashMap<String, u32>, std::io::Error> {
+    let mut ret = HashMap::new();
+    for entry in value.split(';') {
+        if entry.is_empty() {
+            continue;
+        }
+        let bad = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("malformed SANDBOX_CHANNELS entry '{}'", entry),
+            )
+        };
+        let (name, fd) = entry.rsplit_once(':').ok_or_else(bad)?;
+        let fd: u32 = fd.parse().map_err(|_| bad())?;
+        if name.is_empty() || ret.insert(name.to_string(), fd).is_some() {
+            return Err(bad());
+        }
+    }
+    Ok(ret)
+}
+
+/// Decode the `SANDBOX_HANDLES` value into a map of logical FD to handle address.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn parse_handles(value: &str) -> Result<HashMap<u32, usize>, std::io::Error> {
+    let mut ret = HashMap::new();
+    for entry in value.split(';') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let bad = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("malformed SANDBOX_HANDLES entry '{}'", entry),
+            )
+        };
+        let (fd, handle) = entry.split_once(':').ok_or_else(bad)?;
+        let fd: u32 = fd.parse().map_err(|_| bad())?;
+        let handle = handle
+            .strip_prefix("0x")
+            .or_else(|| handle.strip_prefix("0X"))
+            .ok_or_else(bad)?;
+        let handle = usize::from_str_radix(handle, 16).map_err(|_| bad())?;
+        if ret.insert(fd, handle).is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("SANDBOX_HANDLES lists fd {} more than once", fd),
+            ));
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_handles_basic() {
+        let map = parse_handles("3:0x1f4;4:0x00000000000001F8;").unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&3), Some(&0x1f4));
+        assert_eq!(map.get(&4), Some(&0x1f8));
+    }
+
+    #[test]
+    fn parse_handles_empty() {
+        assert!(parse_handles("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_handles_malformed() {
+        assert!(parse_handles("3=0x1f4;").is_err());
+        assert!(parse_handles("x:0x1f4;").is_err());
+        assert!(parse_handles("3:1f4;").is_err());
+        assert!(parse_handles("3:0x1f4;3:0x1f8;").is_err());
+    }
+
+    #[test]
+    fn channels_round_trip() {
+        let encoded = encode_channels(&[("control".to_string(), 3), ("log".to_string(), 4)]);
+        let map = parse_channels(&encoded).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("control"), Some(&3));
+        assert_eq!(map.get("log"), Some(&4));
+        assert!(parse_channels("control=3;").is_err());
+        assert!(parse_channels(":3;").is_err());
+    }
+
+    #[test]
+    fn take_std_streams_is_none() {
+        let mut streams = ChildStreams::from_map(HashMap::new());
+        assert!(streams.take_file(0).is_none());
+        assert!(streams.take_file(2).is_none());
+    }
+
+    #[test]
+    fn fds_round_trip() {
+        let map = parse_fds(&encode_fds(&[3, 4])).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&3), Some(&3));
+        assert_eq!(map.get(&4), Some(&4));
+        assert!(parse_fds("3;x;").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn take_closed_fd_is_none() {
+        // Far above anything the test harness opens.
+        let mut streams = ChildStreams::from_map(parse_fds("4000;").unwrap());
+        assert!(streams.take_file(4000).is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn take_undeclared_fd_is_none() {
+        use std::os::fd::AsRawFd as _;
+
+        let file = tempfile::tempfile().expect("created a temp file");
+        let mut streams = ChildStreams::from_map(HashMap::new());
+        assert!(streams.take_file(file.as_raw_fd() as u32).is_none());
+    }
+}
@@ -4,9 +4,21 @@
 //! process uses the simple STDIN, STDOUT, and STDERR.  The top-level README
 //! contains details about this communication method.
 
+pub mod cancel;
+#[cfg(feature = "codec")]
+pub mod codec;
+pub mod deadline;
 pub mod event;
+pub mod handshake;
+pub mod heartbeat;
+#[cfg(feature = "jsonl")]
+pub mod jsonl;
+pub mod mux;
 pub mod packet;
+pub mod rpc;
 pub mod sizedpacket;
 pub mod splitter;
+#[cfg(any(feature = "bincode", feature = "cbor"))]
+pub mod typed;
 
 mod rwutil;
@@ -0,0 +1,288 @@
+//! Ask a cooperative child to stop a long request.
+//!
+//! The parent sends a `CANCEL` event whose payload is the big-endian packet ID of the
+//! request to stop, or an empty payload to stop all of them.  The child keeps a
+//! `Cancellations` registry: it registers each request it starts, which hands back a
+//! `CancellationToken` for the work to check, and passes each packet it receives to
+//! `Cancellations::handle`, which cancels the matching tokens.  The child still answers
+//! the cancelled request, such as with what it finished so far.
+//!
+//! In the parent, `RpcEndpoint::call_cancellable` sends the `CANCEL` when its token is
+//! cancelled, and falls back to a hard termination if the child doesn't answer within a
+//! grace period.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::event::{EventId, EventPacket, EventPacketHeader};
+use super::rpc::RpcEndpoint;
+
+/// The event ID of a cancellation request.
+pub const CANCEL: EventId = EventId::from_static("cancel");
+
+/// A flag that one side sets to ask the work watching it to stop.  Clones share the flag.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<TokenState>);
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    next_callback: AtomicU64,
+    callbacks: Mutex<HashMap<u64, Box<dyn FnOnce() + Send>>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the work to stop.  Cancelling again does nothing.
+    pub fn cancel(&self) {
+        if self.0.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let callbacks: Vec<_> = match self.0.callbacks.lock() {
+            Ok(mut callbacks) => callbacks.drain().map(|(_, f)| f).collect(),
+            Err(_) => return,
+        };
+        for f in callbacks {
+            f();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fail with `Interrupted` once cancelled, so long loops can stop with `?`.
+    pub fn check(&self) -> Result<(), std::io::Error> {
+        if self.is_cancelled() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "cancelled",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Run `f` when the token is cancelled, or now if it already is.  Returns the key
+    /// to remove it with, if it didn't run.
+    pub(crate) fn on_cancel<F: FnOnce() + Send + 'static>(&self, f: F) -> Option<u64> {
+        let key = self.0.next_callback.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut callbacks) = self.0.callbacks.lock() {
+            // Checked under the lock, so `cancel` either sees the callback or set the flag
+            // before we looked.
+            if !self.is_cancelled() {
+                callbacks.insert(key, Box::new(f));
+                return Some(key);
+            }
+        }
+        f();
+        None
+    }
+
+    pub(crate) fn remove_callback(&self, key: u64) {
+        if let Ok(mut callbacks) = self.0.callbacks.lock() {
+            callbacks.remove(&key);
+        }
+    }
+}
+
+/// The child's tokens for the requests it's working on.
+#[derive(Default)]
+pub struct Cancellations {
+    tokens: Mutex<HashMap<u64, CancellationToken>>,
+}
+
+impl Cancellations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking the request, and return the token its work should check.
+    pub fn register(&self, request: &EventPacketHeader) -> CancellationToken {
+        let token = CancellationToken::new();
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.insert(u64::from_be_bytes(request.packet_id), token.clone());
+        }
+        token
+    }
+
+    /// Stop tracking the request, once it's answered.
+    pub fn finish(&self, request: &EventPacketHeader) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.remove(&u64::from_be_bytes(request.packet_id));
+        }
+    }
+
+    /// Cancel the requests named by the packet, if it's a `CANCEL`.  Returns whether it
+    /// was one, so the request loop can skip it.  A malformed `CANCEL` cancels nothing.
+    pub fn handle(&self, packet: &EventPacket) -> bool {
+        if packet.header.event_id != CANCEL {
+            return false;
+        }
+        let Ok(tokens) = self.tokens.lock() else {
+            return true;
+        };
+        if packet.payload.is_empty() {
+            tokens.values().for_each(CancellationToken::cancel);
+        } else if let Ok(id) = <[u8; 8]>::try_from(packet.payload.as_slice())
+            && let Some(token) = tokens.get(&u64::from_be_bytes(id))
+        {
+            token.cancel();
+        }
+        true
+    }
+}
+
+/// Ask the child to stop the request with the given packet ID, or all of its requests
+/// with `None`, without waiting for it to.
+pub fn send_cancel<W: std::io::Write>(
+    endpoint: &RpcEndpoint<W>,
+    request_id: Option<u64>,
+) -> Result<(), std::io::Error> {
+    let payload = request_id.map_or_else(Vec::new, |id| id.to_be_bytes().to_vec());
+    endpoint.notify(CANCEL, payload).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+
+    const WORK: EventId = EventId::from_static("work");
+    const DONE: EventId = EventId::from_static("done");
+
+    fn connected() -> (
+        RpcEndpoint<std::io::PipeWriter>,
+        RpcEndpoint<std::io::PipeWriter>,
+    ) {
+        let (a_read, b_write) = std::io::pipe().unwrap();
+        let (b_read, a_write) = std::io::pipe().unwrap();
+        (
+            RpcEndpoint::new(a_read, a_write, 1024).unwrap(),
+            RpcEndpoint::new(b_read, b_write, 1024).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_token() {
+        let token = CancellationToken::new();
+        let (ran, on_ran) = mpsc::channel();
+        let key = token.on_cancel(move || ran.send(()).unwrap());
+        assert!(key.is_some());
+        assert!(token.check().is_ok());
+
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(
+            token.check().unwrap_err().kind(),
+            std::io::ErrorKind::Interrupted
+        );
+        on_ran.try_recv().unwrap();
+        assert!(token.on_cancel(|| ()).is_none());
+    }
+
+    #[test]
+    fn test_cooperative_child() {
+        let (parent, child) = connected();
+        let child = std::thread::spawn(move || {
+            let cancellations = Cancellations::new();
+            let request = child.recv_request().unwrap();
+            let token = cancellations.register(&request.header);
+            let cancel = child.recv_request().unwrap();
+            assert!(cancellations.handle(&cancel));
+            assert!(token.is_cancelled());
+            cancellations.finish(&request.header);
+            child.respond(&request.header, DONE, vec![1]).unwrap();
+            child
+        });
+
+        let token = CancellationToken::new();
+        token.cancel();
+        // An already cancelled call isn't sent.
+        let err = parent
+            .call_cancellable(
+                WORK,
+                vec![],
+                Duration::from_secs(10),
+                &token,
+                Duration::ZERO,
+                || panic!("nothing to terminate"),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        let response = parent
+            .call_cancellable(
+                WORK,
+                vec![],
+                Duration::from_secs(10),
+                &token,
+                Duration::from_secs(10),
+                || panic!("the child stopped"),
+            )
+            .unwrap();
+        assert_eq!(response.payload, vec![1]);
+        child.join().unwrap();
+    }
+
+    #[test]
+    fn test_uncooperative_child() {
+        let (parent, child) = connected();
+        let (terminated, on_terminated) = mpsc::channel();
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let child = std::thread::spawn(move || {
+            child.recv_request().unwrap();
+            canceller.cancel();
+            // Ignore the cancel.
+            child.recv_request().unwrap();
+            child
+        });
+
+        let err = parent
+            .call_cancellable(
+                WORK,
+                vec![],
+                Duration::from_secs(10),
+                &token,
+                Duration::from_millis(20),
+                move || terminated.send(()).unwrap(),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        on_terminated.try_recv().unwrap();
+        child.join().unwrap();
+    }
+
+    #[test]
+    fn test_send_cancel() {
+        let cancellations = Cancellations::new();
+        let (parent, child) = connected();
+        let first = parent.notify(WORK, vec![]).unwrap();
+        parent.notify(WORK, vec![]).unwrap();
+        send_cancel(&parent, Some(first)).unwrap();
+        send_cancel(&parent, None).unwrap();
+
+        let first = child.recv_request().unwrap();
+        assert!(!cancellations.handle(&first));
+        let first = cancellations.register(&first.header);
+        let second = cancellations.register(&child.recv_request().unwrap().header);
+
+        assert!(cancellations.handle(&child.recv_request().unwrap()));
+        assert!(first.is_cancelled());
+        assert!(!second.is_cancelled());
+        assert!(cancellations.handle(&child.recv_request().unwrap()));
+        assert!(second.is_cancelled());
+    }
+}
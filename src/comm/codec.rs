@@ -0,0 +1,328 @@
+//! `tokio_util` codecs for the packet formats.
+//!
+//! Wrap any async byte stream, such as a pipe, a Unix socket, or a TCP stream, in a
+//! `tokio_util::codec::Framed` with one of these codecs to read and write whole packets:
+//! `SizePacketCodec` for `SizePacket`s, and `EventCodec` for `EventPacket`s.  The codecs
+//! use the blocking readers and writers on the buffered bytes, so their checksum,
+//! compression, and maximum payload size settings carry over, and they interoperate with
+//! a side using the blocking API.
+//!
+//! To only read packets, `EventReader::stream` and `SizePacketRead::stream` turn an
+//! `AsyncRead` into a `PacketStream`, the async counterpart of `packet::PacketIter`.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{BufMut as _, BytesMut};
+use tokio::io::AsyncRead;
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
+
+use super::event::{EventPacket, EventReader, EventWriter};
+use super::packet::{U8Packet, U8PacketRead as _, U8PacketWrite as _};
+use super::sizedpacket::{SizeHeader, SizePacket, SizePacketRead, SizePacketWrite};
+
+/// Decodes and encodes `SizePacket`s.
+pub struct SizePacketCodec {
+    reader: SizePacketRead,
+    writer: SizePacketWrite,
+}
+
+impl SizePacketCodec {
+    /// Decoding fails, before buffering the payload, when a packet's payload is over
+    /// `max_payload_size`.
+    pub fn new(max_payload_size: usize) -> Self {
+        SizePacketCodec {
+            reader: SizePacketRead::new(max_payload_size),
+            writer: SizePacketWrite::new(),
+        }
+    }
+
+    /// Read and write a CRC32 trailer after each packet, as negotiated with
+    /// `handshake::Features::CHECKSUM`.
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.reader = self.reader.with_checksum(checksum);
+        self.writer = self.writer.with_checksum(checksum);
+        self
+    }
+
+    /// Compress payloads of at least `threshold` bytes, and decompress what the other
+    /// side compressed, as negotiated with `handshake::Features::LZ4`.  `None` turns
+    /// compression off.
+    #[cfg(feature = "lz4")]
+    pub fn with_compression(mut self, threshold: Option<usize>) -> Self {
+        self.reader = self.reader.with_compression(threshold.is_some());
+        self.writer = self.writer.with_compression(threshold);
+        self
+    }
+}
+
+impl Decoder for SizePacketCodec {
+    type Item = SizePacket;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<SizePacket>, std::io::Error> {
+        let Some(packet) = split_packet(src, self.reader.packet_len(src)?) else {
+            return Ok(None);
+        };
+        self.reader.read(&mut &packet[..]).map(Some)
+    }
+}
+
+impl<P: AsRef<[u8]>> Encoder<&U8Packet<SizeHeader, P>> for SizePacketCodec {
+    type Error = std::io::Error;
+
+    fn encode(
+        &mut self,
+        packet: &U8Packet<SizeHeader, P>,
+        dst: &mut BytesMut,
+    ) -> Result<(), std::io::Error> {
+        self.writer.write(&mut dst.writer(), packet)
+    }
+}
+
+impl<P: AsRef<[u8]>> Encoder<U8Packet<SizeHeader, P>> for SizePacketCodec {
+    type Error = std::io::Error;
+
+    fn encode(
+        &mut self,
+        packet: U8Packet<SizeHeader, P>,
+        dst: &mut BytesMut,
+    ) -> Result<(), std::io::Error> {
+        self.encode(&packet, dst)
+    }
+}
+
+/// Decodes and encodes `EventPacket`s.
+pub struct EventCodec {
+    reader: EventReader,
+    writer: EventWriter,
+}
+
+impl EventCodec {
+    /// Decoding fails, before buffering the payload, when a packet's payload is over
+    /// `max_payload_size`.
+    pub fn new(max_payload_size: usize) -> Self {
+        EventCodec {
+            reader: EventReader::new(max_payload_size),
+            writer: EventWriter::new(),
+        }
+    }
+
+    /// Read and write a CRC32 trailer after each packet, as negotiated with
+    /// `handshake::Features::CHECKSUM`.
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.reader = self.reader.with_checksum(checksum);
+        self.writer = self.writer.with_checksum(checksum);
+        self
+    }
+
+    /// Compress payloads of at least `threshold` bytes, and decompress what the other
+    /// side compressed, as negotiated with `handshake::Features::LZ4`.  `None` turns
+    /// compression off.
+    #[cfg(feature = "lz4")]
+    pub fn with_compression(mut self, threshold: Option<usize>) -> Self {
+        self.reader = self.reader.with_compression(threshold.is_some());
+        self.writer = self.writer.with_compression(threshold);
+        self
+    }
+}
+
+impl Decoder for EventCodec {
+    type Item = EventPacket;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<EventPacket>, std::io::Error> {
+        let Some(packet) = split_packet(src, self.reader.packet_len(src)?) else {
+            return Ok(None);
+        };
+        self.reader.read(&mut &packet[..]).map(Some)
+    }
+}
+
+impl<P: AsRef<[u8]>> Encoder<&EventPacket<P>> for EventCodec {
+    type Error = std::io::Error;
+
+    fn encode(
+        &mut self,
+        packet: &EventPacket<P>,
+        dst: &mut BytesMut,
+    ) -> Result<(), std::io::Error> {
+        self.writer.write(&mut dst.writer(), packet)
+    }
+}
+
+impl<P: AsRef<[u8]>> Encoder<EventPacket<P>> for EventCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, packet: EventPacket<P>, dst: &mut BytesMut) -> Result<(), std::io::Error> {
+        self.encode(&packet, dst)
+    }
+}
+
+/// Yields the packets read from an async stream, until it ends or fails.
+///
+/// A stream that ends between packets ends the `Stream`.  Any other error, including one
+/// that ends the stream partway into a packet, is yielded once, and then the `Stream`
+/// ends.
+pub struct PacketStream<S, C> {
+    inner: FramedRead<S, C>,
+}
+
+impl<S: AsyncRead, C: Decoder<Error = std::io::Error>> PacketStream<S, C> {
+    pub fn new(source: S, codec: C) -> Self {
+        PacketStream {
+            inner: FramedRead::new(source, codec),
+        }
+    }
+
+    /// The stream, and any bytes read from it past the last packet.
+    pub fn into_parts(self) -> (S, BytesMut) {
+        let parts = self.inner.into_parts();
+        (parts.io, parts.read_buf)
+    }
+}
+
+impl<S: AsyncRead + Unpin, C: Decoder<Error = std::io::Error>> futures_core::Stream
+    for PacketStream<S, C>
+{
+    type Item = Result<C::Item, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl EventReader {
+    /// Read event packets from the async stream, with this reader's settings.
+    pub fn stream<S: AsyncRead>(&self, source: S) -> PacketStream<S, EventCodec> {
+        let codec = EventCodec {
+            reader: self.clone(),
+            writer: EventWriter::new(),
+        };
+        PacketStream::new(source, codec)
+    }
+}
+
+impl SizePacketRead {
+    /// Read packets from the async stream, with this reader's settings.
+    pub fn stream<S: AsyncRead>(&self, source: S) -> PacketStream<S, SizePacketCodec> {
+        let codec = SizePacketCodec {
+            reader: self.clone(),
+            writer: SizePacketWrite::new(),
+        };
+        PacketStream::new(source, codec)
+    }
+}
+
+/// Take the packet of `len` bytes off the front of the buffer, or, if it hasn't all
+/// arrived, make room for the rest and return `None`.
+fn split_packet(src: &mut BytesMut, len: Option<usize>) -> Option<BytesMut> {
+    let len = len?;
+    if src.len() < len {
+        src.reserve(len - src.len());
+        return None;
+    }
+    Some(src.split_to(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::event::{EventId, EventPacketHeader};
+    use super::*;
+
+    const WORK: EventId = EventId::from_static("work");
+
+    #[test]
+    fn test_packet_stream() {
+        use futures_core::Stream as _;
+        use std::future::poll_fn;
+
+        let mut wire = BytesMut::new();
+        let mut codec = SizePacketCodec::new(10);
+        for payload in [vec![1], vec![2, 3]] {
+            let header = SizeHeader {
+                size: payload.len(),
+            };
+            codec
+                .encode(SizePacket { header, payload }, &mut wire)
+                .unwrap();
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let payloads = runtime.block_on(async {
+            let mut stream = SizePacketRead::new(10).stream(&wire[..]);
+            let mut payloads = Vec::new();
+            while let Some(packet) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+                payloads.push(packet.unwrap().payload);
+            }
+            payloads
+        });
+        assert_eq!(payloads, vec![vec![1], vec![2, 3]]);
+    }
+
+    #[test]
+    fn test_size_packet_codec() {
+        let mut codec = SizePacketCodec::new(10).with_checksum(true);
+        let mut wire = BytesMut::new();
+        for payload in [vec![1, 2, 3], vec![]] {
+            let packet = SizePacket {
+                header: SizeHeader {
+                    size: payload.len(),
+                },
+                payload,
+            };
+            codec.encode(packet, &mut wire).unwrap();
+        }
+
+        // Fed a byte at a time, as a slow stream would.
+        let mut src = BytesMut::new();
+        let mut decoded = Vec::new();
+        for b in wire {
+            src.put_u8(b);
+            if let Some(packet) = codec.decode(&mut src).unwrap() {
+                decoded.push(packet.payload);
+            }
+        }
+        assert_eq!(decoded, vec![vec![1, 2, 3], vec![]]);
+        assert!(src.is_empty());
+
+        let mut src = BytesMut::from(&[0u8, 0, 0, 11][..]);
+        let err = codec.decode(&mut src).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_event_codec() {
+        let mut codec = EventCodec::new(10);
+        let packet = EventPacket {
+            header: EventPacketHeader {
+                packet_id: 1u64.to_be_bytes(),
+                cmd_packet_id: 0u64.to_be_bytes(),
+                event_id: WORK,
+                size: 2,
+            },
+            payload: vec![8, 9],
+        };
+        let mut src = BytesMut::new();
+        codec.encode(&packet, &mut src).unwrap();
+        let whole = src.len();
+        let rest = src.split_off(whole - 1);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        src.unsplit(rest);
+        let decoded = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(decoded.header.event_id, WORK);
+        assert_eq!(decoded.header.packet_id, packet.header.packet_id);
+        assert_eq!(decoded.payload, packet.payload);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        let mut bad = EventPacket {
+            payload: vec![],
+            ..packet
+        };
+        bad.header.size = 1;
+        let err = codec.encode(bad, &mut src).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,254 @@
+//! Give up on a read when the child stops responding.
+//!
+//! Wrap the parent's end of a child stream, such as a `ChildReader`, in a
+//! `DeadlineReader`, and read packets through it with the readers' `read_timeout`.  Each
+//! read first waits for the stream to have data, and fails with `TimedOut` once the
+//! deadline passes, or with `ConnectionAborted` once the reader's `CancellationToken` is
+//! cancelled, such as by another thread that saw the child wedge.
+//!
+//! On Linux the wait is a `poll` on the stream and on a pipe that the cancel wakes up.
+//! On Windows it checks the pipe for data with `PeekNamedPipe` every few milliseconds.
+//!
+//! A packet read that gives up partway leaves the rest of the packet on the stream, so
+//! after a timeout or a cancel, stop reading packets from it.
+
+use std::time::{Duration, Instant};
+
+use super::cancel::CancellationToken;
+
+/// How often the Windows wait checks the pipe for data.
+#[cfg(target_os = "windows")]
+const PEEK_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A reader whose reads wait no longer than its deadline, and stop when its token is
+/// cancelled.
+pub struct DeadlineReader<R> {
+    source: R,
+    deadline: Option<Instant>,
+    token: CancellationToken,
+    callback: Option<u64>,
+    #[cfg(target_os = "linux")]
+    wake: std::io::PipeReader,
+}
+
+impl<R> DeadlineReader<R> {
+    /// Wrap the stream, with no deadline.  Cancelling the token stops any read, now or
+    /// later.
+    pub fn new(source: R, token: CancellationToken) -> Result<Self, std::io::Error> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::io::Write as _;
+            let (wake, waker) = std::io::pipe()?;
+            let callback = token.on_cancel(move || {
+                let _ = (&waker).write(&[0]);
+            });
+            Ok(DeadlineReader {
+                source,
+                deadline: None,
+                token,
+                callback,
+                wake,
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        Ok(DeadlineReader {
+            source,
+            deadline: None,
+            token,
+            callback: None,
+        })
+    }
+
+    /// Fail reads with `TimedOut` once `timeout` from now passes, or never with `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|t| Instant::now() + t);
+    }
+
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.source
+    }
+
+    /// Fail if the read should give up now, or return how long it may still wait.
+    fn remaining(&self) -> Result<Option<Duration>, std::io::Error> {
+        if self.token.is_cancelled() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "read cancelled",
+            ));
+        }
+        match self.deadline {
+            None => Ok(None),
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "read timed out",
+                    ));
+                }
+                Ok(Some(left))
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<R: std::os::fd::AsRawFd> DeadlineReader<R> {
+    fn wait_readable(&self) -> Result<(), std::io::Error> {
+        use std::os::fd::AsRawFd as _;
+        loop {
+            let wait_ms = match self.remaining()? {
+                None => -1,
+                // Round up, so a sub-millisecond remainder doesn't spin.
+                Some(left) => left.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32,
+            };
+            let mut pollfds = [
+                nix::libc::pollfd {
+                    fd: self.source.as_raw_fd(),
+                    events: nix::libc::POLLIN,
+                    revents: 0,
+                },
+                nix::libc::pollfd {
+                    fd: self.wake.as_raw_fd(),
+                    events: nix::libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            if unsafe { nix::libc::poll(pollfds.as_mut_ptr(), 2, wait_ms) } < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            // Data, the end of the stream, or an error; the read reports which.
+            if pollfds[0].revents != 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<R: std::io::Read + std::os::fd::AsRawFd> std::io::Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.wait_readable()?;
+        self.source.read(buf)
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl<R: std::os::windows::io::AsRawHandle> DeadlineReader<R> {
+    fn wait_readable(&self) -> Result<(), std::io::Error> {
+        use windows::Win32::{Foundation::HANDLE, System::Pipes::PeekNamedPipe};
+        loop {
+            let left = self.remaining()?;
+            let mut available = 0u32;
+            let peeked = unsafe {
+                PeekNamedPipe(
+                    HANDLE(self.source.as_raw_handle()),
+                    None,
+                    0,
+                    None,
+                    Some(&mut available as *mut u32),
+                    None,
+                )
+            };
+            // A closed or broken pipe fails the peek; the read reports it.
+            if peeked.is_err() || available > 0 {
+                return Ok(());
+            }
+            std::thread::sleep(left.map_or(PEEK_INTERVAL, |left| left.min(PEEK_INTERVAL)));
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl<R: std::io::Read + std::os::windows::io::AsRawHandle> std::io::Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.wait_readable()?;
+        self.source.read(buf)
+    }
+}
+
+impl<R> Drop for DeadlineReader<R> {
+    fn drop(&mut self) {
+        if let Some(key) = self.callback {
+            self.token.remove_callback(key);
+        }
+    }
+}
+
+/// Read with `read` under a deadline of `timeout` from now, then clear the deadline.
+pub(crate) fn with_timeout<R, T, F>(
+    source: &mut DeadlineReader<R>,
+    timeout: Duration,
+    read: F,
+) -> Result<T, std::io::Error>
+where
+    F: FnOnce(&mut DeadlineReader<R>) -> Result<T, std::io::Error>,
+{
+    source.set_timeout(Some(timeout));
+    let result = read(source);
+    source.set_timeout(None);
+    result
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::io::Read as _;
+
+    use super::super::packet::U8PacketWrite as _;
+    use super::super::sizedpacket::{SizeHeader, SizePacket, SizePacketRead, SizePacketWrite};
+    use super::*;
+
+    #[test]
+    fn test_timeout() {
+        let (source, mut out) = std::io::pipe().unwrap();
+        let mut source = DeadlineReader::new(source, CancellationToken::new()).unwrap();
+        let reader = SizePacketRead::new(10);
+        let started = Instant::now();
+        let err = reader
+            .read_timeout(&mut source, Duration::from_millis(20))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        // Nothing was read, so the stream is still at a packet boundary.
+        let packet = SizePacket {
+            header: SizeHeader { size: 2 },
+            payload: vec![1, 2],
+        };
+        SizePacketWrite::new().write(&mut out, &packet).unwrap();
+        let read = reader
+            .read_timeout(&mut source, Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(read.payload, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (source, _out) = std::io::pipe().unwrap();
+        let token = CancellationToken::new();
+        let mut reader = DeadlineReader::new(source, token.clone()).unwrap();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        let mut buf = [0u8; 1];
+        assert_eq!(
+            reader.read(&mut buf).unwrap_err().kind(),
+            std::io::ErrorKind::ConnectionAborted
+        );
+    }
+}
@@ -2,16 +2,151 @@
 //!
 //! Handles sending an event packet, and receiving an event packet.
 
+use std::io::Read as _;
+use std::time::Duration;
+
+use super::deadline::{self, DeadlineReader};
+use super::packet::{PacketIter, ReadPacket};
+use super::rwutil;
+
 /// The header for event packets.
 /// TODO fix up the size to be constants, throughout this source.
 /// TODO simplify the names.
+#[derive(Debug)]
 pub struct EventPacketHeader {
     pub packet_id: [u8; EVENT_PACKET_HEADER_PACKET_ID_SIZE],
     pub cmd_packet_id: [u8; 8],
-    pub event_id: [u8; 12],
+    pub event_id: EventId,
     pub size: usize,
 }
 
+/// The number of bytes in an event ID.
+pub const EVENT_ID_SIZE: usize = 12;
+
+/// Identifies the kind of an event packet.
+///
+/// A valid ID is 1 to 12 bytes without any zeros; the packet header pads it with zeros.
+/// Declare the IDs a protocol uses as constants, which checks them at compile time:
+///
+/// ```
+/// use gracklezero::comm::event::EventId;
+///
+/// const STARTED: EventId = EventId::from_static("started");
+///
+/// assert_eq!(STARTED, "started");
+/// assert!(EventId::new("much-too-long-id").is_err());
+/// ```
+///
+/// IDs read from a stream keep their bytes as sent, so they may not be valid; match them
+/// against the constants.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EventId([u8; EVENT_ID_SIZE]);
+
+impl EventId {
+    /// Create the ID for a constant.  Panics, at compile time for a `const`, if the name
+    /// is not a valid ID.
+    pub const fn from_static(name: &'static str) -> Self {
+        match pad_event_id(name.as_bytes()) {
+            Ok(id) => EventId(id),
+            Err(_) => panic!("invalid event ID"),
+        }
+    }
+
+    /// Create the ID, or an `InvalidInput` error if the name is empty, too long, or
+    /// contains a zero byte.
+    pub fn new(name: &str) -> Result<Self, std::io::Error> {
+        pad_event_id(name.as_bytes())
+            .map(EventId)
+            .map_err(|msg| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg))
+    }
+
+    /// Wrap the bytes from a packet header, as sent.
+    pub const fn from_bytes(bytes: [u8; EVENT_ID_SIZE]) -> Self {
+        EventId(bytes)
+    }
+
+    /// The bytes as they appear in the packet header.
+    pub const fn as_bytes(&self) -> &[u8; EVENT_ID_SIZE] {
+        &self.0
+    }
+
+    /// Whether `new` would accept this ID's name.
+    pub fn is_valid(&self) -> bool {
+        self.as_str().is_some()
+    }
+
+    /// The ID's name without its padding, or `None` if the ID isn't valid UTF-8 text.
+    pub fn as_str(&self) -> Option<&str> {
+        let name = self.name_bytes();
+        if pad_event_id(name).is_err() {
+            return None;
+        }
+        std::str::from_utf8(name).ok()
+    }
+
+    fn name_bytes(&self) -> &[u8] {
+        let len = self.0.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        &self.0[..len]
+    }
+}
+
+impl TryFrom<&str> for EventId {
+    type Error = std::io::Error;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        EventId::new(name)
+    }
+}
+
+impl PartialEq<str> for EventId {
+    fn eq(&self, other: &str) -> bool {
+        let other = other.as_bytes();
+        other.len() <= EVENT_ID_SIZE
+            && self.0[..other.len()] == *other
+            && self.0[other.len()..].iter().all(|b| *b == 0)
+    }
+}
+
+impl PartialEq<&str> for EventId {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl std::fmt::Display for EventId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.as_str() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.name_bytes().escape_ascii()),
+        }
+    }
+}
+
+impl std::fmt::Debug for EventId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EventId(\"{}\")", self)
+    }
+}
+
+const fn pad_event_id(name: &[u8]) -> Result<[u8; EVENT_ID_SIZE], &'static str> {
+    if name.is_empty() {
+        return Err("event ID is empty");
+    }
+    if name.len() > EVENT_ID_SIZE {
+        return Err("event ID is longer than 12 bytes");
+    }
+    let mut id = [0; EVENT_ID_SIZE];
+    let mut i = 0;
+    while i < name.len() {
+        if name[i] == 0 {
+            return Err("event ID contains a zero byte");
+        }
+        id[i] = name[i];
+        i += 1;
+    }
+    Ok(id)
+}
+
 const EVENT_PACKET_HEADER_PACKET_ID_SIZE: usize = 8;
 const _HEADER_PACKET_ID_POS_START: usize = 0;
 const _HEADER_PACKET_ID_POS_END: usize =
@@ -19,7 +154,7 @@ const _HEADER_PACKET_ID_POS_END: usize =
 const _HEADER_CMD_PACKET_ID_POS_START: usize = _HEADER_PACKET_ID_POS_END;
 const _HEADER_CMD_PACKET_ID_POS_END: usize = _HEADER_CMD_PACKET_ID_POS_START + 8;
 const _HEADER_EVENT_ID_POS_START: usize = _HEADER_CMD_PACKET_ID_POS_END;
-const _HEADER_EVENT_ID_POS_END: usize = _HEADER_EVENT_ID_POS_START + 12;
+const _HEADER_EVENT_ID_POS_END: usize = _HEADER_EVENT_ID_POS_START + EVENT_ID_SIZE;
 const _HEADER_SIZE_POS_START: usize = _HEADER_EVENT_ID_POS_END;
 const _HEADER_SIZE_POS_END: usize = _HEADER_SIZE_POS_START + 4;
 const _HEADER_COUNT: usize = _HEADER_SIZE_POS_END;
@@ -28,61 +163,264 @@ const _HEADER_PAYLOAD_POS_START: usize = _HEADER_SIZE_POS_END;
 /// The full event packet.
 /// The payload length must match the header's size value.
 /// This reads the full event packet into memory.
-pub struct EventPacket {
+///
+/// The reader returns the payload as a `Vec<u8>`; the writer takes any payload that can
+/// be viewed as bytes, such as a `bytes::Bytes` slice of a larger buffer.
+#[derive(Debug)]
+pub struct EventPacket<P = Vec<u8>> {
     pub header: EventPacketHeader,
-    pub payload: Vec<u8>,
+    pub payload: P,
+}
+
+#[cfg(feature = "bytes")]
+impl EventPacket {
+    /// Turn the payload into `Bytes`, without copying it, so it can be sliced and passed
+    /// on cheaply, such as to another endpoint or channel.
+    pub fn freeze(self) -> EventPacket<bytes::Bytes> {
+        EventPacket {
+            header: self.header,
+            payload: self.payload.into(),
+        }
+    }
 }
 
 /// Handles reading events.
+///
+/// Payloads are read straight into the packet's memory.  To read many packets without
+/// an allocation each, keep one packet around and pass it to `read_into`.
+#[derive(Clone)]
 pub struct EventReader {
     max_payload_size: usize,
+    checksum: bool,
+    #[cfg(feature = "lz4")]
+    decompress: bool,
 }
 
 const _BUFFER_SIZE: usize = 8 * 1024;
 
 impl EventReader {
     pub fn new(max_payload_size: usize) -> Self {
-        EventReader { max_payload_size }
+        EventReader {
+            max_payload_size,
+            checksum: false,
+            #[cfg(feature = "lz4")]
+            decompress: false,
+        }
+    }
+
+    /// Decode payloads sent by a writer `with_compression`, as negotiated with
+    /// `handshake::Features::LZ4`.  The maximum payload size applies to the decompressed
+    /// payload.
+    #[cfg(feature = "lz4")]
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.decompress = compression;
+        self
+    }
+
+    /// Expect a CRC32 trailer after each packet, as negotiated with
+    /// `handshake::Features::CHECKSUM`, and fail with `InvalidData` when it doesn't match.
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
     }
 
     /// Read the next event packet from the stream.
-    pub fn read<R: std::io::Read>(self, source: &mut R) -> Result<EventPacket, std::io::Error> {
+    pub fn read<R: std::io::Read>(
+        &mut self,
+        source: &mut R,
+    ) -> Result<EventPacket, std::io::Error> {
+        let mut packet = EventPacket {
+            header: EventPacketHeader {
+                packet_id: [0; 8],
+                cmd_packet_id: [0; 8],
+                event_id: EventId::from_bytes([0; EVENT_ID_SIZE]),
+                size: 0,
+            },
+            payload: Vec::new(),
+        };
+        self.read_into(source, &mut packet)?;
+        Ok(packet)
+    }
+
+    /// Iterate over the event packets read from the stream, until it ends or fails:
+    /// `for packet in reader.iter(&mut source)`.
+    pub fn iter<'a, R: std::io::Read>(
+        &'a mut self,
+        source: &'a mut R,
+    ) -> PacketIter<'a, &'a mut EventReader, R> {
+        PacketIter::new(self, source)
+    }
+
+    /// Read the next event packet, failing with `TimedOut` if it doesn't arrive within
+    /// `timeout`, or with `ConnectionAborted` if the reader's token is cancelled first.
+    ///
+    /// After either failure, part of the packet may have been read; stop reading packets
+    /// from the stream.
+    pub fn read_timeout<R>(
+        &mut self,
+        source: &mut DeadlineReader<R>,
+        timeout: Duration,
+    ) -> Result<EventPacket, std::io::Error>
+    where
+        DeadlineReader<R>: std::io::Read,
+    {
+        deadline::with_timeout(source, timeout, |source| self.read(source))
+    }
+
+    /// Read the next event packet's header, and return a stream over its payload, so a
+    /// payload of up to 4 GiB never has to fit in memory.  The maximum payload size
+    /// doesn't apply.
+    ///
+    /// Read the payload to its end, or call `PayloadStream::finish`, before reading the
+    /// next packet.  With a checksum, the end of the payload fails with `InvalidData` if
+    /// it doesn't match.  Compressed streams can't be read this way; use `read_chunked`.
+    pub fn read_stream<'s, R: std::io::Read>(
+        &self,
+        source: &'s mut R,
+    ) -> Result<PayloadStream<'s, R>, std::io::Error> {
+        #[cfg(feature = "lz4")]
+        if self.decompress {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "can't stream a compressed payload",
+            ));
+        }
         let mut header_buff: [u8; _HEADER_COUNT] = [0; _HEADER_COUNT];
         source.read_exact(&mut header_buff)?;
-        let size = header_size(&header_buff, self.max_payload_size)?;
-
-        let mut remaining = size;
-        let mut payload = Vec::with_capacity(size);
-        let mut buff: [u8; _BUFFER_SIZE] = [0; _BUFFER_SIZE];
-        while remaining > 0 {
-            let read_count = std::cmp::min(_BUFFER_SIZE, remaining);
-            match source.read_exact(&mut buff[0..read_count]) {
-                Ok(_) => (),
-                Err(e) => {
-                    return Err(e);
-                }
-            };
-            payload.extend_from_slice(&buff[0..read_count]);
-            remaining -= read_count;
-        }
-        Ok(EventPacket {
+        let size = header_size(&header_buff, u32::MAX as usize)?;
+        let hasher = self.checksum.then(|| {
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(&header_buff);
+            hasher
+        });
+        Ok(PayloadStream {
+            source,
             header: EventPacketHeader {
                 packet_id: header_packet_id(&header_buff),
                 cmd_packet_id: header_cmd_packet_id(&header_buff),
-                event_id: header_event_id(&header_buff),
+                event_id: EventId::from_bytes(header_event_id(&header_buff)),
                 size,
             },
-            payload,
+            remaining: size,
+            hasher,
         })
     }
+
+    /// Read a payload sent with `EventWriter::write_chunked`, as a stream over the chunks.
+    ///
+    /// Each chunk is read whole, so the maximum payload size, the checksum, and
+    /// compression apply to each chunk; the whole payload can be any size.  The stream
+    /// fails with `InvalidData` if a packet from another payload shows up between the
+    /// chunks.
+    pub fn read_chunked<'s, R: std::io::Read>(
+        &'s mut self,
+        source: &'s mut R,
+    ) -> Result<ChunkedStream<'s, R>, std::io::Error> {
+        let chunk = self.read(source)?;
+        Ok(ChunkedStream {
+            done: chunk.payload.is_empty(),
+            reader: self,
+            source,
+            chunk,
+            pos: 0,
+        })
+    }
+
+    /// Read the next event packet from the stream into the packet, reusing its
+    /// payload's memory.
+    ///
+    /// On an error, the packet's contents are unspecified.
+    pub fn read_into<R: std::io::Read>(
+        &mut self,
+        source: &mut R,
+        packet: &mut EventPacket,
+    ) -> Result<(), std::io::Error> {
+        let mut header_buff: [u8; _HEADER_COUNT] = [0; _HEADER_COUNT];
+        source.read_exact(&mut header_buff)?;
+        let size = header_size(&header_buff, self.max_wire_size())?;
+        let payload = &mut packet.payload;
+        rwutil::read_payload_into(source, size, payload)?;
+        if self.checksum {
+            rwutil::check_crc32(source, &header_buff, payload)?;
+        }
+        #[cfg(feature = "lz4")]
+        if self.decompress {
+            rwutil::decompress_payload(payload, self.max_payload_size)?;
+        }
+        packet.header = EventPacketHeader {
+            packet_id: header_packet_id(&header_buff),
+            cmd_packet_id: header_cmd_packet_id(&header_buff),
+            event_id: EventId::from_bytes(header_event_id(&header_buff)),
+            size: payload.len(),
+        };
+        Ok(())
+    }
+
+    /// The length of the packet at the start of `buffered`, including any checksum
+    /// trailer, or `None` if not even its header is there yet.  Fails like `read` when
+    /// the header's size is over the maximum.
+    #[cfg(feature = "codec")]
+    pub(crate) fn packet_len(&self, buffered: &[u8]) -> Result<Option<usize>, std::io::Error> {
+        let Some(header_buff) = buffered.first_chunk::<_HEADER_COUNT>() else {
+            return Ok(None);
+        };
+        let size = header_size(header_buff, self.max_wire_size())?;
+        let trailer = if self.checksum { rwutil::U32_SIZE } else { 0 };
+        Ok(Some(_HEADER_COUNT + size + trailer))
+    }
+
+    /// The largest payload allowed on the stream, which compression adds to.
+    fn max_wire_size(&self) -> usize {
+        #[cfg(feature = "lz4")]
+        if self.decompress {
+            return self.max_payload_size + rwutil::COMPRESSION_OVERHEAD;
+        }
+        self.max_payload_size
+    }
+}
+
+impl ReadPacket for &mut EventReader {
+    type Packet = EventPacket;
+
+    fn read_packet<R: std::io::Read>(
+        &mut self,
+        source: &mut R,
+    ) -> Result<EventPacket, std::io::Error> {
+        self.read(source)
+    }
 }
 
 /// Handles writing events.
-pub struct EventWriter {}
+#[derive(Default)]
+pub struct EventWriter {
+    checksum: bool,
+    #[cfg(feature = "lz4")]
+    compress_threshold: Option<usize>,
+}
 
 impl EventWriter {
     pub fn new() -> Self {
-        EventWriter {}
+        EventWriter {
+            checksum: false,
+            #[cfg(feature = "lz4")]
+            compress_threshold: None,
+        }
+    }
+
+    /// Compress payloads of at least `threshold` bytes, as negotiated with
+    /// `handshake::Features::LZ4`; smaller ones, and ones LZ4 can't shrink, go as is.
+    /// `None` turns compression off.  The reader must be built `with_compression` too.
+    #[cfg(feature = "lz4")]
+    pub fn with_compression(mut self, threshold: Option<usize>) -> Self {
+        self.compress_threshold = threshold;
+        self
+    }
+
+    /// Write a CRC32 trailer after each packet, as negotiated with
+    /// `handshake::Features::CHECKSUM`.
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
     }
 
     /// Writes the packet to the stream.
@@ -92,62 +430,214 @@ impl EventWriter {
     /// returns an error without writing anything.
     ///
     /// The writer is flushed after the packet is written.
-    pub fn write<W: std::io::Write>(
-        self,
+    pub fn write<W: std::io::Write, P: AsRef<[u8]>>(
+        &self,
         out: &mut W,
-        packet: &EventPacket,
+        packet: &EventPacket<P>,
     ) -> Result<(), std::io::Error> {
-        if packet.header.size != packet.payload.len() {
+        let payload = packet.payload.as_ref();
+        if packet.header.size != payload.len() {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "header size != payload size",
             ));
         }
-        let header_size = size_to_octets(packet.header.size)?;
-        out.write_all(&packet.header.packet_id)?;
-        out.write_all(&packet.header.cmd_packet_id)?;
-        out.write_all(&packet.header.event_id)?;
-        out.write_all(&header_size)?;
+        // The header goes out in one write, rather than one per field.
+        let mut header_buff: [u8; _HEADER_COUNT] = [0; _HEADER_COUNT];
+        header_buff[_HEADER_PACKET_ID_POS_START.._HEADER_PACKET_ID_POS_END]
+            .copy_from_slice(&packet.header.packet_id);
+        header_buff[_HEADER_CMD_PACKET_ID_POS_START.._HEADER_CMD_PACKET_ID_POS_END]
+            .copy_from_slice(&packet.header.cmd_packet_id);
+        header_buff[_HEADER_EVENT_ID_POS_START.._HEADER_EVENT_ID_POS_END]
+            .copy_from_slice(packet.header.event_id.as_bytes());
+        let payload = self.encode_payload(payload);
+        header_buff[_HEADER_SIZE_POS_START.._HEADER_SIZE_POS_END]
+            .copy_from_slice(&size_to_octets(payload.len())?);
+        out.write_all(&header_buff)?;
 
-        let chunks: (&[[u8; _BUFFER_SIZE]], &[u8]) = packet.payload.as_chunks();
+        let chunks: (&[[u8; _BUFFER_SIZE]], &[u8]) = payload.as_chunks();
         for p in chunks.0 {
             out.write_all(p)?;
         }
         out.write_all(chunks.1)?;
+        if self.checksum {
+            rwutil::write_crc32(out, &header_buff, &payload)?;
+        }
 
-        Ok(())
+        out.flush()
     }
 
-    /// Write the event, with the event ID as a &str.
+    /// The payload as it goes on the stream.
+    fn encode_payload<'p>(&self, payload: &'p [u8]) -> std::borrow::Cow<'p, [u8]> {
+        #[cfg(feature = "lz4")]
+        if let Some(threshold) = self.compress_threshold {
+            return rwutil::compress_payload(payload, threshold).into();
+        }
+        payload.into()
+    }
+
+    /// Write everything from `source` as a chunked payload: packets of up to `chunk_size`
+    /// bytes, each with the same IDs, then an empty packet to end it.  Returns the number
+    /// of bytes sent.  The other side reads it with `EventReader::read_chunked`.
     ///
-    /// If the event string is larger than the maximum length (12),
-    /// it returns an error.  If it's less than the length, then it is
-    /// zero padded.
+    /// Nothing else may be written to `out` until this returns.
+    pub fn write_chunked<W: std::io::Write, R: std::io::Read>(
+        &self,
+        out: &mut W,
+        packet_id: u64,
+        cmd_packet_id: u64,
+        event_id: EventId,
+        source: &mut R,
+        chunk_size: usize,
+    ) -> Result<u64, std::io::Error> {
+        if chunk_size == 0 || u32::try_from(chunk_size).is_err() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "chunk size must be from 1 byte to 4 GiB",
+            ));
+        }
+        let mut total = 0u64;
+        loop {
+            let mut chunk = Vec::with_capacity(std::cmp::min(chunk_size, _BUFFER_SIZE));
+            source
+                .by_ref()
+                .take(chunk_size as u64)
+                .read_to_end(&mut chunk)?;
+            let last = chunk.is_empty();
+            total += chunk.len() as u64;
+            self.write_event(out, packet_id, cmd_packet_id, event_id, chunk)?;
+            if last {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Write the event.
     ///
     /// The packet IDs are turned into big-endian formatted bytes.
-    pub fn write_event_str<'a, 'b, W: std::io::Write>(
-        self,
-        out: &'b mut W,
+    pub fn write_event<W: std::io::Write, P: AsRef<[u8]>>(
+        &self,
+        out: &mut W,
         packet_id: u64,
         cmd_packet_id: u64,
-        event: &'a str,
-        payload: Vec<u8>,
+        event_id: EventId,
+        payload: P,
     ) -> Result<(), std::io::Error> {
-        let mut header = EventPacketHeader {
+        let header = EventPacketHeader {
             packet_id: packet_id.to_be_bytes(),
             cmd_packet_id: cmd_packet_id.to_be_bytes(),
-            event_id: [0; 12],
-            size: payload.len(),
+            event_id,
+            size: payload.as_ref().len(),
         };
-        let evt_bytes = event.as_bytes();
-        let evt_size = std::cmp::min(12, evt_bytes.len());
-        for i in 0..evt_size {
-            header.event_id[i] = evt_bytes[i];
+        self.write(out, &EventPacket { header, payload })
+    }
+
+    /// Write the event, with the event ID as a &str.
+    ///
+    /// If the event string is not a valid `EventId`, such as one longer than 12 bytes,
+    /// it returns an `InvalidInput` error and writes nothing.
+    pub fn write_event_str<'a, 'b, W: std::io::Write, P: AsRef<[u8]>>(
+        &self,
+        out: &'b mut W,
+        packet_id: u64,
+        cmd_packet_id: u64,
+        event: &'a str,
+        payload: P,
+    ) -> Result<(), std::io::Error> {
+        let event_id = EventId::new(event)?;
+        self.write_event(out, packet_id, cmd_packet_id, event_id, payload)
+    }
+}
+
+/// A stream over one packet's payload, as returned by `EventReader::read_stream`.
+pub struct PayloadStream<'s, R> {
+    source: &'s mut R,
+    header: EventPacketHeader,
+    remaining: usize,
+    hasher: Option<crc32fast::Hasher>,
+}
+
+impl<R: std::io::Read> PayloadStream<'_, R> {
+    /// The packet's header.  Its size is the payload's size.
+    pub fn header(&self) -> &EventPacketHeader {
+        &self.header
+    }
+
+    /// Skip the rest of the payload, and check the checksum, so the next packet can be read.
+    pub fn finish(mut self) -> Result<(), std::io::Error> {
+        std::io::copy(&mut self, &mut std::io::sink())?;
+        Ok(())
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for PayloadStream<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 {
+            // The checksum trailer follows the payload.
+            if let Some(hasher) = self.hasher.take() {
+                rwutil::check_crc32_trailer(self.source, hasher.finalize().to_be_bytes())?;
+            }
+            return Ok(0);
         }
-        for i in evt_size..12 {
-            header.event_id[i] = 0;
+        let count = std::cmp::min(buf.len(), self.remaining);
+        let n = self.source.read(&mut buf[..count])?;
+        if n == 0 && count > 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
         }
-        self.write(out, &EventPacket { header, payload })
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..n]);
+        }
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+/// A stream over a chunked payload, as returned by `EventReader::read_chunked`.
+pub struct ChunkedStream<'s, R> {
+    reader: &'s mut EventReader,
+    source: &'s mut R,
+    chunk: EventPacket,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: std::io::Read> ChunkedStream<'_, R> {
+    /// The first chunk's header.  Its size is only the first chunk's.
+    pub fn header(&self) -> &EventPacketHeader {
+        &self.chunk.header
+    }
+
+    /// Skip the rest of the chunks, so the next packet can be read.
+    pub fn finish(mut self) -> Result<(), std::io::Error> {
+        std::io::copy(&mut self, &mut std::io::sink())?;
+        Ok(())
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for ChunkedStream<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.payload.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let first = (self.chunk.header.packet_id, self.chunk.header.cmd_packet_id);
+            let event_id = self.chunk.header.event_id;
+            self.reader.read_into(self.source, &mut self.chunk)?;
+            if (self.chunk.header.packet_id, self.chunk.header.cmd_packet_id) != first
+                || self.chunk.header.event_id != event_id
+            {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "another packet came between the payload's chunks",
+                ));
+            }
+            self.pos = 0;
+            self.done = self.chunk.payload.is_empty();
+        }
+        let n = std::cmp::min(buf.len(), self.chunk.payload.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk.payload[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
     }
 }
 
@@ -252,7 +742,8 @@ mod tests {
         let data = EventReader::new(10).read(&mut ZERO_SIZE_EVENT).unwrap();
         assert_eq!(data.header.packet_id, ZERO_SIZE_EVENT[0..8]);
         assert_eq!(data.header.cmd_packet_id, ZERO_SIZE_EVENT[8..16]);
-        assert_eq!(data.header.event_id, ZERO_SIZE_EVENT[16..28]);
+        assert_eq!(data.header.event_id.as_bytes(), &ZERO_SIZE_EVENT[16..28]);
+        assert_eq!(data.header.event_id, "!\"#$%&'()*+,");
         assert_eq!(data.header.size, 0);
         assert_eq!(data.payload.len(), 0);
     }
@@ -280,7 +771,7 @@ mod tests {
                     header: EventPacketHeader {
                         packet_id,
                         cmd_packet_id,
-                        event_id,
+                        event_id: EventId::from_bytes(event_id),
                         size: 0,
                     },
                     payload: vec![],
@@ -290,4 +781,227 @@ mod tests {
         let data = out.get_ref();
         assert_eq!(data.eq(&ZERO_SIZE_EVENT[0.._HEADER_COUNT]), true);
     }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_bytes_payload() {
+        let mut source: &[u8] = &[
+            0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, b'a', b'b', b'c', 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 4, 1, 2, 3, 4,
+        ];
+        let packet = EventReader::new(10).read(&mut source).unwrap().freeze();
+        let memory = packet.payload.as_ptr();
+
+        // Forward part of the payload without copying it.
+        let part = packet.payload.slice(1..3);
+        assert_eq!(part.as_ptr(), memory.wrapping_add(1));
+        let mut out = Vec::new();
+        EventWriter::new()
+            .write_event(&mut out, 2, 1, packet.header.event_id, part)
+            .unwrap();
+        let forwarded = EventReader::new(10).read(&mut out.as_slice()).unwrap();
+        assert_eq!(forwarded.header.event_id, "abc");
+        assert_eq!(forwarded.payload, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_reuse_reader_writer() {
+        let writer = EventWriter::new();
+        let mut out: std::io::Cursor<Vec<u8>> = std::io::Cursor::new(Vec::new());
+        let large = vec![7u8; _BUFFER_SIZE + 3];
+        writer
+            .write_event_str(&mut out, 1, 0, "large", large.clone())
+            .unwrap();
+        writer
+            .write_event_str(&mut out, 2, 1, "small", vec![1, 2, 3])
+            .unwrap();
+
+        let mut source = std::io::Cursor::new(out.into_inner());
+        let mut reader = EventReader::new(large.len());
+        let mut packet = reader.read(&mut source).unwrap();
+        assert_eq!(packet.header.packet_id, 1u64.to_be_bytes());
+        assert_eq!(packet.payload, large);
+        let capacity = packet.payload.capacity();
+
+        reader.read_into(&mut source, &mut packet).unwrap();
+        assert_eq!(packet.header.packet_id, 2u64.to_be_bytes());
+        assert_eq!(packet.header.cmd_packet_id, 1u64.to_be_bytes());
+        assert_eq!(packet.header.event_id, EventId::from_static("small"));
+        assert_eq!(packet.payload, vec![1, 2, 3]);
+        assert_eq!(packet.payload.capacity(), capacity);
+
+        assert_eq!(
+            reader.read(&mut source).err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut out = Vec::new();
+        EventWriter::new()
+            .with_checksum(true)
+            .write_event_str(&mut out, 1, 0, "check", vec![1, 2, 3])
+            .unwrap();
+        assert_eq!(out.len(), _HEADER_COUNT + 3 + rwutil::U32_SIZE);
+
+        let mut reader = EventReader::new(10).with_checksum(true);
+        assert_eq!(
+            reader.read(&mut out.as_slice()).unwrap().payload,
+            vec![1, 2, 3]
+        );
+
+        // Corrupt the event ID.
+        out[_HEADER_EVENT_ID_POS_START] ^= 0x01;
+        let err = reader.read(&mut out.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_compression() {
+        let writer = EventWriter::new()
+            .with_checksum(true)
+            .with_compression(Some(16));
+        let large = b"repeat ".repeat(100);
+        let mut out = Vec::new();
+        writer
+            .write_event_str(&mut out, 1, 0, "large", large.clone())
+            .unwrap();
+        assert!(out.len() < _HEADER_COUNT + large.len() / 2);
+        let compressed_len = out.len();
+        writer
+            .write_event_str(&mut out, 2, 0, "small", vec![1, 2, 3])
+            .unwrap();
+        assert_eq!(
+            out.len() - compressed_len,
+            _HEADER_COUNT + 1 + 3 + rwutil::U32_SIZE
+        );
+
+        let mut reader = EventReader::new(large.len())
+            .with_checksum(true)
+            .with_compression(true);
+        let mut source = out.as_slice();
+        let packet = reader.read(&mut source).unwrap();
+        assert_eq!(packet.payload, large);
+        assert_eq!(packet.header.size, large.len());
+        assert_eq!(reader.read(&mut source).unwrap().payload, vec![1, 2, 3]);
+
+        // The limit applies to the decompressed size.
+        let mut reader = EventReader::new(large.len() - 1).with_compression(true);
+        let mut out = Vec::new();
+        EventWriter::new()
+            .with_compression(Some(16))
+            .write_event_str(&mut out, 1, 0, "large", large)
+            .unwrap();
+        let err = reader.read(&mut out.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_stream() {
+        let writer = EventWriter::new().with_checksum(true);
+        let large: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+        let mut out = Vec::new();
+        writer
+            .write_event_str(&mut out, 1, 0, "large", large.clone())
+            .unwrap();
+        writer
+            .write_event_str(&mut out, 2, 0, "next", vec![1])
+            .unwrap();
+
+        // The stream doesn't need a maximum that fits the payload.
+        let mut reader = EventReader::new(10).with_checksum(true);
+        let mut source = out.as_slice();
+        let mut stream = reader.read_stream(&mut source).unwrap();
+        assert_eq!(stream.header().size, large.len());
+        let mut payload = Vec::new();
+        stream.read_to_end(&mut payload).unwrap();
+        assert_eq!(payload, large);
+        assert_eq!(reader.read(&mut source).unwrap().payload, vec![1]);
+
+        let mut source = out.as_slice();
+        reader.read_stream(&mut source).unwrap().finish().unwrap();
+        assert_eq!(reader.read(&mut source).unwrap().payload, vec![1]);
+
+        out[_HEADER_COUNT + 100] ^= 0x01;
+        let err = reader
+            .read_stream(&mut out.as_slice())
+            .unwrap()
+            .finish()
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_chunked() {
+        let writer = EventWriter::new();
+        let large: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+        let mut out = Vec::new();
+        let blob = EventId::from_static("blob");
+        let sent = writer
+            .write_chunked(&mut out, 1, 0, blob, &mut large.as_slice(), 8000)
+            .unwrap();
+        assert_eq!(sent, large.len() as u64);
+        writer
+            .write_event_str(&mut out, 2, 0, "next", vec![1])
+            .unwrap();
+
+        let mut reader = EventReader::new(8000);
+        let mut source = out.as_slice();
+        let mut stream = reader.read_chunked(&mut source).unwrap();
+        assert_eq!(stream.header().event_id, "blob");
+        let mut payload = Vec::new();
+        stream.read_to_end(&mut payload).unwrap();
+        assert_eq!(payload, large);
+        assert_eq!(reader.read(&mut source).unwrap().payload, vec![1]);
+
+        // A packet from elsewhere between the chunks.
+        let mut out = Vec::new();
+        writer
+            .write_event_str(&mut out, 1, 0, "blob", vec![1])
+            .unwrap();
+        writer
+            .write_event_str(&mut out, 2, 0, "other", vec![2])
+            .unwrap();
+        let mut source = out.as_slice();
+        let err = reader
+            .read_chunked(&mut source)
+            .unwrap()
+            .finish()
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_event_id() {
+        const LONGEST: EventId = EventId::from_static("twelve-bytes");
+        assert_eq!(LONGEST.as_str(), Some("twelve-bytes"));
+        assert_eq!(EventId::new("twelve-bytes").unwrap(), LONGEST);
+        assert_ne!(EventId::new("twelve-byte").unwrap(), LONGEST);
+        assert!(LONGEST != "twelve-byte");
+        for name in ["", "thirteen-byte", "a\0b"] {
+            assert_eq!(
+                EventId::new(name).err().map(|e| e.kind()),
+                Some(std::io::ErrorKind::InvalidInput)
+            );
+        }
+
+        let read = EventId::from_bytes([b'a', 0, b'b', 0, 0, 0, 0, 0, 0, 0, 0, 0xff]);
+        assert!(!read.is_valid());
+        assert_eq!(read.as_str(), None);
+        assert!(read.to_string().starts_with("a\\x00b\\x00"));
+        assert!(read.to_string().ends_with("\\xff"));
+    }
+
+    #[test]
+    fn test_write_event_str_too_long() {
+        let mut out: std::io::Cursor<Vec<u8>> = std::io::Cursor::new(Vec::new());
+        let res = EventWriter::new().write_event_str(&mut out, 1, 0, "thirteen-byte", vec![]);
+        assert_eq!(
+            res.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+        assert!(out.get_ref().is_empty());
+    }
 }
@@ -0,0 +1,117 @@
+//! Agree on the optional packet format features before exchanging packets.
+//!
+//! Each side writes a hello naming the features it supports, then reads the other
+//! side's.  Both end up with the features they have in common, and configure their
+//! packet readers and writers with them.  The hello is 9 bytes: the magic `GZHS`, the
+//! handshake version, and the big-endian feature bits.  Unknown feature bits are ignored,
+//! so a newer side still agrees with an older one.
+//!
+//! Both sides write before reading, so the hellos can't deadlock on a pipe pair.
+
+use super::rwutil;
+
+const MAGIC: &[u8; 4] = b"GZHS";
+const VERSION: u8 = 1;
+const HELLO_LEN: usize = MAGIC.len() + 1 + rwutil::U32_SIZE;
+
+/// A set of optional packet format features.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Features(u32);
+
+impl Features {
+    /// No optional features; the plain packet formats.
+    pub const NONE: Features = Features(0);
+
+    /// A CRC32 of the header and payload follows each packet.
+    pub const CHECKSUM: Features = Features(1 << 0);
+
+    /// Payloads at or over the writer's threshold are LZ4 compressed.
+    #[cfg(feature = "lz4")]
+    pub const LZ4: Features = Features(1 << 1);
+
+    /// Whether all of `other`'s features are in this set.
+    pub const fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The features in both sets.
+    pub const fn intersection(self, other: Features) -> Features {
+        Features(self.0 & other.0)
+    }
+
+    /// The features in either set.
+    pub const fn union(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl std::ops::BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, other: Features) -> Features {
+        self.union(other)
+    }
+}
+
+/// Send our hello, read the other side's, and return the features both support.
+///
+/// Fails with `InvalidData` if the other side didn't send a hello.
+pub fn negotiate<R: std::io::Read, W: std::io::Write>(
+    source: &mut R,
+    out: &mut W,
+    supported: Features,
+) -> Result<Features, std::io::Error> {
+    let mut hello = [0u8; HELLO_LEN];
+    hello[..MAGIC.len()].copy_from_slice(MAGIC);
+    hello[MAGIC.len()] = VERSION;
+    hello[MAGIC.len() + 1..].copy_from_slice(&supported.bits().to_be_bytes());
+    out.write_all(&hello)?;
+    out.flush()?;
+
+    source.read_exact(&mut hello)?;
+    if &hello[..MAGIC.len()] != MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the other side didn't start with a handshake",
+        ));
+    }
+    // Versions only ever add fields after the feature bits, so any version can agree on
+    // them; the current one has none.
+    let theirs = Features(rwutil::get_be_u32(&hello[MAGIC.len() + 1..]));
+    Ok(supported.intersection(theirs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        let (mut a_read, mut b_write) = std::io::pipe().unwrap();
+        let (mut b_read, mut a_write) = std::io::pipe().unwrap();
+        let other = std::thread::spawn(move || {
+            // Offers a feature this version doesn't know.
+            negotiate(
+                &mut b_read,
+                &mut b_write,
+                Features::CHECKSUM | Features(1 << 31),
+            )
+        });
+        let agreed = negotiate(&mut a_read, &mut a_write, Features::CHECKSUM).unwrap();
+        assert_eq!(agreed, Features::CHECKSUM);
+        assert_eq!(other.join().unwrap().unwrap(), Features::CHECKSUM);
+    }
+
+    #[test]
+    fn test_negotiate_not_a_hello() {
+        let mut source: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut out = Vec::new();
+        let err = negotiate(&mut source, &mut out, Features::CHECKSUM).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(&out[..4], MAGIC);
+    }
+}
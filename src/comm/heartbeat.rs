@@ -0,0 +1,181 @@
+//! Detect a wedged child by pinging it over an `RpcEndpoint`.
+//!
+//! Reads from a child block until it writes, so a child stuck in a loop or a deadlock
+//! hangs the handler forever.  `Heartbeat::start` runs a thread that sends a `PING` call
+//! every interval, and calls the `on_unresponsive` closure, usually to terminate the
+//! child through its `ChildKiller`, when no response arrives within the window.
+//! Terminating the child closes its streams, which ends the handler's blocked reads.
+//!
+//! The child answers each ping from its request loop with `answer_ping`, so the
+//! heartbeat shows that loop is still taking requests.  A child busy with one long
+//! request must either answer from another thread or use a wide enough window.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use super::event::{EventId, EventPacket};
+use super::rpc::RpcEndpoint;
+
+/// The event ID of the parent's keepalive call.
+pub const PING: EventId = EventId::from_static("ping");
+
+/// The event ID of the child's answer to a `PING`.
+pub const PONG: EventId = EventId::from_static("pong");
+
+/// How often to ping the child, and how long it has to answer.
+#[derive(Clone, Copy, Debug)]
+pub struct Heartbeat {
+    interval: Duration,
+    window: Duration,
+}
+
+impl Heartbeat {
+    /// Ping every `interval`, and declare the child unresponsive if it doesn't answer
+    /// within `window`.
+    pub fn new(interval: Duration, window: Duration) -> Self {
+        Heartbeat { interval, window }
+    }
+
+    /// Start pinging the child on a new thread.
+    ///
+    /// The thread stops when the monitor is dropped, when the endpoint closes, or after
+    /// calling `on_unresponsive` once.
+    pub fn start<W, F>(
+        self,
+        endpoint: Arc<RpcEndpoint<W>>,
+        on_unresponsive: F,
+    ) -> Result<HeartbeatMonitor, std::io::Error>
+    where
+        W: std::io::Write + Send + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let unresponsive = Arc::new(AtomicBool::new(false));
+        let flag = unresponsive.clone();
+        std::thread::Builder::new()
+            .name("heartbeat".to_string())
+            .spawn(move || {
+                // Both a stop message and a dropped monitor end the wait early.
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(self.interval)
+                {
+                    match endpoint.call(PING, vec![], self.window) {
+                        Ok(_) => (),
+                        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                            flag.store(true, Ordering::SeqCst);
+                            on_unresponsive();
+                            return;
+                        }
+                        // The child closed its side; there's nothing left to watch.
+                        Err(_) => return,
+                    }
+                }
+            })?;
+        Ok(HeartbeatMonitor { stop, unresponsive })
+    }
+}
+
+/// The running heartbeat, as returned by `Heartbeat::start`.  Dropping it stops the pings.
+pub struct HeartbeatMonitor {
+    stop: mpsc::Sender<()>,
+    unresponsive: Arc<AtomicBool>,
+}
+
+impl HeartbeatMonitor {
+    /// Whether a ping went unanswered, and `on_unresponsive` was called.
+    pub fn is_unresponsive(&self) -> bool {
+        self.unresponsive.load(Ordering::SeqCst)
+    }
+
+    /// Stop sending pings.  A ping already sent still waits out its window.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+    }
+}
+
+/// Answer the packet with a `PONG` if it's a `PING`.  Returns whether it was one, so the
+/// request loop can skip it.
+pub fn answer_ping<W: std::io::Write>(
+    endpoint: &RpcEndpoint<W>,
+    packet: &EventPacket,
+) -> Result<bool, std::io::Error> {
+    if packet.header.event_id != PING {
+        return Ok(false);
+    }
+    endpoint.respond(&packet.header, PONG, vec![])?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORK: EventId = EventId::from_static("work");
+
+    fn connected() -> (
+        RpcEndpoint<std::io::PipeWriter>,
+        RpcEndpoint<std::io::PipeWriter>,
+    ) {
+        let (a_read, b_write) = std::io::pipe().unwrap();
+        let (b_read, a_write) = std::io::pipe().unwrap();
+        (
+            RpcEndpoint::new(a_read, a_write, 1024).unwrap(),
+            RpcEndpoint::new(b_read, b_write, 1024).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_wedged_child() {
+        let (parent, child) = connected();
+        let (fired, on_fired) = mpsc::channel();
+        let monitor = Heartbeat::new(Duration::from_millis(10), Duration::from_millis(100))
+            .start(Arc::new(parent), move || fired.send(()).unwrap())
+            .unwrap();
+
+        let child = std::thread::spawn(move || {
+            let mut pongs = 0;
+            loop {
+                let packet = child.recv_request().unwrap();
+                if !answer_ping(&child, &packet).unwrap() {
+                    continue;
+                }
+                pongs += 1;
+                if pongs == 3 {
+                    // Wedge: stop taking requests.
+                    return child;
+                }
+            }
+        });
+
+        on_fired.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(monitor.is_unresponsive());
+        child.join().unwrap();
+    }
+
+    #[test]
+    fn test_responsive_child() {
+        let (parent, child) = connected();
+        let parent = Arc::new(parent);
+        let monitor = Heartbeat::new(Duration::from_millis(5), Duration::from_secs(10))
+            .start(parent.clone(), || panic!("the child answered"))
+            .unwrap();
+
+        let child = std::thread::spawn(move || {
+            let mut pongs = 0;
+            while pongs < 5 {
+                let packet = child.recv_request().unwrap();
+                if answer_ping(&child, &packet).unwrap() {
+                    pongs += 1;
+                } else {
+                    assert_eq!(packet.header.event_id, WORK);
+                }
+            }
+            child
+        });
+        parent.notify(WORK, vec![]).unwrap();
+        let child = child.join().unwrap();
+        assert!(!monitor.is_unresponsive());
+        monitor.stop();
+        drop(child);
+    }
+}
@@ -0,0 +1,123 @@
+//! Exchange serde types as newline-delimited JSON, one value per line.
+//!
+//! For children written in languages such as Python, Node, or Go, where the binary packet
+//! formats take work, JSON lines take only the standard library: write each message with
+//! `json.dumps(message) + "\n"` and flush, and read them with `for line in sys.stdin`.
+//!
+//! `send` writes a value as one line of compact JSON, which never holds a raw newline.
+//! `recv` reads the next line and decodes it.  A line ending in `\r\n` is accepted, and
+//! blank lines are skipped.  A line over the maximum length fails with `InvalidData`
+//! once one byte past the maximum is read, so a child can't make the parent buffer
+//! without bound; the rest of the line, at least its line ending, is left on the stream,
+//! so stop reading from it afterwards.
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use super::splitter;
+
+/// Encode the value as one line of JSON, and write and flush it.
+pub fn send<T: Serialize, W: std::io::Write>(out: &mut W, value: &T) -> Result<(), std::io::Error> {
+    let mut line = serde_json::to_vec(value)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    line.push(b'\n');
+    out.write_all(&line)?;
+    out.flush()
+}
+
+/// Read the next non-blank line, of up to `max_line` bytes without its line ending, and
+/// decode it.
+///
+/// Fails with `UnexpectedEof` once the stream ends, and with `InvalidData` if the line is
+/// too long or doesn't decode as the requested type.  Wrap an unbuffered stream in a
+/// `std::io::BufReader`, and keep using that one reader for the whole stream.
+pub fn recv<T: DeserializeOwned, R: std::io::BufRead>(
+    source: &mut R,
+    max_line: usize,
+) -> Result<T, std::io::Error> {
+    loop {
+        // Read at most one byte past the maximum.  `read_next` stops at its limit without
+        // consuming the newline, so a line of the maximum plus `\r\n` is checked here.
+        let (mut line, mut found) = splitter::read_next(source, b'\n', max_line.saturating_add(1))?;
+        if !found && line.last() == Some(&b'\r') && next_is_newline(source)? {
+            source.consume(1);
+            found = true;
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if !found || line.len() > max_line {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line over the maximum of {} bytes", max_line),
+            ));
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        return serde_json::from_slice(&line)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+    }
+}
+
+/// Is the next byte on the stream a newline?  Leaves it on the stream.
+fn next_is_newline<R: std::io::BufRead>(source: &mut R) -> Result<bool, std::io::Error> {
+    loop {
+        match source.fill_buf() {
+            Ok(available) => return Ok(available.first() == Some(&b'\n')),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Request {
+        name: String,
+        args: Vec<u32>,
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let request = Request {
+            name: "two\nlines".to_string(),
+            args: vec![1, 2],
+        };
+        let mut out = Vec::new();
+        send(&mut out, &request).unwrap();
+        send(&mut out, &request).unwrap();
+        assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 2);
+
+        let mut source = &out[..];
+        assert_eq!(recv::<Request, _>(&mut source, 100).unwrap(), request);
+        assert_eq!(recv::<Request, _>(&mut source, 100).unwrap(), request);
+        let err = recv::<Request, _>(&mut source, 100).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_other_languages() {
+        // As written by Python on Windows, with a blank line between.
+        let mut source: &[u8] = b"{\"name\": \"a\", \"args\": []}\r\n\r\n[1, 2]\r\n";
+        let request: Request = recv(&mut source, 100).unwrap();
+        assert_eq!(request.name, "a");
+        assert_eq!(recv::<Vec<u32>, _>(&mut source, 6).unwrap(), vec![1, 2]);
+
+        let mut source: &[u8] = b"[1, 2, 3]\n";
+        let err = recv::<Vec<u32>, _>(&mut source, 8).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // One byte over fails without reading the line ending.
+        let mut source: &[u8] = b"[1, 2]]\n";
+        let err = recv::<Vec<u32>, _>(&mut source, 6).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(source, b"\n");
+
+        let mut source: &[u8] = b"{\"name\": 1}\n";
+        let err = recv::<Request, _>(&mut source, 100).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
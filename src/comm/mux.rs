@@ -0,0 +1,546 @@
+//! Carry many logical streams over one pipe pair.
+//!
+//! A `Mux` owns the stream it reads from and the stream it writes to, and hands out
+//! `MuxChannel`s by channel ID.  Both sides open the same ID to talk over it: what one
+//! side writes to its channel 3, the other reads from its channel 3.  So a child needs
+//! only its STDIN and STDOUT, rather than one file descriptor per stream.
+//!
+//! Each write goes out as frames, with a 9 byte header: the big-endian channel ID, the
+//! frame kind, and the big-endian payload size.  A background thread reads the frames,
+//! and queues each payload for its channel until the channel is read.  Data for a channel
+//! the side hasn't opened yet waits in the queue.  Closing a channel sends a close frame,
+//! after which the other side reads the end of the stream.  Dropping it sends a reset
+//! frame, after which the other side's writes fail too.
+//!
+//! Each channel's queue holds at most a window of bytes, so a slow reader on one channel
+//! can't grow memory without bound, or hold up the reading thread and with it the other
+//! channels.  A writer starts with a window's worth of credit per channel, spends it on
+//! each data frame, and blocks when it runs out.  The reader hands the credit back in
+//! credit frames as its side reads the queue.  Both sides must use the same window; a
+//! frame that overflows a queue, or frames for too many channels this side hasn't opened,
+//! fail the whole stream with `InvalidData`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use super::rwutil;
+
+/// The largest payload in a frame; longer writes are split.
+pub const MAX_FRAME_PAYLOAD: usize = 64 * 1024;
+
+/// The default number of bytes each channel may have in flight or queued.
+pub const DEFAULT_WINDOW: usize = 256 * 1024;
+
+/// How many channels this side hasn't opened the other may send frames for.
+pub const MAX_PENDING_CHANNELS: usize = 64;
+
+const FRAME_DATA: u8 = 0;
+const FRAME_CLOSE: u8 = 1;
+const FRAME_CREDIT: u8 = 2;
+const FRAME_RESET: u8 = 3;
+
+const _FRAME_CHANNEL_POS_START: usize = 0;
+const _FRAME_CHANNEL_POS_END: usize = _FRAME_CHANNEL_POS_START + rwutil::U32_SIZE;
+const _FRAME_KIND_POS: usize = _FRAME_CHANNEL_POS_END;
+const _FRAME_SIZE_POS_START: usize = _FRAME_KIND_POS + 1;
+const _FRAME_SIZE_POS_END: usize = _FRAME_SIZE_POS_START + rwutil::U32_SIZE;
+const FRAME_HEADER_LEN: usize = _FRAME_SIZE_POS_END;
+
+/// Hands out the logical channels over one pair of streams.
+pub struct Mux<W: std::io::Write> {
+    out: Arc<Mutex<W>>,
+    shared: Arc<Shared>,
+}
+
+/// The channels' queues and credit, shared with the reading thread.
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    channels: HashMap<u32, ChannelState>,
+    window: usize,
+    closed: Option<(std::io::ErrorKind, String)>,
+}
+
+struct ChannelState {
+    data: VecDeque<u8>,
+    opened: bool,
+    // Dropped by this side; the other side's data is thrown away.
+    dropped: bool,
+    peer_closed: bool,
+    // The other side dropped the channel, and reads nothing more.
+    peer_reset: bool,
+    // Bytes read from the queue but not yet handed back as credit.
+    unacked: usize,
+    send_credit: usize,
+}
+
+impl ChannelState {
+    fn new(window: usize) -> Self {
+        ChannelState {
+            data: VecDeque::new(),
+            opened: false,
+            dropped: false,
+            peer_closed: false,
+            peer_reset: false,
+            unacked: 0,
+            send_credit: window,
+        }
+    }
+}
+
+impl State {
+    fn closed_error(&self) -> Option<std::io::Error> {
+        self.closed
+            .as_ref()
+            .map(|(kind, msg)| std::io::Error::new(*kind, format!("mux stream closed: {}", msg)))
+    }
+
+    fn channel(&mut self, id: u32) -> &mut ChannelState {
+        let window = self.window;
+        self.channels
+            .entry(id)
+            .or_insert_with(|| ChannelState::new(window))
+    }
+
+    fn pending_channels(&self) -> usize {
+        self.channels
+            .values()
+            .filter(|c| !c.opened && !c.dropped)
+            .count()
+    }
+}
+
+impl<W: std::io::Write> Mux<W> {
+    /// Start reading frames from `source` on a new thread, and write frames to `out`,
+    /// with the default window.
+    ///
+    /// The reading thread runs until `source` closes, even after the mux is dropped.
+    pub fn new<R: std::io::Read + Send + 'static>(
+        source: R,
+        out: W,
+    ) -> Result<Self, std::io::Error> {
+        Self::with_window(source, out, DEFAULT_WINDOW)
+    }
+
+    /// Like `new`, but with `window` bytes per channel, which the other side must match.
+    ///
+    /// Fails with `InvalidInput` if the window is zero or doesn't fit in 32 bits.
+    pub fn with_window<R: std::io::Read + Send + 'static>(
+        source: R,
+        out: W,
+        window: usize,
+    ) -> Result<Self, std::io::Error> {
+        if window == 0 || u32::try_from(window).is_err() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the mux window must be between 1 byte and 4 GiB",
+            ));
+        }
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                channels: HashMap::new(),
+                window,
+                closed: None,
+            }),
+            changed: Condvar::new(),
+        });
+        let reader_shared = shared.clone();
+        std::thread::Builder::new()
+            .name("mux-reader".to_string())
+            .spawn(move || read_frames(source, &reader_shared))?;
+        Ok(Mux {
+            out: Arc::new(Mutex::new(out)),
+            shared,
+        })
+    }
+
+    /// Open the channel with the given ID.
+    ///
+    /// Fails with `AlreadyExists` if this side already has it open.
+    pub fn channel(&self, id: u32) -> Result<MuxChannel<W>, std::io::Error> {
+        let mut state = lock(&self.shared.state)?;
+        let window = state.window;
+        let channel = state.channel(id);
+        if channel.opened {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("mux channel {} is already open", id),
+            ));
+        }
+        if channel.dropped {
+            *channel = ChannelState::new(window);
+        }
+        channel.opened = true;
+        Ok(MuxChannel {
+            id,
+            out: self.out.clone(),
+            shared: self.shared.clone(),
+            closed: Mutex::new(false),
+        })
+    }
+}
+
+/// One logical stream, as returned by `Mux::channel`.
+///
+/// Like a `TcpStream`, a shared reference reads and writes too, so one thread can read
+/// while another writes.
+pub struct MuxChannel<W: std::io::Write> {
+    id: u32,
+    out: Arc<Mutex<W>>,
+    shared: Arc<Shared>,
+    closed: Mutex<bool>,
+}
+
+impl<W: std::io::Write> MuxChannel<W> {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Tell the other side nothing more will be written.  Reading still works.
+    pub fn close(&self) -> Result<(), std::io::Error> {
+        let mut closed = lock(&self.closed)?;
+        if *closed {
+            return Ok(());
+        }
+        *closed = true;
+        write_frame(&self.out, self.id, FRAME_CLOSE, &[])
+    }
+
+    fn wait<'a>(
+        &self,
+        state: MutexGuard<'a, State>,
+    ) -> Result<MutexGuard<'a, State>, std::io::Error> {
+        self.shared
+            .changed
+            .wait(state)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "lock poisoned"))
+    }
+}
+
+impl<W: std::io::Write> std::io::Read for &MuxChannel<W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = lock(&self.shared.state)?;
+        loop {
+            // Hand the credit back in half window steps, rather than a frame per read.
+            let threshold = state.window.div_ceil(2);
+            let channel = state.channel(self.id);
+            if !channel.data.is_empty() || buf.is_empty() {
+                let n = channel.data.read(buf)?;
+                channel.unacked += n;
+                if channel.unacked < threshold {
+                    return Ok(n);
+                }
+                let credit = std::mem::take(&mut channel.unacked) as u32;
+                drop(state);
+                // A failed write means the other side is gone, and can't use the credit.
+                let _ = write_frame(&self.out, self.id, FRAME_CREDIT, &credit.to_be_bytes());
+                return Ok(n);
+            }
+            if channel.peer_closed {
+                return Ok(0);
+            }
+            if let Some(e) = state.closed_error() {
+                return Err(e);
+            }
+            state = self.wait(state)?;
+        }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for &MuxChannel<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if *lock(&self.closed)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                format!("mux channel {} is closed", self.id),
+            ));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = lock(&self.shared.state)?;
+        let n = loop {
+            if let Some(e) = state.closed_error() {
+                return Err(e);
+            }
+            let channel = state.channel(self.id);
+            if channel.peer_reset {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    format!("mux channel {} was dropped by the other side", self.id),
+                ));
+            }
+            if channel.send_credit > 0 {
+                let n = buf.len().min(channel.send_credit).min(MAX_FRAME_PAYLOAD);
+                channel.send_credit -= n;
+                break n;
+            }
+            state = self.wait(state)?;
+        };
+        drop(state);
+        write_frame(&self.out, self.id, FRAME_DATA, &buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // Every frame is flushed as it's written.
+        Ok(())
+    }
+}
+
+impl<W: std::io::Write> std::io::Read for MuxChannel<W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for MuxChannel<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl<W: std::io::Write> Drop for MuxChannel<W> {
+    fn drop(&mut self) {
+        // The other side may already be gone.
+        let _ = write_frame(&self.out, self.id, FRAME_RESET, &[]);
+        if let Ok(mut state) = self.shared.state.lock() {
+            let channel = state.channel(self.id);
+            channel.opened = false;
+            channel.dropped = true;
+            channel.data = VecDeque::new();
+        }
+    }
+}
+
+fn write_frame<W: std::io::Write>(
+    out: &Mutex<W>,
+    channel: u32,
+    kind: u8,
+    payload: &[u8],
+) -> Result<(), std::io::Error> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    header[_FRAME_CHANNEL_POS_START.._FRAME_CHANNEL_POS_END]
+        .copy_from_slice(&channel.to_be_bytes());
+    header[_FRAME_KIND_POS] = kind;
+    header[_FRAME_SIZE_POS_START.._FRAME_SIZE_POS_END]
+        .copy_from_slice(&(payload.len() as u32).to_be_bytes());
+    let mut out = lock(out)?;
+    out.write_all(&header)?;
+    out.write_all(payload)?;
+    out.flush()
+}
+
+/// The reading thread: apply each frame to its channel until the stream closes or the
+/// other side breaks the protocol, then wake the readers and writers.
+fn read_frames<R: std::io::Read>(mut source: R, shared: &Shared) {
+    let mut payload = vec![0u8; MAX_FRAME_PAYLOAD];
+    let err = loop {
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        if let Err(e) = source.read_exact(&mut header) {
+            break e;
+        }
+        let id = rwutil::get_be_u32(&header[_FRAME_CHANNEL_POS_START.._FRAME_CHANNEL_POS_END]);
+        let size = rwutil::get_be_u32(&header[_FRAME_SIZE_POS_START.._FRAME_SIZE_POS_END]) as usize;
+        if size > MAX_FRAME_PAYLOAD {
+            break std::io::Error::new(std::io::ErrorKind::InvalidData, "mux frame too large");
+        }
+        if let Err(e) = source.read_exact(&mut payload[..size]) {
+            break e;
+        }
+
+        let Ok(mut state) = shared.state.lock() else {
+            return;
+        };
+        if let Err(e) = apply_frame(&mut state, id, header[_FRAME_KIND_POS], &payload[..size]) {
+            break e;
+        }
+        shared.changed.notify_all();
+    };
+
+    if let Ok(mut state) = shared.state.lock() {
+        state.closed = Some((err.kind(), err.to_string()));
+    }
+    shared.changed.notify_all();
+}
+
+fn apply_frame(state: &mut State, id: u32, kind: u8, payload: &[u8]) -> Result<(), std::io::Error> {
+    if !state.channels.contains_key(&id) && state.pending_channels() >= MAX_PENDING_CHANNELS {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "mux frames for too many unopened channels",
+        ));
+    }
+    let window = state.window;
+    let channel = state.channel(id);
+    match kind {
+        FRAME_DATA if channel.dropped => (),
+        FRAME_DATA => {
+            if channel.data.len() + channel.unacked + payload.len() > window {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("mux channel {} overflowed its window", id),
+                ));
+            }
+            channel.data.extend(payload);
+        }
+        FRAME_CLOSE => channel.peer_closed = true,
+        FRAME_CREDIT => {
+            let Ok(credit) = <[u8; rwutil::U32_SIZE]>::try_from(payload) else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "malformed mux credit frame",
+                ));
+            };
+            channel.send_credit = channel
+                .send_credit
+                .saturating_add(u32::from_be_bytes(credit) as usize);
+        }
+        FRAME_RESET => {
+            channel.peer_closed = true;
+            channel.peer_reset = true;
+        }
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unknown mux frame",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn lock<T>(m: &Mutex<T>) -> Result<MutexGuard<'_, T>, std::io::Error> {
+    m.lock()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "lock poisoned"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Connect two muxes to each other.
+    fn connected(window: usize) -> (Mux<std::io::PipeWriter>, Mux<std::io::PipeWriter>) {
+        let (a_read, b_write) = std::io::pipe().unwrap();
+        let (b_read, a_write) = std::io::pipe().unwrap();
+        (
+            Mux::with_window(a_read, a_write, window).unwrap(),
+            Mux::with_window(b_read, b_write, window).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_channels() {
+        let (parent, child) = connected(DEFAULT_WINDOW);
+        let mut parent_1 = parent.channel(1).unwrap();
+        let mut parent_2 = parent.channel(2).unwrap();
+        assert_eq!(
+            parent.channel(1).err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::AlreadyExists)
+        );
+
+        // Written before the child opens the channels.
+        let large = vec![7u8; MAX_FRAME_PAYLOAD * 2 + 5];
+        parent_2.write_all(&large).unwrap();
+        parent_2.close().unwrap();
+        parent_1.write_all(b"one").unwrap();
+
+        let mut child_1 = child.channel(1).unwrap();
+        let mut child_2 = child.channel(2).unwrap();
+        let mut received = Vec::new();
+        child_2.read_to_end(&mut received).unwrap();
+        assert_eq!(received, large);
+        let mut received = [0u8; 3];
+        child_1.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"one");
+
+        child_1.write_all(b"back").unwrap();
+        drop(child_1);
+        let mut received = Vec::new();
+        parent_1.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"back");
+    }
+
+    #[test]
+    fn test_stream_closed() {
+        let (parent, child) = connected(DEFAULT_WINDOW);
+        let channel = parent.channel(1).unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut buf = [0u8; 1];
+            (&channel).read(&mut buf).map_err(|e| e.kind())
+        });
+        drop(child);
+        assert_eq!(
+            reader.join().unwrap(),
+            Err(std::io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_slow_channel() {
+        let (parent, child) = connected(1024);
+        let slow = parent.channel(1).unwrap();
+        let fast = parent.channel(2).unwrap();
+        let large = vec![3u8; 10 * 1024];
+        let expected = large.clone();
+        let writer = std::thread::spawn(move || {
+            (&slow).write_all(&large).unwrap();
+            slow
+        });
+
+        // The slow channel's writer runs out of credit, but the other channel still flows.
+        let child_slow = child.channel(1).unwrap();
+        let child_fast = child.channel(2).unwrap();
+        for _ in 0..100 {
+            (&fast).write_all(b"ping").unwrap();
+            let mut received = [0u8; 4];
+            (&child_fast).read_exact(&mut received).unwrap();
+            assert_eq!(&received, b"ping");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!writer.is_finished());
+
+        let mut received = vec![0u8; expected.len()];
+        (&child_slow).read_exact(&mut received).unwrap();
+        assert_eq!(received, expected);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_reset() {
+        let (parent, child) = connected(16);
+        let channel = parent.channel(1).unwrap();
+        let writer = std::thread::spawn(move || {
+            // Blocks once the window fills.
+            (&channel).write_all(&[0u8; 64]).map_err(|e| e.kind())
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        drop(child.channel(1).unwrap());
+        assert_eq!(writer.join().unwrap(), Err(std::io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_window_overflow() {
+        let (source, mut raw) = std::io::pipe().unwrap();
+        let (_, out) = std::io::pipe().unwrap();
+        let mux = Mux::with_window(source, out, 16).unwrap();
+        let channel = mux.channel(1).unwrap();
+        let mut frame = vec![0, 0, 0, 1, FRAME_DATA, 0, 0, 0, 17];
+        frame.extend([0u8; 17]);
+        raw.write_all(&frame).unwrap();
+
+        let mut buf = [0u8; 1];
+        let err = (&channel).read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -2,16 +2,44 @@
 
 /// Generic u8 data packet (sometimes called an "envelope").
 ///
-/// Packets consist of a header plus a payload.
-pub struct U8Packet<H> {
+/// Packets consist of a header plus a payload.  Readers return the payload as a
+/// `Vec<u8>`; writers take any payload that can be viewed as bytes, such as a
+/// `bytes::Bytes` slice of a larger buffer.
+pub struct U8Packet<H, P = Vec<u8>> {
     pub header: H,
-    pub payload: Vec<u8>,
+    pub payload: P,
+}
+
+#[cfg(feature = "bytes")]
+impl<H> U8Packet<H> {
+    /// Turn the payload into `Bytes`, without copying it, so it can be sliced and passed
+    /// on cheaply.
+    pub fn freeze(self) -> U8Packet<H, bytes::Bytes> {
+        U8Packet {
+            header: self.header,
+            payload: self.payload.into(),
+        }
+    }
 }
 
 /// Reads U8Packet objects from a byte stream.
 pub trait U8PacketRead<H> {
     /// Read the next event packet from the stream.
     fn read<'a, R: std::io::Read>(&self, source: &'a mut R) -> Result<U8Packet<H>, std::io::Error>;
+
+    /// Read the next event packet from the stream into the packet, reusing its payload's
+    /// memory, to avoid an allocation per packet.
+    ///
+    /// On an error, the packet's contents are unspecified.  Without an implementation
+    /// of its own, this reads a new packet over the old one.
+    fn read_into<R: std::io::Read>(
+        &self,
+        source: &mut R,
+        packet: &mut U8Packet<H>,
+    ) -> Result<(), std::io::Error> {
+        *packet = self.read(source)?;
+        Ok(())
+    }
 }
 
 /// Writes U8Packet objects to a byte stream.
@@ -24,9 +52,82 @@ pub trait U8PacketWrite<H> {
     /// it skips validation.
     ///
     /// At the end of a successful packet write, this write call must flush the stream.
-    fn write<'a, 'b, W: std::io::Write>(
+    fn write<'a, 'b, W: std::io::Write, P: AsRef<[u8]>>(
         &self,
         out: &'a mut W,
-        packet: &'b U8Packet<H>,
+        packet: &'b U8Packet<H, P>,
     ) -> Result<(), std::io::Error>;
 }
+
+/// A reader that turns a byte stream into packets one at a time, as used by `PacketIter`.
+pub trait ReadPacket {
+    type Packet;
+
+    /// Read the next packet from the stream.
+    fn read_packet<R: std::io::Read>(
+        &mut self,
+        source: &mut R,
+    ) -> Result<Self::Packet, std::io::Error>;
+}
+
+/// Yields the packets read from a stream, until it ends or fails.
+///
+/// A stream that ends between packets ends the iteration.  Any other error, including
+/// one that ends the stream partway into a packet, is yielded once, and then the
+/// iteration ends.
+pub struct PacketIter<'a, D, R> {
+    reader: D,
+    source: &'a mut R,
+    done: bool,
+}
+
+impl<'a, D: ReadPacket, R: std::io::Read> PacketIter<'a, D, R> {
+    pub fn new(reader: D, source: &'a mut R) -> Self {
+        PacketIter {
+            reader,
+            source,
+            done: false,
+        }
+    }
+}
+
+impl<D: ReadPacket, R: std::io::Read> Iterator for PacketIter<'_, D, R> {
+    type Item = Result<D::Packet, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut source = Counted {
+            source: &mut *self.source,
+            count: 0,
+        };
+        match self.reader.read_packet(&mut source) {
+            Ok(packet) => Some(Ok(packet)),
+            Err(e) => {
+                self.done = true;
+                if e.kind() == std::io::ErrorKind::UnexpectedEof && source.count == 0 {
+                    None
+                } else {
+                    Some(Err(e))
+                }
+            }
+        }
+    }
+}
+
+impl<D: ReadPacket, R: std::io::Read> std::iter::FusedIterator for PacketIter<'_, D, R> {}
+
+/// Counts the bytes read, to tell the end of the stream from the end of a packet.
+struct Counted<'a, R> {
+    source: &'a mut R,
+    count: usize,
+}
+
+impl<R: std::io::Read> std::io::Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.source.read(buf)?;
+        self.count += count;
+        Ok(count)
+    }
+}
@@ -0,0 +1,485 @@
+//! Request and response calls over one pair of event streams.
+//!
+//! An `RpcEndpoint` owns the stream it reads event packets from and the stream it writes
+//! them to.  Each packet it writes gets the next packet ID, counting up from 1.  A
+//! response names the request it answers in its `cmd_packet_id`; a packet with a
+//! `cmd_packet_id` of 0 is a request or notification from the other side.
+//!
+//! A background thread reads the packets.  It hands each response to the `call` waiting
+//! for it, so several threads can have calls in flight at once, and queues the other
+//! side's requests for `recv_request`.  A response that arrives after its call timed out
+//! is dropped.  When the read stream closes or fails, every waiting call fails with that
+//! error.  At most `MAX_QUEUED_REQUESTS` requests wait for `recv_request`; one more closes
+//! the endpoint with `InvalidData`, so a peer can't grow the queue without bound.
+//!
+//! A call made with `call_cancellable` can be stopped early through its
+//! `CancellationToken`; see the `cancel` module.
+//!
+//! Both the parent and the child can use an endpoint: the parent over the child's STDOUT
+//! and STDIN, and the child over its own STDIN and STDOUT.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, mpsc};
+use std::time::Duration;
+
+use super::cancel::{self, CancellationToken};
+use super::event::{EventId, EventPacket, EventPacketHeader, EventReader, EventWriter};
+use super::handshake::Features;
+
+/// The payload size from which an endpoint with `handshake::Features::LZ4` compresses.
+#[cfg(feature = "lz4")]
+pub const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+/// How many of the other side's requests and notifications may wait for `recv_request`.
+pub const MAX_QUEUED_REQUESTS: usize = 1024;
+
+/// Calls the other side, and answers its calls, over one pair of event streams.
+pub struct RpcEndpoint<W: std::io::Write> {
+    out: Mutex<W>,
+    writer: EventWriter,
+    next_packet_id: AtomicU64,
+    state: Arc<Mutex<State>>,
+    requests: Mutex<mpsc::Receiver<EventPacket>>,
+}
+
+/// The calls waiting for a response, shared with the reading thread.
+struct State {
+    pending: HashMap<u64, mpsc::Sender<Reply>>,
+    closed: Option<(std::io::ErrorKind, String)>,
+}
+
+/// What a waiting call receives: its response from the reading thread, or word from its
+/// token that it was cancelled.
+enum Reply {
+    Response(Result<EventPacket, std::io::Error>),
+    Cancelled,
+}
+
+impl State {
+    fn closed_error(&self) -> Option<std::io::Error> {
+        self.closed
+            .as_ref()
+            .map(|(kind, msg)| std::io::Error::new(*kind, format!("rpc stream closed: {}", msg)))
+    }
+}
+
+impl<W: std::io::Write> RpcEndpoint<W> {
+    /// Start reading packets from `source` on a new thread, and write packets to `out`.
+    ///
+    /// Packets with a payload over `max_payload_size` close the endpoint.  The reading
+    /// thread runs until `source` closes, even after the endpoint is dropped.
+    pub fn new<R: std::io::Read + Send + 'static>(
+        source: R,
+        out: W,
+        max_payload_size: usize,
+    ) -> Result<Self, std::io::Error> {
+        Self::with_features(source, out, max_payload_size, Features::NONE)
+    }
+
+    /// Like `new`, using the packet format features agreed on with `handshake::negotiate`.
+    pub fn with_features<R: std::io::Read + Send + 'static>(
+        source: R,
+        out: W,
+        max_payload_size: usize,
+        features: Features,
+    ) -> Result<Self, std::io::Error> {
+        let state = Arc::new(Mutex::new(State {
+            pending: HashMap::new(),
+            closed: None,
+        }));
+        let (requests_tx, requests) = mpsc::sync_channel(MAX_QUEUED_REQUESTS);
+        let reader_state = state.clone();
+        std::thread::Builder::new()
+            .name("rpc-reader".to_string())
+            .spawn(move || {
+                let reader = EventReader::new(max_payload_size)
+                    .with_checksum(features.contains(Features::CHECKSUM));
+                #[cfg(feature = "lz4")]
+                let reader = reader.with_compression(features.contains(Features::LZ4));
+                read_packets(source, reader, &reader_state, &requests_tx)
+            })?;
+        let writer = EventWriter::new().with_checksum(features.contains(Features::CHECKSUM));
+        #[cfg(feature = "lz4")]
+        let writer = writer.with_compression(
+            Some(COMPRESSION_THRESHOLD).filter(|_| features.contains(Features::LZ4)),
+        );
+        Ok(RpcEndpoint {
+            out: Mutex::new(out),
+            writer,
+            next_packet_id: AtomicU64::new(1),
+            state,
+            requests: Mutex::new(requests),
+        })
+    }
+
+    /// Send a request, and wait up to `timeout` for its response.
+    ///
+    /// Fails with `TimedOut` if no response arrives in time, or with the read error if the
+    /// stream closes first.
+    pub fn call<P: AsRef<[u8]>>(
+        &self,
+        event_id: EventId,
+        payload: P,
+        timeout: Duration,
+    ) -> Result<EventPacket, std::io::Error> {
+        let (packet_id, _, reply) = self.start_call(event_id, payload)?;
+        match self.wait(packet_id, &reply, event_id, timeout)? {
+            Reply::Response(response) => response,
+            Reply::Cancelled => Err(std::io::ErrorKind::Interrupted.into()),
+        }
+    }
+
+    /// Send a request, and wait up to `timeout` for its response, unless the token is
+    /// cancelled first.
+    ///
+    /// On a cancel, this sends the child a `cancel::CANCEL` for the request, and waits up
+    /// to `grace` for the child to answer it.  The answer is returned as the response.  If
+    /// none comes, this calls `on_unresponsive`, usually to terminate the child, and fails
+    /// with `Interrupted`.  A token cancelled before the call fails it without sending
+    /// anything.
+    pub fn call_cancellable<P: AsRef<[u8]>, F: FnOnce()>(
+        &self,
+        event_id: EventId,
+        payload: P,
+        timeout: Duration,
+        token: &CancellationToken,
+        grace: Duration,
+        on_unresponsive: F,
+    ) -> Result<EventPacket, std::io::Error> {
+        token.check()?;
+        let (packet_id, reply_tx, reply) = self.start_call(event_id, payload)?;
+        let key = token.on_cancel(move || {
+            let _ = reply_tx.send(Reply::Cancelled);
+        });
+        let ret = self.wait_cancellable(packet_id, &reply, event_id, timeout, grace);
+        if let Some(key) = key {
+            token.remove_callback(key);
+        }
+        lock(&self.state)?.pending.remove(&packet_id);
+        ret.unwrap_or_else(|()| {
+            on_unresponsive();
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!(
+                    "cancelled {}, and the child didn't stop within {:?}",
+                    event_id, grace
+                ),
+            ))
+        })
+    }
+
+    /// Send a packet that doesn't expect a response, and return its packet ID.
+    pub fn notify<P: AsRef<[u8]>>(
+        &self,
+        event_id: EventId,
+        payload: P,
+    ) -> Result<u64, std::io::Error> {
+        let packet_id = self.next_packet_id.fetch_add(1, Ordering::Relaxed);
+        self.write(packet_id, 0, event_id, payload)?;
+        Ok(packet_id)
+    }
+
+    /// Answer the request with the given header.
+    pub fn respond<P: AsRef<[u8]>>(
+        &self,
+        request: &EventPacketHeader,
+        event_id: EventId,
+        payload: P,
+    ) -> Result<(), std::io::Error> {
+        let packet_id = self.next_packet_id.fetch_add(1, Ordering::Relaxed);
+        let request_id = u64::from_be_bytes(request.packet_id);
+        self.write(packet_id, request_id, event_id, payload)
+    }
+
+    /// Wait for the next request or notification from the other side.
+    ///
+    /// Once the stream closes and the queued requests are taken, this returns the read
+    /// error.
+    pub fn recv_request(&self) -> Result<EventPacket, std::io::Error> {
+        let received = lock(&self.requests)?.recv();
+        received.map_err(|_| self.closed())
+    }
+
+    /// Wait up to `timeout` for the next request or notification from the other side.
+    pub fn recv_request_timeout(&self, timeout: Duration) -> Result<EventPacket, std::io::Error> {
+        let received = lock(&self.requests)?.recv_timeout(timeout);
+        match received {
+            Ok(packet) => Ok(packet),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(std::io::ErrorKind::TimedOut.into()),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(self.closed()),
+        }
+    }
+
+    /// Whether the read stream has closed.
+    pub fn is_closed(&self) -> bool {
+        !matches!(lock(&self.state), Ok(state) if state.closed.is_none())
+    }
+
+    /// Register the call, and send its request.
+    fn start_call<P: AsRef<[u8]>>(
+        &self,
+        event_id: EventId,
+        payload: P,
+    ) -> Result<(u64, mpsc::Sender<Reply>, mpsc::Receiver<Reply>), std::io::Error> {
+        let packet_id = self.next_packet_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply) = mpsc::channel();
+        {
+            let mut state = lock(&self.state)?;
+            if let Some(e) = state.closed_error() {
+                return Err(e);
+            }
+            state.pending.insert(packet_id, reply_tx.clone());
+        }
+        if let Err(e) = self.write(packet_id, 0, event_id, payload) {
+            lock(&self.state)?.pending.remove(&packet_id);
+            return Err(e);
+        }
+        Ok((packet_id, reply_tx, reply))
+    }
+
+    /// Wait up to `timeout` for the call's reply.  Gives up on the call when it times out.
+    fn wait(
+        &self,
+        packet_id: u64,
+        reply: &mpsc::Receiver<Reply>,
+        event_id: EventId,
+        timeout: Duration,
+    ) -> Result<Reply, std::io::Error> {
+        match reply.recv_timeout(timeout) {
+            Ok(reply) => Ok(reply),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                lock(&self.state)?.pending.remove(&packet_id);
+                // The response may have come in while taking the lock.
+                match reply.try_recv() {
+                    Ok(Reply::Response(response)) => Ok(Reply::Response(response)),
+                    _ => Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("no response to {} within {:?}", event_id, timeout),
+                    )),
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(self.closed()),
+        }
+    }
+
+    /// Wait for the response, asking the child to stop on a cancel.  Returns `Err(())`
+    /// when the child didn't answer the cancel in time.
+    fn wait_cancellable(
+        &self,
+        packet_id: u64,
+        reply: &mpsc::Receiver<Reply>,
+        event_id: EventId,
+        timeout: Duration,
+        grace: Duration,
+    ) -> Result<Result<EventPacket, std::io::Error>, ()> {
+        match self.wait(packet_id, reply, event_id, timeout) {
+            Ok(Reply::Response(response)) => return Ok(response),
+            Ok(Reply::Cancelled) => (),
+            Err(e) => return Ok(Err(e)),
+        }
+        if let Err(e) = cancel::send_cancel(self, Some(packet_id)) {
+            return Ok(Err(e));
+        }
+        match self.wait(packet_id, reply, event_id, grace) {
+            Ok(Reply::Response(response)) => Ok(response),
+            Err(e) if e.kind() != std::io::ErrorKind::TimedOut => Ok(Err(e)),
+            _ => Err(()),
+        }
+    }
+
+    fn closed(&self) -> std::io::Error {
+        lock(&self.state)
+            .ok()
+            .and_then(|state| state.closed_error())
+            .unwrap_or_else(|| std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn write<P: AsRef<[u8]>>(
+        &self,
+        packet_id: u64,
+        cmd_packet_id: u64,
+        event_id: EventId,
+        payload: P,
+    ) -> Result<(), std::io::Error> {
+        let mut out = lock(&self.out)?;
+        self.writer
+            .write_event(&mut *out, packet_id, cmd_packet_id, event_id, payload)
+    }
+}
+
+/// The reading thread: route each packet until the stream closes, then fail the waiting
+/// calls.
+fn read_packets<R: std::io::Read>(
+    mut source: R,
+    mut reader: EventReader,
+    state: &Mutex<State>,
+    requests: &mpsc::SyncSender<EventPacket>,
+) {
+    let err = loop {
+        let packet = match reader.read(&mut source) {
+            Ok(packet) => packet,
+            Err(e) => break e,
+        };
+        let request_id = u64::from_be_bytes(packet.header.cmd_packet_id);
+        if request_id == 0 {
+            match requests.try_send(packet) {
+                Err(mpsc::TrySendError::Full(_)) => {
+                    break std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("more than {} requests queued", MAX_QUEUED_REQUESTS),
+                    );
+                }
+                // Nobody listening for requests isn't a reason to stop routing responses.
+                _ => continue,
+            }
+        }
+        let waiting = match state.lock() {
+            Ok(mut state) => state.pending.remove(&request_id),
+            Err(_) => return,
+        };
+        if let Some(waiting) = waiting {
+            let _ = waiting.send(Reply::Response(Ok(packet)));
+        }
+    };
+
+    if let Ok(mut state) = state.lock() {
+        state.closed = Some((err.kind(), err.to_string()));
+        for (_, waiting) in state.pending.drain() {
+            let _ = waiting.send(Reply::Response(Err(std::io::Error::new(
+                err.kind(),
+                format!("rpc stream closed: {}", err),
+            ))));
+        }
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> Result<MutexGuard<'_, T>, std::io::Error> {
+    m.lock()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "lock poisoned"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ECHO: EventId = EventId::from_static("echo");
+    const DONE: EventId = EventId::from_static("done");
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Connect two endpoints to each other.
+    fn connected() -> (
+        RpcEndpoint<std::io::PipeWriter>,
+        RpcEndpoint<std::io::PipeWriter>,
+    ) {
+        let (a_read, b_write) = std::io::pipe().unwrap();
+        let (b_read, a_write) = std::io::pipe().unwrap();
+        (
+            RpcEndpoint::new(a_read, a_write, 1024).unwrap(),
+            RpcEndpoint::new(b_read, b_write, 1024).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_responses_out_of_order() {
+        let (parent, child) = connected();
+        let child = std::thread::spawn(move || {
+            let first = child.recv_request().unwrap();
+            let second = child.recv_request().unwrap();
+            for req in [second, first] {
+                let mut payload = req.payload.clone();
+                payload.reverse();
+                child.respond(&req.header, DONE, payload).unwrap();
+            }
+            child
+        });
+
+        let parent = Arc::new(parent);
+        let calls: Vec<_> = [vec![1, 2], vec![3, 4]]
+            .into_iter()
+            .map(|payload| {
+                let parent = parent.clone();
+                std::thread::spawn(move || parent.call(ECHO, payload, TIMEOUT).unwrap())
+            })
+            .collect();
+        let mut responses: Vec<_> = calls.into_iter().map(|c| c.join().unwrap()).collect();
+        responses.sort_by_key(|r| r.payload.clone());
+        assert_eq!(responses[0].payload, vec![2, 1]);
+        assert_eq!(responses[1].payload, vec![4, 3]);
+        assert_eq!(responses[0].header.event_id, DONE);
+        child.join().unwrap();
+    }
+
+    #[test]
+    fn test_call_timeout() {
+        let (parent, child) = connected();
+        let err = parent
+            .call(ECHO, vec![1], Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        // The late response is dropped, and doesn't answer the next call.
+        let late = child.recv_request().unwrap();
+        child.respond(&late.header, DONE, vec![1]).unwrap();
+        let child = std::thread::spawn(move || {
+            let req = child.recv_request().unwrap();
+            child.respond(&req.header, DONE, vec![2]).unwrap();
+            child
+        });
+        assert_eq!(
+            parent.call(ECHO, vec![2], TIMEOUT).unwrap().payload,
+            vec![2]
+        );
+        child.join().unwrap();
+    }
+
+    #[test]
+    fn test_close_fails_calls() {
+        let (parent, child) = connected();
+        parent.notify(ECHO, vec![]).unwrap();
+        let child = std::thread::spawn(move || {
+            child.recv_request().unwrap();
+            // Wait for the call before closing.
+            child.recv_request().unwrap();
+        });
+        let err = parent.call(ECHO, vec![], TIMEOUT).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        child.join().unwrap();
+
+        assert!(parent.is_closed());
+        assert_eq!(
+            parent.recv_request().unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            parent.call(ECHO, vec![], TIMEOUT).unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_request_queue_overflow() {
+        let (parent, child) = connected();
+        for _ in 0..=MAX_QUEUED_REQUESTS {
+            child.notify(ECHO, vec![]).unwrap();
+        }
+        let started = std::time::Instant::now();
+        while !parent.is_closed() {
+            assert!(started.elapsed() < TIMEOUT, "endpoint never closed");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        // The queued requests can still be taken, then the overflow shows.
+        for _ in 0..MAX_QUEUED_REQUESTS {
+            parent.recv_request().unwrap();
+        }
+        assert_eq!(
+            parent.recv_request().unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            parent.call(ECHO, vec![], TIMEOUT).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+}
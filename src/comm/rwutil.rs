@@ -1,5 +1,7 @@
 //! Read & Write utility functions.
 
+use std::io::Read as _;
+
 /// Number of octets (bytes) in a u32.
 pub const U32_SIZE: usize = size_of::<u32>();
 
@@ -18,26 +20,24 @@ pub fn set_be_u32(value: u32, buff: &mut [u8]) {
     }
 }
 
-/// Read the `count` number of bytes from the reader in chunks.
-pub fn read_chunked_bytes<'a, 'b, R: std::io::Read, const COUNT: usize>(
-    source: &'a mut R,
+/// Replace the payload's contents with the next `count` bytes from the reader.
+///
+/// The bytes go straight into the payload's memory, which is reused.  It only grows as
+/// the data arrives, so a bogus size can't force a large allocation.
+pub fn read_payload_into<R: std::io::Read>(
+    source: &mut R,
     count: usize,
-    buff: &'b mut [u8; COUNT],
-) -> Result<Vec<u8>, std::io::Error> {
-    let mut payload = Vec::with_capacity(count);
-    let mut count = count;
-    while count > 0 {
-        let read_count = std::cmp::min(COUNT, count);
-        match source.read_exact(&mut buff[0..read_count]) {
-            Ok(_) => (),
-            Err(e) => {
-                return Err(e);
-            }
-        };
-        payload.extend_from_slice(&buff[0..read_count]);
-        count -= read_count;
+    payload: &mut Vec<u8>,
+) -> Result<(), std::io::Error> {
+    payload.clear();
+    source.take(count as u64).read_to_end(payload)?;
+    if payload.len() < count {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "stream ended within the payload",
+        ));
     }
-    Ok(payload)
+    Ok(())
 }
 
 /// Write the data to the stream in chunks.
@@ -54,7 +54,7 @@ pub fn read_chunked_bytes<'a, 'b, R: std::io::Read, const COUNT: usize>(
 /// }
 pub fn write_chunked<'a, 'b, W: std::io::Write, const COUNT: usize>(
     out: &'a mut W,
-    data: &'b Vec<u8>,
+    data: &'b [u8],
 ) -> Result<(), std::io::Error> {
     let chunks: (&[[u8; COUNT]], &[u8]) = data.as_chunks();
     for p in chunks.0 {
@@ -63,3 +63,115 @@ pub fn write_chunked<'a, 'b, W: std::io::Write, const COUNT: usize>(
     out.write_all(chunks.1)?;
     Ok(())
 }
+
+/// Checksum the packet's header and payload, for the CRC32 trailer.
+fn packet_crc32(header: &[u8], payload: &[u8]) -> [u8; U32_SIZE] {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(header);
+    hasher.update(payload);
+    hasher.finalize().to_be_bytes()
+}
+
+/// Write the CRC32 trailer for the packet's header and payload.
+pub fn write_crc32<W: std::io::Write>(
+    out: &mut W,
+    header: &[u8],
+    payload: &[u8],
+) -> Result<(), std::io::Error> {
+    out.write_all(&packet_crc32(header, payload))
+}
+
+/// Read the packet's CRC32 trailer, and fail with `InvalidData` if it doesn't match the
+/// header and payload.
+pub fn check_crc32<R: std::io::Read>(
+    source: &mut R,
+    header: &[u8],
+    payload: &[u8],
+) -> Result<(), std::io::Error> {
+    check_crc32_trailer(source, packet_crc32(header, payload))
+}
+
+/// Read the packet's CRC32 trailer, and fail with `InvalidData` if it isn't `expected`,
+/// such as from a hasher fed the packet as it streamed by.
+pub fn check_crc32_trailer<R: std::io::Read>(
+    source: &mut R,
+    expected: [u8; U32_SIZE],
+) -> Result<(), std::io::Error> {
+    let mut trailer = [0u8; U32_SIZE];
+    source.read_exact(&mut trailer)?;
+    if trailer != expected {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "packet checksum mismatch",
+        ));
+    }
+    Ok(())
+}
+
+/// Marks a payload sent as is, on a stream with compression.
+#[cfg(feature = "lz4")]
+const PAYLOAD_RAW: u8 = 0;
+
+/// Marks a payload sent as its big-endian size, then its LZ4 block.
+#[cfg(feature = "lz4")]
+const PAYLOAD_LZ4: u8 = 1;
+
+/// The marker and size before an LZ4 block.
+#[cfg(feature = "lz4")]
+const LZ4_PREFIX_LEN: usize = 1 + U32_SIZE;
+
+/// How much larger than the payload the encoded payload may be, on a stream with
+/// compression.
+#[cfg(feature = "lz4")]
+pub const COMPRESSION_OVERHEAD: usize = 1;
+
+/// Encode the payload for a stream with compression: LZ4 compressed if it's at least
+/// `threshold` bytes and compressing shrinks it, or as is.
+#[cfg(feature = "lz4")]
+pub fn compress_payload(payload: &[u8], threshold: usize) -> Vec<u8> {
+    if payload.len() >= threshold
+        && let Ok(size) = u32::try_from(payload.len())
+    {
+        let block = lz4_flex::block::compress(payload);
+        if LZ4_PREFIX_LEN + block.len() < COMPRESSION_OVERHEAD + payload.len() {
+            let mut encoded = Vec::with_capacity(LZ4_PREFIX_LEN + block.len());
+            encoded.push(PAYLOAD_LZ4);
+            encoded.extend_from_slice(&size.to_be_bytes());
+            encoded.extend_from_slice(&block);
+            return encoded;
+        }
+    }
+    let mut encoded = Vec::with_capacity(1 + payload.len());
+    encoded.push(PAYLOAD_RAW);
+    encoded.extend_from_slice(payload);
+    encoded
+}
+
+/// Decode, in place, a payload from a stream with compression.  Fails with `InvalidData`
+/// if it's malformed, or would decompress to more than `max_size` bytes.
+#[cfg(feature = "lz4")]
+pub fn decompress_payload(payload: &mut Vec<u8>, max_size: usize) -> Result<(), std::io::Error> {
+    let invalid = |msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    match payload.first() {
+        Some(&PAYLOAD_RAW) => {
+            payload.remove(0);
+            Ok(())
+        }
+        Some(&PAYLOAD_LZ4) if payload.len() >= LZ4_PREFIX_LEN => {
+            let size = get_be_u32(&payload[1..LZ4_PREFIX_LEN]) as usize;
+            // Checked before allocating, so a small packet can't claim a huge payload.
+            if size > max_size {
+                return Err(invalid("decompressed payload size too large"));
+            }
+            let mut decompressed = vec![0; size];
+            match lz4_flex::block::decompress_into(&payload[LZ4_PREFIX_LEN..], &mut decompressed) {
+                Ok(n) if n == size => {
+                    *payload = decompressed;
+                    Ok(())
+                }
+                _ => Err(invalid("corrupt compressed payload")),
+            }
+        }
+        _ => Err(invalid("unknown payload encoding")),
+    }
+}
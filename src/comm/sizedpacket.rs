@@ -1,5 +1,8 @@
 //! Decode the data stream as a "sized packet", where it has an envelope containing only the size of the payload.
 
+use std::time::Duration;
+
+use super::deadline::{self, DeadlineReader};
 use super::packet;
 use super::rwutil;
 
@@ -24,8 +27,12 @@ pub type SizePacket = packet::U8Packet<SizeHeader>;
 ///
 /// While the size has a theoretical maximum of 2^32 octets (4 GB),
 /// implementations should put a practical cap on this.
+#[derive(Clone)]
 pub struct SizePacketRead {
     max_payload_size: usize,
+    checksum: bool,
+    #[cfg(feature = "lz4")]
+    decompress: bool,
 }
 
 impl SizePacketRead {
@@ -35,62 +42,206 @@ impl SizePacketRead {
             // part of the communication protocol, thus a bug.
             panic!("max_payload_size beyond maximum capability of packet");
         }
-        SizePacketRead { max_payload_size }
+        SizePacketRead {
+            max_payload_size,
+            checksum: false,
+            #[cfg(feature = "lz4")]
+            decompress: false,
+        }
+    }
+
+    /// Read the next packet, failing with `TimedOut` if it doesn't arrive within
+    /// `timeout`, or with `ConnectionAborted` if the reader's token is cancelled first.
+    ///
+    /// After either failure, part of the packet may have been read; stop reading packets
+    /// from the stream.
+    pub fn read_timeout<R>(
+        &self,
+        source: &mut DeadlineReader<R>,
+        timeout: Duration,
+    ) -> Result<SizePacket, std::io::Error>
+    where
+        DeadlineReader<R>: std::io::Read,
+    {
+        use packet::U8PacketRead as _;
+        deadline::with_timeout(source, timeout, |source| self.read(source))
+    }
+
+    /// Decode payloads sent by a writer `with_compression`, as negotiated with
+    /// `handshake::Features::LZ4`.  The maximum payload size applies to the decompressed
+    /// payload.
+    #[cfg(feature = "lz4")]
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.decompress = compression;
+        self
+    }
+
+    /// The largest payload allowed on the stream, which compression adds to.
+    fn max_wire_size(&self) -> usize {
+        #[cfg(feature = "lz4")]
+        if self.decompress {
+            return self.max_payload_size + rwutil::COMPRESSION_OVERHEAD;
+        }
+        self.max_payload_size
+    }
+
+    /// The length of the packet at the start of `buffered`, including any checksum
+    /// trailer, or `None` if not even its header is there yet.  Fails like `read` when
+    /// the header's size is over the maximum.
+    #[cfg(feature = "codec")]
+    pub(crate) fn packet_len(&self, buffered: &[u8]) -> Result<Option<usize>, std::io::Error> {
+        let Some(header_buff) = buffered.first_chunk::<HEADER_LEN>() else {
+            return Ok(None);
+        };
+        let size = rwutil::get_be_u32(header_buff) as usize;
+        if size > self.max_wire_size() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "payload size exceeded packet maximum",
+            ));
+        }
+        let trailer = if self.checksum { rwutil::U32_SIZE } else { 0 };
+        Ok(Some(HEADER_LEN + size + trailer))
+    }
+
+    /// Expect a CRC32 trailer after each packet, as negotiated with
+    /// `handshake::Features::CHECKSUM`, and fail with `InvalidData` when it doesn't match.
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Iterate over the packets read from the stream, until it ends or fails:
+    /// `for packet in reader.iter(&mut source)`.
+    pub fn iter<'a, R: std::io::Read>(
+        &'a self,
+        source: &'a mut R,
+    ) -> packet::PacketIter<'a, &'a SizePacketRead, R> {
+        packet::PacketIter::new(self, source)
     }
 }
 
-const PACKET_BUFFER_SIZE: usize = 8 * 1024;
+impl packet::ReadPacket for &SizePacketRead {
+    type Packet = SizePacket;
+
+    fn read_packet<R: std::io::Read>(
+        &mut self,
+        source: &mut R,
+    ) -> Result<SizePacket, std::io::Error> {
+        use packet::U8PacketRead as _;
+        self.read(source)
+    }
+}
 
 impl packet::U8PacketRead<SizeHeader> for SizePacketRead {
     fn read<R: std::io::Read>(
         &self,
         source: &mut R,
     ) -> Result<packet::U8Packet<SizeHeader>, std::io::Error> {
+        let mut packet = packet::U8Packet {
+            header: SizeHeader { size: 0 },
+            payload: Vec::new(),
+        };
+        self.read_into(source, &mut packet)?;
+        Ok(packet)
+    }
+
+    fn read_into<R: std::io::Read>(
+        &self,
+        source: &mut R,
+        packet: &mut packet::U8Packet<SizeHeader>,
+    ) -> Result<(), std::io::Error> {
         let mut header_buff: [u8; HEADER_LEN] = [0; HEADER_LEN];
         source.read_exact(&mut header_buff)?;
         let size = rwutil::get_be_u32(&header_buff[_HEADER_SIZE_START.._HEADER_SIZE_END]) as usize;
-        if size > self.max_payload_size {
+        if size > self.max_wire_size() {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "payload size exceeded packet maximum",
             ));
         }
-        let header = SizeHeader { size };
-
-        let mut buff = [0u8; PACKET_BUFFER_SIZE];
-        let payload = rwutil::read_chunked_bytes(source, size, &mut buff)?;
-        let packet = packet::U8Packet { header, payload };
-        Ok(packet)
+        let payload = &mut packet.payload;
+        rwutil::read_payload_into(source, size, payload)?;
+        if self.checksum {
+            rwutil::check_crc32(source, &header_buff, payload)?;
+        }
+        #[cfg(feature = "lz4")]
+        if self.decompress {
+            rwutil::decompress_payload(payload, self.max_payload_size)?;
+        }
+        packet.header = SizeHeader {
+            size: payload.len(),
+        };
+        Ok(())
     }
 }
 
 /// Handles writing SizePacket values.
-pub struct SizePacketWrite {}
+pub struct SizePacketWrite {
+    checksum: bool,
+    #[cfg(feature = "lz4")]
+    compress_threshold: Option<usize>,
+}
 
 impl SizePacketWrite {
     pub fn new() -> Self {
-        SizePacketWrite {}
+        SizePacketWrite {
+            checksum: false,
+            #[cfg(feature = "lz4")]
+            compress_threshold: None,
+        }
+    }
+
+    /// Compress payloads of at least `threshold` bytes, as negotiated with
+    /// `handshake::Features::LZ4`; smaller ones, and ones LZ4 can't shrink, go as is.
+    /// `None` turns compression off.  The reader must be built `with_compression` too.
+    #[cfg(feature = "lz4")]
+    pub fn with_compression(mut self, threshold: Option<usize>) -> Self {
+        self.compress_threshold = threshold;
+        self
+    }
+
+    /// The payload as it goes on the stream.
+    fn encode_payload<'p>(&self, payload: &'p [u8]) -> std::borrow::Cow<'p, [u8]> {
+        #[cfg(feature = "lz4")]
+        if let Some(threshold) = self.compress_threshold {
+            return rwutil::compress_payload(payload, threshold).into();
+        }
+        payload.into()
+    }
+
+    /// Write a CRC32 trailer after each packet, as negotiated with
+    /// `handshake::Features::CHECKSUM`.
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
     }
 }
 
 const _SIZE_8K: usize = 8 * 1024;
 
 impl packet::U8PacketWrite<SizeHeader> for SizePacketWrite {
-    fn write<'a, 'b, W: std::io::Write>(
+    fn write<'a, 'b, W: std::io::Write, P: AsRef<[u8]>>(
         &self,
         out: &'a mut W,
-        packet: &'b packet::U8Packet<SizeHeader>,
+        packet: &'b packet::U8Packet<SizeHeader, P>,
     ) -> Result<(), std::io::Error> {
         // Validate the packet.
-        if packet.header.size != packet.payload.len() {
+        let payload = packet.payload.as_ref();
+        if packet.header.size != payload.len() {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "header size != payload size",
             ));
         }
 
-        out.write_all(&(packet.header.size as u32).to_be_bytes())?;
-        rwutil::write_chunked::<W, _SIZE_8K>(out, &packet.payload)?;
+        let payload = self.encode_payload(payload);
+        let header_buff = (payload.len() as u32).to_be_bytes();
+        out.write_all(&header_buff)?;
+        rwutil::write_chunked::<W, _SIZE_8K>(out, &payload)?;
+        if self.checksum {
+            rwutil::write_crc32(out, &header_buff, &payload)?;
+        }
 
         // Finish with flushing the writer.
         out.flush()
@@ -124,6 +275,22 @@ mod tests {
         assert_eq!(data.payload.len(), 0);
     }
 
+    #[test]
+    fn test_iter() {
+        let r = SizePacketRead::new(10);
+        let mut source: &[u8] = &[0, 0, 0, 1, 7, 0, 0, 0, 0];
+        let payloads: Vec<_> = r.iter(&mut source).map(|p| p.unwrap().payload).collect();
+        assert_eq!(payloads, vec![vec![7], vec![]]);
+
+        // Ending partway into a packet is an error, once.
+        let mut source: &[u8] = &[0, 0, 0, 1, 7, 0, 0];
+        let mut packets = r.iter(&mut source);
+        assert_eq!(packets.next().unwrap().unwrap().payload, vec![7]);
+        let err = packets.next().unwrap().err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(packets.next().is_none());
+    }
+
     #[test]
     fn test_write_zero_bytes() {
         let mut out: std::io::Cursor<Vec<u8>> = std::io::Cursor::new(Vec::new());
@@ -144,4 +311,67 @@ mod tests {
             &ZERO_SIZE_EVENT[0..HEADER_LEN]
         );
     }
+
+    #[test]
+    fn test_read_into() {
+        let mut source: &[u8] = &[0, 0, 0, 3, 1, 2, 3, 0, 0, 0, 1, 4, 0, 0, 0, 2, 5];
+        let r = SizePacketRead::new(10);
+        let mut packet = r.read(&mut source).unwrap();
+        assert_eq!(packet.payload, vec![1, 2, 3]);
+        let memory = packet.payload.as_ptr();
+
+        r.read_into(&mut source, &mut packet).unwrap();
+        assert_eq!(packet.header.size, 1);
+        assert_eq!(packet.payload, vec![4]);
+        assert_eq!(packet.payload.as_ptr(), memory);
+
+        let err = r.read_into(&mut source, &mut packet).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_compression() {
+        let payload = vec![0u8; 1000];
+        let mut out = Vec::new();
+        SizePacketWrite::new()
+            .with_compression(Some(100))
+            .write(
+                &mut out,
+                &SizePacket {
+                    header: SizeHeader { size: 1000 },
+                    payload: payload.clone(),
+                },
+            )
+            .unwrap();
+        assert!(out.len() < 100);
+
+        let r = SizePacketRead::new(1000).with_compression(true);
+        let packet = r.read(&mut out.as_slice()).unwrap();
+        assert_eq!(packet.header.size, 1000);
+        assert_eq!(packet.payload, payload);
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut out = Vec::new();
+        SizePacketWrite::new()
+            .with_checksum(true)
+            .write(
+                &mut out,
+                &SizePacket {
+                    header: SizeHeader { size: 3 },
+                    payload: vec![1, 2, 3],
+                },
+            )
+            .unwrap();
+        assert_eq!(out.len(), HEADER_LEN + 3 + rwutil::U32_SIZE);
+
+        let r = SizePacketRead::new(10).with_checksum(true);
+        assert_eq!(r.read(&mut out.as_slice()).unwrap().payload, vec![1, 2, 3]);
+
+        out[HEADER_LEN + 1] ^= 0x10;
+        let err = r.read(&mut out.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
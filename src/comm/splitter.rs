@@ -1,31 +1,40 @@
 //! Split a stream based on a u8 value.
+//!
+//! For text records framed by a longer separator, such as `\r\n`, use a `RecordReader`
+//! and `write_record`.  Those can also escape the separator, so the records may contain
+//! any bytes.
+use std::collections::VecDeque;
+
 use super::rwutil;
 
 /// Read from the stream up to the separator, or the maximum length value.
 ///
 /// On standard exit, it returns the read bytes + whether the separator was found (true)
-/// or the length was encountered (false).
+/// or the length was encountered (false).  The separator is consumed, but not returned.
+/// When the length is reached, nothing past it is consumed, even a separator.  If the
+/// stream ends first, this fails with `UnexpectedEof`.
 ///
-/// While not required, the reader should be a buffered reader for better performance.
-pub fn read_next<R: std::io::Read>(
+/// The reader's buffer is scanned a chunk at a time, so wrap an unbuffered stream in a
+/// `std::io::BufReader`, and keep using that one reader for the whole stream.
+pub fn read_next<R: std::io::BufRead>(
     source: &mut R,
     sep: u8,
     max_len: usize,
 ) -> Result<(Vec<u8>, bool), std::io::Error> {
-    let mut buf = [0];
     let mut ret = vec![];
-    let mut count = 0;
-    let mut sep_found = false;
-    while count < max_len {
-        source.read_exact(&mut buf)?;
-        if buf[0] == sep {
-            sep_found = true;
-            break;
+    while ret.len() < max_len {
+        let available = fill_buf(source)?;
+        let chunk = &available[..std::cmp::min(available.len(), max_len - ret.len())];
+        if let Some(pos) = memchr::memchr(sep, chunk) {
+            ret.extend_from_slice(&chunk[..pos]);
+            source.consume(pos + 1);
+            return Ok((ret, true));
         }
-        ret.push(buf[0]);
-        count += 1;
+        let count = chunk.len();
+        ret.extend_from_slice(chunk);
+        source.consume(count);
     }
-    Ok((ret, sep_found))
+    Ok((ret, false))
 }
 
 const _BUF_SIZE: usize = 8 * 1024;
@@ -40,3 +49,274 @@ pub fn write_next<W: std::io::Write>(
     out.write_all(&[sep])?;
     out.flush()
 }
+
+/// Reads records ended by a multi-byte separator, as written by `write_record`.
+///
+/// With an escape byte, the byte after each escape is part of the record, even if it's
+/// the escape byte or starts the separator.
+pub struct RecordReader<R> {
+    source: R,
+    separator: Vec<u8>,
+    escape: Option<u8>,
+    // Bytes taken from the source but not yet scanned, such as the start of a separator
+    // that turned out to be record data.
+    pending: VecDeque<u8>,
+}
+
+impl<R: std::io::BufRead> RecordReader<R> {
+    /// Fails with `InvalidInput` if the separator is empty.
+    pub fn new(source: R, separator: &[u8]) -> Result<Self, std::io::Error> {
+        check_separator(separator, None)?;
+        Ok(RecordReader {
+            source,
+            separator: separator.to_vec(),
+            escape: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Unescape the records, as written by `write_record` with the same escape byte.
+    ///
+    /// Fails with `InvalidInput` if the separator contains the escape byte.
+    pub fn with_escape(mut self, escape: u8) -> Result<Self, std::io::Error> {
+        check_separator(&self.separator, Some(escape))?;
+        self.escape = Some(escape);
+        Ok(self)
+    }
+
+    /// Read up to the separator, or up to `max_len` bytes of the record, with the same
+    /// results as `read_next`.  Escape bytes don't count towards the length.
+    pub fn read_record(&mut self, max_len: usize) -> Result<(Vec<u8>, bool), std::io::Error> {
+        let mut ret = vec![];
+        // How much of the separator the last bytes matched.
+        let mut matched = 0;
+        loop {
+            if matched == 0 && ret.len() >= max_len {
+                return Ok((ret, false));
+            }
+            let b = match self.pending.pop_front() {
+                Some(b) => b,
+                None if matched == 0 => match self.scan(&mut ret, max_len)? {
+                    Some(b) => b,
+                    None => continue,
+                },
+                None => self.next_byte()?,
+            };
+
+            if b == self.separator[matched] {
+                matched += 1;
+                if matched == self.separator.len() {
+                    return Ok((ret, true));
+                }
+            } else if matched > 0 {
+                // Not a separator after all: its first byte is data, and the separator
+                // may start again in the rest.
+                self.pending.push_front(b);
+                for &s in self.separator[1..matched].iter().rev() {
+                    self.pending.push_front(s);
+                }
+                ret.push(self.separator[0]);
+                matched = 0;
+            } else if Some(b) == self.escape {
+                let b = match self.pending.pop_front() {
+                    Some(b) => b,
+                    None => self.next_byte()?,
+                };
+                ret.push(b);
+            } else {
+                ret.push(b);
+            }
+        }
+    }
+
+    /// Move bytes from the source's buffer to the record, up to the next byte that may
+    /// start a separator or an escape, which is returned consumed.
+    fn scan(&mut self, ret: &mut Vec<u8>, max_len: usize) -> Result<Option<u8>, std::io::Error> {
+        let available = fill_buf(&mut self.source)?;
+        let chunk = &available[..std::cmp::min(available.len(), max_len - ret.len())];
+        let found = match self.escape {
+            Some(escape) => memchr::memchr2(self.separator[0], escape, chunk),
+            None => memchr::memchr(self.separator[0], chunk),
+        };
+        let Some(pos) = found else {
+            let count = chunk.len();
+            ret.extend_from_slice(chunk);
+            self.source.consume(count);
+            return Ok(None);
+        };
+        let b = chunk[pos];
+        ret.extend_from_slice(&chunk[..pos]);
+        self.source.consume(pos + 1);
+        Ok(Some(b))
+    }
+
+    fn next_byte(&mut self) -> Result<u8, std::io::Error> {
+        let b = fill_buf(&mut self.source)?[0];
+        self.source.consume(1);
+        Ok(b)
+    }
+}
+
+/// Write the record to the stream plus the separator, escaping each escape byte and each
+/// start of the separator with the escape byte.
+///
+/// Without an escape byte, fails with `InvalidInput` if the record contains the
+/// separator, and writes nothing.
+pub fn write_record<W: std::io::Write>(
+    out: &mut W,
+    data: &[u8],
+    separator: &[u8],
+    escape: Option<u8>,
+) -> Result<(), std::io::Error> {
+    check_separator(separator, escape)?;
+    let Some(escape) = escape else {
+        if memchr::memmem::find(data, separator).is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the record contains the separator",
+            ));
+        }
+        rwutil::write_chunked::<W, _BUF_SIZE>(out, data)?;
+        out.write_all(separator)?;
+        return out.flush();
+    };
+
+    let mut start = 0;
+    let mut from = 0;
+    while let Some(pos) = memchr::memchr2(separator[0], escape, &data[from..]) {
+        let pos = from + pos;
+        from = pos + 1;
+        if data[pos] == escape || data[pos..].starts_with(separator) {
+            out.write_all(&data[start..pos])?;
+            out.write_all(&[escape])?;
+            start = pos;
+        }
+    }
+    out.write_all(&data[start..])?;
+    out.write_all(separator)?;
+    out.flush()
+}
+
+fn check_separator(separator: &[u8], escape: Option<u8>) -> Result<(), std::io::Error> {
+    if separator.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the separator is empty",
+        ));
+    }
+    if let Some(escape) = escape
+        && separator.contains(&escape)
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the separator contains the escape byte",
+        ));
+    }
+    Ok(())
+}
+
+/// Fill the reader's buffer, and fail with `UnexpectedEof` if the stream ended.
+fn fill_buf<R: std::io::BufRead>(source: &mut R) -> Result<&[u8], std::io::Error> {
+    // Checked before taking the buffer, which the borrow checker can't return from a loop.
+    loop {
+        match source.fill_buf() {
+            Ok([]) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "stream ended before the separator",
+                ));
+            }
+            Ok(_) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    source.fill_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_next() {
+        // A tiny buffer, so the items span several fills.
+        let mut source = std::io::BufReader::with_capacity(3, &b"first\nsecond\n\nlong\nend"[..]);
+        assert_eq!(
+            read_next(&mut source, b'\n', 10).unwrap(),
+            (b"first".to_vec(), true)
+        );
+        assert_eq!(
+            read_next(&mut source, b'\n', 6).unwrap(),
+            (b"second".to_vec(), false)
+        );
+        assert_eq!(read_next(&mut source, b'\n', 6).unwrap(), (vec![], true));
+        assert_eq!(read_next(&mut source, b'\n', 6).unwrap(), (vec![], true));
+        assert_eq!(
+            read_next(&mut source, b'\n', 2).unwrap(),
+            (b"lo".to_vec(), false)
+        );
+        assert_eq!(
+            read_next(&mut source, b'\n', 6).unwrap(),
+            (b"ng".to_vec(), true)
+        );
+        assert_eq!(read_next(&mut source, b'\n', 0).unwrap(), (vec![], false));
+        let err = read_next(&mut source, b'\n', 6).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_records() {
+        let source: &[u8] = b"one\r\ntw\ro\r\r\n\r\nabc\r\n";
+        let source = std::io::BufReader::with_capacity(2, source);
+        let mut reader = RecordReader::new(source, b"\r\n").unwrap();
+        assert_eq!(reader.read_record(10).unwrap(), (b"one".to_vec(), true));
+        assert_eq!(reader.read_record(10).unwrap(), (b"tw\ro\r".to_vec(), true));
+        assert_eq!(reader.read_record(10).unwrap(), (vec![], true));
+        // Cut within what could have been a separator.
+        assert_eq!(reader.read_record(2).unwrap(), (b"ab".to_vec(), false));
+        assert_eq!(reader.read_record(1).unwrap(), (b"c".to_vec(), false));
+        assert_eq!(reader.read_record(1).unwrap(), (vec![], true));
+        let err = reader.read_record(10).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_escaped_records() {
+        let records: [&[u8]; 4] = [b"plain", b"a\r\nb", b"\\\r\r\n\\", b"\r\r\n\r"];
+        let mut out = Vec::new();
+        for record in records {
+            write_record(&mut out, record, b"\r\n", Some(b'\\')).unwrap();
+        }
+        assert_eq!(&out[..14], b"plain\r\na\\\r\nb\r\n");
+
+        let source = std::io::BufReader::with_capacity(3, out.as_slice());
+        let mut reader = RecordReader::new(source, b"\r\n")
+            .unwrap()
+            .with_escape(b'\\')
+            .unwrap();
+        for record in records {
+            assert_eq!(reader.read_record(100).unwrap(), (record.to_vec(), true));
+        }
+
+        let err = write_record(&mut out, b"a\r\nb", b"\r\n", None).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let err = write_record(&mut out, b"a", b"\\\n", Some(b'\\')).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_write_next() {
+        let mut out = Vec::new();
+        write_next(&mut out, &b"one".to_vec(), 0).unwrap();
+        write_next(&mut out, &vec![], 0).unwrap();
+        assert_eq!(out, b"one\0\0");
+
+        let mut source = out.as_slice();
+        assert_eq!(
+            read_next(&mut source, 0, 10).unwrap(),
+            (b"one".to_vec(), true)
+        );
+        assert_eq!(read_next(&mut source, 0, 10).unwrap(), (vec![], true));
+    }
+}
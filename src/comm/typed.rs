@@ -0,0 +1,207 @@
+//! Exchange serde types, rather than raw bytes, over the packet streams.
+//!
+//! `send` encodes a value into a `SizePacket` and `recv` decodes the next one.  For event
+//! streams, `send_event` writes the value as an `EventPacket`'s payload, and
+//! `decode_event` decodes the payload after the caller has matched the event ID.
+//!
+//! Both sides must agree on the `Codec`: bincode with the `bincode` feature, or CBOR with
+//! the `cbor` feature.  A payload that doesn't decode as the requested type is an
+//! `InvalidData` error.
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use super::event::{EventId, EventPacket, EventWriter};
+use super::packet::{U8PacketRead, U8PacketWrite};
+use super::sizedpacket::{self, SizeHeader, SizePacket, SizePacketRead, SizePacketWrite};
+
+/// The encoding for the values in the packet payloads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// bincode's standard configuration: compact, but not self-describing.
+    #[cfg(feature = "bincode")]
+    Bincode,
+    /// CBOR (RFC 8949), which other languages can read.
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Codec {
+    /// Encode the value into a payload.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, std::io::Error> {
+        let payload = match self {
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => bincode::serde::encode_to_vec(value, bincode::config::standard())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                let mut payload = Vec::new();
+                ciborium::into_writer(value, &mut payload)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+                payload
+            }
+        };
+        if payload.len() > sizedpacket::MAX_PAYLOAD_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "encoded value too large for a packet",
+            ));
+        }
+        Ok(payload)
+    }
+
+    /// Decode the whole payload as a value.  Trailing bytes are an error.
+    pub fn decode<T: DeserializeOwned>(self, payload: &[u8]) -> Result<T, std::io::Error> {
+        match self {
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => {
+                let (value, used) =
+                    bincode::serde::decode_from_slice(payload, bincode::config::standard())
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                if used != payload.len() {
+                    return Err(trailing_bytes());
+                }
+                Ok(value)
+            }
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                let mut remaining = payload;
+                let value = ciborium::from_reader(&mut remaining)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                if !remaining.is_empty() {
+                    return Err(trailing_bytes());
+                }
+                Ok(value)
+            }
+        }
+    }
+}
+
+/// Encode the value and write it as one `SizePacket`.
+pub fn send<T: Serialize, W: std::io::Write>(
+    out: &mut W,
+    codec: Codec,
+    value: &T,
+) -> Result<(), std::io::Error> {
+    let payload = codec.encode(value)?;
+    let packet = SizePacket {
+        header: SizeHeader {
+            size: payload.len(),
+        },
+        payload,
+    };
+    SizePacketWrite::new().write(out, &packet)
+}
+
+/// Read the next `SizePacket` and decode its payload.
+///
+/// A packet larger than `max_payload_size` is an error, before any of its payload is read.
+pub fn recv<T: DeserializeOwned, R: std::io::Read>(
+    source: &mut R,
+    codec: Codec,
+    max_payload_size: usize,
+) -> Result<T, std::io::Error> {
+    let packet = SizePacketRead::new(max_payload_size).read(source)?;
+    codec.decode(&packet.payload)
+}
+
+/// Encode the value and write it as the payload of an `EventPacket`.
+pub fn send_event<T: Serialize, W: std::io::Write>(
+    out: &mut W,
+    codec: Codec,
+    packet_id: u64,
+    cmd_packet_id: u64,
+    event_id: EventId,
+    value: &T,
+) -> Result<(), std::io::Error> {
+    let payload = codec.encode(value)?;
+    EventWriter::new().write_event(out, packet_id, cmd_packet_id, event_id, payload)
+}
+
+/// Decode an event packet's payload, once its event ID says what type it holds.
+pub fn decode_event<T: DeserializeOwned>(
+    codec: Codec,
+    packet: &EventPacket,
+) -> Result<T, std::io::Error> {
+    codec.decode(&packet.payload)
+}
+
+fn trailing_bytes() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "payload has bytes after the encoded value",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comm::event::EventReader;
+
+    #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Request {
+        path: String,
+        lines: Vec<u32>,
+    }
+
+    const READ: EventId = EventId::from_static("read");
+
+    fn codecs() -> Vec<Codec> {
+        vec![
+            #[cfg(feature = "bincode")]
+            Codec::Bincode,
+            #[cfg(feature = "cbor")]
+            Codec::Cbor,
+        ]
+    }
+
+    fn request() -> Request {
+        Request {
+            path: "a.txt".to_string(),
+            lines: vec![1, 20, 300],
+        }
+    }
+
+    #[test]
+    fn test_send_recv() {
+        for codec in codecs() {
+            let mut out = Vec::new();
+            send(&mut out, codec, &request()).unwrap();
+            send(&mut out, codec, &"done").unwrap();
+
+            let mut source = out.as_slice();
+            assert_eq!(
+                recv::<Request, _>(&mut source, codec, 1024).unwrap(),
+                request()
+            );
+            assert_eq!(recv::<String, _>(&mut source, codec, 1024).unwrap(), "done");
+            assert!(source.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_send_event() {
+        for codec in codecs() {
+            let mut out = Vec::new();
+            send_event(&mut out, codec, 5, 0, READ, &request()).unwrap();
+
+            let packet = EventReader::new(1024).read(&mut out.as_slice()).unwrap();
+            assert_eq!(packet.header.event_id, READ);
+            assert_eq!(decode_event::<Request>(codec, &packet).unwrap(), request());
+        }
+    }
+
+    #[test]
+    fn test_decode_wrong_type() {
+        for codec in codecs() {
+            let mut out = Vec::new();
+            send(&mut out, codec, &"not a request").unwrap();
+            let err = recv::<Request, _>(&mut out.as_slice(), codec, 1024).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+            let mut payload = codec.encode(&7u32).unwrap();
+            payload.push(0);
+            let err = codec.decode::<u32>(&payload).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,398 @@
+// SPDX-License-Identifier: MIT
+
+//! # Sandbox Escape Self-Test
+//!
+//! Runs a suite of adversarial child programs inside the sandbox on the current host, and
+//! reports which of them managed to get out.  Kernels, OS patches, and this library all change
+//! over time, so running the suite where the sandbox is deployed gives ongoing assurance that
+//! the restrictions still hold.
+//!
+//! The probe programs live in the repository's `test-bin` directory, and must be compiled
+//! before running the suite.  Each probe follows the `test-bin` protocol: it reads one byte
+//! from stdin, writes `1` to stdout, attempts the escape, then writes `2` to stdout only if
+//! the escape worked.
+//!
+//! Requires the `escapetest` feature.
+
+use std::{
+    ffi::OsString,
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    Child, CommHandler, EnvMap, FdMode, FdSet, LaunchEnv, Restrictions,
+    runtime::error::SandboxError, sandbox_child,
+};
+
+/// The probes in the suite, in the order they run.
+pub const PROBES: &[&str] = &[
+    "file-read",
+    "exec-self",
+    "proc-probe",
+    "symlink-race",
+    "abstract-socket",
+    "handle-brute",
+];
+
+/// How a single probe ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeOutcome {
+    /// The sandbox stopped the probe.
+    Contained,
+
+    /// The probe performed the prohibited action.
+    Escaped,
+
+    /// The probe could not run on this host, with the reason.
+    Skipped(String),
+
+    /// The probe did not follow the protocol, so the outcome is unknown.
+    Error(String),
+}
+
+/// The result of running a single probe.
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    pub name: String,
+    pub outcome: ProbeOutcome,
+}
+
+/// The results of running the whole suite.
+#[derive(Debug, Clone, Default)]
+pub struct EscapeReport {
+    pub results: Vec<ProbeResult>,
+}
+
+impl EscapeReport {
+    /// True if any probe escaped the sandbox.
+    pub fn escaped(&self) -> bool {
+        self.results
+            .iter()
+            .any(|r| r.outcome == ProbeOutcome::Escaped)
+    }
+
+    /// True if no probe escaped or failed, so every probe was either contained or skipped.
+    pub fn all_contained_or_skipped(&self) -> bool {
+        self.results.iter().all(|r| {
+            matches!(
+                r.outcome,
+                ProbeOutcome::Contained | ProbeOutcome::Skipped(_)
+            )
+        })
+    }
+
+    /// True if every probe ran and was contained.
+    pub fn all_contained(&self) -> bool {
+        self.results
+            .iter()
+            .all(|r| r.outcome == ProbeOutcome::Contained)
+    }
+}
+
+impl std::fmt::Display for EscapeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for r in &self.results {
+            match &r.outcome {
+                ProbeOutcome::Contained => writeln!(f, "{}: contained", r.name)?,
+                ProbeOutcome::Escaped => writeln!(f, "{}: ESCAPED", r.name)?,
+                ProbeOutcome::Skipped(why) => writeln!(f, "{}: skipped ({})", r.name, why)?,
+                ProbeOutcome::Error(why) => writeln!(f, "{}: error ({})", r.name, why)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run every probe against the restrictions.
+///
+/// The `locate` function returns the path to the compiled probe executable with the given
+/// name, or None if it is not available; missing probes are reported as skipped.
+pub fn run_escape_tests<F>(locate: F, restrictions: &Restrictions) -> EscapeReport
+where
+    F: Fn(&str) -> Option<PathBuf>,
+{
+    let mut report = EscapeReport::default();
+    for name in PROBES {
+        let outcome = match locate(name) {
+            Some(exec) if exec.is_file() => run_probe(name, exec, restrictions),
+            _ => ProbeOutcome::Skipped("probe executable not found".to_string()),
+        };
+        report.results.push(ProbeResult {
+            name: name.to_string(),
+            outcome,
+        });
+    }
+    report
+}
+
+/// Set up the host side of the probe, then run it.
+fn run_probe(name: &str, exec: PathBuf, restrictions: &Restrictions) -> ProbeOutcome {
+    let fixture = match Fixture::new(name) {
+        Ok(Some(f)) => f,
+        Ok(None) => return ProbeOutcome::Skipped("not supported on this OS".to_string()),
+        Err(e) => return ProbeOutcome::Error(format!("probe setup failed: {e}")),
+    };
+    let mut env = EnvMap::new();
+    env.insert(OsString::from("RUST_BACKTRACE"), OsString::from("1"));
+    let res = sandbox_child(
+        LaunchEnv {
+            args: vec![fixture.arg.clone()],
+            env,
+            fds: FdSet::basic(&[FdMode::ToChild, FdMode::FromChild, FdMode::Null]),
+            readable_paths: fixture.readable.clone(),
+            ..LaunchEnv::new(exec, restrictions.clone())
+        },
+        ProbeHandler,
+    );
+    drop(fixture);
+
+    match res.and_then(|r| r.into_result()) {
+        // The sandbox is weaker under a privileged parent, so a contained probe wouldn't
+        // show that it holds where it's deployed.
+        Err(SandboxError::PrivilegedParent) => {
+            ProbeOutcome::Skipped("the parent runs privileged".to_string())
+        }
+        Err(e) => ProbeOutcome::Error(e.to_string()),
+        Ok((Progress::Ended, _)) => ProbeOutcome::Escaped,
+        Ok((Progress::Started, _)) => ProbeOutcome::Contained,
+        Ok((Progress::NotStarted, _)) => ProbeOutcome::Error("probe never started".to_string()),
+    }
+}
+
+/// Resources the host creates for a probe to attack.  Dropping it cleans them up.
+struct Fixture {
+    arg: OsString,
+    readable: Vec<PathBuf>,
+    _dir: Option<tempfile::TempDir>,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Result<Option<Self>, std::io::Error> {
+        match name {
+            "file-read" => {
+                let dir = tempfile::tempdir()?;
+                let secret = dir.path().join("secret.txt");
+                std::fs::write(&secret, "secret\n")?;
+                Ok(Some(Self::with_dir(secret.into_os_string(), dir)))
+            }
+            "exec-self" => Ok(Some(Self::arg("probe"))),
+            "proc-probe" => Ok(Some(Self::arg("/proc/1/environ"))),
+            "handle-brute" => Ok(Some(Self::arg("4096"))),
+            "symlink-race" => Self::symlink_race(),
+            "abstract-socket" => Self::abstract_socket(),
+            _ => Ok(None),
+        }
+    }
+
+    fn arg(arg: &str) -> Self {
+        Fixture {
+            arg: OsString::from(arg),
+            readable: Vec::new(),
+            _dir: None,
+            stop: None,
+        }
+    }
+
+    fn with_dir(arg: OsString, dir: tempfile::TempDir) -> Self {
+        Fixture {
+            arg,
+            readable: Vec::new(),
+            _dir: Some(dir),
+            stop: None,
+        }
+    }
+
+    /// Swap a symbolic link between a decoy and the secret until the probe finishes.
+    /// The probe may read the decoy, but not the secret.
+    #[cfg(unix)]
+    fn symlink_race() -> Result<Option<Self>, std::io::Error> {
+        let dir = tempfile::tempdir()?;
+        let decoy = dir.path().join("decoy.txt");
+        let secret = dir.path().join("secret.txt");
+        let link = dir.path().join("link");
+        std::fs::write(&decoy, "decoy\n")?;
+        std::fs::write(&secret, "secret\n")?;
+        std::os::unix::fs::symlink(&decoy, &link)?;
+        let decoy_allowed = decoy.clone();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = stop.clone();
+        let staged = dir.path().join("link.next");
+        // The probe opens the link, which the thread below keeps swapping.
+        let mut ret = Self::with_dir(link.as_os_str().to_owned(), dir);
+        thread::spawn(move || {
+            let mut flip = false;
+            while !running.load(Ordering::Relaxed) {
+                let target = if flip { &decoy } else { &secret };
+                flip = !flip;
+                let _ = std::fs::remove_file(&staged);
+                if std::os::unix::fs::symlink(target, &staged).is_ok() {
+                    let _ = std::fs::rename(&staged, &link);
+                }
+            }
+        });
+        ret.readable.push(decoy_allowed);
+        ret.stop = Some(stop);
+        Ok(Some(ret))
+    }
+
+    #[cfg(not(unix))]
+    fn symlink_race() -> Result<Option<Self>, std::io::Error> {
+        // Creating symbolic links on Windows requires elevated privileges.
+        Ok(None)
+    }
+
+    /// Listen on an abstract socket until the probe finishes.
+    #[cfg(target_os = "linux")]
+    fn abstract_socket() -> Result<Option<Self>, std::io::Error> {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixListener};
+
+        let name = format!("gracklezero-escapetest-{}", std::process::id());
+        let listener = UnixListener::bind_addr(&SocketAddr::from_abstract_name(&name)?)?;
+        listener.set_nonblocking(true)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = stop.clone();
+        thread::spawn(move || {
+            while !running.load(Ordering::Relaxed) {
+                // Accept and immediately drop; the connection itself is the escape.
+                let _ = listener.accept();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let mut ret = Self::arg(&name);
+        ret.stop = Some(stop);
+        Ok(Some(ret))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn abstract_socket() -> Result<Option<Self>, std::io::Error> {
+        Ok(None)
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        if let Some(stop) = &self.stop {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// How far through the protocol the probe reached.
+#[derive(Debug, Clone, Copy, Default)]
+enum Progress {
+    #[default]
+    NotStarted,
+    Started,
+    Ended,
+}
+
+/// Runs the `test-bin` protocol with the probe, and reports how far it got.
+struct ProbeHandler;
+
+impl CommHandler for ProbeHandler {
+    type Output = Progress;
+    type Error = std::io::Error;
+
+    fn handle(&mut self, mut child: Box<dyn Child>) -> Result<Progress, std::io::Error> {
+        let mut out = child
+            .take_stream_to_child(0)
+            .ok_or_else(|| std::io::Error::new(ErrorKind::BrokenPipe, "no stdin"))?;
+        let mut inp = child
+            .take_stream_from_child(1)
+            .ok_or_else(|| std::io::Error::new(ErrorKind::BrokenPipe, "no stdout"))?;
+
+        // The child may already be dead, so a failed write is not an error.
+        let _ = out.write_all(b"0");
+        drop(out);
+
+        let mut progress = Progress::NotStarted;
+        let mut buf = [0u8];
+        if inp.read_exact(&mut buf).is_ok() && buf[0] == b'1' {
+            progress = Progress::Started;
+            if inp.read_exact(&mut buf).is_ok() && buf[0] == b'2' {
+                progress = Progress::Ended;
+            }
+        }
+
+        // Give the child a moment to exit on its own before the runtime kills it.
+        let _ = child.wait_timeout(Duration::from_millis(500));
+        Ok(progress)
+    }
+}
+
+/// Locate a probe built inside the repository's `test-bin` directory with `cargo build`.
+pub fn test_bin_locator(test_bin: &Path) -> impl Fn(&str) -> Option<PathBuf> + '_ {
+    move |name| {
+        let mut exec = test_bin.join(name).join("target").join("debug").join(name);
+        if cfg!(target_os = "windows") {
+            exec.set_extension("exe");
+        }
+        exec.is_file().then_some(exec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_status() {
+        let mut report = EscapeReport::default();
+        report.results.push(ProbeResult {
+            name: "a".to_string(),
+            outcome: ProbeOutcome::Contained,
+        });
+        assert!(report.all_contained());
+        assert!(report.all_contained_or_skipped());
+        assert!(!report.escaped());
+
+        report.results.push(ProbeResult {
+            name: "b".to_string(),
+            outcome: ProbeOutcome::Escaped,
+        });
+        assert!(!report.all_contained());
+        assert!(!report.all_contained_or_skipped());
+        assert!(report.escaped());
+        assert_eq!(report.to_string(), "a: contained\nb: ESCAPED\n");
+    }
+
+    #[test]
+    fn errors_are_not_contained() {
+        let mut report = EscapeReport::default();
+        report.results.push(ProbeResult {
+            name: "a".to_string(),
+            outcome: ProbeOutcome::Skipped("the parent runs privileged".to_string()),
+        });
+        assert!(report.all_contained_or_skipped());
+
+        report.results.push(ProbeResult {
+            name: "b".to_string(),
+            outcome: ProbeOutcome::Error("probe never started".to_string()),
+        });
+        assert!(!report.all_contained_or_skipped());
+        assert!(!report.escaped());
+    }
+
+    #[test]
+    fn missing_probes_skip() {
+        let report = run_escape_tests(|_| None, &crate::compat_restrictions!("escapetest"));
+        assert_eq!(report.results.len(), PROBES.len());
+        assert!(report.all_contained_or_skipped());
+        assert!(
+            report
+                .results
+                .iter()
+                .all(|r| matches!(r.outcome, ProbeOutcome::Skipped(_)))
+        );
+    }
+}
@@ -4,12 +4,27 @@
 //!
 //! The library that runs child programs with near zero OS permissions.
 //!
-//!
+//! Most programs only need the [`prelude`]: describe the child with a [`LaunchEnv`], talk to
+//! it through a [`CommHandler`], and run it with [`sandbox_child`].
 
+pub mod child;
 pub mod comm;
+#[cfg(feature = "escapetest")]
+pub mod escapetest;
+pub mod logging;
 pub mod macros;
+pub mod policy;
+pub mod prelude;
+#[cfg(feature = "profile")]
+pub mod profile;
 pub mod restrictions;
 pub mod runtime;
 
+pub use policy::SandboxPolicy;
 pub use restrictions::{Restrictions, create_compat_restrictions, create_strict_restrictions};
-pub use runtime::{Child, CommHandler, FdMode, FdSet, LaunchEnv, sandbox_child};
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use runtime::registry::terminate_all;
+pub use runtime::{
+    Child, CommHandler, DependencySearch, EnvMap, ExecResolution, FdMode, FdSet, LaunchEnv,
+    sandbox_child,
+};
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT
+
+//! Diagnostic logging.
+//!
+//! With the `log` feature, the library sends its diagnostics through the
+//! [`log`](https://docs.rs/log) crate, and the embedding program picks the logger and
+//! level.  Without the feature, the macros compile to nothing, so the library stays
+//! silent.
+//!
+//! Command lines, paths, and environment details only appear at the `debug` and `trace`
+//! levels, as they may hold information the caller doesn't want in its logs.
+
+#[cfg(feature = "log")]
+macro_rules! log_at {
+    ( $level:ident, $($arg:tt)+ ) => {
+        ::log::$level!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_at {
+    ( $level:ident, $($arg:tt)+ ) => {
+        // Keep the arguments type checked and used, without evaluating them.
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+
+/// Something failed, and the operation stops.
+#[allow(unused_macros)]
+macro_rules! error {
+    ( $($arg:tt)+ ) => { $crate::logging::log_at!(error, $($arg)+) };
+}
+
+/// Something unexpected happened, but the operation continues.
+#[allow(unused_macros)]
+macro_rules! warning {
+    ( $($arg:tt)+ ) => { $crate::logging::log_at!(warn, $($arg)+) };
+}
+
+/// Details of the launch, jail setup, and termination steps.
+macro_rules! debug {
+    ( $($arg:tt)+ ) => { $crate::logging::log_at!(debug, $($arg)+) };
+}
+
+/// Very detailed progress, such as each discovered library.
+#[allow(unused_macros)]
+macro_rules! trace {
+    ( $($arg:tt)+ ) => { $crate::logging::log_at!(trace, $($arg)+) };
+}
+
+#[allow(unused_imports)]
+pub(crate) use {debug, error, log_at, trace, warning};
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: MIT
+
+//! A cross-platform description of what the child may do, which each OS backend turns into
+//! its own mechanism.
+//!
+//! The `Restrictions` name each OS mechanism directly: Landlock and seccomp on Linux, the
+//! AppContainer and process mitigations on Windows.  A `SandboxPolicy` states the intent
+//! instead (which paths, which ports, how many resources), and `LaunchEnv::with_policy`
+//! compiles it onto the launch:
+//!
+//! | Rule | Linux | Windows |
+//! |------|-------|---------|
+//! | `filesystem.read` | Landlock read rules | not enforced yet |
+//! | `filesystem.write` | Landlock write rules, and the seccomp calls to change files | not enforced yet |
+//! | `network.tcp_connect` | seccomp TCP sockets, and Landlock connect rules for the ports | the `InternetClient` capability, for any port |
+//! | `network.tcp_bind` | seccomp TCP sockets, and Landlock bind rules for the ports | the `InternetClientServer` capability, for any port |
+//! | `process.max_open_files` | the `RLIMIT_NOFILE` rlimit | not enforced |
+//! | `process.kill_on_violation` | seccomp kills rather than failing the call | not enforced |
+//! | `process.cpu_placement` | `sched_setaffinity` | the job's processor affinity |
+//! | `resource_limits` | rlimits | the job's limits |
+//!
+//! The rest of the `Restrictions`, such as the AppContainer name and the Windows
+//! mitigations, keep the values they were created with.
+//!
+//! ```no_run
+//! use gracklezero::{LaunchEnv, SandboxPolicy, strict_restrictions};
+//!
+//! let mut policy = SandboxPolicy::new();
+//! policy.filesystem.read.push("/srv/data".into());
+//! policy.network.tcp_connect.push(443);
+//! let env = LaunchEnv::new("/usr/bin/fetcher".into(), strict_restrictions!("app"))
+//!     .with_policy(&policy);
+//! ```
+//!
+//! The presets give a baseline to start from, and keep the same guarantees as the library
+//! grows:
+//!
+//! * `SandboxPolicy::strict` allows nothing beyond running the executable, with at most
+//!   64 open files, and kills the child on its first denied syscall.
+//! * `SandboxPolicy::compute_only` allows nothing beyond running the executable and using
+//!   the FDs it's handed, but denied syscalls fail with `EPERM` instead, for runtimes that
+//!   probe for features and fall back.
+//! * `SandboxPolicy::read_data` is `compute_only`, plus reading the paths.
+//! * `SandboxPolicy::net_client` is `compute_only`, plus TCP connections to the ports.
+//!   On Windows, this allows connections to any port.
+//!
+//! On Windows, every preset runs the child in its AppContainer with no file access of its
+//! own, whatever the file rules say, and `strict` doesn't limit the open files or kill on
+//! violations.
+//!
+//! With the `serde` feature, the policy serializes and deserializes, with every field
+//! optional, so it can be kept in a config file.  See `crate::profile`.
+
+use std::path::PathBuf;
+
+use crate::restrictions::{
+    CpuPlacement, ResourceLimits, Restrictions,
+    windows::{AppContainerCapability, AppContainerMode},
+};
+use crate::runtime::LaunchEnv;
+
+/// What the child may do, independent of the OS.  See the module documentation for how
+/// each OS enforces the rules.
+///
+/// The default policy allows nothing beyond running the executable: no extra files, no
+/// network, and no resource limits beyond the default open file limit.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct SandboxPolicy {
+    pub filesystem: FilesystemRules,
+    pub network: NetworkRules,
+    pub process: ProcessRules,
+    pub resource_limits: ResourceLimits,
+}
+
+/// The files and directories the child may use, beyond the executable and its libraries.
+/// Directories cover everything beneath them.  Each path must exist at launch time.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct FilesystemRules {
+    /// Paths the child may read.
+    pub read: Vec<PathBuf>,
+    /// Paths the child may read, create, change, and delete.
+    pub write: Vec<PathBuf>,
+}
+
+/// The network access the child may use.  With no ports listed, the child has no network.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct NetworkRules {
+    /// TCP ports the child may connect to.
+    pub tcp_connect: Vec<u16>,
+    /// TCP ports the child may listen on.
+    pub tcp_bind: Vec<u16>,
+}
+
+/// Limits on the child process itself.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ProcessRules {
+    /// The most files the child may have open at once.
+    /// Defaults to 2048.
+    pub max_open_files: u64,
+    /// Kill the child when it tries something the sandbox denies, rather than failing
+    /// the attempt.
+    /// Defaults to false.
+    pub kill_on_violation: bool,
+    /// Which processors the child may run on.
+    /// Defaults to `Any`.
+    pub cpu_placement: CpuPlacement,
+}
+
+impl Default for ProcessRules {
+    fn default() -> Self {
+        ProcessRules {
+            max_open_files: 2048,
+            kill_on_violation: false,
+            cpu_placement: CpuPlacement::Any,
+        }
+    }
+}
+
+impl SandboxPolicy {
+    /// The policy that allows nothing beyond running the executable.
+    pub fn new() -> Self {
+        SandboxPolicy::default()
+    }
+
+    /// Allow nothing beyond running the executable, with at most 64 open files, and kill
+    /// the child on its first denied syscall.
+    pub fn strict() -> Self {
+        SandboxPolicy {
+            process: ProcessRules {
+                max_open_files: 64,
+                kill_on_violation: true,
+                ..ProcessRules::default()
+            },
+            ..SandboxPolicy::default()
+        }
+    }
+
+    /// Allow nothing beyond running the executable and using the FDs the parent hands it,
+    /// such as to read input from STDIN and write results to STDOUT.  Denied syscalls fail
+    /// rather than kill the child.
+    pub fn compute_only() -> Self {
+        SandboxPolicy::default()
+    }
+
+    /// Allow reading the files, and everything beneath the directories, on top of
+    /// `compute_only`.
+    pub fn read_data<P: Into<PathBuf>, I: IntoIterator<Item = P>>(paths: I) -> Self {
+        let mut policy = SandboxPolicy::compute_only();
+        policy.filesystem.read = paths.into_iter().map(Into::into).collect();
+        policy
+    }
+
+    /// Allow TCP connections to the ports, on top of `compute_only`.  The child can't
+    /// look up host names, as DNS needs UDP, so pass it addresses.
+    pub fn net_client<I: IntoIterator<Item = u16>>(ports: I) -> Self {
+        let mut policy = SandboxPolicy::compute_only();
+        policy.network.tcp_connect = ports.into_iter().collect();
+        policy
+    }
+
+    /// Set the restrictions to enforce the policy, keeping the settings the policy doesn't
+    /// cover, such as the AppContainer name and the Windows mitigations.
+    pub fn apply_to_restrictions(&self, mut r: Restrictions) -> Restrictions {
+        r.linux.tcp_connect_ports = self.network.tcp_connect.clone();
+        r.linux.tcp_bind_ports = self.network.tcp_bind.clone();
+        r.linux.max_open_files = self.process.max_open_files;
+        r.linux.secomp_kill = self.process.kill_on_violation;
+        if let AppContainerMode::Enabled(app_container) = &mut r.windows.app_container {
+            let wanted = [
+                (
+                    !self.network.tcp_connect.is_empty(),
+                    AppContainerCapability::InternetClient,
+                ),
+                (
+                    !self.network.tcp_bind.is_empty(),
+                    AppContainerCapability::InternetClientServer,
+                ),
+            ];
+            for (needed, capability) in wanted {
+                if needed && !app_container.capabilities.contains(&capability) {
+                    app_container.capabilities.push(capability);
+                }
+            }
+        }
+        r.cpu_placement = self.process.cpu_placement.clone();
+        r.resource_limits = self.resource_limits.clone();
+        r
+    }
+
+    /// Set the launch to enforce the policy: the paths are added to the ones the
+    /// `LaunchEnv` already grants, and the restrictions are set as by
+    /// `apply_to_restrictions`.
+    pub fn apply(&self, mut env: LaunchEnv) -> LaunchEnv {
+        env.readable_paths
+            .extend(self.filesystem.read.iter().cloned());
+        env.writable_paths
+            .extend(self.filesystem.write.iter().cloned());
+        env.restrictions = self.apply_to_restrictions(env.restrictions);
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_grants_capabilities() {
+        let mut policy = SandboxPolicy::new();
+        policy.network.tcp_connect.push(443);
+        let r = policy.apply_to_restrictions(crate::compat_restrictions!("test"));
+        assert_eq!(r.linux.tcp_connect_ports, vec![443]);
+        assert!(r.linux.tcp_bind_ports.is_empty());
+        match r.windows.app_container {
+            AppContainerMode::Enabled(app_container) => assert_eq!(
+                app_container.capabilities,
+                vec![AppContainerCapability::InternetClient]
+            ),
+            AppContainerMode::Disabled => panic!("the AppContainer was disabled"),
+        }
+    }
+
+    #[test]
+    fn presets() {
+        let strict =
+            SandboxPolicy::strict().apply_to_restrictions(crate::compat_restrictions!("test"));
+        assert!(strict.linux.secomp_kill);
+        assert_eq!(strict.linux.max_open_files, 64);
+        assert_eq!(
+            SandboxPolicy::read_data(["/srv/data"]).filesystem.read,
+            vec![PathBuf::from("/srv/data")]
+        );
+        assert_eq!(
+            SandboxPolicy::net_client([80, 443]).network.tcp_connect,
+            vec![80, 443]
+        );
+    }
+
+    #[test]
+    fn default_policy_keeps_defaults() {
+        let r = crate::compat_restrictions!("test");
+        assert_eq!(SandboxPolicy::new().apply_to_restrictions(r.clone()), r);
+    }
+}
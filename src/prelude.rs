@@ -0,0 +1,24 @@
+// SPDX-License-Identifier: MIT
+
+//! The commonly used types, for glob importing.
+//!
+//! ```
+//! use gracklezero::prelude::*;
+//! ```
+//!
+//! Items stay in the prelude across minor versions.  New entry points are added here as they
+//! land, so code that imports the prelude picks them up without chasing module moves.
+
+pub use crate::policy::SandboxPolicy;
+pub use crate::restrictions::{CpuPlacement, ResourceLimits, Restrictions};
+pub use crate::runtime::{
+    Child, ChildReader, ChildWriter, CommHandler, DependencySearch, EnvMap, ExecResolution,
+    ExitStatus, FdMode, FdSet, FdTee, HandleOnce, LaunchBuilder, LaunchEnv, LifecycleObserver,
+    OnceCommHandler, OutputLimit, ResourceUsage, RunResult, SandboxDenial, SandboxTemplate,
+    error::SandboxError, sandbox_child,
+};
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use crate::runtime::{
+    CollectingHandler, GrackleCommand, LineHandler, Output, OwnedChild, PassthroughHandler, run,
+};
+pub use crate::{compat_restrictions, strict_restrictions};
@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: MIT
+
+//! Sandbox configurations kept in TOML or JSON files, so they can be reviewed like code
+//! and shared between programs.
+//!
+//! A profile names the command, its arguments and environment, the standard restrictions
+//! to start from, and the `SandboxPolicy` to apply on top.  Every field except `cmd` and
+//! `name` may be left out.
+//!
+//! ```toml
+//! cmd = "/usr/bin/fetcher"
+//! args = ["--quiet"]
+//! name = "fetcher"
+//! restrictions = "strict"
+//! pass_env = ["LANG", "TZ"]
+//!
+//! [env]
+//! FETCH_MODE = "batch"
+//!
+//! [policy.filesystem]
+//! read = ["/srv/data"]
+//! write = ["/srv/data/out"]
+//!
+//! [policy.network]
+//! tcp_connect = [443]
+//!
+//! [policy.resource_limits]
+//! max_memory = 268435456
+//! ```
+//!
+//! Load it with `LaunchEnv::from_profile`, or with `Profile::load` to adjust it before
+//! launching.  A file with only the `policy` table's contents, such as `[filesystem]`
+//! and `[network]`, loads as a `SandboxPolicy` with `load_policy`.  Requires the
+//! `profile` feature.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    policy::SandboxPolicy,
+    restrictions::{create_compat_restrictions, create_strict_restrictions},
+    runtime::{LaunchEnv, error::SandboxError},
+};
+
+/// A launch as kept in a profile file.  See the module documentation for the format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// The command to run, resolved as `LaunchEnv::cmd` is.
+    pub cmd: PathBuf,
+
+    /// The arguments passed to the command.
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// The application name, used for the Windows AppContainer profile.
+    pub name: String,
+
+    /// The standard restrictions the policy applies to.
+    /// Defaults to `compat`.
+    #[serde(default)]
+    pub restrictions: BaseRestrictions,
+
+    /// The directory the child runs in.
+    /// Defaults to the parent's current directory.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+
+    /// Allow the child to read everything beneath `cwd`.
+    #[serde(default)]
+    pub grant_cwd_read: bool,
+
+    /// The environment variables to set in the child.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// The parent's environment variables to copy into the child, when the parent sets them.
+    #[serde(default)]
+    pub pass_env: Vec<String>,
+
+    /// Shared libraries the child loads with `dlopen`.  See `LaunchEnv::extra_libraries`.
+    #[serde(default)]
+    pub extra_libraries: Vec<PathBuf>,
+
+    /// What the child may do.
+    #[serde(default)]
+    pub policy: SandboxPolicy,
+
+    /// Launch even when the parent runs privileged.  See
+    /// `LaunchEnv::allow_privileged_parent`.
+    #[serde(default)]
+    pub allow_privileged_parent: bool,
+}
+
+/// Which of the standard restrictions a profile starts from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BaseRestrictions {
+    /// `create_compat_restrictions`, which stays compatible across library upgrades.
+    #[default]
+    Compat,
+    /// `create_strict_restrictions`, which picks up new restrictions as the library adds
+    /// them.
+    Strict,
+}
+
+impl Profile {
+    /// Read the profile from the file.  Files ending in `.json` are read as JSON, and
+    /// everything else as TOML.
+    pub fn load(path: &Path) -> Result<Profile, SandboxError> {
+        load(path)
+    }
+
+    /// Parse the profile from TOML text.
+    pub fn from_toml(text: &str) -> Result<Profile, SandboxError> {
+        from_toml(text).map_err(SandboxError::InvalidProfile)
+    }
+
+    /// Parse the profile from JSON text.
+    pub fn from_json(text: &str) -> Result<Profile, SandboxError> {
+        from_json(text).map_err(SandboxError::InvalidProfile)
+    }
+
+    /// The launch the profile describes, with the policy applied.
+    pub fn launch_env(&self) -> LaunchEnv {
+        let restrictions = match self.restrictions {
+            BaseRestrictions::Compat => create_compat_restrictions(&self.name),
+            BaseRestrictions::Strict => create_strict_restrictions(&self.name),
+        };
+        let mut env = LaunchEnv {
+            args: self.args.iter().map(OsString::from).collect(),
+            grant_cwd_read: self.grant_cwd_read,
+            extra_libraries: self.extra_libraries.clone(),
+            allow_privileged_parent: self.allow_privileged_parent,
+            ..LaunchEnv::new(self.cmd.clone(), restrictions)
+        };
+        if let Some(cwd) = &self.cwd {
+            env.cwd = cwd.clone();
+        }
+        env.env
+            .extend(self.env.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        for name in &self.pass_env {
+            env.env.insert_from_current(name);
+        }
+        env.with_policy(&self.policy)
+    }
+}
+
+/// Read a `SandboxPolicy` from the file, as TOML, or as JSON for files ending in `.json`.
+pub fn load_policy(path: &Path) -> Result<SandboxPolicy, SandboxError> {
+    load(path)
+}
+
+fn load<T: DeserializeOwned>(path: &Path) -> Result<T, SandboxError> {
+    let text = std::fs::read_to_string(path)?;
+    let is_json = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    if is_json {
+        from_json(&text)
+    } else {
+        from_toml(&text)
+    }
+    .map_err(|e| SandboxError::InvalidProfile(format!("{}: {}", path.display(), e)))
+}
+
+fn from_toml<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    toml::from_str(text).map_err(|e| e.to_string())
+}
+
+fn from_json<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    serde_json::from_str(text).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimal_toml() {
+        let profile =
+            Profile::from_toml("cmd = \"/bin/true\"\nname = \"test\"\n").expect("parse failed");
+        assert_eq!(profile.restrictions, BaseRestrictions::Compat);
+        assert_eq!(profile.policy, SandboxPolicy::new());
+        assert_eq!(
+            profile.launch_env().restrictions,
+            crate::compat_restrictions!("test")
+        );
+    }
+
+    #[test]
+    fn unknown_field() {
+        let err = Profile::from_toml("cmd = \"/bin/true\"\nname = \"test\"\nargv = []\n")
+            .expect_err("parse passed");
+        assert!(matches!(err, SandboxError::InvalidProfile(_)), "{:?}", err);
+    }
+}
@@ -8,6 +8,75 @@
 pub struct Restrictions {
     pub linux: linux::LinuxRestrictions,
     pub windows: windows::WindowsRestrictions,
+
+    /// Which processors the child may run on.
+    /// Defaults to `Any`.
+    pub cpu_placement: CpuPlacement,
+
+    /// Memory and CPU usage limits for the child.
+    /// Defaults to no limits.
+    pub resource_limits: ResourceLimits,
+}
+
+/// Processor placement for the child process.
+/// Pinning sandboxed compute jobs away from latency-critical parent threads keeps them
+/// from thrashing the parent's caches on large hosts.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpuPlacement {
+    /// Let the operating system schedule the child on any processor.
+    Any,
+
+    /// Pin the child to the listed logical processor indices.
+    /// On Windows, indices count across all processor groups in group order.
+    Cpus(Vec<usize>),
+
+    /// Pin the child to the processors that belong to the NUMA node.
+    NumaNode(usize),
+}
+
+impl CpuPlacement {
+    /// The placement as the launch report records it, or None for `Any`.
+    pub(crate) fn pinned(&self) -> Option<CpuPlacement> {
+        match self {
+            CpuPlacement::Any => None,
+            placement => Some(placement.clone()),
+        }
+    }
+}
+
+/// Pin the child process to the given processor placement.
+pub fn with_cpu_placement(mut r: Restrictions, placement: CpuPlacement) -> Restrictions {
+    r.cpu_placement = placement;
+    r
+}
+
+/// Memory and CPU usage limits for the child process.
+/// Linux enforces these with rlimits on the child; Windows enforces them on the job object
+/// that holds the child.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ResourceLimits {
+    /// Maximum memory the child may commit, in bytes.
+    /// On Linux, this limits the address space size, which also counts reserved memory.
+    pub max_memory: Option<u64>,
+
+    /// Maximum CPU time the child may consume before the OS terminates it.  What counts
+    /// differs by platform: on Linux, `RLIMIT_CPU` counts user and system time together,
+    /// and rounds this up to whole seconds; on Windows, the job's limit counts only
+    /// user-mode time.
+    pub max_cpu_time: Option<std::time::Duration>,
+
+    /// Maximum share of the total processor time, as a percentage from 1 to 100.
+    /// Only enforced on Windows; Linux needs cgroups for rate control.
+    pub cpu_rate_percent: Option<u32>,
+}
+
+/// Limit the memory and CPU usage of the child process.
+pub fn with_resource_limits(mut r: Restrictions, limits: ResourceLimits) -> Restrictions {
+    r.resource_limits = limits;
+    r
 }
 
 /// Create the default restrictions, compatible across upgrades.
@@ -19,6 +88,8 @@ pub fn create_compat_restrictions(application_name: &String) -> Restrictions {
     Restrictions {
         linux: linux::compatible_linux_restrictions(),
         windows: windows::compatible_windows_restrictions(application_name),
+        cpu_placement: CpuPlacement::Any,
+        resource_limits: ResourceLimits::default(),
     }
 }
 
@@ -30,6 +101,8 @@ pub fn create_strict_restrictions(application_name: &String) -> Restrictions {
     Restrictions {
         linux: linux::strict_linux_restrictions(),
         windows: windows::strict_windows_restrictions(application_name),
+        cpu_placement: CpuPlacement::Any,
+        resource_limits: ResourceLimits::default(),
     }
 }
 
@@ -58,7 +131,7 @@ mod tests {
             windows::defer_aslr_policy_forced,
         );
         assert_eq!(r.linux.max_open_files, 4096);
-        assert_eq!(r.linux.secomp_kill, true);
+        assert!(r.linux.secomp_kill);
         let app_container = match r.windows.app_container {
             windows::AppContainerMode::Enabled(a) => a,
             windows::AppContainerMode::Disabled => {
@@ -78,10 +151,7 @@ mod tests {
     fn test_compat_restrictions() {
         let r = compat_restrictions!(
             "test_app",
-            (
-                linux::with_max_open_files,
-                300,
-            ),
+            (linux::with_max_open_files, 300,),
             windows::disable_app_container,
             windows::disable_data_execution_prevention,
             |r| {
@@ -96,10 +166,145 @@ mod tests {
             windows::AppContainerMode::Disabled => (),
         }
         assert_eq!(r.linux.max_open_files, 300);
-        assert_eq!(r.linux.secomp_kill, false);
+        assert!(!r.linux.secomp_kill);
+        assert_eq!(r.cpu_placement, super::CpuPlacement::Any);
     }
-}
 
+    #[test]
+    fn test_image_load_policy() {
+        let r = strict_restrictions!(
+            "test_app",
+            windows::allow_remote_image_load,
+            windows::prevent_low_label_image_load,
+            windows::defer_image_load_prefer_system32,
+            (
+                windows::with_binary_signature,
+                windows::BinarySignaturePolicy::AllowOnlyMicrosoft,
+            ),
+        );
+        assert_eq!(
+            r.windows.image_load_policy.no_remote,
+            windows::AlwaysMode::AlwaysOff
+        );
+        assert_eq!(
+            r.windows.image_load_policy.no_low_label,
+            windows::AlwaysMode::AlwaysOn
+        );
+        assert_eq!(
+            r.windows.image_load_policy.prefer_system32,
+            windows::AlwaysMode::Defer
+        );
+        assert_eq!(
+            r.windows.binary_signature,
+            windows::BinarySignaturePolicy::AllowOnlyMicrosoft
+        );
+    }
+
+    #[test]
+    fn test_win32k_lockdown() {
+        let r = compat_restrictions!("test_app", windows::prevent_win32k_system_calls);
+        assert_eq!(
+            r.windows.disable_win32k_system_calls,
+            windows::AlwaysMode::AlwaysOn
+        );
+        let r = compat_restrictions!("test_app", windows::allow_win32k_system_calls);
+        assert_eq!(
+            r.windows.disable_win32k_system_calls,
+            windows::AlwaysMode::AlwaysOff
+        );
+    }
+
+    #[test]
+    fn test_audit_denials() {
+        let r = compat_restrictions!("test_app");
+        assert!(!r.windows.audit_denials);
+        let r = compat_restrictions!("test_app", windows::audit_denials);
+        assert!(r.windows.audit_denials);
+    }
+
+    #[test]
+    fn test_existing_desktop() {
+        let r = compat_restrictions!(
+            "test_app",
+            (
+                windows::with_existing_desktop,
+                "WinSta1\\Sandbox".to_string(),
+            ),
+        );
+        assert_eq!(
+            r.windows.desktop_isolate,
+            windows::DesktopIsolateMode::Existing("WinSta1\\Sandbox".to_string())
+        );
+    }
+
+    #[test]
+    fn test_app_container_reuse() {
+        let r = compat_restrictions!(
+            "test_app",
+            windows::force_new_app_container,
+            windows::keep_app_container_profile,
+        );
+        match &r.windows.app_container {
+            windows::AppContainerMode::Enabled(a) => {
+                assert!(!a.reuse_existing);
+                assert!(!a.delete_on_exit);
+            }
+            windows::AppContainerMode::Disabled => panic!("app container disabled"),
+        }
+        let r = compat_restrictions!(
+            "test_app",
+            windows::force_new_app_container,
+            windows::reuse_app_container,
+        );
+        match &r.windows.app_container {
+            windows::AppContainerMode::Enabled(a) => assert!(a.reuse_existing),
+            windows::AppContainerMode::Disabled => panic!("app container disabled"),
+        }
+    }
+
+    #[test]
+    fn test_app_container_metadata() {
+        let r = strict_restrictions!(
+            "test_app",
+            (windows::with_app_container_name, "other_app".to_string()),
+            (
+                windows::with_app_container_metadata,
+                "Other App".to_string(),
+                "Sandbox for the other app".to_string(),
+            ),
+        );
+        match &r.windows.app_container {
+            windows::AppContainerMode::Enabled(a) => {
+                assert_eq!(a.name, "other_app");
+                assert_eq!(a.display_name.as_deref(), Some("Other App"));
+                assert_eq!(a.description.as_deref(), Some("Sandbox for the other app"));
+            }
+            windows::AppContainerMode::Disabled => panic!("app container disabled"),
+        }
+    }
+
+    #[test]
+    fn test_cpu_placement() {
+        let r = compat_restrictions!(
+            "test_app",
+            (super::with_cpu_placement, super::CpuPlacement::NumaNode(1)),
+        );
+        assert_eq!(r.cpu_placement, super::CpuPlacement::NumaNode(1));
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let limits = super::ResourceLimits {
+            max_memory: Some(64 * 1024 * 1024),
+            max_cpu_time: Some(std::time::Duration::from_secs(10)),
+            cpu_rate_percent: Some(25),
+        };
+        let r = strict_restrictions!("test_app", (super::with_resource_limits, limits.clone()),);
+        assert_eq!(r.resource_limits, limits);
+        let r = compat_restrictions!("test_app");
+        assert_eq!(r.resource_limits, super::ResourceLimits::default());
+    }
+}
 
 pub mod linux {
     pub fn compatible_linux_restrictions() -> LinuxRestrictions {
@@ -107,6 +312,8 @@ pub mod linux {
             max_open_files: 2048,
             secomp_kill: false,
             dev_null_accessible: true,
+            tcp_connect_ports: Vec::new(),
+            tcp_bind_ports: Vec::new(),
         }
     }
 
@@ -115,6 +322,8 @@ pub mod linux {
             max_open_files: 2048,
             secomp_kill: false,
             dev_null_accessible: true,
+            tcp_connect_ports: Vec::new(),
+            tcp_bind_ports: Vec::new(),
         }
     }
 
@@ -134,6 +343,18 @@ pub mod linux {
         /// from triggering a SIGSEGV.  In order to prevent this from happening, the Linux
         /// runtime will grant /dev/null read and write access to the process.
         pub dev_null_accessible: bool,
+
+        /// TCP ports the child may connect to.  When this or `tcp_bind_ports` lists any
+        /// ports, the seccomp filter allows IPv4 and IPv6 TCP sockets, and Landlock limits
+        /// them to the listed ports.  Kernels before Landlock ABI 4 can't limit the ports,
+        /// so the child may then use any TCP port.  Other sockets, such as UDP, stay
+        /// blocked, so the child can't look up host names.
+        /// Defaults to none.
+        pub tcp_connect_ports: Vec<u16>,
+
+        /// TCP ports the child may listen on.  See `tcp_connect_ports`.
+        /// Defaults to none.
+        pub tcp_bind_ports: Vec<u16>,
     }
 
     /// Create a default AppContainer restriction structure.
@@ -170,8 +391,8 @@ pub mod windows {
             disable_win32k_system_calls: AlwaysMode::Defer, // verified 'Defer' as correct
             disable_extension_points: AlwaysMode::AlwaysOn,
             control_flow_guard: ControlFlowGuardPolicy::Defer, // verified 'Defer' as correct
-            dynamic_code: DynamicCodePolicy::AllowOptOut, // verified 'AllowOptOut' as correct
-            binary_signature: BinarySignaturePolicy::Defer, // verified 'Defer' as correct
+            dynamic_code: DynamicCodePolicy::AllowOptOut,      // verified 'AllowOptOut' as correct
+            binary_signature: BinarySignaturePolicy::Defer,    // verified 'Defer' as correct
             font_loading_policy: FontLoadingPolicy::AlwaysPrevent,
             image_load_policy: ExecutableImageLoadPolicy {
                 no_remote: AlwaysMode::AlwaysOn,
@@ -185,6 +406,7 @@ pub mod windows {
             restrict_indirect_branch_prediction: true,
             disable_speculative_store_bypass: true,
             disable_fsctl_system_call: AlwaysMode::AlwaysOn,
+            audit_denials: false,
         }
     }
 
@@ -203,8 +425,8 @@ pub mod windows {
             disable_win32k_system_calls: AlwaysMode::Defer, // verified 'Defer' as correct
             disable_extension_points: AlwaysMode::AlwaysOn,
             control_flow_guard: ControlFlowGuardPolicy::Defer, // verified 'Defer' as correct
-            dynamic_code: DynamicCodePolicy::AllowOptOut, // verified 'AllowOptOut' as correct
-            binary_signature: BinarySignaturePolicy::Defer, // verified 'Defer' as correct
+            dynamic_code: DynamicCodePolicy::AllowOptOut,      // verified 'AllowOptOut' as correct
+            binary_signature: BinarySignaturePolicy::Defer,    // verified 'Defer' as correct
             font_loading_policy: FontLoadingPolicy::AlwaysPrevent,
             image_load_policy: ExecutableImageLoadPolicy {
                 no_remote: AlwaysMode::AlwaysOn,
@@ -218,6 +440,7 @@ pub mod windows {
             restrict_indirect_branch_prediction: true,
             disable_speculative_store_bypass: true,
             disable_fsctl_system_call: AlwaysMode::AlwaysOn,
+            audit_denials: false,
         }
     }
 
@@ -344,10 +567,17 @@ pub mod windows {
         /// Ref: PROCESS_CREATION_MITIGATION_POLICY2_FSCTL_SYSTEM_CALL_DISABLE_ALWAYS_ON
         /// Ref: PROCESS_CREATION_MITIGATION_POLICY2_FSCTL_SYSTEM_CALL_DISABLE_ALWAYS_OFF
         pub disable_fsctl_system_call: AlwaysMode,
+
+        /// Report the object and network accesses the sandbox denied the child, through
+        /// `Child::denials`.  Requires failure auditing in the host's audit policy, and
+        /// rights to read the Security event log; the launch fails without the rights.
+        /// Defaults to `false`.
+        pub audit_denials: bool,
     }
 
     /// Windows AppContainer capabilities.
     #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
     pub enum AppContainerCapability {
         /// Access the user's webcam.
         Webcam,
@@ -369,6 +599,14 @@ pub mod windows {
         /// The name of the AppContainer to create.  This must be unique across the system, and should be sufficiently random to avoid collisions with other AppContainers.
         pub name: String,
 
+        /// The name shown for the AppContainer profile, such as in the firewall settings.
+        /// Defaults to the `name`.
+        pub display_name: Option<String>,
+
+        /// The description of the AppContainer profile.
+        /// Defaults to the display name.
+        pub description: Option<String>,
+
         /// The capabilities to add to the AppContainer.  By default, no capabilities are added.
         pub capabilities: Vec<AppContainerCapability>,
 
@@ -383,6 +621,15 @@ pub mod windows {
         ///
         /// Defaults to true.
         pub reuse_existing: bool,
+
+        /// If true, a profile created just for one launch (`reuse_existing` is false) is deleted,
+        /// along with its folder, once the launched process is dropped.  Shared profiles are never
+        /// deleted automatically, as other launches may still use them.
+        ///
+        /// Profiles left behind by a crashed parent can be removed with `cleanup_stale_profiles`.
+        ///
+        /// Defaults to true.
+        pub delete_on_exit: bool,
     }
 
     /// Windows AppContainer restriction modes.
@@ -401,8 +648,11 @@ pub mod windows {
     pub fn default_app_container(application_name: &String) -> AppContainerMode {
         AppContainerMode::Enabled(AppContainer {
             name: application_name.clone(),
+            display_name: None,
+            description: None,
             capabilities: Vec::new(),
             reuse_existing: true,
+            delete_on_exit: true,
         })
     }
 
@@ -422,6 +672,50 @@ pub mod windows {
         r
     }
 
+    /// Keep the per-launch AppContainer profile after the process exits, such as for inspecting
+    /// its folder.
+    pub fn keep_app_container_profile(mut r: super::Restrictions) -> super::Restrictions {
+        if let AppContainerMode::Enabled(app_container) = &mut r.windows.app_container {
+            app_container.delete_on_exit = false;
+        }
+        r
+    }
+
+    /// Set the AppContainer profile name.  Applications sharing a machine should use distinct
+    /// names, so they don't share or delete each other's profiles.
+    pub fn with_app_container_name(
+        mut r: super::Restrictions,
+        name: String,
+    ) -> super::Restrictions {
+        if let AppContainerMode::Enabled(app_container) = &mut r.windows.app_container {
+            app_container.name = name;
+        }
+        r
+    }
+
+    /// Set the display name and description shown for the AppContainer profile.
+    pub fn with_app_container_metadata(
+        mut r: super::Restrictions,
+        display_name: String,
+        description: String,
+    ) -> super::Restrictions {
+        if let AppContainerMode::Enabled(app_container) = &mut r.windows.app_container {
+            app_container.display_name = Some(display_name);
+            app_container.description = Some(description);
+        }
+        r
+    }
+
+    /// Share one AppContainer profile, named after the application, across all launches.
+    /// After the first launch creates it, later launches derive the SID from the name instead
+    /// of creating a profile.
+    pub fn reuse_app_container(mut r: super::Restrictions) -> super::Restrictions {
+        if let AppContainerMode::Enabled(app_container) = &mut r.windows.app_container {
+            app_container.reuse_existing = true;
+        }
+        r
+    }
+
     /// Force a new AppContainer creation if one with the given name already exists.
     pub fn force_new_app_container(mut r: super::Restrictions) -> super::Restrictions {
         if let AppContainerMode::Enabled(app_container) = &mut r.windows.app_container {
@@ -445,6 +739,11 @@ pub mod windows {
 
         /// Do not create a new desktop isolate for this to run in.
         Disabled,
+
+        /// Run on an existing desktop, named as `station\desktop`, that the caller created.
+        /// The caller is responsible for granting the child's AppContainer access to it,
+        /// and for keeping it open while the child runs.
+        Existing(String),
     }
 
     /// Require that the execution environment constructs a desktop isolate for Windows,
@@ -460,6 +759,19 @@ pub mod windows {
         r
     }
 
+    /// Report the accesses the sandbox denies the child, through `Child::denials`.
+    pub fn audit_denials(mut r: super::Restrictions) -> super::Restrictions {
+        r.windows.audit_denials = true;
+        r
+    }
+
+    /// Run the child on a desktop the caller already created, rather than a new isolate.
+    /// The name has the form `station\desktop`.
+    pub fn with_existing_desktop(mut r: super::Restrictions, name: String) -> super::Restrictions {
+        r.windows.desktop_isolate = DesktopIsolateMode::Existing(name);
+        r
+    }
+
     #[derive(Debug, Clone, PartialEq)]
     pub enum DataExecutionPreventionMode {
         /// Do not prevent code from being run from data pages such as the default heap, stacks, and memory pools.
@@ -594,7 +906,15 @@ pub mod windows {
         r
     }
 
+    /// Lock down the win32k system calls, for children that don't need a GUI.
+    /// The child's std handles must be pipes or null, not the parent's console.
     pub fn prevent_win32k_system_calls(mut r: super::Restrictions) -> super::Restrictions {
+        r.windows.disable_win32k_system_calls = AlwaysMode::AlwaysOn;
+        r
+    }
+
+    /// Allow the win32k system calls, even if the executable opts into the lockdown.
+    pub fn allow_win32k_system_calls(mut r: super::Restrictions) -> super::Restrictions {
         r.windows.disable_win32k_system_calls = AlwaysMode::AlwaysOff;
         r
     }
@@ -769,7 +1089,23 @@ pub mod windows {
         AllowStore,
     }
 
-    // TODO add remaining Restrictions setting functions.
+    /// Set which signers the binaries the child loads must be signed by.
+    pub fn with_binary_signature(
+        mut r: super::Restrictions,
+        policy: BinarySignaturePolicy,
+    ) -> super::Restrictions {
+        r.windows.binary_signature = policy;
+        r
+    }
+
+    /// Set whether the child may load non-system fonts.
+    pub fn with_font_loading(
+        mut r: super::Restrictions,
+        policy: FontLoadingPolicy,
+    ) -> super::Restrictions {
+        r.windows.font_loading_policy = policy;
+        r
+    }
 
     /// The font loading prevention policy for the process determines whether non-system fonts can be
     /// loaded for a process.
@@ -819,6 +1155,33 @@ pub mod windows {
         pub prefer_system32: AlwaysMode,
     }
 
+    /// Set where the child may load executable images from.
+    pub fn with_image_load_policy(
+        mut r: super::Restrictions,
+        policy: ExecutableImageLoadPolicy,
+    ) -> super::Restrictions {
+        r.windows.image_load_policy = policy;
+        r
+    }
+
+    /// Allow loading images from remote devices, such as UNC shares.
+    pub fn allow_remote_image_load(mut r: super::Restrictions) -> super::Restrictions {
+        r.windows.image_load_policy.no_remote = AlwaysMode::AlwaysOff;
+        r
+    }
+
+    /// Prevent loading images marked with the "low mandatory" integrity label.
+    pub fn prevent_low_label_image_load(mut r: super::Restrictions) -> super::Restrictions {
+        r.windows.image_load_policy.no_low_label = AlwaysMode::AlwaysOn;
+        r
+    }
+
+    /// Use the standard DLL search order, rather than preferring System32.
+    pub fn defer_image_load_prefer_system32(mut r: super::Restrictions) -> super::Restrictions {
+        r.windows.image_load_policy.prefer_system32 = AlwaysMode::Defer;
+        r
+    }
+
     /// Hardware-enforced Stack Protection (HSP) is a hardware-based security feature where the
     /// CPU verifies function return addresses at runtime by employing a shadow stack mechanism.
     #[derive(Debug, Clone, PartialEq)]
@@ -8,8 +8,49 @@
 //! the command to run, its arguments, environment variables, working directory,
 //! and file descriptor mappings.  It takes a `CommHandler` that manages
 //! the communication with the child process.  When the `CommHandler` instance
-//! exits execution, the child process is terminated if it is still running, and its
-//! exit code is returned.
+//! exits execution, the child process is terminated if it is still running, and the
+//! handler's `Output` is returned along with the child's `ExitStatus`.  See
+//! `ExitStatus` for how each OS's exit codes, signals, and crash codes map onto it.
+//! Unless `LaunchEnv::kill_on_drop` is turned off, the child is also terminated as soon
+//! as the handler drops it, so a handler that panics doesn't leave the process running.
+//!
+//! For the common case of running the child to its end and keeping what it printed, `run`
+//! does this with a built-in handler, and returns the child's STDOUT and STDERR in an
+//! `Output`.  The built-in handlers are also public: `PassthroughHandler` wires the child's
+//! standard streams to the parent's, `LineHandler` calls a closure for each line it prints,
+//! and `CollectingHandler` buffers everything.  With the `log` feature, `StderrLogHandler`
+//! logs each line the child writes to STDERR.  Wrap any handler with `with_timeout` to
+//! give it a deadline, or with `map_output` to post-process what it returns.
+//!
+//! When the handler model doesn't fit, `spawn` launches the child the same way and
+//! returns an `OwnedChild`.  The caller takes its streams and waits on it directly, then
+//! calls `OwnedChild::finish` for the `ExitStatus`.  With the `tokio` feature,
+//! `asynch::sandbox_child_async` does the same with async streams and an exit future, and
+//! with the `futures-io` feature, `futures_io::sandbox_child_async` does it for runtimes
+//! built on `futures::io`, such as smol.  `pipeline` launches several children with each
+//! one's STDOUT connected straight to the next one's STDIN, like a shell pipeline.
+//! `GrackleCommand` wraps `sandbox_child` and `spawn` behind the `std::process::Command`
+//! calls, for code moving over from the standard library.
+//!
+//! Set `LaunchEnv::observer` to a `LifecycleObserver` to follow each phase of the launch,
+//! from resolving the executable to the child's exit, such as for telemetry.
+//!
+//! To review what the sandbox would enforce, `plan` resolves the executable and its
+//! dependencies as a launch would, and returns the rules in a `SandboxPlan`, without
+//! launching anything.  `probe` reports what the host itself supports, such as the
+//! Landlock ABI version, in a `HostCapabilities`.  `deps::dependencies` lists the shared
+//! libraries a launch loads, and which of them are missing.
+//!
+//! `LaunchBuilder` builds a `LaunchEnv` one setting at a time.  Its `plan` keeps the
+//! `SandboxPlan` with the launch, in a `PlannedLaunch`, which then spawns the reviewed
+//! launch.
+//!
+//! `LaunchEnv::with_policy` sets the readable and writable paths and the restrictions from
+//! a `SandboxPolicy`, which states the child's permissions once for every platform.
+//!
+//! The child only sees the environment variables in the `env` field, and never inherits
+//! the parent's environment.  Use `LaunchEnv::pass_env` and `LaunchEnv::pass_env_matching`
+//! to copy selected parent variables, such as `LANG` or `TZ`.
 //!
 //! ## OS specific notes
 //!
@@ -17,6 +58,9 @@
 //!
 //! #### Environment Variables
 //!
+//! Names in the `env` field match case-insensitively on Windows; see `EnvMap` for how
+//! duplicate names are merged.
+//!
 //! On Windows, the operating system requires that the `SystemRoot` environment variable
 //! be passed to the child process.  If the caller does not include it in the `env` field of
 //! `LaunchEnv`, then the `sandbox_child` function will automatically add it with the value
@@ -33,44 +77,137 @@
 //!
 //! There may be additional needs, depending on the executable being launched.
 
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+pub mod asynch;
+mod builder;
+mod combinators;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod command;
+mod dependency_cache;
+pub mod deps;
+mod environ;
 pub mod error;
+#[cfg(all(feature = "futures-io", target_os = "linux"))]
+pub mod futures_io;
+#[cfg(target_os = "linux")]
+mod group;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod handlers;
+mod lifecycle;
+mod limit;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod owned;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod pipeline;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod plan;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod probe;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub mod registry;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod run;
+#[cfg(target_os = "linux")]
+mod select;
 pub mod spawn;
+mod stream;
+mod tee;
+mod template;
+pub mod terminal;
+mod verify;
+mod wildcard;
 
-pub use spawn::{Child, CommHandler, ExitCode, FdMode, FdSet, LaunchEnv};
+pub use builder::LaunchBuilder;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use builder::PlannedLaunch;
+pub use combinators::{MapOutput, WithTimeout, map_output, with_timeout};
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use command::{GrackleCommand, Stdio};
+pub use dependency_cache::DependencyCache;
+pub use environ::EnvMap;
+#[cfg(target_os = "linux")]
+pub use group::{ChildGroup, ChildId, GroupEvent};
+#[cfg(all(feature = "log", any(target_os = "linux", target_os = "windows")))]
+pub use handlers::StderrLogHandler;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use handlers::{CollectingHandler, LineHandler, PassthroughHandler};
+pub use lifecycle::LifecycleObserver;
+pub use limit::{LimitAction, OutputLimit};
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use owned::OwnedChild;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use pipeline::{Pipeline, pipeline};
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use plan::{SandboxPlan, plan};
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use probe::{HostCapabilities, probe};
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub use run::{Output, run};
+#[cfg(target_os = "linux")]
+pub use select::{Selected, StreamSelector};
+pub use spawn::{
+    Child, ChildKiller, CommHandler, DependencySearch, ExecResolution, ExitStatus, FdMode, FdSet,
+    HandleOnce, LaunchEnv, LaunchReport, OnceCommHandler, ResourceUsage, RunResult, SandboxDenial,
+};
+pub use stream::{ChildReader, ChildWriter};
+pub use tee::FdTee;
+pub use template::SandboxTemplate;
+pub use terminal::{ResizeForwarder, Terminal};
+pub use verify::{HashAllowlist, sha256_file};
 
 #[cfg(target_os = "linux")]
 mod spawn_linux;
 
 #[cfg(target_os = "linux")]
+pub use spawn_linux::Zygote;
+
+/// Launch the child, and hand it to the handler.  Once the handler returns, the child is
+/// terminated if it's still running.
+///
+/// Fails when the child can't be launched, or its exit status can't be collected.  The
+/// handler's own failure, and how the child ended, are both in the `RunResult`.
+#[cfg(any(target_os = "linux", target_os = "windows"))]
 pub fn sandbox_child<CH: CommHandler>(
     env: LaunchEnv,
     handler: CH,
-) -> Result<ExitCode, error::SandboxError> {
-    let child = spawn_linux::launch_child(env)?;
-    let state = child.state();
-    let err = handler.handle(Box::new(child));
-    let ret = state.kill().map_err(|e| e.into());
-    err?;
-    ret
+) -> Result<RunResult<CH::Output, CH::Error>, error::SandboxError> {
+    run_child(spawn(env)?, handler)
+}
+
+/// Launch the child, and hand it to the caller to take its streams and wait on it
+/// directly.  This is `sandbox_child` without the `CommHandler`.
+#[cfg(target_os = "linux")]
+pub fn spawn(env: LaunchEnv) -> Result<OwnedChild, error::SandboxError> {
+    Ok(OwnedChild::new(spawn_linux::launch_child(env)?))
+}
+
+/// Launch the child, and hand it to the caller to take its streams and wait on it
+/// directly.  This is `sandbox_child` without the `CommHandler`.
+#[cfg(target_os = "windows")]
+pub fn spawn(env: LaunchEnv) -> Result<OwnedChild, error::SandboxError> {
+    Ok(OwnedChild::new(spawn_windows::launch_child(env)?))
+}
+
+/// Hand the launched child to the handler, then make sure it is stopped.
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn run_child<CH: CommHandler>(
+    child: OwnedChild,
+    mut handler: CH,
+) -> Result<RunResult<CH::Output, CH::Error>, error::SandboxError> {
+    let ender = child.ender();
+    let output = handler.handle(Box::new(child));
+    // Force termination if the handler didn't, and instead quit with an error.
+    Ok(RunResult {
+        handler: output,
+        exit: ender.finish()?,
+    })
 }
 
 #[cfg(target_os = "windows")]
 mod spawn_windows;
 
 #[cfg(target_os = "windows")]
-pub fn sandbox_child<CH: CommHandler>(
-    env: LaunchEnv,
-    handler: CH,
-) -> Result<ExitCode, error::SandboxError> {
-    let child = spawn_windows::launch_child(env)?;
-    let state = child.state();
-    // dropping the child object will kill the child process and all the open handles.
-    let err = handler.handle(Box::new(child));
-    // force termination if the handler didn't and instead quit with an error.
-    let ret = state.exit_code();
-    err?;
-    Ok(ret?)
-}
+pub use spawn_windows::cleanup_stale_profiles;
 
 #[cfg(target_os = "macos")]
 mod spawn_darwin;
@@ -79,6 +216,6 @@ mod spawn_darwin;
 pub fn sandbox_child<CH: CommHandler>(
     _env: LaunchEnv,
     _handler: CH,
-) -> Result<ExitCode, error::SandboxError> {
+) -> Result<RunResult<CH::Output, CH::Error>, error::SandboxError> {
     todo!()
 }
@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: MIT
+
+//! Run sandboxed children on a Tokio runtime.
+//!
+//! `sandbox_child_async` launches the child the same way as `sandbox_child`, but hands
+//! back an `AsyncChild` instead of calling a `CommHandler`.  Its streams implement
+//! Tokio's `AsyncRead` and `AsyncWrite`, and `AsyncChild::wait` is a future that
+//! completes when the child exits, so one task can supervise many children without a
+//! thread per pipe.
+//!
+//! The streams and the exit future register with the runtime's reactor, so they must be
+//! taken and used inside a Tokio runtime with IO enabled.  Taking a stream puts it in
+//! non-blocking mode.  The streams still respect the `FdSet` output limits and log files.
+//!
+//! Only Linux is supported for now.
+
+use std::{
+    fs::File,
+    io::{Read as _, Write as _},
+    os::fd::OwnedFd,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf, unix::AsyncFd};
+
+use super::{
+    Child as _, ExitStatus, LaunchEnv, LaunchReport, ResourceUsage,
+    error::SandboxError,
+    spawn_linux::{LinuxChild, launch_child, set_nonblocking},
+    terminal::Terminal,
+};
+
+/// Launch the child, and hand it back with async streams and an exit future.
+///
+/// This must be called inside a Tokio runtime with IO enabled.
+pub fn sandbox_child_async(env: LaunchEnv) -> Result<AsyncChild, SandboxError> {
+    AsyncChild::new(launch_child(env)?)
+}
+
+/// A sandboxed child supervised from async code, as returned by `sandbox_child_async`.
+///
+/// Dropping it terminates the child, unless `LaunchEnv::kill_on_drop` is turned off.
+pub struct AsyncChild {
+    /// The child's pidfd, which becomes readable once it exits; None if the kernel doesn't
+    /// support pidfds.
+    exited: Option<AsyncFd<OwnedFd>>,
+    inner: LinuxChild,
+}
+
+impl AsyncChild {
+    fn new(inner: LinuxChild) -> Result<Self, SandboxError> {
+        let exited = match inner.pidfd() {
+            Some(pidfd) => Some(AsyncFd::new(pidfd.try_clone_to_owned()?)?),
+            None => None,
+        };
+        Ok(AsyncChild { exited, inner })
+    }
+
+    /// Take the parent's end of a stream the child writes to.
+    pub fn take_stream_from_child(&mut self, fd: u32) -> Option<AsyncChildReader> {
+        let (raw, inner) = self.inner.take_async_reader(fd)?;
+        match AsyncChildReader::new(raw, inner) {
+            Ok(reader) => Some(reader),
+            Err(e) => {
+                crate::logging::warning!("could not register fd {} with the runtime: {}", fd, e);
+                None
+            }
+        }
+    }
+
+    /// Take the parent's end of a stream the child reads from.
+    pub fn take_stream_to_child(&mut self, fd: u32) -> Option<AsyncChildWriter> {
+        let file = self.inner.take_async_writer(fd)?;
+        match AsyncChildWriter::new(file) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                crate::logging::warning!("could not register fd {} with the runtime: {}", fd, e);
+                None
+            }
+        }
+    }
+
+    /// Take the stream for the named channel that the child writes to.
+    pub fn take_channel_from_child(&mut self, name: &str) -> Option<AsyncChildReader> {
+        let fd = self.channel_fd(name)?;
+        self.take_stream_from_child(fd)
+    }
+
+    /// Take the stream for the named channel that the child reads from.
+    pub fn take_channel_to_child(&mut self, name: &str) -> Option<AsyncChildWriter> {
+        let fd = self.channel_fd(name)?;
+        self.take_stream_to_child(fd)
+    }
+
+    /// The FD the child sees for the named channel.
+    pub fn channel_fd(&self, name: &str) -> Option<u32> {
+        self.inner.channel_fd(name)
+    }
+
+    /// The child's process ID.
+    pub fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    /// Kill the child, if it's still running.
+    pub fn terminate(&self) -> Result<(), std::io::Error> {
+        self.inner.terminate()
+    }
+
+    /// The child's pseudo-terminal, if it was launched with one.
+    pub fn terminal(&self) -> Option<Terminal> {
+        self.inner.terminal()
+    }
+
+    /// The resources the child used, or None if it is still running.
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.inner.resource_usage()
+    }
+
+    /// How long each phase of the launch took.
+    pub fn launch_report(&self) -> Option<LaunchReport> {
+        self.inner.launch_report()
+    }
+
+    /// How the child ended, or None if it is still running.  This doesn't block.
+    pub fn try_status(&self) -> Result<Option<ExitStatus>, std::io::Error> {
+        self.inner.exit_status()
+    }
+
+    /// Wait for the child to exit, without blocking the runtime.
+    pub async fn wait(&self) -> Result<ExitStatus, std::io::Error> {
+        match &self.exited {
+            // The pidfd stays readable once the child exits, so the readiness is never cleared.
+            Some(exited) => drop(exited.readable().await?),
+            None => {
+                let state = self.inner.state();
+                tokio::task::spawn_blocking(move || state.wait(None))
+                    .await
+                    .map_err(std::io::Error::other)??;
+            }
+        }
+        self.try_status()?
+            .ok_or_else(|| std::io::Error::other("child exited, but could not be reaped"))
+    }
+
+    /// Terminate the child if it is still running, and get how it ended.
+    ///
+    /// To let the child end on its own, await `wait` first.
+    pub fn finish(self) -> Result<ExitStatus, SandboxError> {
+        let state = self.inner.state();
+        drop(self);
+        Ok(state.kill()?)
+    }
+}
+
+/// The parent's end of a stream the child writes to, read through the runtime.
+///
+/// Reads past the stream's output limit fail, as with the blocking streams.
+pub struct AsyncChildReader {
+    /// A duplicate of the stream's descriptor, registered with the runtime.  None once a
+    /// read failed, so a stream closed by its output limit isn't held open here.
+    ready: Option<AsyncFd<OwnedFd>>,
+    /// Reads the stream through its output limit and log file.
+    inner: Box<dyn std::io::Read + Send>,
+}
+
+impl AsyncChildReader {
+    fn new(raw: OwnedFd, inner: Box<dyn std::io::Read + Send>) -> Result<Self, SandboxError> {
+        set_nonblocking(&raw)?;
+        Ok(AsyncChildReader {
+            ready: Some(AsyncFd::new(raw)?),
+            inner,
+        })
+    }
+}
+
+impl AsyncRead for AsyncChildReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let Some(ready) = this.ready.as_ref() else {
+                // Reports the failure again, such as the exceeded limit.
+                let count = this.inner.read(buf.initialize_unfilled())?;
+                buf.advance(count);
+                return Poll::Ready(Ok(()));
+            };
+            let mut guard = ready!(ready.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            let result = match guard.try_io(|_| this.inner.read(unfilled)) {
+                Ok(result) => result,
+                // Not ready after all; the readiness was cleared, so wait again.
+                Err(_would_block) => continue,
+            };
+            match result {
+                Ok(count) => {
+                    buf.advance(count);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    this.ready = None;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// The parent's end of a stream the child reads from, written through the runtime.
+///
+/// Shutting it down closes the stream, so the child sees the end of its input.
+pub struct AsyncChildWriter {
+    /// None once shut down.
+    inner: Option<AsyncFd<File>>,
+}
+
+impl AsyncChildWriter {
+    fn new(file: File) -> Result<Self, SandboxError> {
+        set_nonblocking(&file)?;
+        Ok(AsyncChildWriter {
+            inner: Some(AsyncFd::new(file)?),
+        })
+    }
+}
+
+impl AsyncWrite for AsyncChildWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let Some(inner) = self.get_mut().inner.as_ref() else {
+            return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+        };
+        loop {
+            let mut guard = ready!(inner.poll_write_ready(cx))?;
+            if let Ok(result) = guard.try_io(|file| file.get_ref().write(buf)) {
+                return Poll::Ready(result);
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        // Writes go straight to the pipe.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().inner = None;
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,261 @@
+// SPDX-License-Identifier: MIT
+
+//! Build a `LaunchEnv` one setting at a time, then review and launch it.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+};
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use super::{CommHandler, OwnedChild, RunResult, SandboxPlan, error::SandboxError};
+use super::{DependencySearch, ExecResolution, FdSet, HashAllowlist, LaunchEnv, LifecycleObserver};
+use crate::policy::SandboxPolicy;
+
+/// Builds a `LaunchEnv`, starting from the defaults of `LaunchEnv::new`.
+///
+/// The builder is the other way to describe a launch, next to filling in the
+/// `LaunchEnv` fields with struct update syntax.  Either one can be turned into the
+/// other, so code can move over one launch at a time.  The launch then goes through
+/// `plan`, to review what the sandbox will enforce, before it's started.
+///
+/// ```no_run
+/// use gracklezero::{compat_restrictions, runtime::LaunchBuilder};
+///
+/// let planned = LaunchBuilder::new("/usr/bin/uname", compat_restrictions!("app"))
+///     .arg("-a")
+///     .pass_env(&["LANG"])
+///     .plan()
+///     .expect("plan failed");
+/// println!("may read {:?}", planned.plan().readable_paths);
+/// let child = planned.spawn().expect("spawn failed");
+/// ```
+#[derive(Clone)]
+pub struct LaunchBuilder {
+    env: LaunchEnv,
+}
+
+impl LaunchBuilder {
+    /// Start a launch of the command, with everything else at its default.
+    pub fn new<P: Into<PathBuf>>(cmd: P, restrictions: crate::Restrictions) -> Self {
+        LaunchBuilder {
+            env: LaunchEnv::new(cmd.into(), restrictions),
+        }
+    }
+
+    /// Append an argument.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.env.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Append each of the arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.env
+            .args
+            .extend(args.into_iter().map(|a| a.as_ref().to_os_string()));
+        self
+    }
+
+    /// Set an environment variable for the child.
+    pub fn env<K: Into<OsString>, V: Into<OsString>>(mut self, key: K, val: V) -> Self {
+        self.env.env.insert(key, val);
+        self
+    }
+
+    /// Pass the parent's value for each named variable to the child.  See
+    /// `LaunchEnv::pass_env`.
+    pub fn pass_env(mut self, names: &[&str]) -> Self {
+        self.env = self.env.pass_env(names);
+        self
+    }
+
+    /// Set the directory the child runs in.
+    pub fn cwd<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.env.cwd = dir.into();
+        self
+    }
+
+    /// Allow the child to read everything beneath its directory.  See
+    /// `LaunchEnv::grant_cwd_read`.
+    pub fn grant_cwd_read(mut self) -> Self {
+        self.env.grant_cwd_read = true;
+        self
+    }
+
+    /// Allow the child to read the file, or everything beneath the directory.
+    pub fn readable_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.env.readable_paths.push(path.into());
+        self
+    }
+
+    /// Allow the child to write the file, or everything beneath the directory.
+    pub fn writable_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.env.writable_paths.push(path.into());
+        self
+    }
+
+    /// Allow the child to load the shared library at runtime.  See
+    /// `LaunchEnv::extra_libraries`.
+    pub fn extra_library<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.env.extra_libraries.push(path.into());
+        self
+    }
+
+    /// Set the child's FDs.
+    pub fn fds(mut self, fds: FdSet) -> Self {
+        self.env.fds = fds;
+        self
+    }
+
+    /// Set the name the child sees as its first argument.
+    pub fn arg0<S: Into<OsString>>(mut self, arg0: S) -> Self {
+        self.env.arg0 = Some(arg0.into());
+        self
+    }
+
+    /// Set how the command is turned into the executable to run.
+    pub fn resolution(mut self, resolution: ExecResolution) -> Self {
+        self.env.resolution = resolution;
+        self
+    }
+
+    /// Set where to look for the executable's shared libraries.
+    pub fn dependency_search(mut self, search: DependencySearch) -> Self {
+        self.env.dependency_search = search;
+        self
+    }
+
+    /// Leave the child running when the parent drops its `Child`.
+    pub fn keep_running_on_drop(mut self) -> Self {
+        self.env.kill_on_drop = false;
+        self
+    }
+
+    /// Tell the observer about each phase of the child's launch and end.
+    pub fn observer<O: LifecycleObserver + 'static>(mut self, observer: O) -> Self {
+        self.env = self.env.with_observer(observer);
+        self
+    }
+
+    /// Refuse to launch unless the executable's digest is in the allowlist.
+    pub fn hash_allowlist(mut self, allowlist: HashAllowlist) -> Self {
+        self.env.hash_allowlist = Some(allowlist);
+        self
+    }
+
+    /// Launch even though the parent runs privileged.  See
+    /// `LaunchEnv::allow_privileged_parent`.
+    pub fn allow_privileged_parent(mut self) -> Self {
+        self.env.allow_privileged_parent = true;
+        self
+    }
+
+    /// Enforce the policy on the launch.  See `SandboxPolicy::apply`.
+    pub fn policy(mut self, policy: &SandboxPolicy) -> Self {
+        self.env = policy.apply(self.env);
+        self
+    }
+
+    /// The launch environment built so far.
+    pub fn build(self) -> LaunchEnv {
+        self.env
+    }
+
+    /// Work out the rules the sandbox will enforce, and keep them with the launch, so the
+    /// child started from it is the one that was reviewed.  See `runtime::plan`.
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    pub fn plan(self) -> Result<PlannedLaunch, SandboxError> {
+        Ok(PlannedLaunch {
+            plan: super::plan(&self.env)?,
+            env: self.env,
+        })
+    }
+}
+
+impl From<LaunchEnv> for LaunchBuilder {
+    fn from(env: LaunchEnv) -> Self {
+        LaunchBuilder { env }
+    }
+}
+
+impl From<LaunchBuilder> for LaunchEnv {
+    fn from(builder: LaunchBuilder) -> Self {
+        builder.env
+    }
+}
+
+/// A launch along with the rules the sandbox will enforce on it, as returned by
+/// `LaunchBuilder::plan`.
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+pub struct PlannedLaunch {
+    plan: SandboxPlan,
+    env: LaunchEnv,
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+impl PlannedLaunch {
+    /// The rules the sandbox will enforce on the child.
+    pub fn plan(&self) -> &SandboxPlan {
+        &self.plan
+    }
+
+    /// The launch environment the child starts from.
+    pub fn env(&self) -> &LaunchEnv {
+        &self.env
+    }
+
+    /// Launch the child, and hand it to the caller.  See `runtime::spawn`.
+    pub fn spawn(self) -> Result<OwnedChild, SandboxError> {
+        super::spawn(self.env)
+    }
+
+    /// Launch the child, and hand it to the handler.  See `sandbox_child`.
+    pub fn sandbox_child<CH: CommHandler>(
+        self,
+        handler: CH,
+    ) -> Result<RunResult<CH::Output, CH::Error>, SandboxError> {
+        super::sandbox_child(self.env, handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compat_restrictions;
+
+    #[test]
+    fn builds_launch_env() {
+        let env = LaunchBuilder::new("/bin/echo", compat_restrictions!("app"))
+            .arg("one")
+            .args(["two", "three"])
+            .env("KEY", "value")
+            .cwd("/tmp")
+            .readable_path("/etc/hosts")
+            .arg0("echo")
+            .keep_running_on_drop()
+            .build();
+        assert_eq!(env.cmd, PathBuf::from("/bin/echo"));
+        assert_eq!(env.args, ["one", "two", "three"]);
+        assert_eq!(env.env.get("KEY"), Some(OsStr::new("value")));
+        assert_eq!(env.cwd, PathBuf::from("/tmp"));
+        assert_eq!(env.readable_paths, [PathBuf::from("/etc/hosts")]);
+        assert_eq!(env.arg0, Some(OsString::from("echo")));
+        assert!(!env.kill_on_drop);
+        assert!(!env.allow_privileged_parent);
+    }
+
+    #[test]
+    fn round_trips_launch_env() {
+        let env = LaunchEnv {
+            args: vec![OsString::from("one")],
+            ..LaunchEnv::new("/bin/echo".into(), compat_restrictions!("app"))
+        };
+        let env = LaunchBuilder::from(env).arg("two").build();
+        assert_eq!(env.args, ["one", "two"]);
+    }
+}
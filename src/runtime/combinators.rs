@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: MIT
+
+//! Wrap any `CommHandler` to add behavior around it, without changing the handler.
+
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use super::{Child, CommHandler, error::SandboxError};
+use crate::logging;
+
+/// Terminate the child if the handler is still running once the timeout passes, and fail
+/// with `std::io::ErrorKind::TimedOut`.
+///
+/// A watchdog thread kills the child through `Child::killer`, which ends the handler's
+/// reads and waits.  The handler's output is dropped when the timeout passes.  Children
+/// without a killer, such as on Windows for now, run without the timeout.
+pub fn with_timeout<H: CommHandler>(handler: H, timeout: Duration) -> WithTimeout<H> {
+    WithTimeout {
+        inner: handler,
+        timeout,
+    }
+}
+
+/// Turn the handler's output into something else, such as to log or summarize it.
+pub fn map_output<H, F, O>(handler: H, f: F) -> MapOutput<H, F>
+where
+    H: CommHandler,
+    F: FnMut(H::Output) -> O,
+{
+    MapOutput { inner: handler, f }
+}
+
+/// A handler with a deadline, as returned by `with_timeout`.
+pub struct WithTimeout<H> {
+    inner: H,
+    timeout: Duration,
+}
+
+impl<H: CommHandler> CommHandler for WithTimeout<H> {
+    type Output = H::Output;
+    type Error = SandboxError;
+
+    fn handle(&mut self, child: Box<dyn Child>) -> Result<H::Output, SandboxError> {
+        let Some(killer) = child.killer() else {
+            logging::warning!("the child has no killer; running the handler without a timeout");
+            return self.inner.handle(child).map_err(Into::into);
+        };
+        let (done, finished) = mpsc::channel::<()>();
+        let timeout = self.timeout;
+        let watchdog = std::thread::spawn(move || {
+            match finished.recv_timeout(timeout) {
+                Err(RecvTimeoutError::Timeout) => {
+                    logging::debug!("handler timed out after {:?}; terminating child", timeout);
+                    if let Err(e) = killer.terminate() {
+                        logging::warning!("could not terminate the timed out child: {}", e);
+                    }
+                    true
+                }
+                // The handler finished, and dropped the sender.
+                _ => false,
+            }
+        });
+        let ret = self.inner.handle(child);
+        drop(done);
+        if watchdog.join().unwrap_or(false) {
+            return Err(SandboxError::Io(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("the handler ran past its {:?} timeout", timeout),
+            )));
+        }
+        ret.map_err(Into::into)
+    }
+}
+
+/// A handler with its output converted, as returned by `map_output`.
+pub struct MapOutput<H, F> {
+    inner: H,
+    f: F,
+}
+
+impl<H, F, O> CommHandler for MapOutput<H, F>
+where
+    H: CommHandler,
+    F: FnMut(H::Output) -> O,
+{
+    type Output = O;
+    type Error = H::Error;
+
+    fn handle(&mut self, child: Box<dyn Child>) -> Result<O, H::Error> {
+        self.inner.handle(child).map(&mut self.f)
+    }
+}
@@ -0,0 +1,314 @@
+// SPDX-License-Identifier: MIT
+
+//! A sandboxed stand-in for `std::process::Command`.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    Child, CommHandler, EnvMap, ExitStatus, FdMode, LaunchEnv, OwnedChild, RunResult, run::Output,
+    sandbox_child,
+};
+
+/// Builds and runs a sandboxed child with the same calls as `std::process::Command`, so
+/// code written against the standard library can move into the sandbox with few changes.
+///
+/// ```no_run
+/// use gracklezero::{compat_restrictions, runtime::{GrackleCommand, Stdio}};
+///
+/// let output = GrackleCommand::new("/usr/bin/sort", compat_restrictions!("app"))
+///     .arg("-r")
+///     .stdin(Stdio::null())
+///     .output()
+///     .expect("sort failed");
+/// assert!(output.status.success());
+/// ```
+///
+/// The command differs from the standard library where the sandbox needs it to:
+/// * It takes the `Restrictions` to run the child under.
+/// * The child starts with an empty environment rather than a copy of the parent's; use
+///   `pass_env` to copy selected variables.
+/// * The child runs in its `current_dir`, but can't read it; convert the command into a
+///   `LaunchEnv` and set `grant_cwd_read` for that.
+///
+/// The settings beyond these calls, such as extra readable paths or named channels, come
+/// from a `LaunchEnv` turned into a command with `GrackleCommand::from`.  FDs 0 through 2
+/// always follow the command's `stdin`, `stdout`, and `stderr`; the other FDs in the
+/// `LaunchEnv`'s `FdSet` are passed through.
+#[derive(Clone)]
+pub struct GrackleCommand {
+    env: LaunchEnv,
+    stdin: Option<Stdio>,
+    stdout: Option<Stdio>,
+    stderr: Option<Stdio>,
+}
+
+/// Where a `GrackleCommand` connects one of the child's standard streams.
+#[derive(Debug, Clone)]
+pub struct Stdio(StdioKind);
+
+#[derive(Debug, Clone)]
+enum StdioKind {
+    Piped,
+    Null,
+    Inherit,
+    Mode(FdMode),
+}
+
+impl Stdio {
+    /// Connect the stream to a pipe with the parent, taken from the `OwnedChild` returned
+    /// by `GrackleCommand::spawn`.
+    pub fn piped() -> Self {
+        Stdio(StdioKind::Piped)
+    }
+
+    /// Attach the stream to the null device.
+    pub fn null() -> Self {
+        Stdio(StdioKind::Null)
+    }
+
+    /// Give the child a duplicate of the parent's own stream.
+    pub fn inherit() -> Self {
+        Stdio(StdioKind::Inherit)
+    }
+
+    /// The child's mode for the stream at the FD.
+    fn mode(&self, fd: u32) -> Result<FdMode, std::io::Error> {
+        Ok(match &self.0 {
+            StdioKind::Piped if fd == 0 => FdMode::ToChild,
+            StdioKind::Piped => FdMode::FromChild,
+            StdioKind::Null => FdMode::NullDevice,
+            StdioKind::Inherit => parent_stream(fd)?,
+            StdioKind::Mode(mode) => mode.clone(),
+        })
+    }
+}
+
+impl From<std::fs::File> for Stdio {
+    fn from(file: std::fs::File) -> Self {
+        Stdio(StdioKind::Mode(FdMode::inherit(file)))
+    }
+}
+
+impl From<std::io::PipeReader> for Stdio {
+    fn from(pipe: std::io::PipeReader) -> Self {
+        Stdio(StdioKind::Mode(FdMode::inherit(pipe)))
+    }
+}
+
+impl From<std::io::PipeWriter> for Stdio {
+    fn from(pipe: std::io::PipeWriter) -> Self {
+        Stdio(StdioKind::Mode(FdMode::inherit(pipe)))
+    }
+}
+
+impl GrackleCommand {
+    /// A command to run the program under the restrictions, with no arguments, an empty
+    /// environment, and the current directory.  The program is found as described by
+    /// `LaunchEnv::resolution`.
+    pub fn new<S: AsRef<OsStr>>(program: S, restrictions: crate::Restrictions) -> Self {
+        GrackleCommand::from(LaunchEnv::new(
+            PathBuf::from(program.as_ref()),
+            restrictions,
+        ))
+    }
+
+    /// Add an argument.
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.env.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Add the arguments.
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Set an environment variable for the child.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, val: V) -> &mut Self {
+        self.env.env.insert(key.as_ref(), val.as_ref());
+        self
+    }
+
+    /// Set the environment variables for the child.
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, val) in vars {
+            self.env(key, val);
+        }
+        self
+    }
+
+    /// Remove an environment variable set for the child.
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.env.env.remove(key);
+        self
+    }
+
+    /// Remove every environment variable set for the child.
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env.env = EnvMap::new();
+        self
+    }
+
+    /// Pass the parent's value for each named variable to the child.  See
+    /// `LaunchEnv::pass_env`.
+    pub fn pass_env(&mut self, names: &[&str]) -> &mut Self {
+        for name in names {
+            self.env.env.insert_from_current(name);
+        }
+        self
+    }
+
+    /// Run the child in the directory.
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.env.cwd = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Where the child's STDIN comes from.  Defaults to `Stdio::inherit` for `spawn` and
+    /// `status`, and `Stdio::null` for `output`.
+    pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.stdin = Some(cfg.into());
+        self
+    }
+
+    /// Where the child's STDOUT goes.  Defaults to `Stdio::inherit` for `spawn` and
+    /// `status`, and `Stdio::piped` for `output`.
+    pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.stdout = Some(cfg.into());
+        self
+    }
+
+    /// Where the child's STDERR goes.  Defaults to `Stdio::inherit` for `spawn` and
+    /// `status`, and `Stdio::piped` for `output`.
+    pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.stderr = Some(cfg.into());
+        self
+    }
+
+    /// The program the child runs.
+    pub fn get_program(&self) -> &OsStr {
+        self.env.cmd.as_os_str()
+    }
+
+    /// The arguments passed to the child.
+    pub fn get_args(&self) -> impl Iterator<Item = &OsStr> {
+        self.env.args.iter().map(OsString::as_os_str)
+    }
+
+    /// The directory the child runs in.
+    pub fn get_current_dir(&self) -> &Path {
+        &self.env.cwd
+    }
+
+    /// Launch the child, and hand it back to take its piped streams and wait on it.
+    pub fn spawn(&mut self) -> Result<OwnedChild, std::io::Error> {
+        let env = self.launch_env(Stdio::inherit(), Stdio::inherit(), Stdio::inherit())?;
+        super::spawn(env).map_err(Into::into)
+    }
+
+    /// Run the child to its end, and get its status.
+    ///
+    /// The child's piped STDIN, if any, is closed right away.
+    pub fn status(&mut self) -> Result<ExitStatus, std::io::Error> {
+        let env = self.launch_env(Stdio::inherit(), Stdio::inherit(), Stdio::inherit())?;
+        let (_, status) = sandbox_child(env, StatusHandler)
+            .and_then(RunResult::into_result)
+            .map_err(Into::<std::io::Error>::into)?;
+        Ok(status)
+    }
+
+    /// Run the child to its end, and collect everything it wrote to its piped STDOUT and
+    /// STDERR.  This is `runtime::run` for the command.
+    ///
+    /// The child's piped STDIN, if any, is closed right away.
+    pub fn output(&mut self) -> Result<Output, std::io::Error> {
+        let env = self.launch_env(Stdio::null(), Stdio::piped(), Stdio::piped())?;
+        super::run(env).map_err(Into::into)
+    }
+
+    /// The launch environment, with the standard streams set or given their defaults.
+    fn launch_env(
+        &self,
+        stdin: Stdio,
+        stdout: Stdio,
+        stderr: Stdio,
+    ) -> Result<LaunchEnv, std::io::Error> {
+        let mut env = self.env.clone();
+        let streams = [
+            (&self.stdin, stdin),
+            (&self.stdout, stdout),
+            (&self.stderr, stderr),
+        ];
+        for (fd, (set, default)) in (0..).zip(streams) {
+            let mode = set.as_ref().unwrap_or(&default).mode(fd)?;
+            env.fds = env.fds.with_fd(fd, mode);
+        }
+        Ok(env)
+    }
+}
+
+impl From<LaunchEnv> for GrackleCommand {
+    fn from(env: LaunchEnv) -> Self {
+        GrackleCommand {
+            env,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        }
+    }
+}
+
+/// Closes the child's input and waits for it.
+struct StatusHandler;
+
+impl CommHandler for StatusHandler {
+    type Output = ();
+    type Error = std::io::Error;
+
+    fn handle(&mut self, mut child: Box<dyn Child>) -> Result<(), std::io::Error> {
+        drop(child.take_stream_to_child(0));
+        child.wait()?;
+        Ok(())
+    }
+}
+
+/// A duplicate of the parent's own standard stream, for the child's FD.
+#[cfg(unix)]
+fn parent_stream(fd: u32) -> Result<FdMode, std::io::Error> {
+    use std::os::fd::AsFd as _;
+
+    let dup = match fd {
+        0 => std::io::stdin().as_fd().try_clone_to_owned()?,
+        1 => std::io::stdout().as_fd().try_clone_to_owned()?,
+        _ => std::io::stderr().as_fd().try_clone_to_owned()?,
+    };
+    Ok(FdMode::inherit(dup))
+}
+
+/// A duplicate of the parent's own standard stream, for the child's FD.
+#[cfg(windows)]
+fn parent_stream(fd: u32) -> Result<FdMode, std::io::Error> {
+    use std::os::windows::io::AsHandle as _;
+
+    let dup = match fd {
+        0 => std::io::stdin().as_handle().try_clone_to_owned()?,
+        1 => std::io::stdout().as_handle().try_clone_to_owned()?,
+        _ => std::io::stderr().as_handle().try_clone_to_owned()?,
+    };
+    Ok(FdMode::inherit(dup))
+}
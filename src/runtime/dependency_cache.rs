@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: MIT
+
+//! Cache of dependency analysis results, shared by the launches given the same cache.
+//!
+//! Running the dependency analysis on every launch costs tens of milliseconds.
+//! The results are cached by the file's path, size, and modification time, along with
+//! the search settings, so sandboxing the same tool again skips the analysis.
+//!
+//! Only the analyzed file's own metadata is part of the key; replacing a shared library
+//! in place without touching the executable keeps serving the old results.  Leave
+//! `DependencySearch::cache` unset when that matters.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::runtime::{deps::Dependency, spawn::DependencySearch};
+
+/// Default number of analyzed files kept in the cache.
+const CAPACITY: usize = 64;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    sysroot: PathBuf,
+    library_paths: Vec<PathBuf>,
+    is_executable: bool,
+}
+
+impl CacheKey {
+    fn new(path: &Path, search: &DependencySearch, is_executable: bool) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        Some(CacheKey {
+            path: path.to_path_buf(),
+            size: meta.len(),
+            modified: meta.modified().ok()?,
+            sysroot: search.sysroot.clone(),
+            library_paths: search.library_paths.clone(),
+            is_executable,
+        })
+    }
+}
+
+/// Reuses the dependency analysis from earlier launches of the same, unchanged files.
+/// Set it as `DependencySearch::cache`.
+///
+/// Clones share the same cache, so create one and give a clone to each launch that
+/// should share it.
+///
+/// ```
+/// use gracklezero::runtime::{DependencyCache, DependencySearch};
+///
+/// let cache = DependencyCache::new();
+/// let search = DependencySearch {
+///     cache: Some(cache.clone()),
+///     ..DependencySearch::default()
+/// };
+/// assert!(cache.is_empty());
+/// ```
+#[derive(Clone, Default)]
+pub struct DependencyCache {
+    inner: Arc<Mutex<LruCache>>,
+}
+
+impl std::fmt::Debug for DependencyCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DependencyCache")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl DependencyCache {
+    /// An empty cache, holding the results for up to 64 files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty cache, holding the results for up to `capacity` files.  Once full, the
+    /// least recently used results are dropped.
+    pub fn with_capacity(capacity: usize) -> Self {
+        DependencyCache {
+            inner: Arc::new(Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    /// The number of files with cached results.
+    pub fn len(&self) -> usize {
+        self.inner.lock().map_or(0, |cache| cache.entries.len())
+    }
+
+    /// Are no results cached?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the cached dependencies for the file, or run the analysis and cache the
+    /// result.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn cached<F>(
+        &self,
+        path: &Path,
+        search: &DependencySearch,
+        is_executable: bool,
+        analyze: F,
+    ) -> Vec<Dependency>
+    where
+        F: FnOnce() -> Vec<Dependency>,
+    {
+        let Some(key) = CacheKey::new(path, search, is_executable) else {
+            return analyze();
+        };
+        let hit = self.inner.lock().ok().and_then(|mut cache| cache.get(&key));
+        if let Some(deps) = hit {
+            return deps.as_ref().clone();
+        }
+        let deps = Arc::new(analyze());
+        // Missing files are not cached, so they are reported again once they appear.
+        if deps.iter().all(|d| !d.invalid())
+            && let Ok(mut cache) = self.inner.lock()
+        {
+            cache.insert(key, deps.clone());
+        }
+        deps.as_ref().clone()
+    }
+}
+
+/// Least recently used cache of dependency lists.
+struct LruCache {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<CacheKey, (u64, Arc<Vec<Dependency>>)>,
+}
+
+impl Default for LruCache {
+    fn default() -> Self {
+        LruCache::new(CAPACITY)
+    }
+}
+
+impl LruCache {
+    fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<Arc<Vec<Dependency>>> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).map(|(used, deps)| {
+            *used = tick;
+            deps.clone()
+        })
+    }
+
+    fn insert(&mut self, key: CacheKey, deps: Arc<Vec<Dependency>>) {
+        self.tick += 1;
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (self.tick, deps));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> CacheKey {
+        CacheKey {
+            path: PathBuf::from(name),
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            sysroot: PathBuf::from("/"),
+            library_paths: Vec::new(),
+            is_executable: true,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.insert(key("a"), Arc::new(Vec::new()));
+        cache.insert(key("b"), Arc::new(Vec::new()));
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(key("c"), Arc::new(Vec::new()));
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("c")).is_some());
+    }
+
+    #[test]
+    fn reuses_analysis() {
+        let exec = std::env::current_exe().unwrap();
+        let search = DependencySearch::default();
+        let fake = || {
+            vec![Dependency {
+                name: "fake".to_string(),
+                path: exec.clone(),
+                realpath: Some(exec.clone()),
+                required: true,
+                needed: Vec::new(),
+            }]
+        };
+        let cache = DependencyCache::new();
+        let first = cache.cached(&exec, &search, true, fake);
+        let shared = cache.clone();
+        let second = shared.cached(&exec, &search, true, || panic!("analysis ran again"));
+        assert_eq!(first.len(), second.len());
+        assert_eq!(cache.len(), 1);
+
+        // Another cache doesn't see the results.
+        let other = DependencyCache::new();
+        assert!(other.cached(&exec, &search, true, Vec::new).is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: MIT
+
+//! Find the files a program loads, for pre-flight checks, packaging, and debugging
+//! "missing library dependencies" errors.
+//!
+//! On Linux, this reads the ELF dynamic sections of the executable and each shared
+//! library, and resolves them as the dynamic loader would, within the
+//! `DependencySearch`.  On Windows, the DLL imports aren't analyzed yet, so only the
+//! executable itself is listed.
+
+use std::path::{Path, PathBuf};
+
+use super::{DependencySearch, LaunchEnv, error::SandboxError};
+
+/// A file the program loads: the executable, or one of its shared libraries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Dependency {
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+    pub(crate) realpath: Option<PathBuf>,
+    pub(crate) required: bool,
+    pub(crate) needed: Vec<String>,
+}
+
+impl Dependency {
+    /// A file named by its path, as the executable or an extra library is.
+    pub(crate) fn from_path(path: &Path, required: bool) -> Self {
+        let realpath = match std::path::absolute(path) {
+            Ok(abs) if path.exists() => Some(abs),
+            _ => None,
+        };
+        Dependency {
+            name: path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            path: path.to_path_buf(),
+            realpath,
+            required,
+            needed: Vec::new(),
+        }
+    }
+
+    /// The name other files load it by, such as `libc.so.6`, or the file name, for the
+    /// executable.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Where the file was looked for.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The absolute path to the file, or None when it couldn't be found.
+    pub fn realpath(&self) -> Option<&Path> {
+        self.realpath.as_deref()
+    }
+
+    /// Some file lists this one as needed, rather than the tree only declaring it.  A
+    /// required file that's missing fails the launch.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// The names of the libraries this file loads directly.
+    pub fn needed(&self) -> &[String] {
+        &self.needed
+    }
+
+    /// Was the file found?
+    pub fn exists(&self) -> bool {
+        self.realpath.is_some()
+    }
+
+    /// The found path, or where it was looked for when it's missing.
+    pub fn best_path(&self) -> &Path {
+        match &self.realpath {
+            Some(r) => r,
+            None => &self.path,
+        }
+    }
+
+    /// Is the file required, but missing?
+    pub fn invalid(&self) -> bool {
+        self.required && self.realpath.is_none()
+    }
+}
+
+/// The files a launch loads, as returned by `dependencies`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DependencyReport {
+    /// The executable `LaunchEnv::cmd` resolved to.
+    pub exec: PathBuf,
+    /// The interpreter that runs `exec`, when it's a script.  Linux only.
+    pub interpreter: Option<PathBuf>,
+    /// The program that's run, the interpreter for a script, followed by every shared
+    /// library its dependency tree declares.
+    pub dependencies: Vec<Dependency>,
+    /// The `LaunchEnv::extra_libraries`, each followed by its own shared libraries.
+    /// Linux only.
+    pub extra_libraries: Vec<Dependency>,
+    /// The paths the child may read, as `SandboxPlan::readable_paths`, less any missing
+    /// libraries.  Linux only.
+    pub readable_paths: Vec<PathBuf>,
+    /// The paths the child may write, as `SandboxPlan::writable_paths`.  Linux only.
+    pub writable_paths: Vec<PathBuf>,
+}
+
+impl DependencyReport {
+    /// The required files that couldn't be found, which would fail the launch.
+    pub fn missing(&self) -> impl Iterator<Item = &Dependency> {
+        self.dependencies
+            .iter()
+            .chain(&self.extra_libraries)
+            .filter(|d| d.invalid())
+    }
+}
+
+/// Resolve the executable, and find the shared libraries it and the extra libraries load,
+/// as a launch would.
+///
+/// Unlike a launch or `plan`, missing libraries don't fail this; they're reported with
+/// no `realpath`.  It only fails when the executable can't be resolved.
+///
+/// ```no_run
+/// use gracklezero::{LaunchEnv, compat_restrictions, runtime::deps::dependencies};
+///
+/// let report =
+///     dependencies(&LaunchEnv::new("/usr/bin/uname".into(), compat_restrictions!("app")))
+///         .expect("could not resolve the executable");
+/// for dep in report.missing() {
+///     eprintln!("missing {}", dep.name());
+/// }
+/// ```
+#[cfg(target_os = "linux")]
+pub fn dependencies(env: &LaunchEnv) -> Result<DependencyReport, SandboxError> {
+    super::spawn_linux::dependencies(env)
+}
+
+/// Resolve the executable, as a launch would.  The DLLs it imports aren't analyzed yet,
+/// so the report only lists the executable.
+#[cfg(target_os = "windows")]
+pub fn dependencies(env: &LaunchEnv) -> Result<DependencyReport, SandboxError> {
+    super::spawn_windows::dependencies(env)
+}
+
+/// Find the shared libraries the executable or library file loads, without a
+/// `LaunchEnv`.  The file itself comes first.  On Windows, only the file is listed.
+///
+/// The results are cached while the file is unchanged, when the search sets
+/// `DependencySearch::cache`.
+pub fn find_dependencies(file: &Path, search: &DependencySearch) -> Vec<Dependency> {
+    #[cfg(target_os = "linux")]
+    {
+        super::spawn_linux::find_bin_dependencies(&file.to_path_buf(), search)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = search;
+        vec![Dependency::from_path(file, true)]
+    }
+}
@@ -0,0 +1,299 @@
+// SPDX-License-Identifier: MIT
+
+//! Environment variables passed to the child process.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+};
+
+use super::wildcard::wildcard_match;
+
+/// Environment variables for the child, keyed by name.
+///
+/// Windows treats variable names as case-insensitive, and refuses an environment block
+/// that holds two names differing only by case.  The map created by `EnvMap::new` follows
+/// the native rules: names match case-insensitively on Windows, and case-sensitively
+/// everywhere else.
+///
+/// Merge policy:
+/// * `insert` replaces any variable with a matching name, including the name's spelling.
+/// * `insert_default` only adds the variable if no variable with a matching name exists.
+/// * `extend` and `merge` insert each entry in turn, so later entries override earlier ones.
+/// * Building the map from an unordered collection, such as a `HashMap`, inserts the
+///   entries sorted by name, so when two names only differ by case, the one that sorts
+///   last wins, no matter the source's iteration order.
+///
+/// Iteration is in a stable order, sorted by the matched name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvMap {
+    case_insensitive: bool,
+    vars: BTreeMap<OsString, (OsString, OsString)>,
+}
+
+impl EnvMap {
+    /// Create an empty map that matches names the way the current OS does.
+    pub fn new() -> Self {
+        EnvMap {
+            case_insensitive: cfg!(target_os = "windows"),
+            vars: BTreeMap::new(),
+        }
+    }
+
+    /// Create an empty map that matches names without regard to case.
+    pub fn case_insensitive() -> Self {
+        EnvMap {
+            case_insensitive: true,
+            vars: BTreeMap::new(),
+        }
+    }
+
+    /// Create an empty map that matches names exactly.
+    pub fn case_sensitive() -> Self {
+        EnvMap {
+            case_insensitive: false,
+            vars: BTreeMap::new(),
+        }
+    }
+
+    /// Create a map with the current process's environment variables.
+    pub fn from_current() -> Self {
+        std::env::vars_os().collect()
+    }
+
+    /// Copy the variable from the current process's environment, if it is set there.
+    /// Returns true if the variable was copied.
+    pub fn insert_from_current<K: AsRef<OsStr>>(&mut self, key: K) -> bool {
+        match std::env::var_os(key.as_ref()) {
+            Some(value) => {
+                self.insert(key.as_ref(), value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copy every variable from the current process's environment whose name matches the
+    /// shell-style pattern, such as `LC_*`.  `*` matches any run of characters, and `?`
+    /// any single character.  Names match with the map's case rules.
+    /// Returns the number of variables copied.
+    pub fn insert_matching_from_current(&mut self, pattern: &str) -> usize {
+        let pattern = self.match_key(OsStr::new(pattern));
+        let mut count = 0;
+        for (key, value) in EnvMap::from_current() {
+            if wildcard_match(
+                pattern.as_encoded_bytes(),
+                self.match_key(&key).as_encoded_bytes(),
+            ) {
+                self.insert(key, value);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Set the variable, replacing any variable with a matching name.
+    /// Returns the replaced value, if any.
+    pub fn insert<K: Into<OsString>, V: Into<OsString>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Option<OsString> {
+        let key = key.into();
+        self.vars
+            .insert(self.match_key(&key), (key, value.into()))
+            .map(|(_, v)| v)
+    }
+
+    /// Set the variable only if no variable with a matching name exists.
+    /// Returns true if the variable was added.
+    pub fn insert_default<K: Into<OsString>, V: Into<OsString>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> bool {
+        let key = key.into();
+        let matched = self.match_key(&key);
+        if self.vars.contains_key(&matched) {
+            return false;
+        }
+        self.vars.insert(matched, (key, value.into()));
+        true
+    }
+
+    /// Insert every variable from the other map, overriding the matching variables here.
+    pub fn merge(&mut self, other: &EnvMap) {
+        for (k, v) in other.iter() {
+            self.insert(k, v);
+        }
+    }
+
+    /// Get the value for the variable with a matching name.
+    pub fn get<K: AsRef<OsStr>>(&self, key: K) -> Option<&OsStr> {
+        self.vars
+            .get(&self.match_key(key.as_ref()))
+            .map(|(_, v)| v.as_os_str())
+    }
+
+    /// Check whether a variable with a matching name exists.
+    pub fn contains_key<K: AsRef<OsStr>>(&self, key: K) -> bool {
+        self.vars.contains_key(&self.match_key(key.as_ref()))
+    }
+
+    /// Remove the variable with a matching name, returning its value.
+    pub fn remove<K: AsRef<OsStr>>(&mut self, key: K) -> Option<OsString> {
+        self.vars
+            .remove(&self.match_key(key.as_ref()))
+            .map(|(_, v)| v)
+    }
+
+    /// The variables, in a stable order.
+    pub fn iter(&self) -> impl Iterator<Item = (&OsStr, &OsStr)> {
+        self.vars
+            .values()
+            .map(|(k, v)| (k.as_os_str(), v.as_os_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// Does the map match names without regard to case?
+    pub fn is_case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    /// Insert the entries sorted by name, so the result doesn't depend on their order.
+    fn extend_sorted<T: IntoIterator<Item = (OsString, OsString)>>(&mut self, iter: T) {
+        let mut entries: Vec<(OsString, OsString)> = iter.into_iter().collect();
+        entries.sort();
+        self.extend(entries);
+    }
+
+    fn match_key(&self, key: &OsStr) -> OsString {
+        if !self.case_insensitive {
+            return key.to_os_string();
+        }
+        match key.to_str() {
+            Some(s) => OsString::from(s.to_uppercase()),
+            None => key.to_ascii_uppercase(),
+        }
+    }
+}
+
+impl Default for EnvMap {
+    fn default() -> Self {
+        EnvMap::new()
+    }
+}
+
+impl<K: Into<OsString>, V: Into<OsString>> Extend<(K, V)> for EnvMap {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K: Into<OsString>, V: Into<OsString>> FromIterator<(K, V)> for EnvMap {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut ret = EnvMap::new();
+        ret.extend_sorted(iter.into_iter().map(|(k, v)| (k.into(), v.into())));
+        ret
+    }
+}
+
+impl From<HashMap<OsString, OsString>> for EnvMap {
+    fn from(map: HashMap<OsString, OsString>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl IntoIterator for EnvMap {
+    type Item = (OsString, OsString);
+    type IntoIter = std::collections::btree_map::IntoValues<OsString, (OsString, OsString)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.vars.into_values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, ffi::OsString};
+
+    use super::EnvMap;
+
+    #[test]
+    fn case_insensitive_insert_replaces_spelling() {
+        let mut env = EnvMap::case_insensitive();
+        env.insert("Path", "a");
+        assert_eq!(env.insert("PATH", "b"), Some(OsString::from("a")));
+        assert_eq!(env.len(), 1);
+        let all: Vec<_> = env.iter().collect();
+        assert_eq!(all, vec![("PATH".as_ref(), "b".as_ref())]);
+        assert_eq!(env.get("path"), Some("b".as_ref()));
+    }
+
+    #[test]
+    fn case_sensitive_keeps_both() {
+        let mut env = EnvMap::case_sensitive();
+        env.insert("http_proxy", "a");
+        env.insert("HTTP_PROXY", "b");
+        assert_eq!(env.len(), 2);
+        assert_eq!(env.get("http_proxy"), Some("a".as_ref()));
+        assert_eq!(env.get("Http_Proxy"), None);
+    }
+
+    #[test]
+    fn insert_default_keeps_existing() {
+        let mut env = EnvMap::case_insensitive();
+        env.insert("SystemRoot", "D:\\Windows");
+        assert!(!env.insert_default("SYSTEMROOT", "C:\\Windows"));
+        assert!(env.insert_default("Windir", "C:\\Windows"));
+        assert_eq!(env.get("systemroot"), Some("D:\\Windows".as_ref()));
+        assert_eq!(env.get("WINDIR"), Some("C:\\Windows".as_ref()));
+    }
+
+    #[test]
+    fn merge_overrides() {
+        let mut base = EnvMap::case_insensitive();
+        base.insert("A", "1");
+        base.insert("B", "2");
+        let mut over = EnvMap::case_insensitive();
+        over.insert("b", "3");
+        base.merge(&over);
+        let all: Vec<_> = base.iter().collect();
+        assert_eq!(
+            all,
+            vec![("A".as_ref(), "1".as_ref()), ("b".as_ref(), "3".as_ref())]
+        );
+    }
+
+    #[test]
+    fn insert_matching_from_current() {
+        // Every test process has PATH (or Path, on Windows).
+        let mut env = EnvMap::new();
+        assert!(env.insert_matching_from_current("PAT?") >= 1);
+        assert!(env.contains_key("PATH"));
+        assert_eq!(env.insert_matching_from_current("NO_SUCH_VARIABLE_*"), 0);
+        assert!(!env.insert_from_current("NO_SUCH_VARIABLE_X"));
+    }
+
+    #[test]
+    fn from_hash_map_is_deterministic() {
+        let mut src = HashMap::new();
+        src.insert(OsString::from("path"), OsString::from("lower"));
+        src.insert(OsString::from("PATH"), OsString::from("upper"));
+        src.insert(OsString::from("Path"), OsString::from("mixed"));
+        let mut env = EnvMap::case_insensitive();
+        env.extend_sorted(src);
+        // "path" sorts last by its raw bytes.
+        assert_eq!(env.get("PATH"), Some("lower".as_ref()));
+        assert_eq!(env.len(), 1);
+    }
+}
@@ -1,38 +1,387 @@
 //! Common error type.
 //!
 
-use std::{ffi::NulError, fmt::Display};
+use std::{
+    error::Error,
+    ffi::NulError,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
 
+/// Why a launch, or running a child, failed.
+///
+/// The variants that wrap an OS failure keep the original error as their `source`, so
+/// `raw_os_error` still reports the errno, or the Windows error code.  Those from a launch
+/// phase also carry `LaunchDiagnostics`, returned by `diagnostics`.
 #[derive(Debug)]
 pub enum SandboxError {
+    /// An I/O failure outside the launch phases below.
     Io(std::io::Error),
-    ProcessError(String),
+    /// The command couldn't be resolved to an executable, following the `ExecResolution`.
+    Resolution {
+        cmd: PathBuf,
+        source: std::io::Error,
+        diagnostics: Option<Box<LaunchDiagnostics>>,
+    },
+    /// Shared libraries the executable needs aren't on this host.
+    Dependency {
+        missing: Vec<PathBuf>,
+        diagnostics: Option<Box<LaunchDiagnostics>>,
+    },
+    /// Creating the child's file descriptors failed, for the fd when it's known.
+    FdSetup {
+        fd: Option<u32>,
+        source: std::io::Error,
+        diagnostics: Option<Box<LaunchDiagnostics>>,
+    },
+    /// The jail can't be set up with the requested configuration.
     JailSetup(String),
+    /// The platform's jail mechanism failed, with the mechanism when it's known.
+    Jail {
+        mechanism: Option<JailMechanism>,
+        source: Box<dyn Error + Send + Sync>,
+        diagnostics: Option<Box<LaunchDiagnostics>>,
+    },
     JailNotSupported(String),
+    /// Starting the child failed, either creating the process or running the executable.
+    Exec {
+        path: PathBuf,
+        source: std::io::Error,
+        diagnostics: Option<Box<LaunchDiagnostics>>,
+    },
+    /// Waiting for the child to end, or ending it, failed.
+    Wait(std::io::Error),
+    ProcessError(String),
+    InvalidFdSet(String),
+    /// A profile file couldn't be parsed.
+    InvalidProfile(String),
+    /// A digest given to the `HashAllowlist` isn't a SHA-256 digest.
+    InvalidDigest(String),
+    /// A file the launch runs isn't in the `HashAllowlist`.
+    UntrustedFile(String),
+    /// The parent runs privileged, such as root or an elevated Administrator, and the
+    /// launch doesn't allow it.  See `LaunchEnv::allow_privileged_parent`.
+    PrivilegedParent,
+    /// The `CommHandler` failed with its own error, which can be recovered by downcasting.
+    Handler(Box<dyn Error + Send + Sync>),
+}
+
+/// Where a launch failed, carried by the `Resolution`, `Dependency`, `FdSetup`, `Jail`,
+/// and `Exec` errors.  See `SandboxError::diagnostics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchDiagnostics {
+    /// The phase that failed.
+    pub phase: LaunchPhase,
+    /// The command, as the `LaunchEnv` gave it.
+    pub cmd: PathBuf,
+    /// The executable the command resolved to, once it's resolved.  For a script, this
+    /// is the script.
+    pub resolved: Option<PathBuf>,
+    /// The file descriptor that couldn't be set up.
+    pub fd: Option<u32>,
+    /// The shared libraries the executable needs that aren't on this host.
+    pub missing_libraries: Vec<PathBuf>,
+    /// The jail mechanism that failed.
+    pub mechanism: Option<JailMechanism>,
+}
+
+/// The steps of a launch, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaunchPhase {
+    /// Checking the `LaunchEnv`, and that the parent may launch.
+    Validate,
+    /// Finding the executable for `LaunchEnv::cmd`.
+    Resolve,
+    /// Finding the executable's interpreter and shared libraries.
+    Dependencies,
+    /// Checking the files against the `HashAllowlist`.
+    Verify,
+    /// Building the jail.
+    Jail,
+    /// Creating the pipes and opening the files for the child's FDs.
+    FdSetup,
+    /// Creating the child process.  On Windows, this covers the whole sandbox setup.
+    Fork,
+    /// Running the executable inside the child.  Linux only.
+    Exec,
+}
+
+/// The OS facilities the jail is built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JailMechanism {
+    /// The Linux Landlock filesystem and network rules.
+    Landlock,
+    /// The Linux seccomp system call filter.
+    Seccomp,
+    /// The Windows AppContainer, with its profile and capabilities.
+    AppContainer,
+    /// The Windows process attributes, such as the handle list and mitigation policies.
+    ProcessAttributes,
+    /// The Windows desktop and window station that isolate the child's UI.
+    Desktop,
+    /// The Windows job object holding the child's resource limits.
+    JobObject,
+}
+
+impl LaunchDiagnostics {
+    pub(crate) fn new(cmd: &Path) -> Self {
+        LaunchDiagnostics {
+            phase: LaunchPhase::Validate,
+            cmd: cmd.to_path_buf(),
+            resolved: None,
+            fd: None,
+            missing_libraries: Vec::new(),
+            mechanism: None,
+        }
+    }
+
+    /// Attach the diagnostics, at the phase the launch reached, to the error, with the
+    /// details the error itself carries.  Errors without a diagnostics slot, and those
+    /// already given diagnostics by an inner launch, are returned as they are.
+    pub(crate) fn attach(mut self, mut e: SandboxError) -> SandboxError {
+        let slot = match &mut e {
+            SandboxError::Dependency {
+                missing,
+                diagnostics,
+            } => {
+                self.missing_libraries = missing.clone();
+                diagnostics
+            }
+            SandboxError::FdSetup {
+                fd, diagnostics, ..
+            } => {
+                self.fd = *fd;
+                diagnostics
+            }
+            SandboxError::Jail {
+                mechanism,
+                diagnostics,
+                ..
+            } => {
+                self.mechanism = *mechanism;
+                diagnostics
+            }
+            SandboxError::Resolution { diagnostics, .. }
+            | SandboxError::Exec { diagnostics, .. } => diagnostics,
+            _ => return e,
+        };
+        if slot.is_none() {
+            *slot = Some(Box::new(self));
+        }
+        e
+    }
+}
+
+impl Display for LaunchPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Validate => "validate",
+            Self::Resolve => "resolve",
+            Self::Dependencies => "dependencies",
+            Self::Verify => "verify",
+            Self::Jail => "jail",
+            Self::FdSetup => "fd setup",
+            Self::Fork => "fork",
+            Self::Exec => "exec",
+        })
+    }
+}
+
+impl Display for JailMechanism {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Landlock => "landlock",
+            Self::Seccomp => "seccomp",
+            Self::AppContainer => "AppContainer",
+            Self::ProcessAttributes => "process attributes",
+            Self::Desktop => "desktop",
+            Self::JobObject => "job object",
+        })
+    }
+}
+
+impl SandboxError {
+    /// Wrap a handler's own error, for a `CommHandler::Error` type that converts into
+    /// `SandboxError`.
+    pub fn handler<E: Into<Box<dyn Error + Send + Sync>>>(e: E) -> Self {
+        SandboxError::Handler(e.into())
+    }
+
+    /// Wrap the failure reported by the platform's jail mechanism.
+    pub(crate) fn jail<E: Into<Box<dyn Error + Send + Sync>>>(
+        mechanism: Option<JailMechanism>,
+        e: E,
+    ) -> Self {
+        SandboxError::Jail {
+            mechanism,
+            source: e.into(),
+            diagnostics: None,
+        }
+    }
+
+    /// Report that setting up the fd failed.
+    pub(crate) fn fd_setup<E: Into<std::io::Error>>(fd: Option<u32>, e: E) -> Self {
+        SandboxError::FdSetup {
+            fd,
+            source: e.into(),
+            diagnostics: None,
+        }
+    }
+
+    /// Name the fd in an `FdSetup` error that doesn't have one.
+    pub(crate) fn for_fd(self, fd: u32) -> Self {
+        match self {
+            SandboxError::FdSetup {
+                fd: None,
+                source,
+                diagnostics,
+            } => SandboxError::FdSetup {
+                fd: Some(fd),
+                source,
+                diagnostics,
+            },
+            e => e,
+        }
+    }
+
+    /// Report that resolving the command failed.
+    pub(crate) fn resolution(cmd: &Path, source: std::io::Error) -> Self {
+        SandboxError::Resolution {
+            cmd: cmd.to_path_buf(),
+            source,
+            diagnostics: None,
+        }
+    }
+
+    /// Report that running the executable failed.
+    pub(crate) fn exec(path: PathBuf, source: std::io::Error) -> Self {
+        SandboxError::Exec {
+            path,
+            source,
+            diagnostics: None,
+        }
+    }
+
+    /// Where the launch failed, for the errors from a launch phase that carry diagnostics.
+    pub fn diagnostics(&self) -> Option<&LaunchDiagnostics> {
+        match self {
+            Self::Resolution { diagnostics, .. }
+            | Self::Dependency { diagnostics, .. }
+            | Self::FdSetup { diagnostics, .. }
+            | Self::Jail { diagnostics, .. }
+            | Self::Exec { diagnostics, .. } => diagnostics.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The OS error code behind the failure, if it came from the OS.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.io_error().and_then(std::io::Error::raw_os_error)
+    }
+
+    /// The kind of I/O failure behind the error, if there is one.
+    pub fn io_kind(&self) -> Option<std::io::ErrorKind> {
+        self.io_error().map(std::io::Error::kind)
+    }
+
+    fn io_error(&self) -> Option<&std::io::Error> {
+        match self {
+            Self::Io(e)
+            | Self::Resolution { source: e, .. }
+            | Self::FdSetup { source: e, .. }
+            | Self::Exec { source: e, .. }
+            | Self::Wait(e) => Some(e),
+            Self::Jail { source, .. } => source.downcast_ref(),
+            _ => None,
+        }
+    }
 }
 
 impl Display for SandboxError {
+    /// The alternate form, `{:#}`, follows with each error in the `source` chain.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("sandbox error: ")?;
+        self.fmt_message(f)?;
+        if f.alternate() {
+            let mut source = self.source();
+            while let Some(cause) = source {
+                write!(f, ": {}", cause)?;
+                source = cause.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SandboxError {
+    fn fmt_message(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            // Only adds the variant, so the I/O error speaks for itself.
             Self::Io(e) => e.fmt(f),
-            Self::ProcessError(e) => f.write_str(e),
+            Self::Resolution { cmd, .. } => {
+                write!(f, "could not resolve the command {}", cmd.display())
+            }
+            Self::Dependency { missing, .. } => {
+                f.write_str("missing library dependencies: ")?;
+                for (i, path) in missing.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", path.display())?;
+                }
+                Ok(())
+            }
+            Self::FdSetup { fd: Some(fd), .. } => write!(f, "could not set up fd {}", fd),
+            Self::FdSetup { fd: None, .. } => {
+                f.write_str("could not set up the child's file descriptors")
+            }
             Self::JailSetup(s) => f.write_str(s),
-            Self::JailNotSupported(s) => f.write_str(s),
-        }?;
-        f.write_str("sandbox error")
+            Self::Jail {
+                mechanism: Some(m), ..
+            } => write!(f, "could not set up the jail's {}", m),
+            Self::Jail {
+                mechanism: None, ..
+            } => f.write_str("could not set up the jail"),
+            Self::JailNotSupported(s) => write!(f, "jail not supported: {}", s),
+            Self::Exec { path, .. } => write!(f, "could not run {}", path.display()),
+            Self::Wait(_) => f.write_str("could not wait for the child to end"),
+            Self::ProcessError(s) => f.write_str(s),
+            Self::InvalidFdSet(s) => write!(f, "invalid fd set: {}", s),
+            Self::InvalidProfile(s) => write!(f, "invalid profile: {}", s),
+            Self::InvalidDigest(s) => write!(f, "invalid SHA-256 digest {:?}", s),
+            Self::UntrustedFile(s) => f.write_str(s),
+            Self::PrivilegedParent => f.write_str(
+                "the parent runs privileged, such as root or an elevated Administrator, which \
+                weakens the sandbox; set LaunchEnv::allow_privileged_parent to launch anyway",
+            ),
+            Self::Handler(_) => f.write_str("the comm handler failed"),
+        }
     }
 }
 
-impl From<std::io::Error> for SandboxError {
-    fn from(e: std::io::Error) -> Self {
-        SandboxError::Io(e)
+impl Error for SandboxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => e.source(),
+            Self::Resolution { source, .. } | Self::Exec { source, .. } => Some(source),
+            Self::FdSetup { source, .. } => Some(source),
+            Self::Wait(e) => Some(e),
+            Self::Jail { source, .. } | Self::Handler(source) => Some(source.as_ref()),
+            Self::Dependency { .. }
+            | Self::JailSetup(_)
+            | Self::JailNotSupported(_)
+            | Self::ProcessError(_)
+            | Self::InvalidFdSet(_)
+            | Self::InvalidProfile(_)
+            | Self::InvalidDigest(_)
+            | Self::UntrustedFile(_)
+            | Self::PrivilegedParent => None,
+        }
     }
 }
 
-impl From<which::Error> for SandboxError {
-    fn from(e: which::Error) -> Self {
-        SandboxError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, e))
+impl From<std::io::Error> for SandboxError {
+    fn from(e: std::io::Error) -> Self {
+        SandboxError::Io(e)
     }
 }
 
@@ -42,13 +391,108 @@ impl From<NulError> for SandboxError {
     }
 }
 
-impl Into<std::io::Error> for SandboxError {
-    fn into(self) -> std::io::Error {
-        match self {
-            Self::Io(e) => e,
-            Self::ProcessError(e) => std::io::Error::new(std::io::ErrorKind::Unsupported, e),
-            Self::JailSetup(e) => std::io::Error::new(std::io::ErrorKind::InvalidInput, e),
-            Self::JailNotSupported(e) => std::io::Error::new(std::io::ErrorKind::NotSeekable, e),
+impl From<SandboxError> for std::io::Error {
+    /// An `Io` error converts back unchanged.  Every other error is wrapped, so
+    /// `get_ref` can downcast it to the `SandboxError`.
+    fn from(e: SandboxError) -> Self {
+        use std::io::ErrorKind;
+
+        if let SandboxError::Io(e) = e {
+            return e;
         }
+        let kind = e.io_kind().unwrap_or(match &e {
+            SandboxError::Dependency { .. } => ErrorKind::NotFound,
+            SandboxError::ProcessError(_) | SandboxError::JailNotSupported(_) => {
+                ErrorKind::Unsupported
+            }
+            SandboxError::JailSetup(_)
+            | SandboxError::InvalidFdSet(_)
+            | SandboxError::InvalidDigest(_) => ErrorKind::InvalidInput,
+            SandboxError::InvalidProfile(_) => ErrorKind::InvalidData,
+            SandboxError::UntrustedFile(_) | SandboxError::PrivilegedParent => {
+                ErrorKind::PermissionDenied
+            }
+            _ => ErrorKind::Other,
+        });
+        std::io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Any code will do; it only has to survive the wrapping.
+    const CODE: i32 = 8;
+
+    #[test]
+    fn chains_source() {
+        let err = SandboxError::exec(
+            PathBuf::from("/bin/true"),
+            std::io::Error::from_raw_os_error(CODE),
+        );
+        assert_eq!(err.to_string(), "could not run /bin/true");
+        assert_eq!(err.raw_os_error(), Some(CODE));
+        let source = err.source().expect("exec error has a source");
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+
+        let io: std::io::Error = err.into();
+        assert_eq!(io.raw_os_error(), None);
+        let inner = io.get_ref().and_then(|e| e.downcast_ref::<SandboxError>());
+        assert_eq!(inner.and_then(SandboxError::raw_os_error), Some(CODE));
+    }
+
+    #[test]
+    fn alternate_shows_chain() {
+        let cmd = PathBuf::from("missing");
+        let mut diagnostics = LaunchDiagnostics::new(&cmd);
+        diagnostics.phase = LaunchPhase::Resolve;
+        let err = diagnostics.attach(SandboxError::resolution(
+            &cmd,
+            std::io::Error::new(std::io::ErrorKind::NotFound, "not on the path"),
+        ));
+        assert_eq!(err.to_string(), "could not resolve the command missing");
+        assert_eq!(
+            format!("{:#}", err),
+            "could not resolve the command missing: not on the path"
+        );
+        assert!(matches!(err, SandboxError::Resolution { .. }));
+        assert_eq!(
+            err.diagnostics().map(|d| d.phase),
+            Some(LaunchPhase::Resolve)
+        );
+        assert_eq!(err.io_kind(), Some(std::io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn attach_keeps_variant() {
+        let cmd = PathBuf::from("cmd");
+        let mut diagnostics = LaunchDiagnostics::new(&cmd);
+        diagnostics.phase = LaunchPhase::FdSetup;
+        let err = diagnostics.clone().attach(SandboxError::fd_setup(
+            Some(3),
+            std::io::Error::from_raw_os_error(CODE),
+        ));
+        assert!(matches!(err, SandboxError::FdSetup { fd: Some(3), .. }));
+        assert_eq!(err.diagnostics().and_then(|d| d.fd), Some(3));
+
+        // The first diagnostics attached are kept.
+        diagnostics.phase = LaunchPhase::Exec;
+        let err = diagnostics.clone().attach(err);
+        assert_eq!(
+            err.diagnostics().map(|d| d.phase),
+            Some(LaunchPhase::FdSetup)
+        );
+
+        let err = diagnostics.attach(SandboxError::InvalidFdSet("conflict".to_string()));
+        assert!(matches!(err, SandboxError::InvalidFdSet(_)));
+        assert!(err.diagnostics().is_none());
+    }
+
+    #[test]
+    fn io_round_trips() {
+        let err = SandboxError::from(std::io::Error::from_raw_os_error(CODE));
+        let io: std::io::Error = err.into();
+        assert_eq!(io.raw_os_error(), Some(CODE));
     }
 }
@@ -0,0 +1,229 @@
+// SPDX-License-Identifier: MIT
+
+//! Run sandboxed children from async code that isn't on Tokio.
+//!
+//! This is the `asynch` module for the `futures::io` traits.  `sandbox_child_async`
+//! launches the child and hands back an `AsyncChild`, whose streams implement
+//! `futures_io::AsyncRead` and `futures_io::AsyncWrite`, and whose `wait` is a future that
+//! completes when the child exits.  The streams and the exit future register with the
+//! `async-io` reactor (epoll on Linux), which smol and async-std also run on, so they work
+//! from any executor.
+//!
+//! Taking a stream puts it in non-blocking mode.  The streams still respect the `FdSet`
+//! output limits and log files.
+//!
+//! Only Linux is supported for now.
+
+use std::{
+    fs::File,
+    io::Read as _,
+    os::fd::OwnedFd,
+    pin::Pin,
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use ::futures_io::{AsyncRead, AsyncWrite};
+use async_io::{Async, Timer};
+
+use super::{
+    Child as _, ExitStatus, LaunchEnv, LaunchReport, ResourceUsage,
+    error::SandboxError,
+    spawn_linux::{LinuxChild, launch_child},
+    terminal::Terminal,
+};
+
+/// How long to sleep between exit status checks, for children without a pidfd.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Launch the child, and hand it back with async streams and an exit future.
+pub fn sandbox_child_async(env: LaunchEnv) -> Result<AsyncChild, SandboxError> {
+    AsyncChild::new(launch_child(env)?)
+}
+
+/// A sandboxed child supervised from async code, as returned by `sandbox_child_async`.
+///
+/// Dropping it terminates the child, unless `LaunchEnv::kill_on_drop` is turned off.
+pub struct AsyncChild {
+    /// The child's pidfd, which becomes readable once it exits; None if the kernel doesn't
+    /// support pidfds.
+    exited: Option<Async<OwnedFd>>,
+    inner: LinuxChild,
+}
+
+impl AsyncChild {
+    fn new(inner: LinuxChild) -> Result<Self, SandboxError> {
+        let exited = match inner.pidfd() {
+            Some(pidfd) => Some(Async::new(pidfd.try_clone_to_owned()?)?),
+            None => None,
+        };
+        Ok(AsyncChild { exited, inner })
+    }
+
+    /// Take the parent's end of a stream the child writes to.
+    pub fn take_stream_from_child(&mut self, fd: u32) -> Option<AsyncChildReader> {
+        let (raw, inner) = self.inner.take_async_reader(fd)?;
+        match Async::new(raw) {
+            Ok(ready) => Some(AsyncChildReader {
+                ready: Some(ready),
+                inner,
+            }),
+            Err(e) => {
+                crate::logging::warning!("could not register fd {} with the reactor: {}", fd, e);
+                None
+            }
+        }
+    }
+
+    /// Take the parent's end of a stream the child reads from.
+    pub fn take_stream_to_child(&mut self, fd: u32) -> Option<AsyncChildWriter> {
+        let file = self.inner.take_async_writer(fd)?;
+        match Async::new(file) {
+            Ok(file) => Some(AsyncChildWriter { inner: Some(file) }),
+            Err(e) => {
+                crate::logging::warning!("could not register fd {} with the reactor: {}", fd, e);
+                None
+            }
+        }
+    }
+
+    /// Take the stream for the named channel that the child writes to.
+    pub fn take_channel_from_child(&mut self, name: &str) -> Option<AsyncChildReader> {
+        let fd = self.channel_fd(name)?;
+        self.take_stream_from_child(fd)
+    }
+
+    /// Take the stream for the named channel that the child reads from.
+    pub fn take_channel_to_child(&mut self, name: &str) -> Option<AsyncChildWriter> {
+        let fd = self.channel_fd(name)?;
+        self.take_stream_to_child(fd)
+    }
+
+    /// The FD the child sees for the named channel.
+    pub fn channel_fd(&self, name: &str) -> Option<u32> {
+        self.inner.channel_fd(name)
+    }
+
+    /// The child's process ID.
+    pub fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    /// Kill the child, if it's still running.
+    pub fn terminate(&self) -> Result<(), std::io::Error> {
+        self.inner.terminate()
+    }
+
+    /// The child's pseudo-terminal, if it was launched with one.
+    pub fn terminal(&self) -> Option<Terminal> {
+        self.inner.terminal()
+    }
+
+    /// The resources the child used, or None if it is still running.
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.inner.resource_usage()
+    }
+
+    /// How long each phase of the launch took.
+    pub fn launch_report(&self) -> Option<LaunchReport> {
+        self.inner.launch_report()
+    }
+
+    /// How the child ended, or None if it is still running.  This doesn't block.
+    pub fn try_status(&self) -> Result<Option<ExitStatus>, std::io::Error> {
+        self.inner.exit_status()
+    }
+
+    /// Wait for the child to exit, without blocking the executor.
+    pub async fn wait(&self) -> Result<ExitStatus, std::io::Error> {
+        loop {
+            if let Some(status) = self.try_status()? {
+                return Ok(status);
+            }
+            match &self.exited {
+                Some(exited) => exited.readable().await?,
+                None => {
+                    Timer::after(EXIT_POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Terminate the child if it is still running, and get how it ended.
+    ///
+    /// To let the child end on its own, await `wait` first.
+    pub fn finish(self) -> Result<ExitStatus, SandboxError> {
+        let state = self.inner.state();
+        drop(self);
+        Ok(state.kill()?)
+    }
+}
+
+/// The parent's end of a stream the child writes to, read through the reactor.
+///
+/// Reads past the stream's output limit fail, as with the blocking streams.
+pub struct AsyncChildReader {
+    /// A duplicate of the stream's descriptor, registered with the reactor.  None once a
+    /// read failed, so a stream closed by its output limit isn't held open here.
+    ready: Option<Async<OwnedFd>>,
+    /// Reads the stream through its output limit and log file.
+    inner: Box<dyn std::io::Read + Send>,
+}
+
+impl AsyncRead for AsyncChildReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let Some(ready) = this.ready.as_ref() else {
+                // Reports the failure again, such as the exceeded limit.
+                return Poll::Ready(this.inner.read(buf));
+            };
+            match this.inner.read(buf) {
+                Ok(count) => return Poll::Ready(Ok(count)),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    ready!(ready.poll_readable(cx))?;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    this.ready = None;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// The parent's end of a stream the child reads from, written through the reactor.
+///
+/// Closing it closes the stream, so the child sees the end of its input.
+pub struct AsyncChildWriter {
+    /// None once closed.
+    inner: Option<Async<File>>,
+}
+
+impl AsyncWrite for AsyncChildWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut().inner.as_mut() {
+            Some(inner) => Pin::new(inner).poll_write(cx, buf),
+            None => Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        // Writes go straight to the pipe.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().inner = None;
+        Poll::Ready(Ok(()))
+    }
+}
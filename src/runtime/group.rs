@@ -0,0 +1,239 @@
+// SPDX-License-Identifier: MIT
+
+//! Supervise many sandboxed children from one thread.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout};
+
+use super::{
+    Child as _, ExitStatus, LaunchEnv, OwnedChild,
+    error::SandboxError,
+    select::{Selected, StreamSelector},
+};
+
+/// Identifies a child in a `ChildGroup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChildId(u64);
+
+/// Something that happened to a child in a `ChildGroup`, as reported by `ChildGroup::wait`.
+#[derive(Debug)]
+pub enum GroupEvent {
+    /// A queued child was launched, now that a running one exited.
+    Started(ChildId),
+    /// A queued child failed to launch.
+    LaunchFailed(ChildId, SandboxError),
+    /// The child's watched stream has data to read, or reached its end.  Read it with
+    /// `ChildGroup::read`.
+    Readable(ChildId, u32),
+    /// The child exited, which frees its place under the group's limit.
+    Exited(ChildId, ExitStatus),
+}
+
+/// Owns a set of sandboxed children, reporting their output and exits as one stream of
+/// `GroupEvent`s, and keeping at most `max_running` of them running at a time.
+///
+/// `spawn` launches the child right away while the group is under its limit, and otherwise
+/// queues it until a running child exits.  The group watches the output streams named at
+/// launch and the child's exit; call `wait` for the next events, and `read` to drain a
+/// readable stream.  A child leaves the group once it has exited and its watched streams
+/// have ended.  Dropping the group terminates its children, unless their
+/// `LaunchEnv::kill_on_drop` is turned off.
+///
+/// Only Linux is supported for now.  Exits are watched through pidfds, so on kernels
+/// before 5.3 the children never report `GroupEvent::Exited`.
+pub struct ChildGroup {
+    epoll: Epoll,
+    max_running: usize,
+    members: HashMap<ChildId, Member>,
+    queued: VecDeque<Queued>,
+    next_id: u64,
+}
+
+struct Member {
+    child: OwnedChild,
+    /// Watches the child's streams and exit; registered with the group's epoll.
+    selector: StreamSelector,
+    exited: bool,
+}
+
+struct Queued {
+    id: ChildId,
+    env: LaunchEnv,
+    watch: Vec<u32>,
+}
+
+impl ChildGroup {
+    /// An empty group that runs at most `max_running` children at a time.
+    pub fn new(max_running: usize) -> Result<Self, std::io::Error> {
+        Ok(ChildGroup {
+            epoll: Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?,
+            max_running: max_running.max(1),
+            members: HashMap::new(),
+            queued: VecDeque::new(),
+            next_id: 0,
+        })
+    }
+
+    /// Add a child to the group, watching its output streams for the FDs in `watch`.
+    ///
+    /// The child launches now if the group is under its limit, and any launch error is
+    /// returned.  Otherwise it waits in the queue, and `wait` reports when it starts.
+    pub fn spawn(&mut self, env: LaunchEnv, watch: &[u32]) -> Result<ChildId, SandboxError> {
+        let id = ChildId(self.next_id);
+        self.next_id += 1;
+        let queued = Queued {
+            id,
+            env,
+            watch: watch.to_vec(),
+        };
+        if self.running() < self.max_running {
+            self.launch(queued)?;
+        } else {
+            self.queued.push_back(queued);
+        }
+        Ok(id)
+    }
+
+    /// The number of children running, counting those whose exit isn't reported yet.
+    pub fn running(&self) -> usize {
+        self.members.values().filter(|m| !m.exited).count()
+    }
+
+    /// The number of children waiting for a place under the limit.
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// True once every child has left the group, and none are queued.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty() && self.queued.is_empty()
+    }
+
+    /// The launched child, to take its other streams or talk to it directly.
+    pub fn child(&mut self, id: ChildId) -> Option<&mut OwnedChild> {
+        self.members.get_mut(&id).map(|m| &mut m.child)
+    }
+
+    /// Read from the child's watched stream, after `wait` reported it readable.
+    ///
+    /// Once the stream ends, or fails, it's dropped, and later reads return 0.
+    pub fn read(&mut self, id: ChildId, fd: u32, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        let Some(member) = self.members.get_mut(&id) else {
+            return Ok(0);
+        };
+        let result = member.selector.read(fd, buf);
+        self.leave_if_done(id);
+        result
+    }
+
+    /// Terminate every running child, and drop the queued ones without launching them.
+    ///
+    /// Their exits are still reported by `wait`.  Returns the first error, after trying
+    /// every child.
+    pub fn terminate_all(&mut self) -> Result<(), std::io::Error> {
+        self.queued.clear();
+        let mut ret = Ok(());
+        for member in self.members.values().filter(|m| !m.exited) {
+            if let Err(e) = member.child.terminate()
+                && ret.is_ok()
+            {
+                ret = Err(e);
+            }
+        }
+        ret
+    }
+
+    /// Block until something happens to a child, or the timeout passes.
+    ///
+    /// Returns everything that happened, which is empty if the timeout passed or the
+    /// group is empty.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Vec<GroupEvent>, std::io::Error> {
+        let mut ret = self.start_queued();
+        if !ret.is_empty() || self.members.is_empty() {
+            return Ok(ret);
+        }
+        let timeout = match timeout {
+            None => EpollTimeout::NONE,
+            // Round up, so a sub-millisecond timeout doesn't spin.
+            Some(t) => {
+                EpollTimeout::try_from(t.as_micros().div_ceil(1000)).unwrap_or(EpollTimeout::MAX)
+            }
+        };
+        let mut events = vec![EpollEvent::empty(); self.members.len()];
+        let count = loop {
+            match self.epoll.wait(&mut events, timeout) {
+                Err(nix::errno::Errno::EINTR) => continue,
+                r => break r?,
+            }
+        };
+        for event in &events[..count] {
+            let id = ChildId(event.data());
+            let Some(member) = self.members.get_mut(&id) else {
+                continue;
+            };
+            for selected in member.selector.wait(Some(Duration::ZERO))? {
+                match selected {
+                    Selected::Readable(fd) => ret.push(GroupEvent::Readable(id, fd)),
+                    Selected::Exited => {
+                        member.exited = true;
+                        let status = member.child.ender().finish();
+                        let status = status.map_err(Into::<std::io::Error>::into)?;
+                        ret.push(GroupEvent::Exited(id, status));
+                    }
+                }
+            }
+            self.leave_if_done(id);
+        }
+        ret.extend(self.start_queued());
+        Ok(ret)
+    }
+
+    fn launch(&mut self, queued: Queued) -> Result<(), SandboxError> {
+        let mut child = super::spawn(queued.env)?;
+        let selector = StreamSelector::from_child(&mut child, &queued.watch)?;
+        let event = EpollEvent::new(EpollFlags::EPOLLIN, queued.id.0);
+        self.epoll
+            .add(&selector, event)
+            .map_err(std::io::Error::from)?;
+        self.members.insert(
+            queued.id,
+            Member {
+                child,
+                selector,
+                exited: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Launch queued children while the group is under its limit.
+    fn start_queued(&mut self) -> Vec<GroupEvent> {
+        let mut ret = Vec::new();
+        while self.running() < self.max_running {
+            let Some(queued) = self.queued.pop_front() else {
+                break;
+            };
+            let id = queued.id;
+            match self.launch(queued) {
+                Ok(()) => ret.push(GroupEvent::Started(id)),
+                Err(e) => ret.push(GroupEvent::LaunchFailed(id, e)),
+            }
+        }
+        ret
+    }
+
+    /// Drop the child from the group once it exited and its streams ended.
+    fn leave_if_done(&mut self, id: ChildId) {
+        let done = match self.members.get(&id) {
+            Some(member) => member.exited && member.selector.is_empty(),
+            None => false,
+        };
+        if done && let Some(member) = self.members.remove(&id) {
+            let _ = self.epoll.delete(&member.selector);
+        }
+    }
+}
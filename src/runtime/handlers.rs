@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: MIT
+
+//! Ready-made `CommHandler`s for children that only print, so simple integrations don't
+//! need their own pump loop.
+//!
+//! Each handler reads the child's STDOUT and STDERR together, so a child filling one pipe
+//! can't stall, then waits for the child to exit.  Only the streams the `FdSet` sends to
+//! the parent (`FdMode::FromChild`) are read.
+//!
+//! On Linux, the streams are watched with a `StreamSelector`.  Windows has no selector
+//! yet, so the streams are polled in turn; launch with `FdSet::with_nonblocking` there, or
+//! each read waits until its own stream has data.
+//!
+//! With the `log` feature, `StderrLogHandler` sends the child's STDERR to the `log` crate.
+
+use std::{collections::HashMap, io::Write as _};
+
+use super::{Child, CommHandler};
+
+/// Copies the child's STDOUT and STDERR to the parent's own, and the parent's STDIN to
+/// the child, as if the child were run directly from the parent's terminal.
+///
+/// The parent's STDIN is copied from a background thread, which ends at the next input
+/// after the child's STDIN closes.  Use `without_stdin` to close the child's STDIN
+/// instead, so the parent keeps its own input.
+#[derive(Debug, Clone)]
+pub struct PassthroughHandler {
+    forward_stdin: bool,
+}
+
+impl PassthroughHandler {
+    pub fn new() -> Self {
+        PassthroughHandler {
+            forward_stdin: true,
+        }
+    }
+
+    /// Close the child's STDIN rather than copying the parent's STDIN to it.
+    pub fn without_stdin(mut self) -> Self {
+        self.forward_stdin = false;
+        self
+    }
+}
+
+impl Default for PassthroughHandler {
+    fn default() -> Self {
+        PassthroughHandler::new()
+    }
+}
+
+impl CommHandler for PassthroughHandler {
+    type Output = ();
+    type Error = std::io::Error;
+
+    fn handle(&mut self, mut child: Box<dyn Child>) -> Result<(), std::io::Error> {
+        let stdin = child.take_stream_to_child(0);
+        if self.forward_stdin
+            && let Some(stdin) = stdin
+        {
+            let mut stdin = stdin.into_inner();
+            std::thread::spawn(move || std::io::copy(&mut std::io::stdin(), &mut stdin));
+        }
+        pump(child.as_mut(), &[1, 2], |fd, data| match fd {
+            1 => {
+                let mut out = std::io::stdout().lock();
+                out.write_all(data)?;
+                out.flush()
+            }
+            _ => std::io::stderr().write_all(data),
+        })?;
+        child.wait()?;
+        Ok(())
+    }
+}
+
+/// Calls the closure with each line the child writes to STDOUT (FD 1) or STDERR (FD 2).
+///
+/// The lines are passed without their line ending, and bytes that aren't UTF-8 are
+/// replaced.  A last line without a line ending is still passed once its stream ends.
+/// The child's STDIN is closed right away.
+pub struct LineHandler<F> {
+    on_line: F,
+}
+
+impl<F: FnMut(u32, &str)> LineHandler<F> {
+    pub fn new(on_line: F) -> Self {
+        LineHandler { on_line }
+    }
+}
+
+impl<F: FnMut(u32, &str)> CommHandler for LineHandler<F> {
+    type Output = ();
+    type Error = std::io::Error;
+
+    fn handle(&mut self, mut child: Box<dyn Child>) -> Result<(), std::io::Error> {
+        drop(child.take_stream_to_child(0));
+        let on_line = &mut self.on_line;
+        let mut partial: HashMap<u32, LineBuffer> = HashMap::new();
+        pump(child.as_mut(), &[1, 2], |fd, data| {
+            partial
+                .entry(fd)
+                .or_default()
+                .push(data, |line| on_line(fd, line));
+            Ok(())
+        })?;
+        for fd in [1, 2] {
+            if let Some(rest) = partial.remove(&fd) {
+                rest.finish(|line| on_line(fd, line));
+            }
+        }
+        child.wait()?;
+        Ok(())
+    }
+}
+
+/// Sends each line the child writes to STDERR (FD 2) to the `log` crate, and hands back
+/// everything it writes to STDOUT (FD 1) once it exits.  The child's STDIN is closed
+/// right away.
+///
+/// Each line is logged at the handler's target and level, without its line ending, with
+/// the child's process ID as the `pid` field and the handler's name, if set, as the
+/// `child` field.  The target defaults to `gracklezero::child`, and the level to `Warn`.
+#[cfg(feature = "log")]
+#[derive(Debug, Clone)]
+pub struct StderrLogHandler {
+    target: String,
+    level: log::Level,
+    name: Option<String>,
+}
+
+#[cfg(feature = "log")]
+impl StderrLogHandler {
+    pub fn new() -> Self {
+        StderrLogHandler {
+            target: "gracklezero::child".to_string(),
+            level: log::Level::Warn,
+            name: None,
+        }
+    }
+
+    /// Log under the target, such as the module that launches the child.
+    pub fn with_target(mut self, target: &str) -> Self {
+        self.target = target.to_string();
+        self
+    }
+
+    /// Log at the level.
+    pub fn with_level(mut self, level: log::Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Name the child in each line's `child` field, to tell apart children that run the
+    /// same way.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    fn log_line(&self, pid: u32, line: &str) {
+        let name = self.name.as_deref();
+        log::log!(target: &self.target, self.level, pid = pid, child = name; "{}", line);
+    }
+}
+
+#[cfg(feature = "log")]
+impl Default for StderrLogHandler {
+    fn default() -> Self {
+        StderrLogHandler::new()
+    }
+}
+
+#[cfg(feature = "log")]
+impl CommHandler for StderrLogHandler {
+    type Output = Vec<u8>;
+    type Error = std::io::Error;
+
+    fn handle(&mut self, mut child: Box<dyn Child>) -> Result<Vec<u8>, std::io::Error> {
+        drop(child.take_stream_to_child(0));
+        let pid = child.id();
+        let mut stdout = Vec::new();
+        let mut stderr = LineBuffer::default();
+        pump(child.as_mut(), &[1, 2], |fd, data| {
+            match fd {
+                1 => stdout.extend_from_slice(data),
+                _ => stderr.push(data, |line| self.log_line(pid, line)),
+            }
+            Ok(())
+        })?;
+        stderr.finish(|line| self.log_line(pid, line));
+        child.wait()?;
+        Ok(stdout)
+    }
+}
+
+/// Splits a stream into lines as its data arrives, holding back the last partial line.
+#[derive(Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Add the data, and pass each line it completes.
+    fn push<F: FnMut(&str)>(&mut self, data: &[u8], mut on_line: F) {
+        self.pending.extend_from_slice(data);
+        let mut start = 0;
+        while let Some(end) = self.pending[start..].iter().position(|b| *b == b'\n') {
+            emit_line(&mut on_line, &self.pending[start..start + end]);
+            start += end + 1;
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Pass the last line, if the stream ended without a line ending.
+    fn finish<F: FnMut(&str)>(self, mut on_line: F) {
+        if !self.pending.is_empty() {
+            emit_line(&mut on_line, &self.pending);
+        }
+    }
+}
+
+fn emit_line<F: FnMut(&str)>(on_line: &mut F, line: &[u8]) {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    on_line(&String::from_utf8_lossy(line));
+}
+
+/// Buffers everything the child writes to STDOUT and STDERR, and hands both back, in that
+/// order, once the child exits.  The child's STDIN is closed right away.
+///
+/// This is the handler behind `runtime::run`.
+#[derive(Debug, Clone, Default)]
+pub struct CollectingHandler;
+
+impl CollectingHandler {
+    pub fn new() -> Self {
+        CollectingHandler
+    }
+}
+
+impl CommHandler for CollectingHandler {
+    type Output = (Vec<u8>, Vec<u8>);
+    type Error = std::io::Error;
+
+    fn handle(&mut self, mut child: Box<dyn Child>) -> Result<Self::Output, std::io::Error> {
+        drop(child.take_stream_to_child(0));
+        let mut ret = [Vec::new(), Vec::new()];
+        pump(child.as_mut(), &[1, 2], |fd, data| {
+            ret[fd as usize - 1].extend_from_slice(data);
+            Ok(())
+        })?;
+        child.wait()?;
+        let [stdout, stderr] = ret;
+        Ok((stdout, stderr))
+    }
+}
+
+/// Take the child's streams for the FDs, and read them together until they all end,
+/// passing each chunk to `on_data` with its FD.
+#[cfg(target_os = "linux")]
+pub(crate) fn pump<F>(child: &mut dyn Child, fds: &[u32], mut on_data: F) -> std::io::Result<()>
+where
+    F: FnMut(u32, &[u8]) -> std::io::Result<()>,
+{
+    use super::select::{Selected, StreamSelector};
+
+    let mut selector = StreamSelector::new()?;
+    for fd in fds {
+        if let Some(stream) = child.take_stream_from_child(*fd) {
+            selector.add(*fd, stream)?;
+        }
+    }
+    let mut buf = [0u8; 8192];
+    while !selector.is_empty() {
+        for ready in selector.wait(None)? {
+            if let Selected::Readable(fd) = ready {
+                let count = selector.read(fd, &mut buf)?;
+                if count > 0 {
+                    on_data(fd, &buf[..count])?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Take the child's streams for the FDs, and read them together until they all end,
+/// passing each chunk to `on_data` with its FD.  Non-blocking streams are swept in turn,
+/// sleeping when none has data.
+#[cfg(target_os = "windows")]
+pub(crate) fn pump<F>(child: &mut dyn Child, fds: &[u32], mut on_data: F) -> std::io::Result<()>
+where
+    F: FnMut(u32, &[u8]) -> std::io::Result<()>,
+{
+    use std::io::{ErrorKind, Read as _};
+
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(5);
+
+    let mut streams: Vec<_> = fds
+        .iter()
+        .filter_map(|fd| Some((*fd, child.take_stream_from_child(*fd)?)))
+        .collect();
+    let mut buf = [0u8; 8192];
+    while !streams.is_empty() {
+        let mut progress = false;
+        let mut index = 0;
+        while index < streams.len() {
+            let (fd, stream) = &mut streams[index];
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    streams.remove(index);
+                    progress = true;
+                    continue;
+                }
+                Ok(count) => {
+                    on_data(*fd, &buf[..count])?;
+                    progress = true;
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(e),
+            }
+            index += 1;
+        }
+        if !progress {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+    Ok(())
+}
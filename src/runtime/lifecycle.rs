@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: MIT
+
+//! Callbacks for each phase of a child's launch and end, for telemetry and audit logs.
+
+use std::path::Path;
+
+use super::ExitStatus;
+
+/// Told about each phase of launching and ending a child, in order.  Attach it with
+/// `LaunchEnv::observer`.
+///
+/// Every method does nothing by default, so an observer only implements the phases it
+/// cares about.  The methods run on the launching thread, in the parent process, so keep
+/// them quick.  The child's process ID identifies it across the calls.
+///
+/// * `on_resolved` once the executable is found, before anything is launched.  On Linux,
+///   a `SandboxTemplate` or `Zygote` reports this once, when it's prepared.
+/// * `on_forked` once the child process exists, but before it's restricted.
+/// * `on_restricted` once the child is inside its jail.
+/// * `on_exec` once the child starts running the executable.  A child that fails to
+///   exec skips this, and the launch fails with `SandboxError::Exec`.
+/// * `on_exit` when the child ended on its own, or `on_killed` when the sandbox killed
+///   it.  These are reported by `sandbox_child`, `SandboxTemplate::spawn`, and
+///   `OwnedChild::finish`, when they collect the exit status.
+///
+/// On Windows, the child is created suspended inside its job and resumed before the
+/// launch returns, so `on_forked`, `on_restricted`, and `on_exec` are reported together.
+/// Children launched by a `Zygote` only report `on_resolved`.
+pub trait LifecycleObserver: Send + Sync {
+    /// The executable that will run, after resolving `LaunchEnv::cmd`.
+    fn on_resolved(&self, _exec: &Path) {}
+
+    /// The child process was created.
+    fn on_forked(&self, _pid: u32) {}
+
+    /// The child applied its restrictions.
+    fn on_restricted(&self, _pid: u32) {}
+
+    /// The child started running the executable.
+    fn on_exec(&self, _pid: u32) {}
+
+    /// The child exited, or the OS ended it.
+    fn on_exit(&self, _pid: u32, _status: ExitStatus) {}
+
+    /// The sandbox killed the child.
+    fn on_killed(&self, _pid: u32) {}
+}
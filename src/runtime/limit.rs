@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: MIT
+
+//! Cap how much output the handler reads from a child's stream.
+
+use std::{collections::HashMap, fs::File, io::Read};
+
+use crate::{
+    logging,
+    runtime::{
+        FdSet,
+        tee::{TeeReader, open_tees},
+    },
+};
+
+/// What happens once a stream reaches its output limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitAction {
+    /// Close the parent's end of the stream, so the child's next write fails.
+    CloseStream,
+    /// Terminate the child, as `Child::terminate` does.
+    Terminate,
+}
+
+/// Caps the bytes the handler can read from a child's output stream, so a child that
+/// floods its output can't make a collecting handler use unbounded memory.  Add it with
+/// `FdSet::with_output_limit`.
+///
+/// The handler reads up to `max_bytes`, and every read after that fails with
+/// `std::io::ErrorKind::Other`, once the limit's action has been taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputLimit {
+    pub max_bytes: u64,
+    pub action: LimitAction,
+}
+
+impl OutputLimit {
+    /// Close the stream after the child sends `max_bytes`.
+    pub fn close_after(max_bytes: u64) -> Self {
+        OutputLimit {
+            max_bytes,
+            action: LimitAction::CloseStream,
+        }
+    }
+
+    /// Terminate the child after it sends `max_bytes`.
+    pub fn terminate_after(max_bytes: u64) -> Self {
+        OutputLimit {
+            max_bytes,
+            action: LimitAction::Terminate,
+        }
+    }
+}
+
+/// Stops the child, for `LimitAction::Terminate`.
+pub(crate) type Terminator = Box<dyn Fn() -> Result<(), std::io::Error>>;
+
+/// A stream taken from the child, capped at its limit and copied to its log file.
+pub(crate) type Guarded<R, T> = TeeReader<LimitedReader<R, T>>;
+
+/// The output limits and open log files for a launched child's streams.
+#[derive(Default)]
+pub(crate) struct OutputGuards {
+    limits: HashMap<u32, OutputLimit>,
+    tees: HashMap<u32, File>,
+}
+
+impl OutputGuards {
+    /// Open the set's log files, in the parent.
+    pub(crate) fn open(fds: &FdSet) -> Result<Self, std::io::Error> {
+        Ok(OutputGuards {
+            limits: fds.output_limits(),
+            tees: open_tees(&fds.tees())?,
+        })
+    }
+
+    /// Cap the stream taken from the child, then copy it to its log file.
+    pub(crate) fn wrap(
+        &mut self,
+        fd: u32,
+        stream: Box<dyn Read>,
+        terminate: Terminator,
+    ) -> Box<dyn Read> {
+        Box::new(self.guard(fd, stream, terminate))
+    }
+
+    /// Like `wrap`, but keeps the stream's type, so it stays `Send` if its parts are.
+    pub(crate) fn guard<R, T>(&mut self, fd: u32, stream: R, terminate: T) -> Guarded<R, T>
+    where
+        R: Read,
+        T: Fn() -> Result<(), std::io::Error>,
+    {
+        let stream = LimitedReader::new(fd, stream, self.limits.remove(&fd), terminate);
+        TeeReader::new(stream, self.tees.remove(&fd))
+    }
+}
+
+/// Caps the stream at its limit, if it has one.
+pub(crate) struct LimitedReader<R, T> {
+    fd: u32,
+    /// None once the limit is reached.
+    inner: Option<R>,
+    /// None if the stream has no limit.
+    remaining: Option<u64>,
+    action: LimitAction,
+    terminate: T,
+}
+
+impl<R, T> LimitedReader<R, T> {
+    pub(crate) fn new(fd: u32, inner: R, limit: Option<OutputLimit>, terminate: T) -> Self {
+        LimitedReader {
+            fd,
+            inner: Some(inner),
+            remaining: limit.map(|l| l.max_bytes),
+            action: limit.map_or(LimitAction::CloseStream, |l| l.action),
+            terminate,
+        }
+    }
+
+    fn exceeded(&self) -> std::io::Error {
+        std::io::Error::other(format!("fd {} exceeded its output limit", self.fd))
+    }
+}
+
+impl<R, T> Read for LimitedReader<R, T>
+where
+    R: Read,
+    T: Fn() -> Result<(), std::io::Error>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Err(self.exceeded());
+        };
+        let Some(remaining) = self.remaining else {
+            return inner.read(buf);
+        };
+        if remaining > 0 {
+            let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+            let count = inner.read(&mut buf[..len])?;
+            self.remaining = Some(remaining - count as u64);
+            return Ok(count);
+        }
+        // Only a read past the limit tells a flood apart from output that fits exactly.
+        let mut probe = [0u8; 1];
+        if inner.read(&mut probe)? == 0 {
+            return Ok(0);
+        }
+        logging::warning!("fd {} exceeded its output limit", self.fd);
+        self.inner = None;
+        if self.action == LimitAction::Terminate {
+            (self.terminate)()?;
+        }
+        Err(self.exceeded())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    #[test]
+    fn stops_at_limit() {
+        let source = &b"0123456789"[..];
+        let mut reader =
+            LimitedReader::new(1, source, Some(OutputLimit::close_after(4)), || Ok(()));
+        let mut got = Vec::new();
+        let err = reader
+            .read_to_end(&mut got)
+            .expect_err("should exceed the limit");
+        assert_eq!(err.kind(), std::io::ErrorKind::Other);
+        assert_eq!(got, b"0123");
+        assert!(reader.read(&mut [0u8; 4]).is_err());
+    }
+
+    #[test]
+    fn exact_fit_is_not_exceeded() {
+        let source = &b"0123"[..];
+        let mut reader =
+            LimitedReader::new(1, source, Some(OutputLimit::close_after(4)), || Ok(()));
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).expect("should fit");
+        assert_eq!(got, b"0123");
+    }
+
+    #[test]
+    fn terminates_child() {
+        let called = Rc::new(Cell::new(false));
+        let flag = called.clone();
+        let source = &b"0123456789"[..];
+        let mut reader = LimitedReader::new(
+            1,
+            source,
+            Some(OutputLimit::terminate_after(2)),
+            move || {
+                flag.set(true);
+                Ok(())
+            },
+        );
+        let mut got = Vec::new();
+        assert!(reader.read_to_end(&mut got).is_err());
+        assert!(called.get());
+    }
+}
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: MIT
+
+//! A launched child that the caller owns, rather than handing it to a `CommHandler`.
+
+use std::{sync::Arc, time::Duration};
+
+use super::{
+    Child, ChildKiller, ExitStatus, LaunchReport, ResourceUsage, SandboxDenial,
+    error::SandboxError,
+    lifecycle::LifecycleObserver,
+    stream::{ChildReader, ChildWriter},
+    terminal::Terminal,
+};
+
+#[cfg(target_os = "linux")]
+type PlatformChild = super::spawn_linux::LinuxChild;
+
+#[cfg(target_os = "windows")]
+type PlatformChild = super::spawn_windows::WindowsChild;
+
+/// A sandboxed child that the caller owns directly, as returned by `runtime::spawn`.
+///
+/// The caller takes the streams, talks to the child, and waits on it through the `Child`
+/// methods, without writing a `CommHandler`.  Call `finish` to get the child's
+/// `ExitStatus`.  Dropping it instead terminates the child, unless
+/// `LaunchEnv::kill_on_drop` is turned off.
+pub struct OwnedChild {
+    inner: PlatformChild,
+}
+
+impl OwnedChild {
+    pub(crate) fn new(inner: PlatformChild) -> Self {
+        OwnedChild { inner }
+    }
+
+    /// Terminate the child if it is still running, and get how it ended.
+    ///
+    /// To let the child end on its own, call `wait` first.
+    pub fn finish(self) -> Result<ExitStatus, SandboxError> {
+        let ender = self.ender();
+        drop(self);
+        ender.finish()
+    }
+
+    /// What's needed to stop the child, after it was handed to a handler.
+    pub(crate) fn ender(&self) -> ChildEnder {
+        ChildEnder {
+            state: self.inner.state(),
+            pid: self.inner.id(),
+            observer: self.inner.observer(),
+        }
+    }
+}
+
+/// Stops the child and collects its status, outliving the `OwnedChild`.
+pub(crate) struct ChildEnder {
+    #[cfg(target_os = "linux")]
+    state: super::spawn_linux::LinuxChildState,
+    #[cfg(target_os = "windows")]
+    state: super::spawn_windows::ProcessState,
+    pid: u32,
+    observer: Option<Arc<dyn LifecycleObserver>>,
+}
+
+impl ChildEnder {
+    /// Terminate the child if it is still running, and get how it ended.
+    pub(crate) fn finish(&self) -> Result<ExitStatus, SandboxError> {
+        let status = self.collect()?;
+        if let Some(observer) = &self.observer {
+            match status {
+                ExitStatus::SandboxKilled => observer.on_killed(self.pid),
+                _ => observer.on_exit(self.pid, status),
+            }
+        }
+        Ok(status)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn collect(&self) -> Result<ExitStatus, SandboxError> {
+        self.state.kill().map_err(SandboxError::Wait)
+    }
+
+    #[cfg(target_os = "windows")]
+    fn collect(&self) -> Result<ExitStatus, SandboxError> {
+        self.state.kill().map_err(SandboxError::Wait)
+    }
+}
+
+impl Child for OwnedChild {
+    fn terminate(&self) -> Result<(), std::io::Error> {
+        self.inner.terminate()
+    }
+
+    fn take_stream_from_child(&mut self, fd: u32) -> Option<ChildReader> {
+        self.inner.take_stream_from_child(fd)
+    }
+
+    fn take_stream_to_child(&mut self, fd: u32) -> Option<ChildWriter> {
+        self.inner.take_stream_to_child(fd)
+    }
+
+    fn channel_fd(&self, name: &str) -> Option<u32> {
+        self.inner.channel_fd(name)
+    }
+
+    fn exit_status(&self) -> Result<Option<ExitStatus>, std::io::Error> {
+        self.inner.exit_status()
+    }
+
+    fn wait(&self) -> Result<ExitStatus, std::io::Error> {
+        self.inner.wait()
+    }
+
+    fn wait_timeout(&self, timeout: Duration) -> Result<Option<ExitStatus>, std::io::Error> {
+        self.inner.wait_timeout(timeout)
+    }
+
+    fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn pidfd(&self) -> Option<std::os::fd::BorrowedFd<'_>> {
+        self.inner.pidfd()
+    }
+
+    #[cfg(target_os = "windows")]
+    fn process_handle(&self) -> Option<std::os::windows::io::BorrowedHandle<'_>> {
+        self.inner.process_handle()
+    }
+
+    fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.inner.resource_usage()
+    }
+
+    fn launch_report(&self) -> Option<LaunchReport> {
+        self.inner.launch_report()
+    }
+
+    fn killer(&self) -> Option<ChildKiller> {
+        self.inner.killer()
+    }
+
+    fn denials(&self) -> Vec<SandboxDenial> {
+        self.inner.denials()
+    }
+
+    fn terminal(&self) -> Option<Terminal> {
+        self.inner.terminal()
+    }
+}
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: MIT
+
+//! Chain sandboxed children like a shell pipeline.
+
+use super::{
+    Child as _, ExitStatus, FdMode, LaunchEnv, OwnedChild,
+    error::SandboxError,
+    stream::{ChildReader, ChildWriter},
+};
+
+/// Launch the children with each one's STDOUT connected straight to the next one's STDIN,
+/// like `a | b | c` in a shell.
+///
+/// The pipes between the children are plain OS pipes handed to them through
+/// `FdMode::inherit`, so the data never passes through the parent.  The FD 1 mode of every
+/// child but the last, and the FD 0 mode of every child but the first, are replaced; the
+/// other FDs, such as STDERR, keep the modes from their `LaunchEnv`.  Use
+/// `Pipeline::take_stdin` and `Pipeline::take_stdout` for the ends of the pipeline, when
+/// the first child's FD 0 is `FdMode::ToChild` and the last child's FD 1 is
+/// `FdMode::FromChild`.
+///
+/// If a child fails to launch, the ones already launched are dropped, which terminates
+/// them unless their `LaunchEnv::kill_on_drop` is turned off.
+pub fn pipeline(envs: &[LaunchEnv]) -> Result<Pipeline, SandboxError> {
+    if envs.is_empty() {
+        return Err(SandboxError::ProcessError(
+            "a pipeline needs at least one child".to_string(),
+        ));
+    }
+    let mut children = Vec::with_capacity(envs.len());
+    let mut upstream = None;
+    for (index, env) in envs.iter().enumerate() {
+        let mut env = env.clone();
+        if let Some(reader) = upstream.take() {
+            env.fds = env.fds.with_fd(0, FdMode::inherit(reader));
+        }
+        if index + 1 < envs.len() {
+            let (reader, writer) = std::io::pipe()?;
+            env.fds = env.fds.with_fd(1, FdMode::inherit(writer));
+            upstream = Some(reader);
+        }
+        // Launching consumes the env, which closes the parent's copies of the pipe ends
+        // given to this child.
+        children.push(super::spawn(env)?);
+    }
+    Ok(Pipeline { children })
+}
+
+/// The children launched by `runtime::pipeline`, in pipeline order.
+///
+/// Dropping it terminates the children, unless their `LaunchEnv::kill_on_drop` is turned
+/// off.
+pub struct Pipeline {
+    children: Vec<OwnedChild>,
+}
+
+impl Pipeline {
+    /// Take the parent's end of the first child's STDIN.  Drop it to end the pipeline's
+    /// input.
+    pub fn take_stdin(&mut self) -> Option<ChildWriter> {
+        self.children.first_mut()?.take_stream_to_child(0)
+    }
+
+    /// Take the parent's end of the last child's STDOUT.
+    pub fn take_stdout(&mut self) -> Option<ChildReader> {
+        self.children.last_mut()?.take_stream_from_child(1)
+    }
+
+    /// The child at the position in the pipeline, to take its other streams, such as
+    /// STDERR.
+    pub fn child(&mut self, index: usize) -> Option<&mut OwnedChild> {
+        self.children.get_mut(index)
+    }
+
+    /// The number of children in the pipeline.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Always false; a pipeline has at least one child.
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Wait for every child to exit, and get how each one ended, in pipeline order.
+    pub fn wait(&self) -> Result<Vec<ExitStatus>, std::io::Error> {
+        self.children.iter().map(|child| child.wait()).collect()
+    }
+
+    /// Terminate the children still running, and get how each one ended, in pipeline
+    /// order.
+    ///
+    /// To let the pipeline end on its own, call `wait` first.
+    pub fn finish(self) -> Result<Vec<ExitStatus>, SandboxError> {
+        self.children.into_iter().map(OwnedChild::finish).collect()
+    }
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MIT
+
+//! Work out what the sandbox would enforce for a launch, without launching it.
+
+use std::path::PathBuf;
+
+use super::{LaunchEnv, error::SandboxError};
+use crate::restrictions::{CpuPlacement, ResourceLimits, windows::AppContainerCapability};
+
+/// The concrete rules the sandbox would apply to a launch, as returned by `plan`, for
+/// policy review and debugging.
+///
+/// Fields that only one platform enforces are empty, or None, on the others.
+///
+/// With the `serde` feature, the plan serializes, such as to JSON, so each launch's
+/// permissions can be kept in an audit log and compared across versions.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SandboxPlan {
+    /// The executable `LaunchEnv::cmd` resolved to.
+    pub exec: PathBuf,
+    /// The interpreter that runs `exec`, when it's a script.  Linux only.
+    pub interpreter: Option<PathBuf>,
+    /// The directory the child runs in.
+    pub cwd: PathBuf,
+    /// The Landlock rules for reading: the child may read these files, and everything
+    /// beneath these directories.  This covers the executable, its shared libraries, and
+    /// the paths the `LaunchEnv` grants.  Linux only.
+    pub readable_paths: Vec<PathBuf>,
+    /// The Landlock rules for writing.  Linux only.
+    pub writable_paths: Vec<PathBuf>,
+    /// The syscalls the seccomp filter allows; every other syscall is denied.  Linux only.
+    pub allowed_syscalls: Vec<String>,
+    /// A denied syscall kills the child, rather than failing with `EPERM`.  Linux only.
+    pub syscall_violation_kills: bool,
+    /// The TCP ports the child may connect to.  Linux only; on Windows, the AppContainer's
+    /// network capabilities don't limit the ports.
+    pub tcp_connect_ports: Vec<u16>,
+    /// The TCP ports the child may listen on.  Linux only.
+    pub tcp_bind_ports: Vec<u16>,
+    /// The limit on the child's open files.  Linux only.
+    pub max_open_files: Option<u64>,
+    /// The memory and CPU limits: rlimits on Linux, and the job's limits on Windows.
+    pub resource_limits: ResourceLimits,
+    /// The processors the child may run on.
+    pub cpu_placement: CpuPlacement,
+    /// The AppContainer profile the child runs in, or None when it's disabled.  Windows
+    /// only.
+    pub app_container: Option<String>,
+    /// The capabilities granted to the AppContainer.  Windows only.
+    pub capabilities: Vec<AppContainerCapability>,
+}
+
+/// Resolve the executable and find its dependencies, as a launch would, and return the
+/// rules the sandbox would apply, without launching anything.
+///
+/// This fails the same way the launch would, such as when the executable or one of its
+/// libraries can't be found.
+///
+/// ```no_run
+/// use gracklezero::{LaunchEnv, compat_restrictions, runtime::plan};
+///
+/// let plan = plan(&LaunchEnv::new("/usr/bin/uname".into(), compat_restrictions!("app")))
+///     .expect("plan failed");
+/// for path in &plan.readable_paths {
+///     println!("may read {:?}", path);
+/// }
+/// ```
+#[cfg(target_os = "linux")]
+pub fn plan(env: &LaunchEnv) -> Result<SandboxPlan, SandboxError> {
+    super::spawn_linux::plan(env)
+}
+
+/// Resolve the executable, as a launch would, and return the rules the sandbox would
+/// apply, without launching anything.
+#[cfg(target_os = "windows")]
+pub fn plan(env: &LaunchEnv) -> Result<SandboxPlan, SandboxError> {
+    super::spawn_windows::plan(env)
+}
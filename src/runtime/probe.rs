@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: MIT
+
+//! Check which sandbox mechanisms the host supports, before launching anything.
+
+use std::fmt::Display;
+
+use crate::runtime::{LaunchEnv, error::SandboxError};
+
+/// The sandbox mechanisms the host supports, as returned by `probe`.
+///
+/// Fields for the other platform's mechanisms are empty, or false.
+///
+/// With the `serde` feature, the capabilities serialize, such as to JSON for fleet
+/// inventories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HostCapabilities {
+    /// `linux` or `windows`.
+    pub os: String,
+    /// The kernel release on Linux, or the Windows version as `major.minor.build`.
+    pub os_version: String,
+    /// The Landlock ABI version the kernel supports, or None when Landlock isn't built
+    /// in or is disabled.  The jail needs version 1; network rules need version 4.
+    /// Linux only.
+    pub landlock_abi: Option<u32>,
+    /// The kernel supports seccomp filters.  Linux only.
+    pub seccomp: bool,
+    /// Unprivileged processes may create user namespaces.  The sandbox doesn't use them
+    /// yet.  Linux only.
+    pub user_namespaces: bool,
+    /// The host supports AppContainers.  Windows only.
+    pub app_container: bool,
+    /// This process runs privileged, as described by `LaunchEnv::allow_privileged_parent`,
+    /// so launches fail unless that is set.
+    pub privileged: bool,
+}
+
+impl HostCapabilities {
+    /// Does the host support everything the sandbox requires to launch a child?
+    pub fn can_sandbox(&self) -> bool {
+        match self.os.as_str() {
+            "linux" => self.landlock_abi.is_some() && self.seccomp,
+            "windows" => self.app_container,
+            _ => false,
+        }
+    }
+}
+
+impl Display for HostCapabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        writeln!(f, "os:              {} {}", self.os, self.os_version)?;
+        if self.os == "linux" {
+            match self.landlock_abi {
+                Some(abi) => writeln!(f, "landlock:        ABI {}", abi)?,
+                None => writeln!(f, "landlock:        unavailable")?,
+            }
+            writeln!(f, "seccomp:         {}", yes_no(self.seccomp))?;
+            writeln!(f, "user namespaces: {}", yes_no(self.user_namespaces))?;
+        } else {
+            writeln!(f, "app container:   {}", yes_no(self.app_container))?;
+        }
+        writeln!(f, "privileged:      {}", yes_no(self.privileged))?;
+        writeln!(f, "can sandbox:     {}", yes_no(self.can_sandbox()))
+    }
+}
+
+/// Check which sandbox mechanisms the host supports.  This only asks the OS, and doesn't
+/// launch anything.
+///
+/// ```no_run
+/// use gracklezero::runtime::probe;
+///
+/// let host = probe();
+/// if !host.can_sandbox() {
+///     eprint!("this host can't run the sandbox:\n{}", host);
+/// }
+/// ```
+#[cfg(target_os = "linux")]
+pub fn probe() -> HostCapabilities {
+    super::spawn_linux::probe()
+}
+
+/// Check which sandbox mechanisms the host supports.  This only asks the OS, and doesn't
+/// launch anything.
+#[cfg(target_os = "windows")]
+pub fn probe() -> HostCapabilities {
+    super::spawn_windows::probe()
+}
+
+/// Fail the launch when this process is privileged, unless the launch allows it.
+pub(crate) fn refuse_privileged_parent(env: &LaunchEnv) -> Result<(), SandboxError> {
+    if env.allow_privileged_parent {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    let privileged = super::spawn_linux::is_privileged();
+    #[cfg(target_os = "windows")]
+    let privileged = super::spawn_windows::is_privileged();
+    if privileged {
+        Err(SandboxError::PrivilegedParent)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: MIT
+
+//! An opt-in, process-wide list of the running sandboxed children, so a service can make
+//! sure none of them outlive it.
+//!
+//! ```no_run
+//! use gracklezero::runtime::registry;
+//!
+//! // Early in main, before launching anything.
+//! registry::install_shutdown_hooks();
+//! // Optionally, also end them when any thread panics.
+//! registry::install_panic_hook();
+//!
+//! // ... launch children, some with `kill_on_drop` turned off ...
+//!
+//! // On a shutdown request, such as SIGTERM, which skips the exit hook.
+//! gracklezero::terminate_all();
+//! ```
+//!
+//! Only children launched after `enable` are tracked.  Children launched by a `Zygote`
+//! belong to the zygote process, and aren't tracked.
+
+use std::sync::{Mutex, MutexGuard, Once, TryLockError};
+
+use crate::logging;
+
+#[cfg(target_os = "linux")]
+type Entry = super::spawn_linux::LinuxChildState;
+
+#[cfg(target_os = "windows")]
+type Entry = super::spawn_windows::ProcessRef;
+
+/// The tracked children, or None until `enable` is called.
+static LIVE: Mutex<Option<Vec<Entry>>> = Mutex::new(None);
+
+static HOOKS: Once = Once::new();
+
+static PANIC_HOOK: Once = Once::new();
+
+/// The process that installed the exit hook.  Forked children that fail before their exec
+/// run the exit hooks too, and must leave the parent's children alone.
+#[cfg(target_os = "linux")]
+static HOOK_PID: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+/// Start tracking every child launched from now on.
+pub fn enable() {
+    let mut live = lock();
+    if live.is_none() {
+        *live = Some(Vec::new());
+    }
+}
+
+/// Is the registry tracking children?
+pub fn is_enabled() -> bool {
+    lock().is_some()
+}
+
+/// Start tracking children, and terminate them all when the process exits through
+/// `std::process::exit` or by returning from `main`.
+///
+/// Signals that end the process, such as SIGTERM, don't run the hook; call
+/// `terminate_all` from the signal handling instead.  On Windows, the children's job
+/// objects already end them when the process exits, so no hook is added.  A panic
+/// doesn't end the children unless `install_panic_hook` is called too.
+pub fn install_shutdown_hooks() {
+    enable();
+    HOOKS.call_once(|| {
+        #[cfg(target_os = "linux")]
+        {
+            HOOK_PID.store(std::process::id(), std::sync::atomic::Ordering::SeqCst);
+            if unsafe { nix::libc::atexit(terminate_at_exit) } != 0 {
+                logging::warning!("could not add the exit hook to terminate children");
+            }
+        }
+    });
+}
+
+/// Start tracking children, and terminate them all when any thread panics, even one
+/// whose panic is later caught.  The panic hook that was set before still runs first.
+///
+/// Only install this when a panic anywhere means the service is failing.  A panic while
+/// the registry is busy, such as from another thread launching a child, skips the
+/// termination rather than waiting on it.
+pub fn install_panic_hook() {
+    enable();
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            // The panicking thread may hold the lock, so never wait on it.
+            let entries = match LIVE.try_lock() {
+                Ok(mut live) => live.as_mut().map(std::mem::take),
+                Err(TryLockError::Poisoned(e)) => e.into_inner().as_mut().map(std::mem::take),
+                Err(TryLockError::WouldBlock) => {
+                    logging::warning!("registry busy; children not terminated on panic");
+                    return;
+                }
+            };
+            terminate_entries(entries);
+        }));
+    });
+}
+
+#[cfg(target_os = "linux")]
+extern "C" fn terminate_at_exit() {
+    if std::process::id() == HOOK_PID.load(std::sync::atomic::Ordering::SeqCst) {
+        terminate_all();
+    }
+}
+
+/// Terminate every tracked child that's still running, and wait for each to end.
+/// Returns the number of children terminated.  Does nothing unless the registry is
+/// enabled.
+pub fn terminate_all() -> usize {
+    let entries = lock().as_mut().map(std::mem::take);
+    terminate_entries(entries)
+}
+
+/// Terminate the children taken from the registry, if it was enabled.
+fn terminate_entries(entries: Option<Vec<Entry>>) -> usize {
+    let Some(entries) = entries else {
+        return 0;
+    };
+    let mut count = 0;
+    for entry in entries.iter().filter(|e| is_running(e)) {
+        match terminate(entry) {
+            Ok(()) => count += 1,
+            Err(e) => logging::warning!("could not terminate child: {}", e),
+        }
+    }
+    count
+}
+
+/// The number of tracked children still running.
+pub fn live_count() -> usize {
+    lock()
+        .as_ref()
+        .map_or(0, |live| live.iter().filter(|e| is_running(e)).count())
+}
+
+/// Track the newly launched child, when the registry is enabled.
+#[cfg(target_os = "linux")]
+pub(crate) fn register(state: &super::spawn_linux::LinuxChildState) {
+    add(|| Ok(state.clone()));
+}
+
+/// Track the newly launched child, when the registry is enabled.
+#[cfg(target_os = "windows")]
+pub(crate) fn register(state: &super::spawn_windows::ProcessState) {
+    add(|| state.process_ref());
+}
+
+fn add<F: FnOnce() -> Result<Entry, std::io::Error>>(entry: F) {
+    let mut live = lock();
+    let Some(live) = live.as_mut() else {
+        return;
+    };
+    // Drop the children that ended, so a long running service doesn't collect them.
+    live.retain(is_running);
+    match entry() {
+        Ok(entry) => live.push(entry),
+        Err(e) => logging::warning!("could not track child: {}", e),
+    }
+}
+
+fn lock() -> MutexGuard<'static, Option<Vec<Entry>>> {
+    // A panic while holding the lock leaves the list intact.
+    LIVE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(entry: &Entry) -> bool {
+    !matches!(entry.exit_status(), Ok(Some(_)))
+}
+
+#[cfg(target_os = "windows")]
+fn is_running(entry: &Entry) -> bool {
+    entry.is_running()
+}
+
+#[cfg(target_os = "linux")]
+fn terminate(entry: &Entry) -> Result<(), std::io::Error> {
+    entry.kill().and(Ok(()))
+}
+
+#[cfg(target_os = "windows")]
+fn terminate(entry: &Entry) -> Result<(), std::io::Error> {
+    entry.terminate()
+}
@@ -0,0 +1,45 @@
+// SPDX-License-Identifier: MIT
+
+//! Run a child to its end and collect its output, without writing a `CommHandler`.
+
+use super::{
+    ExitStatus, LaunchEnv, error::SandboxError, handlers::CollectingHandler, sandbox_child,
+};
+
+/// What a child wrote to its STDOUT and STDERR, and how it ended, as returned by `run`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Run the child to its end, and collect everything it wrote to STDOUT and STDERR, with
+/// a `CollectingHandler`.
+///
+/// Both streams are read at the same time, so a child filling one pipe can't stall.  Only
+/// streams the `FdSet` sends to the parent (`FdMode::FromChild`) are collected; the
+/// others come back empty.  The child's STDIN, if the parent has it, is closed right away,
+/// and the streams keep their output limits and log files.
+///
+/// ```no_run
+/// use gracklezero::{LaunchEnv, compat_restrictions, runtime::run};
+///
+/// let output = run(LaunchEnv::new("/usr/bin/uname".into(), compat_restrictions!("app")))
+///     .expect("run failed");
+/// assert!(output.status.success());
+/// ```
+pub fn run(env: LaunchEnv) -> Result<Output, SandboxError> {
+    // Windows has no selector yet, so the handler polls the pipes.
+    #[cfg(target_os = "windows")]
+    let env = LaunchEnv {
+        fds: env.fds.clone().with_nonblocking(),
+        ..env
+    };
+    let ((stdout, stderr), status) = sandbox_child(env, CollectingHandler)?.into_result()?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: MIT
+
+//! Wait on several of a child's output streams, and its exit, from one thread.
+
+use std::{
+    collections::HashMap,
+    io::Read as _,
+    os::fd::{AsFd, AsRawFd as _, BorrowedFd, OwnedFd},
+    time::Duration,
+};
+
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout};
+
+use super::{Child, stream::ChildReader};
+
+/// The epoll token for the child's exit; stream tokens are their FD numbers.
+const EXIT_TOKEN: u64 = u64::MAX;
+
+/// What `StreamSelector::wait` found ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selected {
+    /// The stream for the child's FD has data to read, or reached its end.
+    Readable(u32),
+    /// The child exited.  This is reported once.
+    Exited,
+}
+
+/// Waits until any of a child's output streams has data, or the child exits, so a handler
+/// can drain STDOUT, STDERR, and a data channel together without a thread per stream.
+///
+/// The selector owns the streams it watches.  Read them with `StreamSelector::read`,
+/// which drops a stream once it ends, so the loop finishes when `is_empty` is true:
+///
+/// ```no_run
+/// # use gracklezero::runtime::{Child, Selected, StreamSelector};
+/// # fn drain(child: &mut dyn Child) -> std::io::Result<()> {
+/// let mut selector = StreamSelector::from_child(child, &[1, 2])?;
+/// let mut buf = [0u8; 4096];
+/// while !selector.is_empty() {
+///     for ready in selector.wait(None)? {
+///         if let Selected::Readable(fd) = ready {
+///             let count = selector.read(fd, &mut buf)?;
+///             // Handle buf[..count] from the FD.
+///         }
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Only Linux is supported for now.  It waits through epoll, and watches the exit through
+/// the child's pidfd.
+pub struct StreamSelector {
+    epoll: Epoll,
+    streams: HashMap<u32, ChildReader>,
+    /// A duplicate of the child's pidfd, until its exit is reported.
+    exit: Option<OwnedFd>,
+}
+
+impl StreamSelector {
+    /// A selector watching nothing yet.
+    pub fn new() -> Result<Self, std::io::Error> {
+        Ok(StreamSelector {
+            epoll: Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?,
+            streams: HashMap::new(),
+            exit: None,
+        })
+    }
+
+    /// Take the child's streams for the FDs, and watch them and the child's exit.
+    ///
+    /// FDs without a stream to take are skipped.  A child without a pidfd, such as one
+    /// launched by a `Zygote`, never reports `Selected::Exited`.
+    pub fn from_child(child: &mut dyn Child, fds: &[u32]) -> Result<Self, std::io::Error> {
+        let mut selector = StreamSelector::new()?;
+        for fd in fds {
+            if let Some(stream) = child.take_stream_from_child(*fd) {
+                selector.add(*fd, stream)?;
+            }
+        }
+        if let Some(pidfd) = child.pidfd() {
+            selector.watch_exit(pidfd)?;
+        }
+        Ok(selector)
+    }
+
+    /// Watch the stream taken from the child's FD.  This replaces a stream already watched
+    /// for the FD.
+    pub fn add(&mut self, fd: u32, stream: ChildReader) -> Result<(), std::io::Error> {
+        self.remove(fd);
+        let raw = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+        self.epoll
+            .add(raw, EpollEvent::new(EpollFlags::EPOLLIN, fd as u64))?;
+        self.streams.insert(fd, stream);
+        Ok(())
+    }
+
+    /// Stop watching the FD's stream, and hand it back.
+    pub fn remove(&mut self, fd: u32) -> Option<ChildReader> {
+        let stream = self.streams.remove(&fd)?;
+        // Fails if the stream was already closed by its output limit, which removed it.
+        let raw = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+        let _ = self.epoll.delete(raw);
+        Some(stream)
+    }
+
+    /// Watch for the child's exit, through its pidfd (`Child::pidfd`).
+    pub fn watch_exit(&mut self, pidfd: BorrowedFd<'_>) -> Result<(), std::io::Error> {
+        let pidfd = pidfd.try_clone_to_owned()?;
+        if let Some(old) = self.exit.take() {
+            let _ = self.epoll.delete(&old);
+        }
+        self.epoll
+            .add(&pidfd, EpollEvent::new(EpollFlags::EPOLLIN, EXIT_TOKEN))?;
+        self.exit = Some(pidfd);
+        Ok(())
+    }
+
+    /// The stream watched for the FD.
+    pub fn stream(&mut self, fd: u32) -> Option<&mut ChildReader> {
+        self.streams.get_mut(&fd)
+    }
+
+    /// True once there are no streams, and no exit, left to watch.
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty() && self.exit.is_none()
+    }
+
+    /// Read from the FD's stream, after `wait` reported it readable.
+    ///
+    /// Once the stream ends, or fails, it's dropped, and later reads return 0.
+    pub fn read(&mut self, fd: u32, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        let Some(stream) = self.streams.get_mut(&fd) else {
+            return Ok(0);
+        };
+        let result = stream.read(buf);
+        match result {
+            Ok(0) | Err(_) => {
+                self.remove(fd);
+            }
+            Ok(_) => {}
+        }
+        result
+    }
+
+    /// Block until a stream is readable or the child exits, or the timeout passes.
+    ///
+    /// Returns everything that's ready, which is empty if the timeout passed.  A stream
+    /// stays readable until it's read, so an unread stream is reported again.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Vec<Selected>, std::io::Error> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        let timeout = match timeout {
+            None => EpollTimeout::NONE,
+            // Round up, so a sub-millisecond timeout doesn't spin.
+            Some(t) => {
+                EpollTimeout::try_from(t.as_micros().div_ceil(1000)).unwrap_or(EpollTimeout::MAX)
+            }
+        };
+        let mut events = vec![EpollEvent::empty(); self.streams.len() + 1];
+        let count = loop {
+            match self.epoll.wait(&mut events, timeout) {
+                Err(nix::errno::Errno::EINTR) => continue,
+                r => break r?,
+            }
+        };
+        let mut ret = Vec::with_capacity(count);
+        for event in &events[..count] {
+            match event.data() {
+                EXIT_TOKEN => {
+                    // The pidfd stays readable, so stop watching it.
+                    if let Some(pidfd) = self.exit.take() {
+                        let _ = self.epoll.delete(&pidfd);
+                    }
+                    ret.push(Selected::Exited);
+                }
+                fd => ret.push(Selected::Readable(fd as u32)),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// The selector's epoll descriptor becomes readable when `wait` has something to report,
+/// so a selector can itself be watched by another event loop.
+impl AsFd for StreamSelector {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.epoll.0.as_fd()
+    }
+}
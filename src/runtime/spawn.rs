@@ -2,13 +2,85 @@
 
 //! General model for spawning child processes and managing their state.
 
-use std::{collections::HashMap, ffi::OsString, path::PathBuf};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use super::{
+    DependencyCache, EnvMap,
+    error::SandboxError,
+    lifecycle::LifecycleObserver,
+    limit::OutputLimit,
+    stream::{ChildReader, ChildWriter},
+    tee::FdTee,
+    terminal::Terminal,
+    verify::HashAllowlist,
+};
+use crate::{policy::SandboxPolicy, restrictions::CpuPlacement};
 
 /// Handles communication to the child from the parent process.
 ///
 /// This is the basic communication method for handling requests from the child process.
+/// The handler is borrowed for each launch, so one handler, and its state, can serve
+/// several children.  Pass `&mut handler` to keep ownership of it.
 pub trait CommHandler {
-    fn handle(self, child: Box<dyn Child>) -> Result<(), std::io::Error>;
+    /// What the handler hands back to the caller, along with the child's exit status.
+    /// Use `()` when the handler has nothing to report.
+    type Output;
+
+    /// The error the handler fails with.  It reaches the caller of `sandbox_child` as is,
+    /// in `RunResult::handler`, and as the `SandboxError` it converts into from
+    /// `RunResult::into_result`; wrap domain errors with `SandboxError::handler` to keep
+    /// them intact there.
+    type Error: Into<SandboxError>;
+
+    fn handle(&mut self, child: Box<dyn Child>) -> Result<Self::Output, Self::Error>;
+}
+
+impl<H: CommHandler + ?Sized> CommHandler for &mut H {
+    type Output = H::Output;
+    type Error = H::Error;
+
+    fn handle(&mut self, child: Box<dyn Child>) -> Result<Self::Output, Self::Error> {
+        (**self).handle(child)
+    }
+}
+
+/// A handler that consumes itself to handle the child, as `CommHandler` did before it
+/// borrowed the handler.  Wrap it in `HandleOnce` to launch a child with it.
+pub trait OnceCommHandler {
+    type Output;
+    type Error: Into<SandboxError>;
+
+    fn handle_once(self, child: Box<dyn Child>) -> Result<Self::Output, Self::Error>;
+}
+
+/// Adapts a `OnceCommHandler` into a `CommHandler` for a single launch.  Launching a
+/// second child with it fails with `SandboxError::ProcessError`.
+pub struct HandleOnce<H>(Option<H>);
+
+impl<H: OnceCommHandler> HandleOnce<H> {
+    pub fn new(handler: H) -> Self {
+        HandleOnce(Some(handler))
+    }
+}
+
+impl<H: OnceCommHandler> CommHandler for HandleOnce<H> {
+    type Output = H::Output;
+    type Error = SandboxError;
+
+    fn handle(&mut self, child: Box<dyn Child>) -> Result<Self::Output, SandboxError> {
+        match self.0.take() {
+            Some(handler) => handler.handle_once(child).map_err(Into::into),
+            None => Err(SandboxError::ProcessError(
+                "the handler was already used for another launch".to_string(),
+            )),
+        }
+    }
 }
 
 /// Simple method for communicating with the child process.
@@ -18,36 +90,283 @@ pub trait Child {
 
     /// Take the stream that receives from the child, as was marked with the child's FD.
     /// If called again with the same FD, this will return None.
-    fn take_stream_from_child(&mut self, fd: u32) -> Option<Box<dyn std::io::Read>>;
+    fn take_stream_from_child(&mut self, fd: u32) -> Option<ChildReader>;
 
     /// Take the stream that sends to the child, as was marked with the child's FD.
     /// If called again with the same FD, this will return None.
-    fn take_stream_to_child(&mut self, fd: u32) -> Option<Box<dyn std::io::Write>>;
+    fn take_stream_to_child(&mut self, fd: u32) -> Option<ChildWriter>;
+
+    /// The FD assigned to the named channel, as declared with `FdSet::with_channel`.
+    fn channel_fd(&self, _name: &str) -> Option<u32> {
+        None
+    }
+
+    /// Take the stream that receives from the child on the named channel.
+    fn take_channel_from_child(&mut self, name: &str) -> Option<ChildReader> {
+        let fd = self.channel_fd(name)?;
+        self.take_stream_from_child(fd)
+    }
+
+    /// Take the stream that sends to the child on the named channel.
+    fn take_channel_to_child(&mut self, name: &str) -> Option<ChildWriter> {
+        let fd = self.channel_fd(name)?;
+        self.take_stream_to_child(fd)
+    }
+
+    /// How the child ended, or None while it is still running.  See `ExitStatus` for how
+    /// each OS's exit codes, signals, and crash codes map onto it.
+    fn exit_status(&self) -> Result<Option<ExitStatus>, std::io::Error>;
+
+    /// Block until the child exits.
+    fn wait(&self) -> Result<ExitStatus, std::io::Error>;
+
+    /// Block until the child exits, or until the timeout passes.
+    /// Returns None if the timeout passed first.
+    fn wait_timeout(&self, timeout: Duration) -> Result<Option<ExitStatus>, std::io::Error>;
 
-    /// Get the current exit status for the child process.
-    /// NOTE: OS may have its own error codes in here to indicate some extra-process failure.
-    /// For example, in Windows, an exit code of 0xC0000142 (STATUS_DLL_INIT_FAILED) indicates that
-    /// the process failed to load necessary DLLs.
-    /// TODO: this should instead return a richer enum that can distinguish between an actual
-    /// exit code and an OS error code.
-    fn exit_status(&self) -> ExitCode;
+    /// The OS process id of the child, for monitoring tools and debuggers.
+    ///
+    /// The OS can reuse the id once the child is reaped, so prefer `pidfd` or
+    /// `process_handle` where a stable reference matters.
+    fn id(&self) -> u32;
+
+    /// A pidfd that refers to the child, which stays tied to this process even after its
+    /// id is reused.  None if the kernel doesn't support pidfds, or the child was launched
+    /// through a zygote.
+    #[cfg(target_os = "linux")]
+    fn pidfd(&self) -> Option<std::os::fd::BorrowedFd<'_>> {
+        None
+    }
+
+    /// The child's process handle, which stays valid until the child is dropped.
+    #[cfg(target_os = "windows")]
+    fn process_handle(&self) -> Option<std::os::windows::io::BorrowedHandle<'_>> {
+        None
+    }
+
+    /// The resources the child used, once it has exited and been reaped.
+    ///
+    /// None while the child runs, or when the platform can't report it, such as for
+    /// children launched through a zygote.
+    fn resource_usage(&self) -> Option<ResourceUsage> {
+        None
+    }
+
+    /// How long each phase of the launch took.  None for children launched through a
+    /// zygote.
+    fn launch_report(&self) -> Option<LaunchReport> {
+        None
+    }
+
+    /// The accesses the sandbox denied the child so far.
+    ///
+    /// Currently only reported on Windows, when the restrictions enable
+    /// `windows::audit_denials`.
+    fn denials(&self) -> Vec<SandboxDenial> {
+        Vec::new()
+    }
+
+    /// The child's pseudo-terminal, if the `FdSet` attached any FD with `FdMode::Terminal`.
+    fn terminal(&self) -> Option<Terminal> {
+        None
+    }
+
+    /// Resize the child's pseudo-terminal, in character cells.
+    /// Fails with `std::io::ErrorKind::Unsupported` if the child has no terminal.
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), std::io::Error> {
+        match self.terminal() {
+            Some(terminal) => terminal.resize(cols, rows),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "the child has no terminal",
+            )),
+        }
+    }
+
+    /// A handle that terminates the child from another thread, such as a watchdog
+    /// enforcing a deadline.  None if the child can't be terminated that way.
+    /// Currently only supported on Linux.
+    fn killer(&self) -> Option<ChildKiller> {
+        None
+    }
 }
 
-#[derive(Debug, Clone)]
-pub enum ExitCode {
-    /// The process exited with the given code.
-    Exited(i32),
-    /// The process is still running.
-    Running,
-    /// The process failed to start due to an OS error, with the given code.
-    OsError(OsTermination),
+/// Terminates a child from any thread, as returned by `Child::killer`.  It stays usable
+/// after the child is gone, when terminating does nothing.
+#[derive(Clone)]
+pub struct ChildKiller(Arc<dyn Fn() -> Result<(), std::io::Error> + Send + Sync>);
+
+impl ChildKiller {
+    pub(crate) fn new<F>(terminate: F) -> Self
+    where
+        F: Fn() -> Result<(), std::io::Error> + Send + Sync + 'static,
+    {
+        ChildKiller(Arc::new(terminate))
+    }
+
+    /// Kill the child, if it's still running, as `Child::terminate` does.
+    pub fn terminate(&self) -> Result<(), std::io::Error> {
+        (self.0)()
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct OsTermination {
-    pub message: String,
+/// How the child process ended, with the same meaning on every OS.
+///
+/// Mapping rules:
+/// * A process that returns from `main` or calls `exit` has a `Code`.  On Linux, this is
+///   the 0 to 255 `waitpid` exit status; on Windows, the `GetExitCodeProcess` value, which
+///   is never an NTSTATUS failure code.
+/// * A process the OS ended has a `Signaled` value.  On Linux, this is the signal number,
+///   such as 11 for `SIGSEGV` or 31 for `SIGSYS` from a seccomp violation.  On Windows, it
+///   is the NTSTATUS code with the high bit set, such as 0xC0000005
+///   (STATUS_ACCESS_VIOLATION), stored as the same bits in an `i32`.
+/// * A process the sandbox killed, through `Child::terminate` or when cleaning up after
+///   the handler, is `SandboxKilled`, rather than the `SIGKILL` or exit code 255 the
+///   OS reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process exited on its own, with the given exit code.
+    Code(i32),
+    /// The OS ended the process, with the signal number or NTSTATUS code.
+    Signaled(i32),
+    /// The sandbox killed the process.
+    SandboxKilled,
+}
+
+impl ExitStatus {
+    /// Did the process exit on its own with a zero code?
+    pub fn success(&self) -> bool {
+        matches!(self, ExitStatus::Code(0))
+    }
+
+    /// The exit code, if the process exited on its own.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            ExitStatus::Code(c) => Some(*c),
+            _ => None,
+        }
+    }
+}
+
+/// What `sandbox_child` hands back once the child is done: the handler's own result, and
+/// how the child ended.
+///
+/// The two are kept apart, so a handler that fails on a garbled message still reports
+/// whether the sandbox killed the child, and a handler that succeeds still reports a
+/// child that crashed.
+#[derive(Debug)]
+pub struct RunResult<O, E> {
+    /// What the handler returned.
+    pub handler: Result<O, E>,
+    /// How the child ended.
+    pub exit: ExitStatus,
+}
+
+impl<O, E> RunResult<O, E> {
+    /// Did the handler succeed, and the child exit on its own with a zero code?
+    pub fn success(&self) -> bool {
+        self.handler.is_ok() && self.exit.success()
+    }
+}
+
+impl<O, E: Into<SandboxError>> RunResult<O, E> {
+    /// The handler's output along with the exit status, or the handler's error.  Use this
+    /// when a failed handler matters more than how the child ended.
+    pub fn into_result(self) -> Result<(O, ExitStatus), SandboxError> {
+        Ok((self.handler.map_err(Into::into)?, self.exit))
+    }
+}
+
+/// Convert to the standard library's status, as `std::process::Child::wait` would report
+/// it.  A sandbox kill becomes the OS's own kill status: `SIGKILL` on Unix, and exit code
+/// 255 on Windows.
+#[cfg(unix)]
+impl From<ExitStatus> for std::process::ExitStatus {
+    fn from(status: ExitStatus) -> Self {
+        use std::os::unix::process::ExitStatusExt;
+
+        // The raw value is the `waitpid` status word.
+        match status {
+            ExitStatus::Code(c) => std::process::ExitStatus::from_raw((c & 0xff) << 8),
+            ExitStatus::Signaled(s) => std::process::ExitStatus::from_raw(s & 0x7f),
+            ExitStatus::SandboxKilled => std::process::ExitStatus::from_raw(9),
+        }
+    }
+}
+
+/// Convert to the standard library's status, as `std::process::Child::wait` would report
+/// it.  A sandbox kill becomes the OS's own kill status: `SIGKILL` on Unix, and exit code
+/// 255 on Windows.
+#[cfg(windows)]
+impl From<ExitStatus> for std::process::ExitStatus {
+    fn from(status: ExitStatus) -> Self {
+        use std::os::windows::process::ExitStatusExt;
+
+        match status {
+            ExitStatus::Code(c) | ExitStatus::Signaled(c) => {
+                std::process::ExitStatus::from_raw(c as u32)
+            }
+            ExitStatus::SandboxKilled => std::process::ExitStatus::from_raw(255),
+        }
+    }
+}
+
+/// The system resources a child used over its whole run, to meter untrusted workloads.
+///
+/// On Linux, this is the `wait4` usage, which includes any descendants the child waited
+/// for.  On Windows, it is the accounting for the child's job, so it covers every process
+/// in the sandbox.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// CPU time spent running the child's own code.
+    pub user_cpu: Duration,
+    /// CPU time the OS spent on the child's behalf.
+    pub sys_cpu: Duration,
+    /// Peak memory use, in bytes.  This is the peak resident set size on Linux, and the
+    /// peak committed memory of any one process in the job on Windows.
+    pub max_rss: u64,
+    /// Page faults, including the ones served without reading from disk.
+    pub page_faults: u64,
+}
+
+/// How long each phase of launching the child took, to see where the spawn latency goes,
+/// as returned by `Child::launch_report`.
+///
+/// A phase is None when the platform doesn't have it or can't measure it.  On Linux, a
+/// `SandboxTemplate` resolves the executable and finds its dependencies once, so each of
+/// its children reports the time that took.  The report also records where the jail
+/// placed the child.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LaunchReport {
+    /// Finding the executable for `LaunchEnv::cmd`.
+    pub resolve: Option<Duration>,
+    /// Finding the shared libraries the executable loads.  Linux only.
+    pub dependencies: Option<Duration>,
+    /// Creating the pipes and opening the files for the child's FDs.
+    pub fd_setup: Option<Duration>,
+    /// Creating the child process.  On Windows, this covers the whole sandbox setup: the
+    /// AppContainer, the job, and the process.
+    pub fork: Option<Duration>,
+    /// Applying the jail inside the child, as the child measured it.  Linux only.
+    pub restrict: Option<Duration>,
+    /// From starting to create the child process until it started running the
+    /// executable, so it includes `fork` and `restrict`.  None if the exec failed.  Linux only.
+    pub exec: Option<Duration>,
+    /// The processors the child was pinned to, from `Restrictions::cpu_placement`.  None
+    /// when the child may run on any processor.
+    pub placement: Option<CpuPlacement>,
+}
+
+/// An access the sandbox refused the child.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxDenial {
+    /// What the child tried to access, such as a file path, registry key, or network
+    /// address.
+    pub resource: String,
+    /// The kind of access, as the OS reports it.
+    pub access: String,
+    /// The OS specific code for the denial, such as the Windows Security log event ID.
     pub code: i64,
-    pub subcode: Option<i64>,
 }
 
 /// Defines the required file descriptors used in the construction of the child process.
@@ -56,6 +375,16 @@ pub struct OsTermination {
 #[derive(Debug, Clone)]
 pub struct FdSet {
     fds: Vec<Fd>,
+    io_timeout: Option<Duration>,
+    nonblocking: bool,
+    /// Named channels, and the FD assigned to each.
+    channels: Vec<(String, u32)>,
+    /// Initial (columns, rows) of the pseudo-terminal.
+    terminal_size: Option<(u16, u16)>,
+    /// Log files that copy the output FDs.
+    tees: Vec<(u32, FdTee)>,
+    /// Caps on the bytes read from the output FDs.
+    output_limits: Vec<(u32, OutputLimit)>,
 }
 
 /// The FD mode description, indicating the direction of data.
@@ -63,12 +392,90 @@ pub struct FdSet {
 pub enum FdMode {
     // Used only for the 'basic' format, where the FD is closed in the child but not used for communication.
     Null,
+    // The FD is attached to the null device (`/dev/null` or `NUL`), so reads see the end of
+    // the file, and writes are discarded rather than failing.
+    NullDevice,
     // The data flows from the parent to the child.
     ToChild,
     // The data flows from the child to the parent.
     FromChild,
     // The FD is kept open in the child without redirection.
     KeepInChild,
+    // The child reads from the file, opened by the parent.
+    FromFile(PathBuf),
+    // The child writes to the file, opened by the parent.  Creates or truncates the file.
+    ToFile(PathBuf),
+    // The child writes to the end of the file, opened by the parent.  Creates the file
+    // if it doesn't exist.
+    AppendFile(PathBuf),
+    // The child gets a duplicate of the parent's open file, socket, or pipe.  Create with
+    // `FdMode::inherit`.
+    #[cfg(unix)]
+    Inherit(std::sync::Arc<std::os::fd::OwnedFd>),
+    // The child gets a duplicate of the parent's open file, socket, or pipe handle.
+    // Create with `FdMode::inherit`.
+    #[cfg(windows)]
+    Inherit(std::sync::Arc<std::os::windows::io::OwnedHandle>),
+    // The child writes to the same stream as the target FD, such as `MergeInto(1)` for
+    // FD 2 to interleave stderr with stdout.  The target must be an output FD in the
+    // same set.
+    MergeInto(u32),
+    // The FD attaches to a pseudo-terminal, shared by every `Terminal` FD in the set, which
+    // becomes the child's controlling terminal.  The parent writes input through FD 0, and
+    // reads all the terminal output through the first other `Terminal` FD in the set.
+    // Currently only supported on Linux.
+    Terminal,
+}
+
+impl FdMode {
+    /// Pass the object to the child at the FD.  The child gets its own duplicate for each
+    /// launch, so the parent's copy stays open until the `FdSet` is dropped.
+    #[cfg(unix)]
+    pub fn inherit<T: Into<std::os::fd::OwnedFd>>(obj: T) -> Self {
+        FdMode::Inherit(std::sync::Arc::new(obj.into()))
+    }
+
+    /// Pass the object to the child at the FD.  The child gets its own duplicate for each
+    /// launch, so the parent's copy stays open until the `FdSet` is dropped.
+    #[cfg(windows)]
+    pub fn inherit<T: Into<std::os::windows::io::OwnedHandle>>(obj: T) -> Self {
+        FdMode::Inherit(std::sync::Arc::new(obj.into()))
+    }
+
+    /// Open the file for a file redirection mode or the null device, or duplicate the
+    /// inherited object, or None for the other modes.
+    ///
+    /// The parent opens the file outside the jail, so the child only ever sees the open
+    /// descriptor, and never needs access to the path.
+    pub(crate) fn open_file(&self) -> Result<Option<std::fs::File>, std::io::Error> {
+        let null_device = std::path::Path::new(if cfg!(target_os = "windows") {
+            "NUL"
+        } else {
+            "/dev/null"
+        });
+        let (path, file) = match self {
+            FdMode::FromFile(path) => (path.as_path(), std::fs::File::open(path)),
+            FdMode::ToFile(path) => (path.as_path(), std::fs::File::create(path)),
+            FdMode::AppendFile(path) => (
+                path.as_path(),
+                std::fs::File::options()
+                    .append(true)
+                    .create(true)
+                    .open(path),
+            ),
+            FdMode::NullDevice => (
+                null_device,
+                std::fs::File::options()
+                    .read(true)
+                    .write(true)
+                    .open(null_device),
+            ),
+            FdMode::Inherit(obj) => return Ok(Some(std::fs::File::from(obj.try_clone()?))),
+            _ => return Ok(None),
+        };
+        file.map(Some)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("could not open {:?}: {}", path, e)))
+    }
 }
 
 /// A single file descriptor, which has an index and a direction.
@@ -91,15 +498,16 @@ impl FdSet {
                 mode: modes[i].clone(),
             });
         }
-        FdSet { fds }
+        FdSet::new(fds)
     }
 
     /// Construct the file descriptors from the list of values.
     pub fn from_vec(fds: Vec<Fd>) -> Self {
-        FdSet { fds }
+        FdSet::new(fds)
     }
 
     /// Construct the file descriptors from an index map.
+    /// The entries are ordered by FD number.
     pub fn from_map(map: HashMap<u32, FdMode>) -> Self {
         let mut fds = Vec::with_capacity(map.len());
         for e in map.iter() {
@@ -108,7 +516,21 @@ impl FdSet {
                 mode: e.1.clone(),
             });
         }
-        FdSet { fds }
+        fds.sort_by_key(|f| f.fd);
+        FdSet::new(fds)
+    }
+
+    /// The set of the FDs, with every other setting at its default.
+    fn new(fds: Vec<Fd>) -> Self {
+        FdSet {
+            fds,
+            io_timeout: None,
+            nonblocking: false,
+            channels: Vec::new(),
+            terminal_size: None,
+            tees: Vec::new(),
+            output_limits: Vec::new(),
+        }
     }
 
     /// Define the standard IoRequest, using STDIN, STDOUT, and STDERR.
@@ -124,17 +546,688 @@ impl FdSet {
     pub fn len(&self) -> usize {
         self.fds.len()
     }
+
+    /// Check that the set can be launched.  `sandbox_child` runs this before starting
+    /// the child.
+    ///
+    /// Fails if an FD number is listed more than once, if STDIN (FD 0) sends data from
+    /// the child, if STDOUT or STDERR (FD 1 and 2) send data to the child, if a
+    /// `MergeInto` FD doesn't target another output FD in the set, if a channel name is
+    /// repeated or can't be encoded, or if a tee or output limit doesn't apply to a
+    /// stream from the child.
+    pub fn validate(&self) -> Result<(), SandboxError> {
+        let mut seen = std::collections::HashSet::new();
+        for fd in &self.fds {
+            if !seen.insert(fd.fd) {
+                return Err(SandboxError::InvalidFdSet(format!(
+                    "fd {} is listed more than once",
+                    fd.fd
+                )));
+            }
+            match (fd.fd, &fd.mode) {
+                (0, FdMode::FromChild | FdMode::ToFile(_) | FdMode::AppendFile(_)) => {
+                    return Err(SandboxError::InvalidFdSet(
+                        "fd 0 (stdin) cannot send data from the child".to_string(),
+                    ));
+                }
+                (1 | 2, FdMode::ToChild | FdMode::FromFile(_)) => {
+                    return Err(SandboxError::InvalidFdSet(format!(
+                        "fd {} (stdout or stderr) cannot send data to the child",
+                        fd.fd
+                    )));
+                }
+                (0, FdMode::MergeInto(_)) => {
+                    return Err(SandboxError::InvalidFdSet(
+                        "fd 0 (stdin) cannot merge into another fd".to_string(),
+                    ));
+                }
+                (_, FdMode::MergeInto(target)) => match self.mode_of(*target) {
+                    Some(
+                        FdMode::FromChild
+                        | FdMode::KeepInChild
+                        | FdMode::ToFile(_)
+                        | FdMode::AppendFile(_)
+                        | FdMode::NullDevice
+                        | FdMode::Inherit(_)
+                        | FdMode::Terminal,
+                    ) if *target != fd.fd => {}
+                    _ => {
+                        return Err(SandboxError::InvalidFdSet(format!(
+                            "fd {} must merge into another output fd, not fd {}",
+                            fd.fd, target
+                        )));
+                    }
+                },
+                _ => {}
+            }
+        }
+        let mut names = std::collections::HashSet::new();
+        for (name, _) in &self.channels {
+            if name.is_empty() || name.contains([':', ';', '=']) {
+                return Err(SandboxError::InvalidFdSet(format!(
+                    "channel name '{}' must not be empty, or contain ':', ';', or '='",
+                    name
+                )));
+            }
+            if !names.insert(name) {
+                return Err(SandboxError::InvalidFdSet(format!(
+                    "channel '{}' is listed more than once",
+                    name
+                )));
+            }
+        }
+        self.validate_output_fds(self.tees.iter().map(|(fd, _)| *fd), "teed")?;
+        self.validate_output_fds(self.output_limits.iter().map(|(fd, _)| *fd), "limited")?;
+        Ok(())
+    }
+
+    /// Check that each FD is listed once, and gives the parent a stream from the child.
+    fn validate_output_fds<I: Iterator<Item = u32>>(
+        &self,
+        fds: I,
+        what: &str,
+    ) -> Result<(), SandboxError> {
+        let mut seen = std::collections::HashSet::new();
+        for fd in fds {
+            if !matches!(self.mode_of(fd), Some(FdMode::FromChild | FdMode::Terminal)) || fd == 0 {
+                return Err(SandboxError::InvalidFdSet(format!(
+                    "fd {} must send data from the child to be {}",
+                    fd, what
+                )));
+            }
+            if !seen.insert(fd) {
+                return Err(SandboxError::InvalidFdSet(format!(
+                    "fd {} is {} more than once",
+                    fd, what
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a named channel.  The set picks the next free FD number above the standard
+    /// streams, and tells the child the mapping through the `SANDBOX_CHANNELS` environment
+    /// variable, so neither side tracks the number.  See `Child::take_channel_from_child`
+    /// and `crate::child::ChildStreams` for opening it.
+    pub fn with_channel(mut self, name: &str, mode: FdMode) -> Self {
+        let fd = self.fds.iter().map(|f| f.fd + 1).max().unwrap_or(0).max(3);
+        self.fds.push(Fd { fd, mode });
+        self.channels.push((name.to_string(), fd));
+        self
+    }
+
+    /// The named channels, and their FD numbers.
+    pub fn channels(&self) -> HashMap<String, u32> {
+        self.channels.iter().cloned().collect()
+    }
+
+    /// The encoded channel mapping for the child, or None if there are no channels.
+    pub(crate) fn channels_env(&self) -> Option<OsString> {
+        if self.channels.is_empty() {
+            return None;
+        }
+        Some(OsString::from(crate::child::encode_channels(
+            &self.channels,
+        )))
+    }
+
+    /// The `SANDBOX_FDS` value listing the streams above the standard ones the child
+    /// gets, or None if there are none.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn streams_env(&self) -> Option<OsString> {
+        let fds: Vec<u32> = self
+            .fds
+            .iter()
+            .filter(|f| f.fd > 2 && !matches!(f.mode, FdMode::Null))
+            .map(|f| f.fd)
+            .collect();
+        if fds.is_empty() {
+            return None;
+        }
+        Some(OsString::from(crate::child::encode_fds(&fds)))
+    }
+
+    /// The mode for the FD, if the set lists it.
+    pub fn mode_of(&self, fd: u32) -> Option<&FdMode> {
+        self.fds.iter().find(|f| f.fd == fd).map(|f| &f.mode)
+    }
+
+    /// Set the mode for the FD, replacing its mode if the set already lists it.
+    pub fn with_fd(mut self, fd: u32, mode: FdMode) -> Self {
+        match self.fds.iter_mut().find(|f| f.fd == fd) {
+            Some(f) => f.mode = mode,
+            None => self.fds.push(Fd { fd, mode }),
+        }
+        self
+    }
+
+    /// Fail reads and writes on the streams that take longer than the timeout, with
+    /// `std::io::ErrorKind::TimedOut`, so a stuck child can't block the handler forever.
+    /// The stream stays usable after a timeout.
+    /// Currently only applies to the FDs above 2 on Windows.
+    pub fn with_io_timeout(mut self, timeout: Duration) -> Self {
+        self.io_timeout = Some(timeout);
+        self
+    }
+
+    /// The read and write timeout for the streams, if any.
+    pub fn io_timeout(&self) -> Option<Duration> {
+        self.io_timeout
+    }
+
+    /// Make the parent's end of each pipe non-blocking, for handlers that drive the streams
+    /// from a poll loop.  A read or write that can't make progress right away fails with
+    /// `std::io::ErrorKind::WouldBlock`, instead of waiting.  The child's ends stay
+    /// blocking.
+    ///
+    /// On Windows, the pipes use overlapped I/O, and a pending operation is cancelled
+    /// rather than left running.
+    pub fn with_nonblocking(mut self) -> Self {
+        self.nonblocking = true;
+        self
+    }
+
+    /// Are the parent's ends of the pipes non-blocking?
+    pub fn nonblocking(&self) -> bool {
+        self.nonblocking
+    }
+
+    /// Start the pseudo-terminal at the size, in character cells.  Without this, the
+    /// terminal copies the parent's own terminal size, or is 80 by 24 if the parent has
+    /// none.  Use `Child::resize` to change it later.
+    pub fn with_terminal_size(mut self, cols: u16, rows: u16) -> Self {
+        self.terminal_size = Some((cols, rows));
+        self
+    }
+
+    /// The initial (columns, rows) for the pseudo-terminal.
+    pub fn terminal_size(&self) -> (u16, u16) {
+        self.terminal_size
+            .or_else(crate::runtime::terminal::parent_size)
+            .unwrap_or(crate::runtime::terminal::DEFAULT_SIZE)
+    }
+
+    /// Copy everything the handler reads from the output FD into the log file, such as
+    /// `FdTee::append(path)` to keep a build log while the handler parses the output.
+    /// The FD must be `FromChild`, or the terminal's output FD.
+    pub fn with_tee(mut self, fd: u32, tee: FdTee) -> Self {
+        self.tees.push((fd, tee));
+        self
+    }
+
+    /// The log files, keyed by the FD they copy.
+    pub fn tees(&self) -> HashMap<u32, FdTee> {
+        self.tees.iter().cloned().collect()
+    }
+
+    /// Cap the bytes the handler can read from the output FD.  The FD must be
+    /// `FromChild`, or the terminal's output FD.  See `OutputLimit`.
+    pub fn with_output_limit(mut self, fd: u32, limit: OutputLimit) -> Self {
+        self.output_limits.push((fd, limit));
+        self
+    }
+
+    /// The output limits, keyed by FD.
+    pub fn output_limits(&self) -> HashMap<u32, OutputLimit> {
+        self.output_limits.iter().cloned().collect()
+    }
+
+    /// Does any FD attach to the pseudo-terminal?
+    pub fn has_terminal(&self) -> bool {
+        self.fds.iter().any(|f| matches!(f.mode, FdMode::Terminal))
+    }
 }
 
 /// Describes how to launch the child process.
+#[derive(Clone)]
 pub struct LaunchEnv {
     pub cmd: PathBuf,
     pub args: Vec<OsString>,
-    pub env: HashMap<OsString, OsString>,
+    pub env: EnvMap,
     pub fds: FdSet,
     pub restrictions: crate::Restrictions,
 
     // TODO even the `cwd` looks suspiciously like something the library should handle, to construct
     // something that's safe for use and has correct, safe permissions.
     pub cwd: PathBuf,
+
+    /// Allow the child to read everything beneath `cwd`.
+    /// Without this, the child runs inside `cwd` but cannot list or read its contents.
+    /// Currently only enforced by the Linux jail.
+    pub grant_cwd_read: bool,
+
+    /// Additional files or directories the child may read, beyond the executable and its
+    /// shared libraries.  Directories grant read access to everything beneath them.
+    /// Each path must exist at launch time.
+    /// Currently only enforced by the Linux jail.
+    pub readable_paths: Vec<PathBuf>,
+
+    /// Files or directories the child may create, change, and delete, as well as read.
+    /// Directories grant write access to everything beneath them.  Each path must exist
+    /// at launch time.
+    /// Currently only enforced by the Linux jail.
+    pub writable_paths: Vec<PathBuf>,
+
+    /// Shared libraries the child loads at runtime with `dlopen`, such as NSS modules or
+    /// plugins.  These, along with their own dependencies, are added to the libraries the
+    /// child may read.  The file name may contain `*` and `?` wildcards.
+    /// Currently only enforced by the Linux jail.
+    pub extra_libraries: Vec<PathBuf>,
+
+    /// Where to look for the executable's shared libraries.
+    /// Currently only used by the Linux jail.
+    pub dependency_search: DependencySearch,
+
+    /// The name the child sees as its first argument (`argv[0]`), for multi-call binaries
+    /// and programs that check their invocation name.  When None, the child sees a fixed
+    /// name (`sandboxed` on Linux, `command.com` on Windows), so the real path doesn't
+    /// leak into the child.
+    pub arg0: Option<OsString>,
+
+    /// How `cmd` is turned into the executable to run.
+    pub resolution: ExecResolution,
+
+    /// Terminate and reap the child when the parent drops its `Child`, so a handler that
+    /// panics or returns early never leaves the sandboxed process running.
+    /// On Windows, the sandbox's job object ends the child once it is dropped either way;
+    /// this only controls whether the drop waits for it to exit.
+    pub kill_on_drop: bool,
+
+    /// Told about each phase of the child's launch and end, such as for telemetry or an
+    /// audit log.  See `LifecycleObserver`.
+    pub observer: Option<Arc<dyn LifecycleObserver>>,
+
+    /// Refuse to launch unless the executable's SHA-256 digest is in the list.  See
+    /// `HashAllowlist`.
+    pub hash_allowlist: Option<HashAllowlist>,
+
+    /// Launch even though the parent runs privileged.  A privileged parent weakens the
+    /// sandbox: root keeps its capabilities inside the Landlock jail, and the Windows
+    /// restricted token can't strip everything an Administrator holds.  Without this, the
+    /// launch fails with `SandboxError::PrivilegedParent`.
+    ///
+    /// On Linux, the parent is privileged when it runs as root, or when its effective
+    /// capabilities include `CAP_DAC_OVERRIDE`, `CAP_DAC_READ_SEARCH`, `CAP_SETGID`,
+    /// `CAP_SETUID`, `CAP_SYS_PTRACE`, or `CAP_SYS_ADMIN`.  On Windows, it is privileged
+    /// when its token is elevated; privileges granted to an account that isn't elevated,
+    /// such as `SeDebugPrivilege`, aren't checked.
+    pub allow_privileged_parent: bool,
+}
+
+impl LaunchEnv {
+    /// Create a launch environment for the command with everything else at its default:
+    /// no arguments, an empty environment, the standard FDs, and the current directory.
+    ///
+    /// Use this with struct update syntax (`..LaunchEnv::new(cmd, r)`) so that new
+    /// fields added in later versions don't break existing code.
+    pub fn new(cmd: PathBuf, restrictions: crate::Restrictions) -> Self {
+        LaunchEnv {
+            cmd,
+            args: Vec::new(),
+            env: EnvMap::new(),
+            fds: FdSet::std(),
+            restrictions,
+            cwd: PathBuf::from("."),
+            grant_cwd_read: false,
+            readable_paths: Vec::new(),
+            writable_paths: Vec::new(),
+            extra_libraries: Vec::new(),
+            dependency_search: DependencySearch::default(),
+            arg0: None,
+            resolution: ExecResolution::default(),
+            kill_on_drop: true,
+            observer: None,
+            hash_allowlist: None,
+            allow_privileged_parent: false,
+        }
+    }
+
+    /// Start the child from an empty environment.
+    ///
+    /// The launcher still adds its own variables: `SANDBOX_CHANNELS` when the `FdSet` has
+    /// named channels, and on Windows, `SANDBOX_HANDLES` and the variables the
+    /// AppContainer needs (see `crate::runtime`).
+    pub fn clear_env(mut self) -> Self {
+        self.env = EnvMap::new();
+        self
+    }
+
+    /// Pass the parent's value for each named variable, such as `LANG` or `TZ`, to the
+    /// child.  Variables the parent doesn't set are skipped.  On Windows, the names match
+    /// without regard to case.
+    pub fn pass_env(mut self, names: &[&str]) -> Self {
+        for name in names {
+            self.env.insert_from_current(name);
+        }
+        self
+    }
+
+    /// Pass every parent variable whose name matches the shell-style pattern, such as
+    /// `LC_*`, to the child.  See `EnvMap::insert_matching_from_current`.
+    pub fn pass_env_matching(mut self, pattern: &str) -> Self {
+        self.env.insert_matching_from_current(pattern);
+        self
+    }
+
+    /// Tell the observer about each phase of the child's launch and end.
+    pub fn with_observer<O: LifecycleObserver + 'static>(mut self, observer: O) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Enforce the policy on the launch, through each platform's own mechanism.  See
+    /// `SandboxPolicy::apply`.
+    pub fn with_policy(self, policy: &SandboxPolicy) -> Self {
+        policy.apply(self)
+    }
+
+    /// Load the launch from a TOML or JSON profile file.  See `crate::profile`.
+    #[cfg(feature = "profile")]
+    pub fn from_profile<P: AsRef<Path>>(path: P) -> Result<Self, SandboxError> {
+        Ok(crate::profile::Profile::load(path.as_ref())?.launch_env())
+    }
+}
+
+/// How the launcher finds the executable named by `LaunchEnv::cmd`.
+///
+/// Searching the parent's `PATH` can pick up an unexpected binary when the environment
+/// isn't under the caller's control, so security-sensitive callers should pin the
+/// executable with `RequireAbsolute` or `SearchPath`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ExecResolution {
+    /// Search the parent's `PATH` for a bare name, as a shell would; a path with a
+    /// directory resolves against the parent's working directory.
+    ///
+    /// On Windows, the command always resolves against the parent's working directory.
+    #[default]
+    ParentPath,
+    /// Run `cmd` only if it is an absolute path to an executable.
+    RequireAbsolute,
+    /// Search only these directories, in order, for `cmd`, which must be a bare name.
+    SearchPath(Vec<PathBuf>),
+}
+
+impl ExecResolution {
+    /// Find the executable for the command, following the policy.
+    pub fn resolve(&self, cmd: &Path) -> Result<PathBuf, SandboxError> {
+        self.find(cmd).map_err(|e| SandboxError::resolution(cmd, e))
+    }
+
+    fn find(&self, cmd: &Path) -> Result<PathBuf, std::io::Error> {
+        let not_found = |e| std::io::Error::new(std::io::ErrorKind::NotFound, e);
+        match self {
+            ExecResolution::ParentPath => which::which(cmd).map_err(not_found),
+            ExecResolution::RequireAbsolute => {
+                if !cmd.is_absolute() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "not an absolute path",
+                    ));
+                }
+                which::which(cmd).map_err(not_found)
+            }
+            ExecResolution::SearchPath(dirs) => {
+                if cmd.components().count() != 1 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "must be a bare name to search for it",
+                    ));
+                }
+                let paths = std::env::join_paths(dirs)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+                // The cwd is only used for names with a directory, which were rejected.
+                which::which_in(cmd, Some(paths), ".").map_err(not_found)
+            }
+        }
+    }
+}
+
+/// Controls how the executable's shared library dependencies are discovered.
+///
+/// Override this when the executable comes from a chroot, container image, or
+/// cross-compiled tree, so the libraries are found in that tree instead of the host's.
+#[derive(Debug, Clone)]
+pub struct DependencySearch {
+    /// Root directory used when resolving library paths.  Defaults to `/`.
+    pub sysroot: PathBuf,
+
+    /// Additional directories to search for libraries, searched before the standard
+    /// locations, like `LD_LIBRARY_PATH`.
+    pub library_paths: Vec<PathBuf>,
+
+    /// Reuse the analysis from earlier launches of the same, unchanged executable, that
+    /// were given the same cache.  Defaults to None, which analyzes every launch.
+    pub cache: Option<DependencyCache>,
+}
+
+impl Default for DependencySearch {
+    fn default() -> Self {
+        DependencySearch {
+            sysroot: PathBuf::from("/"),
+            library_paths: Vec::new(),
+            cache: None,
+        }
+    }
+}
+
+impl DependencySearch {
+    /// Search for libraries inside the given root directory.
+    pub fn sysroot(root: PathBuf) -> Self {
+        DependencySearch {
+            sysroot: root,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_status_code() {
+        assert!(ExitStatus::Code(0).success());
+        assert!(!ExitStatus::Signaled(31).success());
+        assert_eq!(ExitStatus::Code(3).code(), Some(3));
+        assert_eq!(ExitStatus::SandboxKilled.code(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_status_to_std() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let code: std::process::ExitStatus = ExitStatus::Code(3).into();
+        assert_eq!(code.code(), Some(3));
+        let signaled: std::process::ExitStatus = ExitStatus::Signaled(11).into();
+        assert_eq!(signaled.signal(), Some(11));
+        let killed: std::process::ExitStatus = ExitStatus::SandboxKilled.into();
+        assert_eq!(killed.signal(), Some(9));
+    }
+
+    #[test]
+    fn fd_set_valid() {
+        assert!(FdSet::std().validate().is_ok());
+        assert!(
+            FdSet::basic(&[FdMode::Null, FdMode::KeepInChild, FdMode::Null])
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn null_device_opens() {
+        assert!(FdMode::NullDevice.open_file().unwrap().is_some());
+        assert!(FdMode::Null.open_file().unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn inherit_duplicates() {
+        use std::io::{Read, Write};
+
+        let (mut ours, theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mode = FdMode::inherit(theirs);
+        let mut first = mode.open_file().unwrap().unwrap();
+        let mut second = mode.open_file().unwrap().unwrap();
+        first.write_all(b"a").unwrap();
+        second.write_all(b"b").unwrap();
+        drop((first, second, mode));
+        let mut got = Vec::new();
+        ours.read_to_end(&mut got).unwrap();
+        assert_eq!(got, b"ab");
+    }
+
+    #[test]
+    fn fd_set_duplicate() {
+        let fds = FdSet::from_vec(vec![
+            Fd {
+                fd: 3,
+                mode: FdMode::ToChild,
+            },
+            Fd {
+                fd: 3,
+                mode: FdMode::FromChild,
+            },
+        ]);
+        assert!(matches!(fds.validate(), Err(SandboxError::InvalidFdSet(_))));
+    }
+
+    #[test]
+    fn fd_set_wrong_direction() {
+        let stdin_out = FdSet::basic(&[FdMode::FromChild]);
+        assert!(matches!(
+            stdin_out.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+        let stderr_in = FdSet::basic(&[FdMode::ToChild, FdMode::FromChild, FdMode::ToChild]);
+        assert!(matches!(
+            stderr_in.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+    }
+
+    #[test]
+    fn fd_set_merge() {
+        let merged = FdSet::basic(&[FdMode::Null, FdMode::FromChild, FdMode::MergeInto(1)]);
+        assert!(merged.validate().is_ok());
+        let missing = FdSet::basic(&[FdMode::Null, FdMode::Null, FdMode::MergeInto(1)]);
+        assert!(matches!(
+            missing.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+        let chained = FdSet::basic(&[FdMode::Null, FdMode::MergeInto(2), FdMode::MergeInto(1)]);
+        assert!(matches!(
+            chained.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+    }
+
+    #[test]
+    fn fd_set_channels() {
+        let fds = FdSet::std()
+            .with_channel("control", FdMode::ToChild)
+            .with_channel("log", FdMode::FromChild);
+        assert!(fds.validate().is_ok());
+        assert_eq!(fds.channels().get("control"), Some(&3));
+        assert_eq!(fds.channels().get("log"), Some(&4));
+        assert_eq!(fds.channels_env(), Some(OsString::from("control:3;log:4;")));
+        let repeated = FdSet::std()
+            .with_channel("log", FdMode::FromChild)
+            .with_channel("log", FdMode::FromChild);
+        assert!(matches!(
+            repeated.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+        let bad_name = FdSet::std().with_channel("a;b", FdMode::FromChild);
+        assert!(matches!(
+            bad_name.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+    }
+
+    #[test]
+    fn fd_set_from_map_sorted() {
+        let map = HashMap::from([
+            (4, FdMode::FromChild),
+            (0, FdMode::ToChild),
+            (3, FdMode::ToChild),
+        ]);
+        let order: Vec<u32> = FdSet::from_map(map).modes().iter().map(|f| f.fd).collect();
+        assert_eq!(order, vec![0, 3, 4]);
+    }
+
+    #[test]
+    fn fd_set_with_fd() {
+        let fds = FdSet::std()
+            .with_fd(1, FdMode::NullDevice)
+            .with_fd(3, FdMode::ToChild);
+        assert!(matches!(fds.mode_of(1), Some(FdMode::NullDevice)));
+        let order: Vec<u32> = fds.modes().iter().map(|f| f.fd).collect();
+        assert_eq!(order, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn fd_set_terminal() {
+        let fds = FdSet::basic(&[FdMode::Terminal, FdMode::Terminal, FdMode::MergeInto(1)])
+            .with_terminal_size(132, 43);
+        assert!(fds.validate().is_ok());
+        assert!(fds.has_terminal());
+        assert_eq!(fds.terminal_size(), (132, 43));
+        assert!(!FdSet::std().has_terminal());
+    }
+
+    #[test]
+    fn fd_set_tee() {
+        let teed = FdSet::std().with_tee(1, FdTee::append(PathBuf::from("out.log")));
+        assert!(teed.validate().is_ok());
+        assert_eq!(teed.tees().len(), 1);
+        let input = FdSet::std().with_tee(0, FdTee::append(PathBuf::from("in.log")));
+        assert!(matches!(
+            input.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+        let twice = FdSet::std()
+            .with_tee(2, FdTee::append(PathBuf::from("a.log")))
+            .with_tee(2, FdTee::create(PathBuf::from("b.log")));
+        assert!(matches!(
+            twice.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_resolution() {
+        let sh = Path::new("sh");
+        assert!(ExecResolution::ParentPath.resolve(sh).is_ok());
+        assert!(ExecResolution::RequireAbsolute.resolve(sh).is_err());
+        let found = ExecResolution::ParentPath.resolve(sh).unwrap();
+        assert_eq!(
+            ExecResolution::RequireAbsolute.resolve(&found).unwrap(),
+            found
+        );
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let pinned = ExecResolution::SearchPath(vec![dir.path().to_path_buf()]);
+        assert!(pinned.resolve(sh).is_err());
+        assert!(pinned.resolve(&found).is_err());
+        let bin = ExecResolution::SearchPath(vec![found.parent().unwrap().to_path_buf()]);
+        assert_eq!(bin.resolve(sh).unwrap(), found);
+    }
+
+    #[test]
+    fn fd_set_output_limit() {
+        let limited = FdSet::std().with_output_limit(1, OutputLimit::close_after(1024));
+        assert!(limited.validate().is_ok());
+        assert_eq!(
+            limited.output_limits().get(&1),
+            Some(&OutputLimit::close_after(1024))
+        );
+        let missing = FdSet::std().with_output_limit(5, OutputLimit::terminate_after(1));
+        assert!(matches!(
+            missing.validate(),
+            Err(SandboxError::InvalidFdSet(_))
+        ));
+    }
 }
@@ -6,7 +6,18 @@
 mod call_names;
 mod dependencies;
 mod fd;
+mod interpreter;
 mod jail;
 mod launch;
+mod placement;
+mod probe;
+mod zygote;
 
-pub(crate) use launch::launch_child;
+pub(crate) use dependencies::find_bin_dependencies;
+#[cfg(feature = "tokio")]
+pub(crate) use fd::set_nonblocking;
+pub(crate) use launch::{
+    LinuxChild, LinuxChildState, PreparedLaunch, dependencies, launch_child, plan,
+};
+pub(crate) use probe::{is_privileged, probe};
+pub use zygote::Zygote;
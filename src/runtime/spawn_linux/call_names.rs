@@ -50,8 +50,10 @@ pub(crate) const ALLOW_LIST: &[&str] = &[
     "futex",
     "rseq",
     "rt_sigreturn",
-    // Allow the command execution to happen.
+    // Allow the command execution to happen.  A launch with a hash allowlist runs the
+    // executable from the file it hashed.
     "execve",
+    "execveat",
     // For lazy loaded libraries, some limited use of openat is allowed.
     // This should be a conditional, but I can't figure out the right semantics
     // to get it to run.  Instead, we rely on landlock to prevent bad opens.
@@ -72,3 +74,85 @@ pub(crate) const ALLOW_LIST: &[&str] = &[
     // "timer_create",
     // "clock_gettime",
 ];
+
+/// Allowed along with `ALLOW_LIST` when the child may write beneath some paths, so it can
+/// change the files there and not just write to the ones it opens.  Landlock keeps these
+/// to the writable paths.
+pub(crate) const FILE_WRITE_ALLOW_LIST: &[&str] = &[
+    "ftruncate",
+    "truncate",
+    "fsync",
+    "fdatasync",
+    "mkdir",
+    "mkdirat",
+    "rmdir",
+    "unlink",
+    "unlinkat",
+    "rename",
+    "renameat",
+    "renameat2",
+];
+
+/// Allowed along with `ALLOW_LIST` when the child may use TCP.  The `socket` call itself is
+/// allowed separately, only for IPv4 and IPv6 TCP sockets, and Landlock keeps the
+/// connections to the allowed ports.
+pub(crate) const NETWORK_ALLOW_LIST: &[&str] = &[
+    "connect",
+    "bind",
+    "listen",
+    "accept",
+    "accept4",
+    "sendto",
+    "recvfrom",
+    "sendmsg",
+    "recvmsg",
+    "shutdown",
+    "getsockname",
+    "getpeername",
+    "getsockopt",
+    "setsockopt",
+    "ppoll",
+    "epoll_create1",
+    "epoll_ctl",
+    "epoll_wait",
+    "epoll_pwait",
+];
+
+/// Allowed along with the child's lists in a zygote's own filter, so it can fork the
+/// children and put their jails on them.  The children's filters don't allow these.
+pub(crate) const ZYGOTE_ALLOW_LIST: &[&str] = &[
+    // Take requests from the caller, and pass it the children's streams.
+    "sendmsg",
+    "recvmsg",
+    // Set up the children's streams.
+    "pipe",
+    "pipe2",
+    "dup",
+    "dup2",
+    "dup3",
+    // Fork the children, and watch, kill and collect them.
+    "clone",
+    "clone3",
+    "fork",
+    "vfork",
+    "pidfd_open",
+    "ppoll",
+    "wait4",
+    "waitid",
+    "kill",
+    "nanosleep",
+    "clock_nanosleep",
+    "clock_gettime",
+    "sched_yield",
+    "restart_syscall",
+    "statx",
+    // Put each child in its jail, between the fork and the exec.
+    "chdir",
+    "setrlimit",
+    "sched_setaffinity",
+    "prctl",
+    "seccomp",
+    "landlock_create_ruleset",
+    "landlock_add_rule",
+    "landlock_restrict_self",
+];
@@ -4,87 +4,79 @@
 //!
 //! This inspects the executable and its associated shared libraries.
 
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    collections::HashSet,
+    io::Read,
+    path::{Path, PathBuf},
+};
 
-/// A binary dependency.  If the `realpath` is None, then it could not be found.
-pub struct Dependency {
-    pub path: PathBuf,
-    pub realpath: Option<PathBuf>,
-    pub required: bool,
-}
+use crate::logging;
+use crate::runtime::{deps::Dependency, spawn::DependencySearch, wildcard::wildcard_match};
 
 impl Dependency {
-    fn from_path(path: &PathBuf, required: bool) -> Self {
-        let abs = match std::path::absolute(&path) {
-            Ok(p) => p,
-            Err(_) => {
-                return Dependency {
-                    path: path.to_path_buf(),
-                    realpath: None,
-                    required,
-                };
-            }
-        };
-
-        if path.exists() {
-            Dependency {
-                path: path.to_path_buf(),
-                realpath: Some(abs),
-                required,
-            }
-        } else {
-            Dependency {
-                path: path.to_path_buf(),
-                realpath: None,
-                required,
-            }
-        }
-    }
-
     fn from_library(lib: &lddtree::Library, required_set: &HashSet<String>) -> Self {
         Dependency {
+            name: lib.name.clone(),
             path: lib.path.clone(),
             realpath: lib.realpath.clone(),
             required: required_set.contains(&lib.name),
+            needed: lib.needed.clone(),
         }
     }
 
-    pub fn exists(&self) -> bool {
-        self.realpath.is_some()
-    }
-
-    pub fn best_path(&self) -> &PathBuf {
-        match &self.realpath {
-            Some(r) => r,
-            None => &self.path,
-        }
-    }
-
-    pub fn invalid(&self) -> bool {
-        self.required && self.realpath.is_none()
-    }
-
     fn not_visited(&self, visited: &mut HashSet<PathBuf>) -> bool {
         let r = self.best_path();
         let ret = !visited.contains(r);
         if ret {
-            visited.insert(r.clone());
+            visited.insert(r.to_path_buf());
         }
         ret
     }
 }
 
 /// Discovers all binary dependencies for the executable.
-pub fn find_bin_dependencies(exec: &PathBuf) -> Vec<Dependency> {
+pub fn find_bin_dependencies(exec: &PathBuf, search: &DependencySearch) -> Vec<Dependency> {
+    find_elf_dependencies(exec, search, true)
+}
+
+/// Discovers the dependencies for the executable or shared library, reusing earlier results
+/// when the file hasn't changed.
+fn find_elf_dependencies(
+    exec: &PathBuf,
+    search: &DependencySearch,
+    is_executable: bool,
+) -> Vec<Dependency> {
+    match &search.cache {
+        Some(cache) => cache.cached(exec, search, is_executable, || {
+            analyze_elf_dependencies(exec, search, is_executable)
+        }),
+        None => analyze_elf_dependencies(exec, search, is_executable),
+    }
+}
+
+/// Runs the dependency analysis for the executable or shared library.
+fn analyze_elf_dependencies(
+    exec: &PathBuf,
+    search: &DependencySearch,
+    is_executable: bool,
+) -> Vec<Dependency> {
     // Only perform the inspection if the executable exists.
     let exec_dep = Dependency::from_path(exec, true);
     if exec_dep.realpath.is_none() {
         return vec![exec_dep];
     }
 
-    let analyzer = lddtree::DependencyAnalyzer::new(PathBuf::from("/"));
+    // An executable without a program interpreter is statically linked, so it
+    // loads no shared libraries.  Shared libraries never have an interpreter,
+    // so this check only applies to executables.
+    if is_executable && is_static_executable(exec_dep.best_path()) {
+        return vec![exec_dep];
+    }
+
+    let analyzer = lddtree::DependencyAnalyzer::new(search.sysroot.clone())
+        .library_paths(search.library_paths.clone());
     let mut visited = HashSet::new();
-    println!("Finding dependencies for: {:?}", &exec_dep.best_path());
+    logging::debug!("finding dependencies for {:?}", exec_dep.best_path());
     let mut ret = vec![exec_dep];
 
     // Populate a search path by scanning the dependency tree.
@@ -97,18 +89,131 @@ pub fn find_bin_dependencies(exec: &PathBuf) -> Vec<Dependency> {
             return ret;
         }
     };
+    ret[0].needed = deps.needed.clone();
     let required = load_required_libs(&deps);
     for lib in deps.libraries.values() {
-        println!("Library: {:?}", lib.name);
+        logging::trace!("library {:?}", lib.name);
         let dep = Dependency::from_library(lib, &required);
         if dep.not_visited(&mut visited) {
-            println!("Found dependency: {:?}", dep.best_path());
+            logging::trace!("found dependency {:?}", dep.best_path());
             ret.push(dep);
         }
     }
     ret
 }
 
+/// Discovers the dependencies for libraries the program loads at runtime (`dlopen`),
+/// which do not show up in the executable's dependency tree.
+///
+/// The final path component may contain `*` and `?` wildcards.  A pattern with
+/// wildcards that matches nothing is ignored, while an explicit path that does not
+/// exist is reported as a missing, required dependency.
+pub fn find_extra_library_dependencies(
+    patterns: &[PathBuf],
+    search: &DependencySearch,
+) -> Vec<Dependency> {
+    let mut visited = HashSet::new();
+    let mut ret = Vec::new();
+    for pattern in patterns {
+        for lib in expand_library_pattern(pattern) {
+            for dep in find_elf_dependencies(&lib, search, false) {
+                if dep.not_visited(&mut visited) {
+                    ret.push(dep);
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Check whether the file is an ELF executable without a PT_INTERP program header.
+/// Anything that can't be parsed as ELF is treated as dynamic, and left to the
+/// dependency analyzer to report.
+fn is_static_executable(path: &Path) -> bool {
+    let file = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(_) => return false,
+    };
+    // The headers of interest are almost always at the start of the file;
+    // 4 KiB covers the ELF header plus the program header table.
+    let mut buf = Vec::with_capacity(4096);
+    if file.take(4096).read_to_end(&mut buf).is_err() {
+        return false;
+    }
+    matches!(elf_has_interp(&buf), Some(false))
+}
+
+/// Program header type for the program interpreter.
+const PT_INTERP: u32 = 3;
+
+/// Scan the ELF program headers for PT_INTERP.
+/// Returns None if the data is not a complete ELF header and program header table.
+fn elf_has_interp(data: &[u8]) -> Option<bool> {
+    if data.len() < 20 || &data[0..4] != b"\x7fELF" {
+        return None;
+    }
+    let is_64 = match data[4] {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let little = match data[5] {
+        1 => true,
+        2 => false,
+        _ => return None,
+    };
+    let read = |off: usize, len: usize| -> Option<u64> {
+        let bytes = data.get(off..off.checked_add(len)?)?;
+        let mut v: u64 = 0;
+        for i in 0..len {
+            let b = if little { bytes[len - 1 - i] } else { bytes[i] };
+            v = (v << 8) | b as u64;
+        }
+        Some(v)
+    };
+    let (phoff, phentsize, phnum) = if is_64 {
+        (read(0x20, 8)?, read(0x36, 2)?, read(0x38, 2)?)
+    } else {
+        (read(0x1c, 4)?, read(0x2a, 2)?, read(0x2c, 2)?)
+    };
+    for i in 0..phnum {
+        let off = i
+            .checked_mul(phentsize)
+            .and_then(|o| o.checked_add(phoff))
+            .and_then(|o| usize::try_from(o).ok())?;
+        if read(off, 4)? as u32 == PT_INTERP {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+/// Expand the wildcards in the file name of the pattern.
+fn expand_library_pattern(pattern: &Path) -> Vec<PathBuf> {
+    let name = match pattern.file_name().and_then(|n| n.to_str()) {
+        Some(n) if n.contains(['*', '?']) => n,
+        _ => return vec![pattern.to_path_buf()],
+    };
+    let parent = match pattern.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let entries = match std::fs::read_dir(&parent) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+    let mut ret: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| match e.file_name().to_str() {
+            Some(n) => wildcard_match(name.as_bytes(), n.as_bytes()),
+            None => false,
+        })
+        .map(|e| e.path())
+        .collect();
+    ret.sort();
+    ret
+}
+
 fn load_required_libs(tree: &lddtree::DependencyTree) -> HashSet<String> {
     let mut ret = HashSet::new();
     for name in &tree.needed {
@@ -133,7 +238,7 @@ mod tests {
         assert_eq!(p_exec.exists(), true);
 
         // At a minimum, it should have 1 resolved dependency.
-        let deps = find_bin_dependencies(&p_exec.into());
+        let deps = find_bin_dependencies(&p_exec.into(), &DependencySearch::default());
         // let mut unresolved_count = 0;
         let mut found_count = 0;
         for d in deps {
@@ -147,4 +252,65 @@ mod tests {
         }
         assert_eq!(found_count > 0, true, "Must have at least 1 dependency");
     }
+
+    #[test]
+    fn ls_is_dynamic() {
+        let p_exec = which::which("ls").unwrap();
+        assert!(!is_static_executable(&p_exec));
+    }
+
+    /// Build a minimal 64-bit little-endian ELF image with the given program header types.
+    fn elf64(types: &[u32]) -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[0..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2;
+        data[5] = 1;
+        data[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        data[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        data[0x38..0x3a].copy_from_slice(&(types.len() as u16).to_le_bytes());
+        for t in types {
+            let mut ph = vec![0u8; 56];
+            ph[0..4].copy_from_slice(&t.to_le_bytes());
+            data.extend(ph);
+        }
+        data
+    }
+
+    #[test]
+    fn elf_interp() {
+        // PT_LOAD, PT_DYNAMIC, PT_INTERP
+        assert_eq!(elf_has_interp(&elf64(&[1, 2, 3])), Some(true));
+        // PT_LOAD only; statically linked.
+        assert_eq!(elf_has_interp(&elf64(&[1])), Some(false));
+        assert_eq!(elf_has_interp(b"#!/bin/sh\n"), None);
+        // Truncated program header table.
+        let mut data = elf64(&[1, 3]);
+        data.truncate(100);
+        assert_eq!(elf_has_interp(&data), None);
+    }
+
+    #[test]
+    fn elf_oversized_header_table() {
+        // A program header offset that overflows once the entry is read.
+        let mut data = elf64(&[1, 3]);
+        data[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(elf_has_interp(&data), None);
+        // An entry size that puts the second entry past the end of the file.
+        let mut data = elf64(&[1, 3]);
+        data[0x36..0x38].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(elf_has_interp(&data), None);
+    }
+
+    #[test]
+    fn extra_missing_library() {
+        let deps = find_extra_library_dependencies(
+            &[
+                PathBuf::from("/does/not/exist/libplugin.so"),
+                PathBuf::from("/does/not/exist/libplugin-*.so"),
+            ],
+            &DependencySearch::default(),
+        );
+        assert_eq!(deps.len(), 1);
+        assert!(deps[0].invalid());
+    }
 }
@@ -5,7 +5,7 @@
 use std::{
     collections::HashSet,
     fs::File,
-    os::fd::{AsRawFd, OwnedFd, RawFd},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
 };
 
 use nix::{libc::dup2, unistd::pipe};
@@ -15,6 +15,9 @@ use crate::runtime::{error::SandboxError, spawn::FdSet};
 pub struct ForkedFd {
     fds: Vec<FdForkMap>,
     keep_fds: HashSet<nix::libc::c_int>,
+    /// The parent's side of the pseudo-terminal, and the child FD that makes it the
+    /// controlling terminal.
+    terminal: Option<(OwnedFd, nix::libc::c_int)>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -29,6 +32,8 @@ pub struct FdMap {
     pub dup_to: u32,
     pub stream: File,
     pub direction: StreamDirection,
+    /// True if the stream is the parent's side of the pseudo-terminal.
+    pub terminal: bool,
 }
 
 impl ForkedFd {
@@ -37,37 +42,163 @@ impl ForkedFd {
     pub fn new(config: FdSet) -> Result<Self, SandboxError> {
         let mut fds: Vec<FdForkMap> = Vec::new();
         let mut keep_fds: HashSet<nix::libc::c_int> = HashSet::new();
+        let mut merges = Vec::new();
+        let nonblocking = config.nonblocking();
+        let pty = if config.has_terminal() {
+            Some(open_terminal(&config)?)
+        } else {
+            None
+        };
+        let mut terminal_streams = (false, false);
 
         for fd_m in config.modes() {
             match fd_m.mode {
                 crate::runtime::spawn::FdMode::Null => {}
+                crate::runtime::spawn::FdMode::MergeInto(target) => {
+                    // Handled once the target exists.
+                    merges.push((fd_m.fd, target));
+                }
+                crate::runtime::spawn::FdMode::Terminal => {
+                    let Some(opened) = &pty else { continue };
+                    // The parent gets one stream for each direction, as every FD shares
+                    // the same terminal.
+                    let (direction, parent_fd) = if fd_m.fd == 0 {
+                        let first = !terminal_streams.0;
+                        terminal_streams.0 = true;
+                        (StreamDirection::ToChild, first)
+                    } else {
+                        let first = !terminal_streams.1;
+                        terminal_streams.1 = true;
+                        (StreamDirection::FromChild, first)
+                    };
+                    let parent_fd = if parent_fd {
+                        Some(
+                            opened
+                                .master
+                                .try_clone()
+                                .map_err(|e| SandboxError::fd_setup(Some(fd_m.fd), e))?,
+                        )
+                    } else {
+                        None
+                    };
+                    fds.push(FdForkMap {
+                        dup_to: fd_m.fd,
+                        parent_fd,
+                        child_fd: opened
+                            .slave
+                            .try_clone()
+                            .map_err(|e| SandboxError::fd_setup(Some(fd_m.fd), e))?,
+                        direction,
+                        terminal: true,
+                    });
+                    keep_fds.insert(fd_m.fd as nix::libc::c_int);
+                }
                 crate::runtime::spawn::FdMode::KeepInChild => {
                     // Keep the FD open in the child without redirection.
                     keep_fds.insert(fd_m.fd as nix::libc::c_int);
                 }
                 crate::runtime::spawn::FdMode::FromChild => {
-                    let (read_fd, write_fd) = pipe().map_err(|e| errno_to_error(e))?;
+                    let (read_fd, write_fd) =
+                        pipe().map_err(|e| SandboxError::fd_setup(Some(fd_m.fd), e))?;
+                    if nonblocking {
+                        set_nonblocking(&read_fd).map_err(|e| e.for_fd(fd_m.fd))?;
+                    }
                     fds.push(FdForkMap {
                         dup_to: fd_m.fd,
-                        parent_fd: read_fd,
+                        parent_fd: Some(read_fd),
                         child_fd: write_fd,
                         direction: StreamDirection::FromChild,
+                        terminal: false,
                     });
                     keep_fds.insert(fd_m.fd as nix::libc::c_int);
                 }
                 crate::runtime::spawn::FdMode::ToChild => {
-                    let (read_fd, write_fd) = pipe().map_err(|e| errno_to_error(e))?;
+                    let (read_fd, write_fd) =
+                        pipe().map_err(|e| SandboxError::fd_setup(Some(fd_m.fd), e))?;
+                    if nonblocking {
+                        set_nonblocking(&write_fd).map_err(|e| e.for_fd(fd_m.fd))?;
+                    }
                     fds.push(FdForkMap {
                         dup_to: fd_m.fd,
-                        parent_fd: write_fd,
+                        parent_fd: Some(write_fd),
                         child_fd: read_fd,
                         direction: StreamDirection::ToChild,
+                        terminal: false,
                     });
                     keep_fds.insert(fd_m.fd as nix::libc::c_int);
                 }
+                crate::runtime::spawn::FdMode::FromFile(_)
+                | crate::runtime::spawn::FdMode::ToFile(_)
+                | crate::runtime::spawn::FdMode::AppendFile(_)
+                | crate::runtime::spawn::FdMode::NullDevice
+                | crate::runtime::spawn::FdMode::Inherit(_) => {
+                    // The child gets the file itself, so the parent has no stream.
+                    let direction = match fd_m.mode {
+                        crate::runtime::spawn::FdMode::FromFile(_) => StreamDirection::ToChild,
+                        _ => StreamDirection::FromChild,
+                    };
+                    let file = fd_m
+                        .mode
+                        .open_file()
+                        .map_err(|e| SandboxError::fd_setup(Some(fd_m.fd), e))?;
+                    if let Some(file) = file {
+                        fds.push(FdForkMap {
+                            dup_to: fd_m.fd,
+                            parent_fd: None,
+                            child_fd: OwnedFd::from(file),
+                            direction,
+                            terminal: false,
+                        });
+                        keep_fds.insert(fd_m.fd as nix::libc::c_int);
+                    }
+                }
             }
         }
-        Ok(ForkedFd { fds, keep_fds })
+        for (fd, target) in merges {
+            // The child shares the target's stream, or the parent's own FD when the child
+            // keeps it.
+            let child_fd = match fds.iter().find(|m| m.dup_to == target) {
+                Some(m) => m.child_fd.try_clone(),
+                None => unsafe { BorrowedFd::borrow_raw(target as RawFd) }.try_clone_to_owned(),
+            }?;
+            fds.push(FdForkMap {
+                dup_to: fd,
+                parent_fd: None,
+                child_fd,
+                direction: StreamDirection::FromChild,
+                terminal: false,
+            });
+            keep_fds.insert(fd as nix::libc::c_int);
+        }
+        let terminal = match pty {
+            None => None,
+            Some(opened) => {
+                let ctty = config
+                    .modes()
+                    .iter()
+                    .find(|f| matches!(f.mode, crate::runtime::spawn::FdMode::Terminal))
+                    .map(|f| f.fd as nix::libc::c_int)
+                    .unwrap_or(0);
+                Some((opened.master, ctty))
+            }
+        };
+        Ok(ForkedFd {
+            fds,
+            keep_fds,
+            terminal,
+        })
+    }
+
+    /// A copy of the parent's side of the pseudo-terminal, if the set has one.
+    pub fn terminal(&self) -> Result<Option<File>, SandboxError> {
+        match &self.terminal {
+            None => Ok(None),
+            Some((master, _)) => Ok(Some(File::from(
+                master
+                    .try_clone()
+                    .map_err(|e| SandboxError::fd_setup(None, e))?,
+            ))),
+        }
     }
 
     /// Get the list of FDs that the child process will use.
@@ -76,11 +207,13 @@ impl ForkedFd {
     }
 
     /// Called by the parent process after fork, to retrieve the parent process's version of the FDs.
-    /// This will drop the child's end of the pipes.
+    /// This will drop the child's end of the pipes, and the redirected files.
     pub fn parent_after_fork(self) -> Vec<FdMap> {
         let mut ret = Vec::new();
         for fd in self.fds {
-            ret.push(fd.parent_after_fork());
+            if let Some(m) = fd.parent_after_fork() {
+                ret.push(m);
+            }
         }
         ret
     }
@@ -94,16 +227,28 @@ impl ForkedFd {
         for fd in self.fds {
             fd.child_after_fork();
         }
+        if let Some((master, ctty)) = self.terminal {
+            drop(master);
+            // A new session, so the terminal can become the controlling terminal, and the
+            // child receives its SIGWINCH and job control signals.
+            if unsafe { nix::libc::setsid() } < 0
+                || unsafe { nix::libc::ioctl(ctty, nix::libc::TIOCSCTTY, 0) } < 0
+            {
+                std::process::exit(253);
+            }
+        }
     }
 }
 
 struct FdForkMap {
     dup_to: u32,
-    /// FD used by the parent.
-    parent_fd: OwnedFd,
+    /// FD used by the parent; None when the child's end is a file.
+    parent_fd: Option<OwnedFd>,
     /// FD used by the child.
     child_fd: OwnedFd,
     direction: StreamDirection,
+    /// True if the child's end is the pseudo-terminal.
+    terminal: bool,
 }
 
 impl FdForkMap {
@@ -131,17 +276,39 @@ impl FdForkMap {
     // Closes the also_close FD, and returns the fd as a stream,
     // which passes ownership of the OwnedFd to the stream object, which prevents
     // it from closing.
-    fn parent_after_fork(self) -> FdMap {
-        FdMap {
+    fn parent_after_fork(self) -> Option<FdMap> {
+        Some(FdMap {
             dup_to: self.dup_to,
-            stream: File::from(self.parent_fd),
+            stream: File::from(self.parent_fd?),
             direction: self.direction,
-        }
+            terminal: self.terminal,
+        })
     }
 }
 
+/// Open the pseudo-terminal at the set's initial size.
+fn open_terminal(config: &FdSet) -> Result<nix::pty::OpenptyResult, SandboxError> {
+    let (cols, rows) = config.terminal_size();
+    let opened = nix::pty::openpty(Some(&crate::runtime::terminal::winsize(cols, rows)), None)
+        .map_err(errno_to_error)?;
+    if config.nonblocking() {
+        set_nonblocking(&opened.master)?;
+    }
+    Ok(opened)
+}
+
+/// Set O_NONBLOCK on the parent's end.  Each end of a pipe has its own status flags, so
+/// the child's end still blocks.
+pub(crate) fn set_nonblocking(fd: impl AsFd) -> Result<(), SandboxError> {
+    let fd = fd.as_fd();
+    let flags = nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_GETFL).map_err(errno_to_error)?;
+    let flags = nix::fcntl::OFlag::from_bits_truncate(flags) | nix::fcntl::OFlag::O_NONBLOCK;
+    nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_SETFL(flags)).map_err(errno_to_error)?;
+    Ok(())
+}
+
 fn errno_to_error(err: nix::Error) -> SandboxError {
-    SandboxError::Io(err.into())
+    SandboxError::fd_setup(None, err)
 }
 
 #[cfg(test)]
@@ -154,6 +321,7 @@ mod tests {
     use std::fs::File;
     use std::io::{Read, Write};
     use std::os::fd::FromRawFd;
+    use std::path::PathBuf;
 
     /// Test that the parent mapping direction construction are correct.
     #[test]
@@ -337,6 +505,151 @@ mod tests {
         }
     }
 
+    /// Test the child writing straight to a file, with no parent stream.
+    #[test]
+    fn to_file_via_stdout() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("out.txt");
+        let fds = FdSet::from_vec(vec![Fd {
+            fd: 1,
+            mode: FdMode::ToFile(path.clone()),
+        }]);
+        let forked = ForkedFd::new(fds).expect("Failed to create ForkedFd");
+
+        match unsafe { fork() } {
+            Ok(ForkResult::Parent { child }) => {
+                // Parent: the file replaces the stream.
+                let maps = forked.parent_after_fork();
+                assert_eq!(maps.len(), 0);
+                assert_child_exit_ok(child);
+                assert_eq!(std::fs::read(&path).expect("read failed"), b"OK");
+            }
+            Ok(ForkResult::Child) => {
+                // Child: install dup2 mappings, then write to FD 1.
+                forked.child_after_fork();
+                let mut f = unsafe { File::from_raw_fd(1) };
+                exit_on_err(f.write_all(b"OK"));
+                exit_on_err(f.flush());
+                exit_ok();
+            }
+            Err(e) => panic!("fork failed: {}", e),
+        }
+    }
+
+    /// Test stderr merged into stdout, so both arrive on one stream.
+    #[test]
+    fn merge_stderr_into_stdout() {
+        let fds = FdSet::basic(&[FdMode::Null, FdMode::FromChild, FdMode::MergeInto(1)]);
+        let forked = ForkedFd::new(fds).expect("Failed to create ForkedFd");
+
+        match unsafe { fork() } {
+            Ok(ForkResult::Parent { child }) => {
+                // Parent: only the stdout stream exists.
+                let maps = forked.parent_after_fork();
+                assert_eq!(maps.len(), 1);
+                let reader = maps.into_iter().next().expect("missing one element");
+                assert_eq!(reader.dup_to, 1);
+                let mut reader = reader.stream;
+                let mut buf = Vec::new();
+                reader.read_to_end(&mut buf).expect("parent read failed");
+                assert_eq!(buf, b"OKER", "unexpected data from child");
+                assert_child_exit_ok(child);
+            }
+            Ok(ForkResult::Child) => {
+                // Child: write to FD 1, then FD 2.
+                forked.child_after_fork();
+                let mut out = unsafe { File::from_raw_fd(1) };
+                exit_on_err(out.write_all(b"OK"));
+                drop(out);
+                let mut err = unsafe { File::from_raw_fd(2) };
+                exit_on_err(err.write_all(b"ER"));
+                drop(err);
+                exit_ok();
+            }
+            Err(e) => panic!("fork failed: {}", e),
+        }
+    }
+
+    /// Test the child writing to a pseudo-terminal, at the requested size.
+    #[test]
+    fn terminal_via_stdout() {
+        let fds = FdSet::basic(&[FdMode::Terminal, FdMode::Terminal, FdMode::MergeInto(1)])
+            .with_terminal_size(100, 30);
+        let forked = ForkedFd::new(fds).expect("Failed to create ForkedFd");
+        let terminal = forked
+            .terminal()
+            .expect("dup failed")
+            .expect("missing terminal");
+
+        match unsafe { fork() } {
+            Ok(ForkResult::Parent { child }) => {
+                // Parent: one stream for each direction.
+                let mut maps = forked.parent_after_fork();
+                assert_eq!(maps.len(), 2);
+                maps.sort_by_key(|m| m.dup_to);
+                assert!(maps[0].terminal && maps[1].terminal);
+                matches_direction(&maps[0], StreamDirection::ToChild);
+                let reader = maps.pop().expect("missing reader");
+                assert_eq!(reader.dup_to, 1);
+                let mut buf = Vec::new();
+                crate::runtime::terminal::TerminalReader(reader.stream)
+                    .read_to_end(&mut buf)
+                    .expect("parent read failed");
+                assert_eq!(buf, b"OK", "unexpected data from child");
+                assert_child_exit_ok(child);
+                drop(terminal);
+            }
+            Ok(ForkResult::Child) => {
+                forked.child_after_fork();
+                if unsafe { libc::isatty(1) } != 1 || unsafe { libc::isatty(2) } != 1 {
+                    exit_with(2);
+                }
+                let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+                if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } < 0
+                    || size.ws_col != 100
+                    || size.ws_row != 30
+                {
+                    exit_with(3);
+                }
+                let mut f = unsafe { File::from_raw_fd(1) };
+                exit_on_err(f.write_all(b"OK"));
+                exit_ok();
+            }
+            Err(e) => panic!("fork failed: {}", e),
+        }
+    }
+
+    /// A non-blocking parent end reports WouldBlock while the child has written nothing.
+    #[test]
+    fn nonblocking_parent_end() {
+        let fds = FdSet::from_vec(vec![Fd {
+            fd: 9,
+            mode: FdMode::FromChild,
+        }])
+        .with_nonblocking();
+        let forked = ForkedFd::new(fds).expect("Failed to create ForkedFd");
+        // Keep a copy of the child's end open, as a running child would.
+        let child_end = forked.fds[0].child_fd.try_clone().expect("dup failed");
+        let mut maps = forked.parent_after_fork();
+        let mut buf = [0u8; 4];
+        let err = maps[0]
+            .stream
+            .read(&mut buf)
+            .expect_err("read should not block");
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+        drop(child_end);
+    }
+
+    /// Opening a missing input file fails before the fork.
+    #[test]
+    fn from_missing_file() {
+        let fds = FdSet::from_vec(vec![Fd {
+            fd: 0,
+            mode: FdMode::FromFile(PathBuf::from("/does/not/exist")),
+        }]);
+        assert!(ForkedFd::new(fds).is_err());
+    }
+
     // Match the map's direction.
     // Avoids pulling in PartialEq for enum in public API.
     fn matches_direction(map: &FdMap, expected: StreamDirection) {
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: MIT
+
+//! Resolve the interpreter for scripts that start with a shebang (`#!`) line.
+//!
+//! The kernel would normally load the interpreter itself, but the jail only allows
+//! reading the files discovered before the launch.  Instead, the launch runs the
+//! interpreter directly with the script as its argument, so the interpreter and its
+//! libraries can be added to the readable set.
+//!
+//! A shebang of `#!/usr/bin/env name` is resolved on the parent's PATH, so the child
+//! does not need to run `env` to find the interpreter.
+
+use std::{
+    ffi::OsString,
+    io::Read,
+    os::unix::ffi::OsStringExt as _,
+    path::{Path, PathBuf},
+};
+
+use crate::runtime::error::SandboxError;
+
+/// The kernel limits the shebang line to this many bytes (BINPRM_BUF_SIZE).
+const MAX_SHEBANG: u64 = 256;
+
+/// The program that runs a script.
+#[derive(Debug, PartialEq)]
+pub struct Interpreter {
+    /// The interpreter executable.
+    pub path: PathBuf,
+
+    /// The optional single argument from the shebang line.
+    pub arg: Option<OsString>,
+}
+
+/// Find the interpreter for the file, or None if the file is not a script.
+pub fn find_interpreter(script: &Path) -> Result<Option<Interpreter>, SandboxError> {
+    let mut buf = Vec::new();
+    std::fs::File::open(script)?
+        .take(MAX_SHEBANG)
+        .read_to_end(&mut buf)?;
+    let (path, arg) = match parse_shebang(&buf) {
+        None => return Ok(None),
+        Some(s) => s,
+    };
+    if path.file_name().is_some_and(|n| n == "env") {
+        // Resolve the real interpreter now; any further arguments can't be split
+        // reliably, so they aren't supported.
+        let name = arg.ok_or_else(|| {
+            SandboxError::JailSetup(format!(
+                "script {} uses env without an interpreter",
+                script.display()
+            ))
+        })?;
+        return Ok(Some(Interpreter {
+            path: which::which(&name).map_err(|e| {
+                let e = std::io::Error::new(std::io::ErrorKind::NotFound, e);
+                SandboxError::resolution(Path::new(&name), e)
+            })?,
+            arg: None,
+        }));
+    }
+    Ok(Some(Interpreter { path, arg }))
+}
+
+/// Split the shebang line into the interpreter path and the optional argument.
+/// Like the kernel, everything after the interpreter is one argument.
+fn parse_shebang(data: &[u8]) -> Option<(PathBuf, Option<OsString>)> {
+    let line = data.strip_prefix(b"#!")?;
+    let line = match line.iter().position(|&b| b == b'\n') {
+        Some(end) => &line[..end],
+        None => line,
+    };
+    let line = line.trim_ascii();
+    if line.is_empty() {
+        return None;
+    }
+    let (interp, arg) = match line.iter().position(|b| b.is_ascii_whitespace()) {
+        Some(pos) => (&line[..pos], Some(line[pos..].trim_ascii())),
+        None => (line, None),
+    };
+    Some((
+        PathBuf::from(OsString::from_vec(interp.to_vec())),
+        arg.filter(|a| !a.is_empty())
+            .map(|a| OsString::from_vec(a.to_vec())),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_script() {
+        assert_eq!(parse_shebang(b"\x7fELF"), None);
+        assert_eq!(parse_shebang(b"#!\n"), None);
+    }
+
+    #[test]
+    fn interpreter_only() {
+        assert_eq!(
+            parse_shebang(b"#!/bin/sh\necho hi\n"),
+            Some((PathBuf::from("/bin/sh"), None))
+        );
+    }
+
+    #[test]
+    fn interpreter_with_arg() {
+        assert_eq!(
+            parse_shebang(b"#! /usr/bin/python3  -u -E \r\n"),
+            Some((
+                PathBuf::from("/usr/bin/python3"),
+                Some(OsString::from("-u -E"))
+            ))
+        );
+    }
+
+    #[test]
+    fn env_resolves_on_path() {
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut script, b"#!/usr/bin/env sh\n").unwrap();
+        let interp = find_interpreter(script.path()).unwrap().unwrap();
+        assert_eq!(interp.path, which::which("sh").unwrap());
+        assert_eq!(interp.arg, None);
+    }
+}
@@ -19,26 +19,34 @@
 //!
 //! ### rlimits
 //!
-//! Limit the number of open files.  Currently, this is hard coded to 2048.
+//! Limit the number of open files, and optionally the address space size and CPU time
+//! from the resource limits.
 //!
 //! ### seccomp
 //!
 //! Defaults to deny access, with a list of allowed syscalls in the call_names
 //! file.
 //!
+//! ### CPU placement
+//!
+//! Optionally pins the child to a set of CPUs or a NUMA node with
+//! `sched_setaffinity`.
+//!
 
 use std::io::Write;
 use std::path::PathBuf;
 
 use landlock::{
-    ABI, Access, AccessFs, AccessNet, Compatible, LandlockStatus, Ruleset, RulesetAttr,
+    ABI, Access, AccessFs, AccessNet, Compatible, LandlockStatus, NetPort, Ruleset, RulesetAttr,
     RulesetCreatedAttr, Scope, path_beneath_rules,
 };
+use nix::sched::{CpuSet, sched_setaffinity};
 use nix::sys::prctl::set_no_new_privs;
 use nix::sys::resource::{Resource, rlim_t, setrlimit};
 
-use crate::runtime::error::SandboxError;
+use crate::logging;
 use crate::restrictions::Restrictions;
+use crate::runtime::error::{JailMechanism, SandboxError};
 
 /// A structure that allows for easy execution of the sandbox mode.
 /// Intended to be constructed before entering the fork, in order to
@@ -47,32 +55,175 @@ pub struct LandlockJail {
     ruleset: landlock::RulesetCreated,
     seccomp: libseccomp::ScmpFilterContext,
     max_open_files: u64,
+    max_memory: Option<u64>,
+    max_cpu_seconds: Option<u64>,
+    cpu_set: Option<CpuSet>,
 }
 
 const DEV_NULL_PATH: &str = "/dev/null";
 
 impl LandlockJail {
     pub fn new(
-        allowed_read_paths: &Vec<PathBuf>,
+        allowed_read_paths: &[PathBuf],
+        allowed_write_paths: &[PathBuf],
         restrictions: &Restrictions,
     ) -> Result<Self, SandboxError> {
-        let mut allowed_read_paths = allowed_read_paths.clone();
-        let mut allowed_write_paths: Vec<PathBuf> = Vec::new();
-        if restrictions.linux.dev_null_accessible {
-            let dev_null: PathBuf = DEV_NULL_PATH.into();
-            allowed_read_paths.push(dev_null.clone());
-            allowed_write_paths.push(dev_null);
-        }
+        let file_writes = !allowed_write_paths.is_empty();
+        let (allowed_read_paths, allowed_write_paths) =
+            jail_paths(allowed_read_paths, allowed_write_paths, restrictions);
 
+        logging::debug!(
+            "building jail with {} readable and {} writable paths",
+            allowed_read_paths.len(),
+            allowed_write_paths.len()
+        );
         Ok(LandlockJail {
-            ruleset: new_sandbox(&allowed_read_paths, &allowed_write_paths)
-                .map_err(|e| SandboxError::JailSetup(e.to_string()))?,
-            seccomp: setup_seccomp(restrictions.linux.secomp_kill)
-                .map_err(|e| SandboxError::JailSetup(e.to_string()))?,
+            ruleset: new_sandbox(&allowed_read_paths, &allowed_write_paths, restrictions)
+                .map_err(|e| SandboxError::jail(Some(JailMechanism::Landlock), e))?,
+            seccomp: setup_seccomp(
+                restrictions.linux.secomp_kill,
+                file_writes,
+                uses_network(restrictions),
+                &[],
+            )
+            .map_err(|e| SandboxError::jail(Some(JailMechanism::Seccomp), e))?,
             max_open_files: restrictions.linux.max_open_files,
+            max_memory: restrictions.resource_limits.max_memory,
+            // Round up, so a sub-second limit doesn't become unlimited.
+            max_cpu_seconds: restrictions
+                .resource_limits
+                .max_cpu_time
+                .map(|t| t.as_secs() + u64::from(t.subsec_nanos() > 0)),
+            cpu_set: super::placement::cpu_set_for(&restrictions.cpu_placement)?,
+        })
+    }
+
+    /// Ready the jail for one launch.  The Landlock ruleset is consumed when it is
+    /// applied, so each launch gets its own copy.  Must be called before the fork.
+    pub fn arm(&self) -> Result<ArmedJail<'_>, SandboxError> {
+        Ok(ArmedJail {
+            jail: self,
+            ruleset: self
+                .ruleset
+                .try_clone()
+                .map_err(|e| SandboxError::jail(Some(JailMechanism::Landlock), e))?,
         })
     }
+}
+
+/// The Landlock floor and seccomp baseline a zygote puts on itself before it serves any
+/// launch.  Landlock domains and seccomp filters stack, so each child still gets its own
+/// jail on top of this one; the baseline covers everything the children may do, plus
+/// what the zygote needs to open their files and fork them.
+pub struct BaselineJail {
+    ruleset: landlock::RulesetCreated,
+    seccomp: libseccomp::ScmpFilterContext,
+}
+
+impl BaselineJail {
+    /// Build the baseline for children that may read and write the paths.  `file_writes`
+    /// is true when the children may change files beneath their writable paths.
+    pub fn new(
+        allowed_read_paths: &[PathBuf],
+        allowed_write_paths: &[PathBuf],
+        file_writes: bool,
+        restrictions: &Restrictions,
+    ) -> Result<Self, SandboxError> {
+        use super::call_names::ZYGOTE_ALLOW_LIST;
+
+        let (allowed_read_paths, allowed_write_paths) =
+            jail_paths(allowed_read_paths, allowed_write_paths, restrictions);
+        Ok(BaselineJail {
+            ruleset: new_sandbox(&allowed_read_paths, &allowed_write_paths, restrictions)
+                .map_err(|e| SandboxError::jail(Some(JailMechanism::Landlock), e))?,
+            seccomp: setup_seccomp(
+                restrictions.linux.secomp_kill,
+                file_writes,
+                uses_network(restrictions),
+                ZYGOTE_ALLOW_LIST,
+            )
+            .map_err(|e| SandboxError::jail(Some(JailMechanism::Seccomp), e))?,
+        })
+    }
+
+    /// Restrict the calling process.  Unlike `ArmedJail::restrict`, this runs in a process
+    /// that can still report the error, so it returns it rather than exiting.
+    pub fn restrict(self) -> Result<(), SandboxError> {
+        set_no_new_privs().map_err(|e| SandboxError::jail(None, std::io::Error::from(e)))?;
+        let status = self
+            .ruleset
+            .restrict_self()
+            .map_err(|e| SandboxError::jail(Some(JailMechanism::Landlock), e))?;
+        if !matches!(status.landlock, LandlockStatus::Available { .. }) {
+            return Err(SandboxError::jail(
+                Some(JailMechanism::Landlock),
+                "Landlock is not enabled in this kernel",
+            ));
+        }
+        self.seccomp
+            .load()
+            .map_err(|e| SandboxError::jail(Some(JailMechanism::Seccomp), e))
+    }
+}
+
+/// The paths the jail lets the child read and write, given the paths the launch needs.
+/// The child may also read the paths it may write.
+pub fn jail_paths(
+    allowed_read_paths: &[PathBuf],
+    allowed_write_paths: &[PathBuf],
+    restrictions: &Restrictions,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut allowed_read_paths = allowed_read_paths.to_vec();
+    allowed_read_paths.extend(allowed_write_paths.iter().cloned());
+    let mut allowed_write_paths = allowed_write_paths.to_vec();
+    if restrictions.linux.dev_null_accessible {
+        let dev_null: PathBuf = DEV_NULL_PATH.into();
+        allowed_read_paths.push(dev_null.clone());
+        allowed_write_paths.push(dev_null);
+    }
+    (allowed_read_paths, allowed_write_paths)
+}
+
+/// The syscalls the seccomp filter allows, leaving out the ones this OS doesn't have.
+/// `socket` is only allowed for TCP.
+pub fn allowed_syscalls(file_writes: bool, restrictions: &Restrictions) -> Vec<String> {
+    let network = uses_network(restrictions);
+    let mut ret: Vec<String> = syscall_names(file_writes, network)
+        .filter(|name| libseccomp::ScmpSyscall::from_name(name).is_ok())
+        .map(|name| name.to_string())
+        .collect();
+    if network {
+        ret.push("socket".to_string());
+    }
+    ret
+}
+
+/// Does the child get any TCP access?
+fn uses_network(restrictions: &Restrictions) -> bool {
+    !restrictions.linux.tcp_connect_ports.is_empty()
+        || !restrictions.linux.tcp_bind_ports.is_empty()
+}
+
+/// The names of the syscalls to allow, whether the OS has them or not.
+fn syscall_names(file_writes: bool, network: bool) -> impl Iterator<Item = &'static str> {
+    use super::call_names::{ALLOW_LIST, FILE_WRITE_ALLOW_LIST, NETWORK_ALLOW_LIST};
+
+    let file_writes = if file_writes {
+        FILE_WRITE_ALLOW_LIST
+    } else {
+        &[]
+    };
+    let network = if network { NETWORK_ALLOW_LIST } else { &[] };
+    ALLOW_LIST.iter().chain(file_writes).chain(network).copied()
+}
 
+/// A jail ready to apply to a single forked child.
+pub struct ArmedJail<'a> {
+    jail: &'a LandlockJail,
+    ruleset: landlock::RulesetCreated,
+}
+
+impl ArmedJail<'_> {
     /// Perform the restriction within the jail.
     /// Because this *must* run within the forked process,
     /// it will exit on error.  And, because the expectation is that
@@ -82,13 +233,35 @@ impl LandlockJail {
     /// Note: landlock works by allocating an FD that contains the ruleset.
     /// That means the child must wait to close FDs until after the restriction is applied.
     pub fn restrict(self) {
+        // cpu placement
+        if let Some(cpu_set) = &self.jail.cpu_set {
+            sched_setaffinity(nix::unistd::Pid::from_raw(0), cpu_set)
+                .unwrap_or_else(|_| exit_err());
+        }
+
         // rlimits
         setrlimit(
             Resource::RLIMIT_NOFILE,
-            self.max_open_files as rlim_t,
-            self.max_open_files as rlim_t,
+            self.jail.max_open_files as rlim_t,
+            self.jail.max_open_files as rlim_t,
         )
         .unwrap_or_else(|_| exit_err());
+        if let Some(max_memory) = self.jail.max_memory {
+            setrlimit(
+                Resource::RLIMIT_AS,
+                max_memory as rlim_t,
+                max_memory as rlim_t,
+            )
+            .unwrap_or_else(|_| exit_err());
+        }
+        if let Some(max_cpu_seconds) = self.jail.max_cpu_seconds {
+            setrlimit(
+                Resource::RLIMIT_CPU,
+                max_cpu_seconds as rlim_t,
+                max_cpu_seconds as rlim_t,
+            )
+            .unwrap_or_else(|_| exit_err());
+        }
 
         // no_new_privs is required for seccomp.  Should be done before landlock.
         set_no_new_privs().unwrap_or_else(|_| exit_err());
@@ -126,7 +299,7 @@ impl LandlockJail {
 
         // install seccomp filter after landlock.
         // That way, we don't need to add landlock rules to seccomp.
-        self.seccomp.load().unwrap_or_else(|_| exit_err());
+        self.jail.seccomp.load().unwrap_or_else(|_| exit_err());
     }
 }
 
@@ -138,6 +311,7 @@ fn exit_err() {
 fn new_sandbox(
     allowed_read_paths: &Vec<PathBuf>,
     allowed_write_paths: &Vec<PathBuf>,
+    restrictions: &Restrictions,
 ) -> Result<landlock::RulesetCreated, landlock::RulesetError> {
     let read_paths: Vec<PathBuf> = allowed_read_paths.clone();
     let write_paths: Vec<PathBuf> = allowed_write_paths.clone();
@@ -168,15 +342,28 @@ fn new_sandbox(
             .add_rules(path_beneath_rules(read_paths, AccessFs::from_read(abi_min)))?;
     }
     if write_paths.len() > 0 {
-        ruleset = ruleset
-            .add_rules(path_beneath_rules(write_paths, AccessFs::from_write(abi_min)))?;
+        ruleset = ruleset.add_rules(path_beneath_rules(
+            write_paths,
+            AccessFs::from_write(abi_min),
+        ))?;
+    }
+    for port in &restrictions.linux.tcp_connect_ports {
+        ruleset = ruleset.add_rule(NetPort::new(*port, AccessNet::ConnectTcp))?;
+    }
+    for port in &restrictions.linux.tcp_bind_ports {
+        ruleset = ruleset.add_rule(NetPort::new(*port, AccessNet::BindTcp))?;
     }
 
     Ok(ruleset)
 }
 
 /// Set up seccomp filtering to limit syscalls.
-fn setup_seccomp(violation_kills: bool) -> Result<libseccomp::ScmpFilterContext, libseccomp::error::SeccompError> {
+fn setup_seccomp(
+    violation_kills: bool,
+    file_writes: bool,
+    network: bool,
+    extra: &[&'static str],
+) -> Result<libseccomp::ScmpFilterContext, libseccomp::error::SeccompError> {
     use libseccomp::*;
 
     // This uses deny-by-default.  While "kill" may be preferred,
@@ -190,11 +377,9 @@ fn setup_seccomp(violation_kills: bool) -> Result<libseccomp::ScmpFilterContext,
     // for debugging
     // violation_action = ScmpAction::Log;
 
-    let mut ctx = ScmpFilterContext::new(
-        violation_action,
-    )?;
+    let mut ctx = ScmpFilterContext::new(violation_action)?;
 
-    for name in super::call_names::ALLOW_LIST.iter() {
+    for name in syscall_names(file_writes, network).chain(extra.iter().copied()) {
         match ScmpSyscall::from_name(name) {
             Ok(syscall) => {
                 ctx.add_rule(ScmpAction::Allow, syscall)?;
@@ -209,6 +394,27 @@ fn setup_seccomp(violation_kills: bool) -> Result<libseccomp::ScmpFilterContext,
         }
     }
 
+    if network {
+        // Only TCP, which Landlock limits to the allowed ports.  The type may carry the
+        // SOCK_NONBLOCK and SOCK_CLOEXEC flags.
+        const SOCK_TYPE_MASK: u64 = 0xf;
+        let socket = ScmpSyscall::from_name("socket")?;
+        for domain in [nix::libc::AF_INET, nix::libc::AF_INET6] {
+            ctx.add_rule_conditional(
+                ScmpAction::Allow,
+                socket,
+                &[
+                    ScmpArgCompare::new(0, ScmpCompareOp::Equal, domain as u64),
+                    ScmpArgCompare::new(
+                        1,
+                        ScmpCompareOp::MaskedEqual(SOCK_TYPE_MASK),
+                        nix::libc::SOCK_STREAM as u64,
+                    ),
+                ],
+            )?;
+        }
+    }
+
     Ok(ctx)
 }
 
@@ -221,7 +427,11 @@ mod tests {
     #[test]
     fn test_landlock_jail() {
         let allowed_paths = vec![PathBuf::from("/tmp"), PathBuf::from("/var/log")];
-        let jail = new_sandbox(&allowed_paths, &vec![]);
+        let jail = new_sandbox(
+            &allowed_paths,
+            &vec![],
+            &crate::compat_restrictions!("test"),
+        );
         assert!(jail.is_ok());
     }
 }
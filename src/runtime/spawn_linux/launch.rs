@@ -4,177 +4,734 @@
 
 use std::{
     collections::{HashMap, HashSet},
-    ffi::CString,
-    os::unix::ffi::OsStrExt as _,
-    path::PathBuf,
+    ffi::{CString, OsStr, OsString},
+    os::{
+        fd::{AsFd as _, AsRawFd as _, BorrowedFd, FromRawFd as _, OwnedFd, RawFd},
+        unix::ffi::OsStrExt as _,
+    },
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use nix::sys::wait::WaitStatus;
 
+use crate::logging;
 use crate::runtime::{
-    ExitCode,
-    error::SandboxError,
-    spawn::{Child, LaunchEnv, OsTermination},
+    deps::{Dependency, DependencyReport},
+    error::{LaunchDiagnostics, LaunchPhase, SandboxError},
+    lifecycle::LifecycleObserver,
+    limit::{OutputGuards, Terminator},
+    plan::SandboxPlan,
+    probe::refuse_privileged_parent,
+    registry,
+    spawn::{Child, ChildKiller, ExitStatus, FdSet, LaunchEnv, LaunchReport, ResourceUsage},
     spawn_linux::{
-        dependencies::find_bin_dependencies,
+        dependencies::{find_bin_dependencies, find_extra_library_dependencies},
         fd::{FdMap, ForkedFd, StreamDirection},
-        jail::LandlockJail,
+        interpreter::find_interpreter,
+        jail::{BaselineJail, LandlockJail, allowed_syscalls, jail_paths},
     },
+    stream::{ChildReader, ChildWriter},
+    terminal::{Terminal, TerminalReader},
+    verify::HashAllowlist,
 };
 
 pub struct LinuxChild {
     state: LinuxChildState,
     fds: HashMap<u32, FdMap>,
+    channels: HashMap<String, u32>,
+    terminal: Option<Terminal>,
+    /// Limits and log files for the output streams.
+    output: OutputGuards,
+    kill_on_drop: bool,
+    observer: Option<Arc<dyn LifecycleObserver>>,
+    report: LaunchReport,
 }
 
 impl LinuxChild {
     pub(crate) fn state(&self) -> LinuxChildState {
         self.state.clone()
     }
+
+    /// The observer to tell how the child ended.
+    pub(crate) fn observer(&self) -> Option<Arc<dyn LifecycleObserver>> {
+        self.observer.clone()
+    }
+
+    /// Hand the child's lifetime over to the caller, so dropping this no longer kills it.
+    pub(crate) fn detach(mut self) -> LinuxChildState {
+        self.kill_on_drop = false;
+        self.state.clone()
+    }
+
+    /// Take all the parent side streams, to hand them to another process.
+    pub(crate) fn take_streams(&mut self) -> Vec<FdMap> {
+        self.fds.drain().map(|(_, m)| m).collect()
+    }
+
+    /// Take the stream that receives from the child, for an async reader: the stream's
+    /// descriptor, and a reader with its limit and log file that can move across threads.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub(crate) fn take_async_reader(
+        &mut self,
+        fd: u32,
+    ) -> Option<(OwnedFd, Box<dyn std::io::Read + Send>)> {
+        let source = take_source(&mut self.fds, fd)?;
+        let raw = match source.stream.as_fd().try_clone_to_owned() {
+            Ok(raw) => raw,
+            Err(e) => {
+                logging::warning!("could not duplicate fd {} for async reads: {}", fd, e);
+                return None;
+            }
+        };
+        let stream = source_reader(source);
+        let state = self.state.clone();
+        let terminate = move || state.kill().and(Ok(()));
+        Some((raw, Box::new(self.output.guard(fd, stream, terminate))))
+    }
+
+    /// Take the stream that sends to the child, for an async writer.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub(crate) fn take_async_writer(&mut self, fd: u32) -> Option<std::fs::File> {
+        take_sink(&mut self.fds, fd)
+    }
 }
 
 /// Handle the child process launching.
 pub fn launch_child(env: LaunchEnv) -> Result<LinuxChild, SandboxError> {
-    // As much as possible is performed before the fork.
-    // That's because, according to the fork docs:
-    //
-    // > In a multithreaded program, only [async-signal-safe] functions like `pause`
-    // > and `_exit` may be called by the child (the parent isn't restricted) until
-    // > a call of `execve(2)`. Note that memory allocation may **not** be
-    // > async-signal-safe and thus must be prevented.
-    let exec_path = which::which(&env.cmd)?;
-    let sandbox = LandlockJail::new(
-        &extract_dependencies(find_bin_dependencies(&exec_path))?,
-        &env.restrictions,
-    )?;
-    let fd_set = ForkedFd::new(env.fds)?;
-    let exec_path = CString::new(exec_path.as_os_str().as_bytes())?;
-    let exec_path = exec_path.as_c_str();
-    let cwd = CString::new(env.cwd.as_os_str().as_bytes())?;
-    let cwd = cwd.as_c_str();
-    let mut args = vec![
-        // This is interesting.  Because the first argument is the
-        // executable, and this is controlling all the aspects for setting
-        // up the program, we need to construct the first argument here as
-        // the executable name.  In order to avoid leaking information, this
-        // constructs a hard-coded executable name.
-        CString::new("sandboxed")?,
-    ];
-    for arg in env.args {
-        args.push(CString::new(arg.as_os_str().as_bytes())?);
-    }
-    let args = args.as_slice();
-    let mut environ = Vec::new();
-    for (key, val) in env.env.iter() {
-        let mut entry = key.clone();
-        entry.push("=");
-        entry.push(val);
-        environ.push(CString::new(entry.as_os_str().as_bytes())?);
-    }
-    let environ = environ.as_slice();
-    let child_fds = fd_set.child_fd_list();
-
-    match unsafe { nix::unistd::fork() } {
-        Err(e) => Err(SandboxError::Io(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            e,
-        ))),
-        Ok(nix::unistd::ForkResult::Child) => {
-            // Any errors in here must trigger an immediate exit.
-            // Anything that runs here can't allocate memory.
-            fd_set.child_after_fork();
-
-            // This looks like it just creates data in the stack, not allocated
-            // on the heap, which means it's fine to call.
-            if nix::unistd::chdir(cwd).is_err() {
-                std::process::exit(253);
+    PreparedLaunch::new(env)?.launch(&[])
+}
+
+/// Everything needed to launch the child, computed once so it can be
+/// launched repeatedly.
+pub struct PreparedLaunch {
+    sandbox: LandlockJail,
+    fds: FdSet,
+    exec_path: CString,
+    cwd: CString,
+    args: Vec<CString>,
+    environ: Vec<CString>,
+    kill_on_drop: bool,
+    observer: Option<Arc<dyn LifecycleObserver>>,
+    /// The allowlist, and the files to check against it before each launch.
+    allowlist: Option<(HashAllowlist, Vec<PathBuf>)>,
+    /// The time spent preparing, reported with each launch.
+    prepare_report: LaunchReport,
+    /// The resolved command, for the diagnostics of a failed launch.
+    diagnostics: LaunchDiagnostics,
+    /// The paths the child may read and write, and its restrictions, for a zygote's
+    /// baseline jail.
+    read_paths: Vec<PathBuf>,
+    writable_paths: Vec<PathBuf>,
+    restrictions: crate::Restrictions,
+}
+
+impl PreparedLaunch {
+    /// Find the executable, its dependencies, and construct the jail.
+    pub fn new(env: LaunchEnv) -> Result<Self, SandboxError> {
+        let mut diagnostics = LaunchDiagnostics::new(&env.cmd);
+        Self::prepare(env, &mut diagnostics).map_err(|e| diagnostics.attach(e))
+    }
+
+    /// Prepare the launch, keeping the diagnostics up to date with the phase reached.
+    fn prepare(env: LaunchEnv, diagnostics: &mut LaunchDiagnostics) -> Result<Self, SandboxError> {
+        // As much as possible is performed before the fork.
+        // That's because, according to the fork docs:
+        //
+        // > In a multithreaded program, only [async-signal-safe] functions like `pause`
+        // > and `_exit` may be called by the child (the parent isn't restricted) until
+        // > a call of `execve(2)`. Note that memory allocation may **not** be
+        // > async-signal-safe and thus must be prevented.
+        env.fds.validate()?;
+        refuse_privileged_parent(&env)?;
+        let started = Instant::now();
+        diagnostics.phase = LaunchPhase::Resolve;
+        let cmd_path = env.resolution.resolve(&env.cmd)?;
+        diagnostics.resolved = Some(cmd_path.clone());
+        let resolved = Instant::now();
+        if let Some(observer) = &env.observer {
+            observer.on_resolved(&cmd_path);
+        }
+
+        diagnostics.phase = LaunchPhase::Dependencies;
+        let LaunchPaths {
+            exec_path,
+            script_args,
+            read_paths,
+            loaded,
+        } = find_launch_paths(&env, &cmd_path)?;
+        let allowlist = env.hash_allowlist.map(|allowlist| {
+            let mut files = vec![cmd_path.clone()];
+            if exec_path != cmd_path {
+                files.push(exec_path.clone());
+            }
+            if allowlist.verifies_libraries() {
+                for file in loaded {
+                    if !files.contains(&file) {
+                        files.push(file);
+                    }
+                }
             }
-            sandbox.restrict();
+            (allowlist, files)
+        });
+        let prepare_report = LaunchReport {
+            resolve: Some(resolved - started),
+            dependencies: Some(resolved.elapsed()),
+            placement: env.restrictions.cpu_placement.pinned(),
+            ..LaunchReport::default()
+        };
+        logging::debug!(
+            "prepared launch of {:?} with {} readable paths",
+            exec_path,
+            read_paths.len()
+        );
+        diagnostics.phase = LaunchPhase::Jail;
+        let sandbox = LandlockJail::new(&read_paths, &env.writable_paths, &env.restrictions)?;
+        diagnostics.phase = LaunchPhase::Validate;
+        let exec_path = CString::new(exec_path.as_os_str().as_bytes())?;
+        let cwd = CString::new(env.cwd.as_os_str().as_bytes())?;
+        let mut args = vec![
+            // This is interesting.  Because the first argument is the
+            // executable, and this is controlling all the aspects for setting
+            // up the program, we need to construct the first argument here as
+            // the executable name.  In order to avoid leaking information, this
+            // constructs a hard-coded executable name, unless the caller picked one.
+            match &env.arg0 {
+                Some(arg0) => CString::new(arg0.as_bytes())?,
+                None => CString::new("sandboxed")?,
+            },
+        ];
+        for arg in script_args.into_iter().chain(env.args) {
+            args.push(CString::new(arg.as_os_str().as_bytes())?);
+        }
+        let mut environ = Vec::new();
+        if let Some(fds) = env.fds.streams_env() {
+            let mut entry = OsString::from(crate::child::SANDBOX_FDS_ENV);
+            entry.push("=");
+            entry.push(fds);
+            environ.push(CString::new(entry.as_os_str().as_bytes())?);
+        }
+        if let Some(channels) = env.fds.channels_env() {
+            let mut entry = OsString::from(crate::child::SANDBOX_CHANNELS_ENV);
+            entry.push("=");
+            entry.push(channels);
+            environ.push(CString::new(entry.as_os_str().as_bytes())?);
+        }
+        for (key, val) in env.env.iter() {
+            let mut entry = key.to_os_string();
+            entry.push("=");
+            entry.push(val);
+            environ.push(CString::new(entry.as_os_str().as_bytes())?);
+        }
+        Ok(PreparedLaunch {
+            sandbox,
+            fds: env.fds,
+            exec_path,
+            cwd,
+            args,
+            environ,
+            kill_on_drop: env.kill_on_drop,
+            observer: env.observer,
+            allowlist,
+            prepare_report,
+            diagnostics: diagnostics.clone(),
+            read_paths,
+            writable_paths: env.writable_paths,
+            restrictions: env.restrictions,
+        })
+    }
+
+    /// The baseline jail for a zygote holding this launch, which must also open the extra
+    /// paths for the children.
+    pub(crate) fn baseline(
+        &self,
+        extra_read_paths: &[PathBuf],
+        extra_write_paths: &[PathBuf],
+    ) -> Result<BaselineJail, SandboxError> {
+        let mut read_paths = self.read_paths.clone();
+        read_paths.extend(extra_read_paths.iter().cloned());
+        let mut write_paths = self.writable_paths.clone();
+        write_paths.extend(extra_write_paths.iter().cloned());
+        BaselineJail::new(
+            &read_paths,
+            &write_paths,
+            !self.writable_paths.is_empty(),
+            &self.restrictions,
+        )
+    }
+
+    /// The FDs the child is launched with.
+    pub(crate) fn fds(&self) -> &FdSet {
+        &self.fds
+    }
+
+    /// Fork and run the child, with the extra arguments appended to the prepared ones.
+    pub fn launch(&self, extra_args: &[OsString]) -> Result<LinuxChild, SandboxError> {
+        let output = OutputGuards::open(&self.fds)?;
+        let mut child = self.fork_observed(extra_args, self.observer.clone())?;
+        child.output = output;
+        Ok(child)
+    }
 
-            // Because the landlock uses a FD under the hood, the child FDs must be
-            // closed after calling restrict.
-            close_open_fds(&child_fds);
+    /// Fork and run the child, without opening the log files or telling the observer.
+    /// The zygote uses this, as the caller opens those itself, and the observer belongs to
+    /// the caller's process.
+    pub(crate) fn fork(&self, extra_args: &[OsString]) -> Result<LinuxChild, SandboxError> {
+        self.fork_observed(extra_args, None)
+    }
 
-            // Run the executable.
-            let _ = nix::unistd::execve(exec_path, args, environ);
-            // To reach here means the exec failed.
-            std::process::exit(254);
+    /// The program the child execs, for reporting.
+    fn exec_path(&self) -> PathBuf {
+        PathBuf::from(OsStr::from_bytes(self.exec_path.as_bytes()))
+    }
+
+    fn fork_observed(
+        &self,
+        extra_args: &[OsString],
+        observer: Option<Arc<dyn LifecycleObserver>>,
+    ) -> Result<LinuxChild, SandboxError> {
+        let mut diagnostics = self.diagnostics.clone();
+        self.fork_child(extra_args, observer, &mut diagnostics.phase)
+            .map_err(|e| diagnostics.attach(e))
+    }
+
+    fn fork_child(
+        &self,
+        extra_args: &[OsString],
+        observer: Option<Arc<dyn LifecycleObserver>>,
+        phase: &mut LaunchPhase,
+    ) -> Result<LinuxChild, SandboxError> {
+        // Check the files as close to the exec as the parent can, rather than once when
+        // prepared, as a prepared launch may run long after.
+        *phase = LaunchPhase::Validate;
+        let mut args = self.args.clone();
+        for arg in extra_args {
+            args.push(CString::new(arg.as_bytes())?);
         }
-        Ok(nix::unistd::ForkResult::Parent { child }) => {
-            let fds = fd_set.parent_after_fork();
-            Ok(LinuxChild {
-                state: LinuxChildState::new(child),
-                fds: fd_map(fds),
-            })
+        *phase = LaunchPhase::Verify;
+        // The executable is run from the file that was hashed, so it can't be replaced
+        // between the check and the exec.
+        let mut exec_file = None;
+        if let Some((allowlist, files)) = &self.allowlist {
+            let exec_path = self.exec_path();
+            for file in files {
+                if *file == exec_path {
+                    exec_file = Some(allowlist.open_verified(file)?);
+                } else {
+                    allowlist.verify(file)?;
+                }
+            }
+        }
+        *phase = LaunchPhase::Jail;
+        let jail = self.sandbox.arm()?;
+        *phase = LaunchPhase::FdSetup;
+        let fd_started = Instant::now();
+        let fd_set = ForkedFd::new(self.fds.clone())?;
+        let exec_path = self.exec_path.as_c_str();
+        let cwd = self.cwd.as_c_str();
+        let args = args.as_slice();
+        let environ = self.environ.as_slice();
+        let mut child_fds = fd_set.child_fd_list();
+        let terminal = fd_set.terminal()?.map(Terminal::new);
+        let progress = ProgressPipe::new(&child_fds)?;
+        let progress_fd = progress.child_fd();
+        child_fds.insert(progress_fd);
+        let exec_fd = match exec_file {
+            Some(file) => Some(fd_above(file.into(), &child_fds)?),
+            None => None,
+        };
+        if let Some(fd) = &exec_fd {
+            child_fds.insert(fd.as_raw_fd());
+        }
+        *phase = LaunchPhase::Fork;
+        let fork_started = Instant::now();
+
+        match unsafe { nix::unistd::fork() } {
+            Err(e) => Err(SandboxError::exec(self.exec_path(), e.into())),
+            Ok(nix::unistd::ForkResult::Child) => {
+                // Any errors in here must trigger an immediate exit.
+                // Anything that runs here can't allocate memory.
+                fd_set.child_after_fork();
+
+                // This looks like it just creates data in the stack, not allocated
+                // on the heap, which means it's fine to call.
+                if nix::unistd::chdir(cwd).is_err() {
+                    std::process::exit(253);
+                }
+                // Reading the clock doesn't allocate.
+                let restrict_started = Instant::now();
+                jail.restrict();
+
+                // Because the landlock uses a FD under the hood, the child FDs must be
+                // closed after calling restrict.
+                close_open_fds(&child_fds);
+                ProgressPipe::report(progress_fd, PROGRESS_RESTRICTED, restrict_started.elapsed());
+
+                // Run the executable.  To return means the exec failed.
+                let Err(errno) = match &exec_fd {
+                    Some(fd) => nix::unistd::execveat(
+                        fd,
+                        c"",
+                        args,
+                        environ,
+                        nix::fcntl::AtFlags::AT_EMPTY_PATH,
+                    ),
+                    None => nix::unistd::execve(exec_path, args, environ),
+                };
+                ProgressPipe::send(progress_fd, PROGRESS_EXEC_FAILED, errno as u64);
+                std::process::exit(254);
+            }
+            Ok(nix::unistd::ForkResult::Parent { child }) => {
+                let forked = Instant::now();
+                logging::debug!("launched child {}", child);
+                let fds = fd_set.parent_after_fork();
+                let mut ret = LinuxChild {
+                    state: LinuxChildState::new(child),
+                    fds: fd_map(fds),
+                    channels: self.fds.channels(),
+                    terminal,
+                    output: OutputGuards::default(),
+                    kill_on_drop: self.kill_on_drop,
+                    observer,
+                    report: LaunchReport::default(),
+                };
+                registry::register(&ret.state);
+                let progress =
+                    progress.watch(child.as_raw() as u32, fork_started, ret.observer.as_deref());
+                if let Some(errno) = progress.exec_error {
+                    // Collect the child, which exits right after reporting.
+                    let _ = ret.state.kill();
+                    *phase = LaunchPhase::Exec;
+                    return Err(SandboxError::exec(
+                        self.exec_path(),
+                        std::io::Error::from_raw_os_error(errno),
+                    ));
+                }
+                ret.report = LaunchReport {
+                    fd_setup: Some(fork_started - fd_started),
+                    fork: Some(forked - fork_started),
+                    restrict: progress.restrict,
+                    exec: progress.exec,
+                    ..self.prepare_report.clone()
+                };
+                Ok(ret)
+            }
         }
     }
 }
 
+/// Work out the rules the jail would apply to the launch, without launching anything.
+pub(crate) fn plan(env: &LaunchEnv) -> Result<SandboxPlan, SandboxError> {
+    env.fds.validate()?;
+    let cmd_path = env.resolution.resolve(&env.cmd)?;
+    let paths = find_launch_paths(env, &cmd_path)?;
+    let (readable_paths, writable_paths) =
+        jail_paths(&paths.read_paths, &env.writable_paths, &env.restrictions);
+    Ok(SandboxPlan {
+        interpreter: (paths.exec_path != cmd_path).then_some(paths.exec_path),
+        exec: cmd_path,
+        cwd: env.cwd.clone(),
+        readable_paths,
+        writable_paths,
+        allowed_syscalls: allowed_syscalls(!env.writable_paths.is_empty(), &env.restrictions),
+        syscall_violation_kills: env.restrictions.linux.secomp_kill,
+        tcp_connect_ports: env.restrictions.linux.tcp_connect_ports.clone(),
+        tcp_bind_ports: env.restrictions.linux.tcp_bind_ports.clone(),
+        max_open_files: Some(env.restrictions.linux.max_open_files),
+        resource_limits: env.restrictions.resource_limits.clone(),
+        cpu_placement: env.restrictions.cpu_placement.clone(),
+        app_container: None,
+        capabilities: Vec::new(),
+    })
+}
+
+/// Find the files the launch loads, without running anything, and without failing on
+/// missing libraries.
+pub(crate) fn dependencies(env: &LaunchEnv) -> Result<DependencyReport, SandboxError> {
+    let cmd_path = env.resolution.resolve(&env.cmd)?;
+    let launch = find_launch_deps(env, &cmd_path)?;
+    let found = launch
+        .dependencies
+        .iter()
+        .chain(&launch.extra_libraries)
+        .filter(|d| d.exists())
+        .map(|d| d.best_path().to_path_buf())
+        .collect();
+    let read_paths = granted_read_paths(env, &cmd_path, &launch.exec_path, found);
+    let (readable_paths, writable_paths) =
+        jail_paths(&read_paths, &env.writable_paths, &env.restrictions);
+    Ok(DependencyReport {
+        interpreter: (launch.exec_path != cmd_path).then_some(launch.exec_path),
+        exec: cmd_path,
+        dependencies: launch.dependencies,
+        extra_libraries: launch.extra_libraries,
+        readable_paths,
+        writable_paths,
+    })
+}
+
+/// What the launch runs, and what the child needs to read to run it.
+struct LaunchPaths {
+    /// The program to exec; the interpreter, for a script.
+    exec_path: PathBuf,
+    /// The arguments the interpreter needs before the caller's.
+    script_args: Vec<OsString>,
+    read_paths: Vec<PathBuf>,
+    /// The files the child loads: the program to exec, and the shared libraries.
+    loaded: Vec<PathBuf>,
+}
+
+/// What the launch runs, and the files it loads, found or not.
+struct LaunchDeps {
+    /// The program to exec; the interpreter, for a script.
+    exec_path: PathBuf,
+    /// The arguments the interpreter needs before the caller's.
+    script_args: Vec<OsString>,
+    /// The program to exec, followed by its shared libraries.
+    dependencies: Vec<Dependency>,
+    /// The `LaunchEnv::extra_libraries`, and their shared libraries.
+    extra_libraries: Vec<Dependency>,
+}
+
+/// Find the program to exec for the resolved command, and its dependencies.
+fn find_launch_paths(env: &LaunchEnv, cmd_path: &Path) -> Result<LaunchPaths, SandboxError> {
+    let mut launch = find_launch_deps(env, cmd_path)?;
+    launch.dependencies.append(&mut launch.extra_libraries);
+    let loaded = extract_dependencies(launch.dependencies)?;
+    Ok(LaunchPaths {
+        read_paths: granted_read_paths(env, cmd_path, &launch.exec_path, loaded.clone()),
+        loaded,
+        exec_path: launch.exec_path,
+        script_args: launch.script_args,
+    })
+}
+
+fn find_launch_deps(env: &LaunchEnv, cmd_path: &Path) -> Result<LaunchDeps, SandboxError> {
+    // Scripts run through their interpreter, with the script as the first argument.
+    let mut script_args = Vec::new();
+    let exec_path = match find_interpreter(cmd_path)? {
+        Some(interp) => {
+            script_args.extend(interp.arg);
+            script_args.push(cmd_path.as_os_str().to_os_string());
+            interp.path
+        }
+        None => cmd_path.to_path_buf(),
+    };
+    Ok(LaunchDeps {
+        dependencies: find_bin_dependencies(&exec_path, &env.dependency_search),
+        extra_libraries: find_extra_library_dependencies(
+            &env.extra_libraries,
+            &env.dependency_search,
+        ),
+        exec_path,
+        script_args,
+    })
+}
+
+/// Everything the child may read: the files it loads, the script, and what the
+/// `LaunchEnv` grants.
+fn granted_read_paths(
+    env: &LaunchEnv,
+    cmd_path: &Path,
+    exec_path: &Path,
+    mut read_paths: Vec<PathBuf>,
+) -> Vec<PathBuf> {
+    if exec_path != cmd_path {
+        read_paths.push(cmd_path.to_path_buf());
+    }
+    read_paths.extend(env.readable_paths.iter().cloned());
+    if env.grant_cwd_read {
+        read_paths.push(env.cwd.clone());
+    }
+    read_paths
+}
+
 impl Child for LinuxChild {
     fn terminate(&self) -> Result<(), std::io::Error> {
         self.state.kill().and(Ok(()))
     }
 
-    fn take_stream_from_child(&mut self, fd: u32) -> Option<Box<dyn std::io::Read>> {
-        match self.fds.remove(&fd) {
-            Some(fd) => match fd.direction {
-                StreamDirection::FromChild => Some(Box::new(fd.stream)),
-                _ => None,
-            },
-            None => None,
-        }
+    fn killer(&self) -> Option<ChildKiller> {
+        let state = self.state.clone();
+        Some(ChildKiller::new(move || state.kill().and(Ok(()))))
     }
 
-    fn take_stream_to_child(&mut self, fd: u32) -> Option<Box<dyn std::io::Write>> {
-        match self.fds.remove(&fd) {
-            Some(fd) => match fd.direction {
-                StreamDirection::ToChild => Some(Box::new(fd.stream)),
-                _ => None,
-            },
-            None => None,
+    fn take_stream_from_child(&mut self, fd: u32) -> Option<ChildReader> {
+        let state = self.state.clone();
+        let terminate = Box::new(move || state.kill().and(Ok(())));
+        take_reader(&mut self.fds, &mut self.output, fd, terminate)
+    }
+
+    fn take_stream_to_child(&mut self, fd: u32) -> Option<ChildWriter> {
+        take_writer(&mut self.fds, fd)
+    }
+
+    fn channel_fd(&self, name: &str) -> Option<u32> {
+        self.channels.get(name).copied()
+    }
+
+    fn exit_status(&self) -> Result<Option<ExitStatus>, std::io::Error> {
+        self.state.exit_status()
+    }
+
+    fn wait(&self) -> Result<ExitStatus, std::io::Error> {
+        self.state.wait(None)?.ok_or_else(|| {
+            std::io::Error::other("BUG: wait without a timeout returned while running")
+        })
+    }
+
+    fn wait_timeout(&self, timeout: Duration) -> Result<Option<ExitStatus>, std::io::Error> {
+        self.state.wait(Some(timeout))
+    }
+
+    fn id(&self) -> u32 {
+        self.state.pid() as u32
+    }
+
+    fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+        self.state.pidfd()
+    }
+
+    fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.state.resource_usage()
+    }
+
+    fn launch_report(&self) -> Option<LaunchReport> {
+        Some(self.report.clone())
+    }
+
+    fn terminal(&self) -> Option<Terminal> {
+        self.terminal.clone()
+    }
+}
+
+impl Drop for LinuxChild {
+    fn drop(&mut self) {
+        if self.kill_on_drop
+            && let Err(e) = self.state.kill()
+        {
+            logging::warning!("could not stop child {} on drop: {}", self.state.pid, e);
         }
     }
+}
+
+/// Take the parent's stream that receives from the child, with its limit and log file.
+pub(crate) fn take_reader(
+    fds: &mut HashMap<u32, FdMap>,
+    output: &mut OutputGuards,
+    fd: u32,
+    terminate: Terminator,
+) -> Option<ChildReader> {
+    let source = take_source(fds, fd)?;
+    let raw = source.stream.as_raw_fd();
+    let stream = source_reader(source);
+    Some(ChildReader::new(output.wrap(fd, stream, terminate), raw))
+}
+
+fn take_source(fds: &mut HashMap<u32, FdMap>, fd: u32) -> Option<FdMap> {
+    match fds.remove(&fd) {
+        Some(fd) => match fd.direction {
+            StreamDirection::FromChild => Some(fd),
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+/// Read the stream, through the terminal's reader if it's the terminal.
+fn source_reader(fd: FdMap) -> Box<dyn std::io::Read + Send> {
+    if fd.terminal {
+        Box::new(TerminalReader(fd.stream))
+    } else {
+        Box::new(fd.stream)
+    }
+}
+
+/// Take the parent's stream that sends to the child.
+pub(crate) fn take_writer(fds: &mut HashMap<u32, FdMap>, fd: u32) -> Option<ChildWriter> {
+    let sink = take_sink(fds, fd)?;
+    let raw = sink.as_raw_fd();
+    Some(ChildWriter::new(Box::new(sink), raw))
+}
+
+fn take_sink(fds: &mut HashMap<u32, FdMap>, fd: u32) -> Option<std::fs::File> {
+    match fds.remove(&fd) {
+        Some(fd) => match fd.direction {
+            StreamDirection::ToChild => Some(fd.stream),
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+/// Open a pidfd for the child, or None if the kernel doesn't support them (before 5.3).
+fn open_pidfd(pid: nix::unistd::Pid) -> Option<OwnedFd> {
+    let fd = unsafe { nix::libc::syscall(nix::libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    if fd < 0 {
+        logging::debug!(
+            "pidfd_open for child {} failed: {}",
+            pid,
+            std::io::Error::last_os_error()
+        );
+        return None;
+    }
+    Some(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
 
-    fn exit_status(&self) -> ExitCode {
-        self.state.exit_code()
+/// How long to sleep between exit status checks while waiting.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Poll the exit status until the child is no longer running, or the timeout passes.
+///
+/// This is the fallback for children without a pidfd to block on.
+pub(crate) fn poll_exit<F>(
+    timeout: Option<Duration>,
+    mut status: F,
+) -> Result<Option<ExitStatus>, std::io::Error>
+where
+    F: FnMut() -> Result<Option<ExitStatus>, std::io::Error>,
+{
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        if let Some(status) = status()? {
+            return Ok(Some(status));
+        }
+        let pause = match deadline {
+            None => WAIT_POLL_INTERVAL,
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(None);
+                }
+                WAIT_POLL_INTERVAL.min(deadline - now)
+            }
+        };
+        std::thread::sleep(pause);
     }
 }
 
-fn extract_dependencies(
-    deps: Vec<super::dependencies::Dependency>,
-) -> Result<Vec<PathBuf>, SandboxError> {
-    let mut is_ok = true;
-    let mut missing = String::new();
+fn extract_dependencies(deps: Vec<Dependency>) -> Result<Vec<PathBuf>, SandboxError> {
+    let mut missing = Vec::new();
     let mut ret = Vec::new();
     for dep in deps {
         if dep.invalid() {
-            if is_ok {
-                is_ok = false;
-            } else {
-                missing.push_str(", ");
-            }
-            missing.push_str(
-                dep.best_path()
-                    .as_os_str()
-                    .to_string_lossy()
-                    .to_string()
-                    .as_str(),
-            );
+            missing.push(dep.best_path().to_path_buf());
         } else if dep.exists() {
-            ret.push(dep.best_path().clone());
+            ret.push(dep.best_path().to_path_buf());
         } // else ignore
     }
-    if is_ok {
+    if missing.is_empty() {
         Ok(ret)
     } else {
-        Err(SandboxError::JailSetup(format!(
-            "missing library dependencies: {missing}"
-        )))
+        Err(SandboxError::Dependency {
+            missing,
+            diagnostics: None,
+        })
     }
 }
 
-fn fd_map(src: Vec<FdMap>) -> HashMap<u32, FdMap> {
+pub(crate) fn fd_map(src: Vec<FdMap>) -> HashMap<u32, FdMap> {
     let mut ret = HashMap::new();
     for f in src {
         ret.insert(f.dup_to, f);
@@ -182,6 +739,112 @@ fn fd_map(src: Vec<FdMap>) -> HashMap<u32, FdMap> {
     ret
 }
 
+/// Sent by the child once it's inside its jail, followed by how long that took, in
+/// nanoseconds.
+const PROGRESS_RESTRICTED: u8 = b'R';
+
+/// Sent by the child when the exec fails, followed by the errno.  A successful exec
+/// closes the pipe instead.
+const PROGRESS_EXEC_FAILED: u8 = b'F';
+
+/// Lets the parent follow the child's launch up to the exec, for the `LifecycleObserver`
+/// and the `LaunchReport`.
+struct ProgressPipe {
+    reader: std::io::PipeReader,
+    /// Numbered above every FD the child sets up, so redirecting those can't replace it.
+    writer: OwnedFd,
+}
+
+/// How long the child's phases took, as followed through the `ProgressPipe`.
+struct ChildProgress {
+    restrict: Option<Duration>,
+    exec: Option<Duration>,
+    /// The errno from the failed exec.
+    exec_error: Option<i32>,
+}
+
+/// Move the parent's FD above the child's FDs, so setting those up in the child doesn't
+/// replace it.  The new FD closes on exec.
+fn fd_above(fd: OwnedFd, child_fds: &HashSet<nix::libc::c_int>) -> Result<OwnedFd, SandboxError> {
+    let lowest = child_fds.iter().max().map_or(3, |fd| fd + 1).max(3);
+    let raw = nix::fcntl::fcntl(&fd, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(lowest))
+        .map_err(|e| SandboxError::fd_setup(None, e))?;
+    Ok(unsafe { OwnedFd::from_raw_fd(raw) })
+}
+
+impl ProgressPipe {
+    fn new(child_fds: &HashSet<nix::libc::c_int>) -> Result<Self, SandboxError> {
+        let (reader, writer) = std::io::pipe().map_err(|e| SandboxError::fd_setup(None, e))?;
+        Ok(ProgressPipe {
+            reader,
+            writer: fd_above(writer.into(), child_fds)?,
+        })
+    }
+
+    fn child_fd(&self) -> RawFd {
+        self.writer.as_raw_fd()
+    }
+
+    /// Send the progress from the child, with the time the phase took.  This only uses
+    /// the stack and the write call, so it's safe between the fork and the exec.
+    fn report(fd: RawFd, progress: u8, took: Duration) {
+        Self::send(fd, progress, took.as_nanos() as u64);
+    }
+
+    /// Send the progress from the child, with its value.
+    fn send(fd: RawFd, progress: u8, value: u64) {
+        let mut msg = [progress; 9];
+        msg[1..].copy_from_slice(&value.to_ne_bytes());
+        // Pipe writes this small are never split.
+        let _ = unsafe { nix::libc::write(fd, msg.as_ptr().cast(), msg.len()) };
+    }
+
+    /// Follow the child until it execs or fails, telling the observer about each phase.
+    fn watch(
+        self,
+        pid: u32,
+        fork_started: Instant,
+        observer: Option<&dyn LifecycleObserver>,
+    ) -> ChildProgress {
+        use std::io::Read as _;
+
+        let ProgressPipe { mut reader, writer } = self;
+        // Only the child's copy may hold the pipe open.
+        drop(writer);
+        if let Some(observer) = observer {
+            observer.on_forked(pid);
+        }
+        let mut ret = ChildProgress {
+            restrict: None,
+            exec: None,
+            exec_error: None,
+        };
+        let mut msg = [0u8; 9];
+        if reader.read_exact(&mut msg).is_err() || msg[0] != PROGRESS_RESTRICTED {
+            return ret;
+        }
+        let mut took = [0u8; 8];
+        took.copy_from_slice(&msg[1..]);
+        ret.restrict = Some(Duration::from_nanos(u64::from_ne_bytes(took)));
+        if let Some(observer) = observer {
+            observer.on_restricted(pid);
+        }
+        // The exec closes the pipe; anything else means it failed.
+        let mut rest = Vec::new();
+        if reader.read_to_end(&mut rest).is_ok() && rest.is_empty() {
+            ret.exec = Some(fork_started.elapsed());
+            if let Some(observer) = observer {
+                observer.on_exec(pid);
+            }
+        } else if rest.len() == msg.len() && rest[0] == PROGRESS_EXEC_FAILED {
+            let mut errno = [0u8; 8];
+            errno.copy_from_slice(&rest[1..]);
+            ret.exec_error = Some(u64::from_ne_bytes(errno) as i32);
+        }
+        ret
+    }
+}
+
 /// Close all open file descriptors except those listed.
 /// This method may be imperfect if
 /// the system has a very high limit on open FDs.
@@ -210,91 +873,192 @@ fn close_open_fds(except: &HashSet<nix::libc::c_int>) {
 #[derive(Clone)]
 pub(crate) struct LinuxChildState {
     pid: nix::unistd::Pid,
-    killed: Arc<Mutex<bool>>,
-    exit_code: Arc<Mutex<Option<i32>>>,
+    /// Becomes readable once the child exits; None if the kernel doesn't support pidfds.
+    pidfd: Option<Arc<OwnedFd>>,
+    ended: Arc<Mutex<Ended>>,
+}
+
+/// What's known about how the child ended.
+struct Ended {
+    /// Set once the child is reaped.
+    reaped: Option<Reaped>,
+    /// True if the sandbox sent the kill signal.
+    sandbox_killed: bool,
+    /// Set along with `reaped`.
+    usage: Option<ResourceUsage>,
+}
+
+#[derive(Clone, Copy)]
+enum Reaped {
+    Exited(i32),
+    Signaled(nix::sys::signal::Signal),
+}
+
+impl Ended {
+    fn exit_status(&self) -> Option<ExitStatus> {
+        self.reaped.map(|r| match r {
+            Reaped::Exited(c) => ExitStatus::Code(c),
+            Reaped::Signaled(nix::sys::signal::Signal::SIGKILL) if self.sandbox_killed => {
+                ExitStatus::SandboxKilled
+            }
+            Reaped::Signaled(sig) => ExitStatus::Signaled(sig as i32),
+        })
+    }
+}
+
+/// Like `waitpid`, but through `wait4`, which also reports a reaped child's resource
+/// usage.
+fn wait4(
+    pid: nix::unistd::Pid,
+    options: nix::libc::c_int,
+) -> nix::Result<(WaitStatus, ResourceUsage)> {
+    let mut status: nix::libc::c_int = 0;
+    let mut usage: nix::libc::rusage = unsafe { std::mem::zeroed() };
+    let ret = unsafe { nix::libc::wait4(pid.as_raw(), &mut status, options, &mut usage) };
+    match nix::errno::Errno::result(ret)? {
+        0 => Ok((WaitStatus::StillAlive, ResourceUsage::default())),
+        _ => Ok((WaitStatus::from_raw(pid, status)?, resource_usage(&usage))),
+    }
+}
+
+fn resource_usage(usage: &nix::libc::rusage) -> ResourceUsage {
+    let duration = |t: nix::libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    ResourceUsage {
+        user_cpu: duration(usage.ru_utime),
+        sys_cpu: duration(usage.ru_stime),
+        // Linux reports this in kilobytes.
+        max_rss: usage.ru_maxrss as u64 * 1024,
+        page_faults: (usage.ru_minflt + usage.ru_majflt) as u64,
+    }
 }
 
 impl LinuxChildState {
     pub(crate) fn new(pid: nix::unistd::Pid) -> Self {
         LinuxChildState {
             pid,
-            killed: Arc::new(Mutex::new(false)),
-            exit_code: Arc::new(Mutex::new(None)),
+            pidfd: open_pidfd(pid).map(Arc::new),
+            ended: Arc::new(Mutex::new(Ended {
+                reaped: None,
+                sandbox_killed: false,
+                usage: None,
+            })),
         }
     }
 
-    pub(crate) fn exit_code(&self) -> ExitCode {
-        let mut k = match self.killed.lock() {
-            Ok(guard) => guard,
-            Err(_) => return ExitCode::Running, // poisoned lock; assume still running.
-        };
-        let mut c = match self.exit_code.lock() {
-            Ok(guard) => guard,
-            Err(_) => return ExitCode::Running, // poisoned lock; assume still running.
+    pub(crate) fn pid(&self) -> i32 {
+        self.pid.as_raw()
+    }
+
+    pub(crate) fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+        self.pidfd.as_ref().map(|fd| fd.as_fd())
+    }
+
+    /// Reap the child if it ended, and get how it ended, or None if it is still running.
+    pub(crate) fn exit_status(&self) -> Result<Option<ExitStatus>, std::io::Error> {
+        let mut ended = self
+            .ended
+            .lock()
+            .map_err(|_| std::io::Error::other("lock poisoned"))?;
+        if ended.reaped.is_none() {
+            match wait4(self.pid, nix::libc::WNOHANG) {
+                // An error usually means that the child never started.  However,
+                // this should never receive a PID if that's the case.
+                // It can also mean that this process doesn't have access, or some
+                // very weird state.
+                Err(err) => {
+                    return Err(std::io::Error::other(format!("waitpid failed: {}", err)));
+                }
+                Ok((WaitStatus::Exited(_pid, ec), usage)) => {
+                    // What we expect.
+                    ended.reaped = Some(Reaped::Exited(ec));
+                    ended.usage = Some(usage);
+                }
+                Ok((WaitStatus::Signaled(_pid, sig, _was_core_dump), usage)) => {
+                    ended.reaped = Some(Reaped::Signaled(sig));
+                    ended.usage = Some(usage);
+                }
+                Ok(_) => {
+                    // Still alive
+                }
+            }
+        }
+        Ok(ended.exit_status())
+    }
+
+    /// Block until the child exits, or the timeout passes.
+    ///
+    /// A blocking waitpid would hold the state lock for the whole wait, which keeps
+    /// another thread from terminating the child.  Instead, this blocks in `poll` on the
+    /// child's pidfd, and only takes the lock to reap the child once it has exited.
+    /// Returns None if the timeout passed first.
+    pub(crate) fn wait(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<ExitStatus>, std::io::Error> {
+        let Some(pidfd) = &self.pidfd else {
+            return poll_exit(timeout, || self.exit_status());
         };
-        match *c {
-            Some(code) => ExitCode::Exited(code),
-            None => {
-                // FIXME if c is None, then perform a wait-pid.
-                match nix::sys::wait::waitpid(
-                    self.pid,
-                    nix::sys::wait::WaitPidFlag::from_bits(nix::libc::WNOHANG),
-                ) {
-                    // An error usually means that the child never started.  However,
-                    // this should never receive a PID if that's the case.
-                    // It can also mean that this process doesn't have access, or some
-                    // very weird state.
-                    Err(err) => ExitCode::OsError(OsTermination {
-                        message: format!("waitpid failed: {}", err),
-                        code: 0,
-                        subcode: None,
-                    }),
-                    Ok(WaitStatus::Exited(_pid, ec)) => {
-                        // What we expect.
-                        *k = true;
-                        *c = Some(ec);
-                        ExitCode::Exited(ec)
-                    }
-                    Ok(WaitStatus::Signaled(_pid, sig, _was_core_dump)) => {
-                        *k = true;
-                        *c = Some(-1);
-                        ExitCode::OsError(OsTermination {
-                            message: sig.as_str().to_string(), code: 1, subcode: None,
-                        })
-                    }
-                    Ok(_) => {
-                        // Still alive
-                        ExitCode::Running
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            if let Some(status) = self.exit_status()? {
+                return Ok(Some(status));
+            }
+            let wait_ms = match deadline {
+                None => -1,
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
                     }
+                    // Round up, so a sub-millisecond remainder doesn't spin.
+                    (deadline - now)
+                        .as_micros()
+                        .div_ceil(1000)
+                        .min(i32::MAX as u128) as i32
+                }
+            };
+            let mut pollfd = nix::libc::pollfd {
+                fd: pidfd.as_raw_fd(),
+                events: nix::libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { nix::libc::poll(&mut pollfd, 1, wait_ms) } < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() != std::io::ErrorKind::Interrupted {
+                    logging::debug!("poll on child {} pidfd failed: {}", self.pid, err);
+                    let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                    return poll_exit(remaining, || self.exit_status());
                 }
             }
         }
     }
 
-    pub(crate) fn kill(&self) -> Result<ExitCode, std::io::Error> {
-        let mut k = self
-            .killed
-            .lock()
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "lock poisoned"))?;
-        let mut ec = self
-            .exit_code
+    /// The resources the child used, or None if it is still running.
+    pub(crate) fn resource_usage(&self) -> Option<ResourceUsage> {
+        // Reap the child, if it ended.
+        let _ = self.exit_status();
+        self.ended.lock().ok()?.usage
+    }
+
+    /// Kill the child, if it's still running, and wait for it to end.
+    /// Returns how the child ended.
+    pub(crate) fn kill(&self) -> Result<ExitStatus, std::io::Error> {
+        let mut ended = self
+            .ended
             .lock()
             .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "lock poisoned"))?;
-        if *k {
-            match *ec {
-                Some(c) => return Ok(ExitCode::Exited(c)),
-                None => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "BUG: process already killed, but exit code not set",
-                    ));
-                }
-            }
+        if let Some(status) = ended.exit_status() {
+            return Ok(status);
         }
 
         // The child cannot listen to signals, so kill it hard.
+        logging::debug!("terminating child {}", self.pid);
         match nix::sys::signal::kill(self.pid, nix::sys::signal::Signal::SIGKILL) {
-            Ok(_) => {}
+            Ok(_) => {
+                ended.sandbox_killed = true;
+            }
             Err(e) => match e {
                 nix::errno::Errno::ESRCH => {
                     // The process is already dead.
@@ -309,36 +1073,32 @@ impl LinuxChildState {
             },
         };
 
-        // Monitor the process until it dies.
-        // This may not immediately return the exit code,
-        // but may intermediately return that the process
-        // encountered a signal.
+        // Wait until the process dies.
         loop {
-            match nix::sys::wait::waitpid(
-                self.pid,
-                // After running kill, wait until it dies.
-                nix::sys::wait::WaitPidFlag::from_bits(0),
-            ) {
+            // After running kill, wait until it dies.
+            match wait4(self.pid, 0) {
                 // An error usually means that the child never started.  However,
                 // this should never receive a PID if that's the case.
                 // It can also mean that this process doesn't have access, or some
                 // very weird state.
+                Err(nix::errno::Errno::EINTR) => continue,
                 Err(r) => {
-                    // Don't mark the process as killed.
+                    // Don't mark the process as reaped.
                     // It might be an intermittent error?
                     return Err(r.into());
                 }
-                Ok(WaitStatus::Exited(_pid, c)) => {
-                    // What we expect.
-                    *k = true;
-                    *ec = Some(c);
-                    return Ok(ExitCode::Exited(c));
+                Ok((WaitStatus::Exited(_pid, c), usage)) => {
+                    // It ended on its own before the signal arrived.
+                    ended.reaped = Some(Reaped::Exited(c));
+                    ended.usage = Some(usage);
+                    break;
                 }
-                Ok(WaitStatus::Signaled(_pid, _sig, _b)) => {
-                    // The process was killed by a signal, keep waiting.
-                    continue;
+                Ok((WaitStatus::Signaled(_pid, sig, _b), usage)) => {
+                    ended.reaped = Some(Reaped::Signaled(sig));
+                    ended.usage = Some(usage);
+                    break;
                 }
-                Ok(v) => {
+                Ok((v, _)) => {
                     // The kill didn't work, and the process is alive in some odd
                     // state.
                     return Err(std::io::Error::new(
@@ -348,5 +1108,68 @@ impl LinuxChildState {
                 }
             }
         }
+        ended.exit_status().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "BUG: process reaped, but exit status not set",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_blocks_until_exit() {
+        let pid = match unsafe { nix::unistd::fork() }.expect("fork failed") {
+            nix::unistd::ForkResult::Child => unsafe {
+                nix::libc::usleep(200_000);
+                nix::libc::_exit(3);
+            },
+            nix::unistd::ForkResult::Parent { child } => child,
+        };
+        let state = LinuxChildState::new(pid);
+        assert_eq!(state.pid(), pid.as_raw());
+        assert!(state.pidfd().is_some());
+        assert_eq!(
+            state
+                .wait(Some(Duration::from_millis(10)))
+                .expect("wait failed"),
+            None
+        );
+        assert_eq!(
+            state.wait(None).expect("wait failed"),
+            Some(ExitStatus::Code(3))
+        );
+        assert!(state.resource_usage().expect("no usage after exit").max_rss > 0);
+    }
+
+    #[test]
+    fn drop_kills_child() {
+        let pid = match unsafe { nix::unistd::fork() }.expect("fork failed") {
+            nix::unistd::ForkResult::Child => unsafe {
+                nix::libc::pause();
+                nix::libc::_exit(0);
+            },
+            nix::unistd::ForkResult::Parent { child } => child,
+        };
+        let child = LinuxChild {
+            state: LinuxChildState::new(pid),
+            fds: HashMap::new(),
+            channels: HashMap::new(),
+            terminal: None,
+            output: OutputGuards::default(),
+            kill_on_drop: true,
+            observer: None,
+            report: LaunchReport::default(),
+        };
+        let state = child.state();
+        drop(child);
+        assert_eq!(
+            state.exit_status().expect("exit status failed"),
+            Some(ExitStatus::SandboxKilled)
+        );
     }
 }
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: MIT
+
+//! Resolve the requested CPU placement into an affinity mask.
+//!
+//! The mask is built before the fork, so the child only needs to make the
+//! `sched_setaffinity` call, which does not allocate.
+
+use nix::sched::CpuSet;
+
+use crate::restrictions::CpuPlacement;
+use crate::runtime::error::SandboxError;
+
+/// Build the affinity mask for the placement, or None if the child may run on any CPU.
+pub fn cpu_set_for(placement: &CpuPlacement) -> Result<Option<CpuSet>, SandboxError> {
+    let cpus = match placement {
+        CpuPlacement::Any => return Ok(None),
+        CpuPlacement::Cpus(cpus) => cpus.clone(),
+        CpuPlacement::NumaNode(node) => numa_node_cpus(*node)?,
+    };
+    if cpus.is_empty() {
+        return Err(SandboxError::JailSetup(
+            "cpu placement does not contain any CPUs".to_string(),
+        ));
+    }
+    let mut set = CpuSet::new();
+    for cpu in cpus {
+        set.set(cpu).map_err(|_| {
+            SandboxError::JailSetup(format!("cpu {cpu} is beyond the supported cpu set size"))
+        })?;
+    }
+    Ok(Some(set))
+}
+
+/// Read the list of CPUs attached to the NUMA node from sysfs.
+fn numa_node_cpus(node: usize) -> Result<Vec<usize>, SandboxError> {
+    let path = format!("/sys/devices/system/node/node{node}/cpulist");
+    let list = std::fs::read_to_string(&path).map_err(|e| {
+        SandboxError::JailSetup(format!("could not read cpus for NUMA node {node}: {e}"))
+    })?;
+    parse_cpu_list(&list)
+}
+
+/// Parse the kernel's cpu list format, such as `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>, SandboxError> {
+    let invalid = || SandboxError::JailSetup(format!("invalid cpu list: {}", list.trim()));
+    let mut ret = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            None => ret.push(part.parse().map_err(|_| invalid())?),
+            Some((start, end)) => {
+                let start: usize = start.parse().map_err(|_| invalid())?;
+                let end: usize = end.parse().map_err(|_| invalid())?;
+                if end < start {
+                    return Err(invalid());
+                }
+                ret.extend(start..=end);
+            }
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ranges_and_singles() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n").unwrap(),
+            vec![0, 1, 2, 3, 8, 10, 11]
+        );
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert_eq!(parse_cpu_list("\n").unwrap(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+
+    #[test]
+    fn any_has_no_mask() {
+        assert!(cpu_set_for(&CpuPlacement::Any).unwrap().is_none());
+    }
+
+    #[test]
+    fn explicit_cpus() {
+        let set = cpu_set_for(&CpuPlacement::Cpus(vec![0])).unwrap().unwrap();
+        assert!(set.is_set(0).unwrap());
+        assert!(!set.is_set(1).unwrap());
+    }
+}
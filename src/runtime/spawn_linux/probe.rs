@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: MIT
+
+//! Check what the host's kernel supports.
+
+use nix::libc;
+
+use crate::runtime::probe::HostCapabilities;
+
+/// Asks `landlock_create_ruleset` for the ABI version, rather than creating a ruleset.
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_long = 1;
+
+pub(crate) fn probe() -> HostCapabilities {
+    HostCapabilities {
+        os: "linux".to_string(),
+        os_version: nix::sys::utsname::uname()
+            .map(|u| u.release().to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        landlock_abi: landlock_abi(),
+        seccomp: seccomp_available(),
+        user_namespaces: user_namespaces_available(),
+        app_container: false,
+        privileged: is_privileged(),
+    }
+}
+
+/// Capabilities that get around the jail, by number: read any file (`CAP_DAC_OVERRIDE`,
+/// `CAP_DAC_READ_SEARCH`), change user or group (`CAP_SETGID`, `CAP_SETUID`), trace other
+/// processes (`CAP_SYS_PTRACE`), or administer the system (`CAP_SYS_ADMIN`).
+const PRIVILEGED_CAPABILITIES: [u32; 6] = [1, 2, 6, 7, 19, 21];
+
+/// Root keeps its capabilities inside the jail, such as to read any file descriptor it's
+/// handed, or to change its own limits.  Any other user holding one of the
+/// `PRIVILEGED_CAPABILITIES`, such as from file capabilities, can do the same.
+pub(crate) fn is_privileged() -> bool {
+    nix::unistd::geteuid().is_root()
+        || effective_capabilities()
+            .is_some_and(|caps| PRIVILEGED_CAPABILITIES.iter().any(|c| caps & (1 << c) != 0))
+}
+
+/// The effective capability set, as reported in `/proc/self/status`.
+fn effective_capabilities() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let caps = status.lines().find_map(|l| l.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(caps.trim(), 16).ok()
+}
+
+fn landlock_abi() -> Option<u32> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<libc::c_void>(),
+            0 as libc::c_long,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    // Fails with ENOSYS when the kernel lacks Landlock, and EOPNOTSUPP when it's disabled.
+    (abi > 0).then_some(abi as u32)
+}
+
+fn seccomp_available() -> bool {
+    // Fails with EINVAL when the kernel lacks seccomp; otherwise reports this process's mode.
+    unsafe { libc::prctl(libc::PR_GET_SECCOMP) >= 0 }
+}
+
+fn user_namespaces_available() -> bool {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+    };
+    // The first is the upstream limit, and the others are distribution switches that only
+    // exist on some hosts.
+    read("/proc/sys/user/max_user_namespaces").is_some_and(|n| n > 0)
+        && read("/proc/sys/kernel/unprivileged_userns_clone") != Some(0)
+        && read("/proc/sys/kernel/apparmor_restrict_unprivileged_userns") != Some(1)
+}
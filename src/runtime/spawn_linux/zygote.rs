@@ -0,0 +1,664 @@
+// SPDX-License-Identifier: MIT
+
+//! Zygote helper process for high spawn throughput.
+//!
+//! The zygote is forked once, holding an already prepared launch.  The caller then
+//! asks it to spawn children over a Unix socket pair.  The zygote is single threaded,
+//! so its forks never run into the multithreaded `fork` limitations, and the caller
+//! never forks again after starting the zygote.
+//!
+//! The zygote forks the children, so it is their parent.  The caller receives the
+//! parent side of the child's pipes through `SCM_RIGHTS`, and asks the zygote for the
+//! child's exit status or to kill it.
+//!
+//! Before serving requests, the zygote arranges to die with the caller, closes every
+//! file descriptor it does not need, and puts itself in a baseline jail: `no_new_privs`,
+//! a Landlock floor, and a seccomp baseline.  The floor allows the paths and ports the
+//! children are allowed, the files the FD set opens for them (the directory of a file
+//! the child writes, as the file may not exist yet), and the pseudo-terminal devices.
+//! The baseline filter allows the children's syscalls, plus the ones the zygote needs to
+//! fork and jail them.  Landlock domains and seccomp filters stack, so each child still
+//! gets the full jail from the prepared launch on top, the same as a child launched
+//! directly.  The zygote has no resource limits of its own.
+//!
+//! Each request and reply is a single `SOCK_SEQPACKET` message.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::File,
+    io::{IoSlice, IoSliceMut},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::{OsStrExt as _, OsStringExt as _},
+    },
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use nix::sys::socket::{
+    AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags, SockFlag, SockType, recvmsg,
+    sendmsg, socketpair,
+};
+
+use super::{
+    fd::{FdMap, StreamDirection},
+    jail::BaselineJail,
+    launch::{
+        LinuxChild, LinuxChildState, PreparedLaunch, fd_map, poll_exit, take_reader, take_writer,
+    },
+};
+use crate::runtime::{
+    Child, ChildKiller, CommHandler, ExitStatus, FdMode, FdSet, LaunchEnv, RunResult,
+    error::SandboxError,
+    limit::OutputGuards,
+    stream::{ChildReader, ChildWriter},
+    terminal::Terminal,
+};
+
+/// Largest request or reply message.
+const MAX_MESSAGE: usize = 256 * 1024;
+
+/// Most file descriptors passed for a single child.
+const MAX_FDS: usize = 64;
+
+const OP_SPAWN: u8 = 1;
+const OP_STATUS: u8 = 2;
+const OP_KILL: u8 = 3;
+const OP_RELEASE: u8 = 4;
+
+const REPLY_OK: u8 = 0;
+const REPLY_ERR: u8 = 1;
+
+/// A long-lived helper process that launches sandboxed children from a prepared launch.
+/// The helper itself runs in a baseline jail that allows what its children may do, and
+/// each child gets the prepared launch's jail on top.
+///
+/// Start the zygote early, before the program creates any threads; this is the only
+/// fork the calling process performs.
+pub struct Zygote {
+    conn: Arc<Connection>,
+    pid: nix::unistd::Pid,
+    fds: FdSet,
+}
+
+impl Zygote {
+    /// Prepare the launch environment, and fork the zygote process that holds it.
+    pub fn start(env: LaunchEnv) -> Result<Self, SandboxError> {
+        // Anything already open belongs to the caller, and the zygote closes it.
+        // The prepared launch opens its own descriptors, which the zygote must keep.
+        let inherited = open_fds()?;
+        let prepared = PreparedLaunch::new(env)?;
+        let fds = prepared.fds().clone();
+        let (read_paths, write_paths) = fd_paths(&fds);
+        let baseline = prepared.baseline(&read_paths, &write_paths)?;
+        let (parent_sock, zygote_sock) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .map_err(|e| SandboxError::Io(e.into()))?;
+
+        match unsafe { nix::unistd::fork() } {
+            Err(e) => Err(SandboxError::Io(e.into())),
+            Ok(nix::unistd::ForkResult::Child) => {
+                drop(parent_sock);
+                serve(zygote_sock, prepared, baseline, &inherited);
+                std::process::exit(0);
+            }
+            Ok(nix::unistd::ForkResult::Parent { child }) => {
+                drop(zygote_sock);
+                drop(baseline);
+                let zygote = Zygote {
+                    conn: Arc::new(Connection {
+                        sock: Mutex::new(parent_sock),
+                    }),
+                    pid: child,
+                    fds,
+                };
+                // The zygote reports whether it could jail itself before serving.
+                zygote.conn.receive()?;
+                Ok(zygote)
+            }
+        }
+    }
+
+    /// The zygote's process ID.
+    pub fn id(&self) -> u32 {
+        self.pid.as_raw() as u32
+    }
+
+    /// Launch a child with the arguments appended to the prepared arguments, and pass it
+    /// to the handler.  Works like `sandbox_child`.
+    pub fn spawn<CH: CommHandler>(
+        &self,
+        args: &[OsString],
+        mut handler: CH,
+    ) -> Result<RunResult<CH::Output, CH::Error>, SandboxError> {
+        // The zygote can't open the log files for the caller.
+        let output = OutputGuards::open(&self.fds)?;
+        let mut req = vec![OP_SPAWN];
+        put_u32(&mut req, args.len() as u32);
+        for arg in args {
+            put_bytes(&mut req, arg.as_bytes());
+        }
+        let (reply, fds) = self.conn.request(&req)?;
+        let mut r = Reader::new(&reply);
+        let pid = r.i32()?;
+        let count = r.u32()? as usize;
+        if count != fds.len() {
+            return Err(SandboxError::ProcessError(
+                "zygote sent the wrong number of streams".to_string(),
+            ));
+        }
+        let mut streams = Vec::new();
+        let mut terminal = None;
+        for fd in fds {
+            let dup_to = r.u32()?;
+            let direction = match r.u8()? {
+                0 => StreamDirection::ToChild,
+                _ => StreamDirection::FromChild,
+            };
+            let stream = File::from(fd);
+            let is_terminal = r.u8()? != 0;
+            if is_terminal && terminal.is_none() {
+                terminal = Some(Terminal::new(stream.try_clone()?));
+            }
+            streams.push(FdMap {
+                dup_to,
+                stream,
+                direction,
+                terminal: is_terminal,
+            });
+        }
+        let child = ZygoteChild {
+            conn: self.conn.clone(),
+            pid,
+            streams: fd_map(streams),
+            channels: self.fds.channels(),
+            terminal,
+            output,
+        };
+        let output = handler.handle(Box::new(child));
+        let ret = kill(&self.conn, pid);
+        let mut req = vec![OP_RELEASE];
+        put_i32(&mut req, pid);
+        self.conn.request(&req)?;
+        Ok(RunResult {
+            handler: output,
+            exit: ret?,
+        })
+    }
+}
+
+impl Drop for Zygote {
+    fn drop(&mut self) {
+        // Closing the socket tells the zygote to stop its children and exit.
+        if let Ok(sock) = self.conn.sock.lock() {
+            let _ = nix::sys::socket::shutdown(sock.as_raw_fd(), nix::sys::socket::Shutdown::Both);
+        }
+        let _ = nix::sys::wait::waitpid(self.pid, None);
+    }
+}
+
+/// The caller's side of the socket pair.
+struct Connection {
+    sock: Mutex<OwnedFd>,
+}
+
+impl Connection {
+    /// Send the request, and wait for the reply.  Returns the reply payload after the
+    /// status byte, and any passed file descriptors.
+    fn request(&self, req: &[u8]) -> Result<Message, SandboxError> {
+        let sock = self
+            .sock
+            .lock()
+            .map_err(|_| SandboxError::ProcessError("zygote lock poisoned".to_string()))?;
+        send(sock.as_raw_fd(), req, &[])?;
+        reply(sock.as_raw_fd())
+    }
+
+    /// Wait for a reply without sending a request.
+    fn receive(&self) -> Result<Message, SandboxError> {
+        let sock = self
+            .sock
+            .lock()
+            .map_err(|_| SandboxError::ProcessError("zygote lock poisoned".to_string()))?;
+        reply(sock.as_raw_fd())
+    }
+}
+
+/// Receive a reply.  Returns the payload after the status byte, and any passed file
+/// descriptors.
+fn reply(fd: RawFd) -> Result<Message, SandboxError> {
+    let (reply, fds) = recv(fd)?
+        .ok_or_else(|| SandboxError::ProcessError("zygote closed the connection".to_string()))?;
+    match reply.split_first() {
+        Some((&REPLY_OK, rest)) => Ok((rest.to_vec(), fds)),
+        Some((_, rest)) => Err(SandboxError::ProcessError(format!(
+            "zygote: {}",
+            String::from_utf8_lossy(rest)
+        ))),
+        None => Err(SandboxError::ProcessError(
+            "zygote sent an empty reply".to_string(),
+        )),
+    }
+}
+
+/// Ask the zygote to kill the child.
+fn kill(conn: &Connection, pid: i32) -> Result<ExitStatus, SandboxError> {
+    let mut req = vec![OP_KILL];
+    put_i32(&mut req, pid);
+    let (reply, _) = conn.request(&req)?;
+    get_exit_status(&mut Reader::new(&reply))?.ok_or_else(|| {
+        SandboxError::ProcessError("zygote reported a killed child as running".to_string())
+    })
+}
+
+/// A child launched by the zygote.
+struct ZygoteChild {
+    conn: Arc<Connection>,
+    pid: i32,
+    streams: HashMap<u32, FdMap>,
+    channels: HashMap<String, u32>,
+    terminal: Option<Terminal>,
+    output: OutputGuards,
+}
+
+impl ZygoteChild {
+    /// Ask the zygote how the child ended, if it has.
+    fn status(&self) -> Result<Option<ExitStatus>, SandboxError> {
+        let mut req = vec![OP_STATUS];
+        put_i32(&mut req, self.pid);
+        let (reply, _) = self.conn.request(&req)?;
+        get_exit_status(&mut Reader::new(&reply))
+    }
+}
+
+impl Child for ZygoteChild {
+    fn terminate(&self) -> Result<(), std::io::Error> {
+        kill(&self.conn, self.pid).map_err(Into::<std::io::Error>::into)?;
+        Ok(())
+    }
+
+    fn killer(&self) -> Option<ChildKiller> {
+        let (conn, pid) = (self.conn.clone(), self.pid);
+        Some(ChildKiller::new(move || {
+            kill(&conn, pid).and(Ok(())).map_err(Into::into)
+        }))
+    }
+
+    fn take_stream_from_child(&mut self, fd: u32) -> Option<ChildReader> {
+        let (conn, pid) = (self.conn.clone(), self.pid);
+        let terminate = Box::new(move || kill(&conn, pid).and(Ok(())).map_err(Into::into));
+        take_reader(&mut self.streams, &mut self.output, fd, terminate)
+    }
+
+    fn take_stream_to_child(&mut self, fd: u32) -> Option<ChildWriter> {
+        take_writer(&mut self.streams, fd)
+    }
+
+    fn terminal(&self) -> Option<Terminal> {
+        self.terminal.clone()
+    }
+
+    fn channel_fd(&self, name: &str) -> Option<u32> {
+        self.channels.get(name).copied()
+    }
+
+    fn exit_status(&self) -> Result<Option<ExitStatus>, std::io::Error> {
+        Ok(self.status()?)
+    }
+
+    fn id(&self) -> u32 {
+        self.pid as u32
+    }
+
+    fn wait(&self) -> Result<ExitStatus, std::io::Error> {
+        // Each check is a round trip to the zygote, which does not block on the child.
+        poll_exit(None, || self.exit_status())?.ok_or_else(|| {
+            std::io::Error::other("BUG: wait without a timeout returned while running")
+        })
+    }
+
+    fn wait_timeout(&self, timeout: Duration) -> Result<Option<ExitStatus>, std::io::Error> {
+        poll_exit(Some(timeout), || self.exit_status())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Zygote side.
+
+/// Jail the zygote, then serve launch requests until the caller closes the socket.
+fn serve(sock: OwnedFd, prepared: PreparedLaunch, baseline: BaselineJail, inherited: &[RawFd]) {
+    // Die with the caller.
+    let _ = nix::sys::prctl::set_pdeathsig(nix::sys::signal::Signal::SIGKILL);
+    for fd in inherited {
+        // Keep the standard streams, so children can still use KeepInChild.
+        if *fd > 2 {
+            let _ = unsafe { nix::libc::close(*fd) };
+        }
+    }
+    let fd = sock.as_raw_fd();
+    // Tell the caller whether the zygote is jailed, and stop if it isn't.
+    let jailed = baseline.restrict();
+    let ready = match &jailed {
+        Ok(()) => vec![REPLY_OK],
+        Err(e) => {
+            let mut reply = vec![REPLY_ERR];
+            reply.extend(format!("{:#}", e).as_bytes());
+            reply
+        }
+    };
+    if send(fd, &ready, &[]).is_err() || jailed.is_err() {
+        return;
+    }
+
+    let mut children: HashMap<i32, LinuxChildState> = HashMap::new();
+    while let Ok(Some((req, _))) = recv(fd) {
+        let mut reply = vec![REPLY_OK];
+        let mut pass: Vec<File> = Vec::new();
+        if let Err(e) = handle_request(&req, &prepared, &mut children, &mut reply, &mut pass) {
+            reply = vec![REPLY_ERR];
+            reply.extend(format!("{:#}", e).as_bytes());
+            pass.clear();
+        }
+        let raw: Vec<RawFd> = pass.iter().map(|f| f.as_raw_fd()).collect();
+        if send(fd, &reply, &raw).is_err() {
+            break;
+        }
+        // The parent side of the pipes now belong to the caller.
+        drop(pass);
+    }
+    for state in children.values() {
+        let _ = state.kill();
+    }
+}
+
+fn handle_request(
+    req: &[u8],
+    prepared: &PreparedLaunch,
+    children: &mut HashMap<i32, LinuxChildState>,
+    reply: &mut Vec<u8>,
+    pass: &mut Vec<File>,
+) -> Result<(), SandboxError> {
+    let mut r = Reader::new(req);
+    match r.u8()? {
+        OP_SPAWN => {
+            let count = r.u32()?;
+            let mut args = Vec::new();
+            for _ in 0..count {
+                args.push(OsString::from_vec(r.bytes()?.to_vec()));
+            }
+            let mut child: LinuxChild = prepared.fork(&args)?;
+            let pid = child.state().pid();
+            let streams = child.take_streams();
+            if streams.len() > MAX_FDS {
+                return Err(SandboxError::JailSetup(format!(
+                    "too many streams for the zygote: {}",
+                    streams.len()
+                )));
+            }
+            put_i32(reply, pid);
+            put_u32(reply, streams.len() as u32);
+            for m in streams {
+                put_u32(reply, m.dup_to);
+                reply.push(match m.direction {
+                    StreamDirection::ToChild => 0,
+                    StreamDirection::FromChild => 1,
+                });
+                reply.push(m.terminal as u8);
+                pass.push(m.stream);
+            }
+            children.insert(pid, child.detach());
+            Ok(())
+        }
+        OP_STATUS => {
+            let pid = r.i32()?;
+            let state = children.get(&pid).ok_or_else(|| unknown_child(pid))?;
+            put_exit_status(reply, state.exit_status()?);
+            Ok(())
+        }
+        OP_KILL => {
+            let pid = r.i32()?;
+            let state = children.get(&pid).ok_or_else(|| unknown_child(pid))?;
+            put_exit_status(reply, Some(state.kill()?));
+            Ok(())
+        }
+        OP_RELEASE => {
+            let pid = r.i32()?;
+            children.remove(&pid).ok_or_else(|| unknown_child(pid))?;
+            Ok(())
+        }
+        op => Err(SandboxError::ProcessError(format!(
+            "unknown zygote request {op}"
+        ))),
+    }
+}
+
+/// The paths the zygote opens for the children's FDs, as the paths it reads and the
+/// paths it writes.  A written file may not exist yet, so its directory is listed.
+fn fd_paths(fds: &FdSet) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let (mut read_paths, mut write_paths) = (Vec::new(), Vec::new());
+    for fd in fds.modes() {
+        match fd.mode {
+            FdMode::FromFile(path) => read_paths.push(path),
+            FdMode::ToFile(path) | FdMode::AppendFile(path) => {
+                let dir = match path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                    _ => PathBuf::from("."),
+                };
+                write_paths.push(dir);
+            }
+            FdMode::NullDevice => write_paths.push(PathBuf::from("/dev/null")),
+            FdMode::Terminal => {
+                write_paths.push(PathBuf::from("/dev/ptmx"));
+                write_paths.push(PathBuf::from("/dev/pts"));
+            }
+            _ => {}
+        }
+    }
+    (read_paths, write_paths)
+}
+
+fn unknown_child(pid: i32) -> SandboxError {
+    SandboxError::ProcessError(format!("zygote has no child {pid}"))
+}
+
+/// List the file descriptors currently open in this process.
+fn open_fds() -> Result<Vec<RawFd>, SandboxError> {
+    let mut ret = Vec::new();
+    for entry in std::fs::read_dir("/proc/self/fd")? {
+        if let Some(fd) = entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+            ret.push(fd);
+        }
+    }
+    // The listing includes the directory's own descriptor, which is closed by now, and
+    // its number is free for the prepared launch to reuse.
+    ret.retain(|fd| {
+        let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(*fd) };
+        nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_GETFD).is_ok()
+    });
+    Ok(ret)
+}
+
+// ---------------------------------------------------------------------------
+// Wire format.
+
+fn send(fd: RawFd, data: &[u8], fds: &[RawFd]) -> Result<(), SandboxError> {
+    if data.len() > MAX_MESSAGE {
+        return Err(SandboxError::ProcessError(
+            "zygote message too large".to_string(),
+        ));
+    }
+    let iov = [IoSlice::new(data)];
+    let cmsgs = [ControlMessage::ScmRights(fds)];
+    let cmsgs: &[ControlMessage] = if fds.is_empty() { &[] } else { &cmsgs };
+    sendmsg::<()>(fd, &iov, cmsgs, MsgFlags::empty(), None)
+        .map_err(|e| SandboxError::Io(e.into()))?;
+    Ok(())
+}
+
+/// A message payload, and the file descriptors passed with it.
+type Message = (Vec<u8>, Vec<OwnedFd>);
+
+/// Receive one message, or None if the other side closed the socket.
+fn recv(fd: RawFd) -> Result<Option<Message>, SandboxError> {
+    let mut buf = vec![0u8; MAX_MESSAGE];
+    let mut cmsg_buf = nix::cmsg_space!([RawFd; MAX_FDS]);
+    let (len, fds) = {
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let msg = recvmsg::<()>(
+            fd,
+            &mut iov,
+            Some(&mut cmsg_buf),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )
+        .map_err(|e| SandboxError::Io(e.into()))?;
+        let mut fds = Vec::new();
+        for cmsg in msg.cmsgs().map_err(|e| SandboxError::Io(e.into()))? {
+            if let ControlMessageOwned::ScmRights(raw) = cmsg {
+                fds.extend(raw.into_iter().map(|f| unsafe { OwnedFd::from_raw_fd(f) }));
+            }
+        }
+        (msg.bytes, fds)
+    };
+    if len == 0 {
+        return Ok(None);
+    }
+    buf.truncate(len);
+    Ok(Some((buf, fds)))
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend(v.to_le_bytes());
+}
+
+fn put_i32(buf: &mut Vec<u8>, v: i32) {
+    buf.extend(v.to_le_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, v: &[u8]) {
+    put_u32(buf, v.len() as u32);
+    buf.extend(v);
+}
+
+/// Encode how the child ended, or None if it is still running.
+fn put_exit_status(buf: &mut Vec<u8>, status: Option<ExitStatus>) {
+    match status {
+        Some(ExitStatus::Code(c)) => {
+            buf.push(0);
+            put_i32(buf, c);
+        }
+        None => buf.push(1),
+        Some(ExitStatus::Signaled(sig)) => {
+            buf.push(2);
+            put_i32(buf, sig);
+        }
+        Some(ExitStatus::SandboxKilled) => buf.push(3),
+    }
+}
+
+fn get_exit_status(r: &mut Reader) -> Result<Option<ExitStatus>, SandboxError> {
+    match r.u8()? {
+        0 => Ok(Some(ExitStatus::Code(r.i32()?))),
+        1 => Ok(None),
+        2 => Ok(Some(ExitStatus::Signaled(r.i32()?))),
+        3 => Ok(Some(ExitStatus::SandboxKilled)),
+        kind => Err(SandboxError::ProcessError(format!(
+            "unknown exit status kind from the zygote: {kind}"
+        ))),
+    }
+}
+
+/// Reads values from a message.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SandboxError> {
+        if self.data.len() < len {
+            return Err(SandboxError::ProcessError(
+                "truncated zygote message".to_string(),
+            ));
+        }
+        let (ret, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(ret)
+    }
+
+    fn u8(&mut self) -> Result<u8, SandboxError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SandboxError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, SandboxError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], SandboxError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_status_round_trip() {
+        let statuses = [
+            Some(ExitStatus::Code(3)),
+            None,
+            Some(ExitStatus::Signaled(11)),
+            Some(ExitStatus::SandboxKilled),
+        ];
+        for status in statuses {
+            let mut buf = Vec::new();
+            put_exit_status(&mut buf, status);
+            let back = get_exit_status(&mut Reader::new(&buf)).unwrap();
+            assert_eq!(back, status);
+        }
+    }
+
+    #[test]
+    fn truncated_message() {
+        let mut r = Reader::new(&[1, 0]);
+        assert!(r.u32().is_err());
+    }
+
+    #[test]
+    fn passes_fds() {
+        let (a, b) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+        let (read, write) = nix::unistd::pipe().unwrap();
+        send(a.as_raw_fd(), b"hello", &[write.as_raw_fd()]).unwrap();
+        drop(write);
+        let (data, fds) = recv(b.as_raw_fd()).unwrap().unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(fds.len(), 1);
+
+        let mut out = File::from(fds.into_iter().next().unwrap());
+        std::io::Write::write_all(&mut out, b"x").unwrap();
+        drop(out);
+        let mut got = Vec::new();
+        std::io::Read::read_to_end(&mut File::from(read), &mut got).unwrap();
+        assert_eq!(got, b"x");
+    }
+}
@@ -13,6 +13,7 @@
 
 mod appcontainer;
 mod attribute_list;
+mod audit;
 mod conv;
 mod desktop;
 mod error;
@@ -22,9 +23,23 @@ mod launch;
 mod launch_quote;
 mod monitor;
 mod os_lock;
+mod overlapped;
+mod placement;
+mod probe;
 mod process_token;
 mod rand;
 mod sec_attributes;
 mod sid;
 
-pub(crate) use launch::launch_child;
+pub(crate) use launch::{WindowsChild, dependencies, launch_child, plan};
+pub(crate) use monitor::{ProcessRef, ProcessState};
+pub(crate) use probe::{is_privileged, probe};
+
+/// Delete the per-launch AppContainer profiles that a crashed parent left behind for the
+/// application name.  Only call this while no sandboxes for the application are running.
+/// Returns the number of deleted profiles.
+pub fn cleanup_stale_profiles(
+    application_name: &str,
+) -> Result<usize, crate::runtime::error::SandboxError> {
+    appcontainer::cleanup_stale_profiles(application_name).map_err(|e| e.into())
+}
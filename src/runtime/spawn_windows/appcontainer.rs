@@ -10,7 +10,7 @@ use std::rc::Rc;
 use std::time::Duration;
 use windows::Win32::Foundation;
 use windows::Win32::Security;
-use windows::Win32::System::Threading;
+use windows::Win32::System::{Registry, Threading};
 
 use crate::restrictions;
 use crate::runtime::spawn_windows::process_token::ProcessToken;
@@ -25,7 +25,20 @@ pub struct AppContainer {
     // uid: String,
     sid: Option<Rc<Box<dyn Sid>>>,
     acl: Option<SecurityAttributesWithAcl>,
-    drop_remove: Option<OsString>,
+    drop_remove: Option<ProfileRemover>,
+}
+
+/// Deletes a per-launch AppContainer profile when dropped.
+/// Held by the launched process state, so the profile outlives the child.
+pub struct ProfileRemover {
+    name: OsString,
+}
+
+impl Drop for ProfileRemover {
+    fn drop(&mut self) {
+        // The profile may already be gone; nothing useful to do on failure.
+        let _ = delete_profile(&self.name);
+    }
 }
 
 impl AppContainer {
@@ -43,7 +56,16 @@ impl AppContainer {
                 });
             }
         };
-        let display_name = as_c_str_w(&OsString::from(&app_container_policy.name));
+        let display_name = app_container_policy
+            .display_name
+            .as_ref()
+            .unwrap_or(&app_container_policy.name);
+        let description = app_container_policy
+            .description
+            .as_ref()
+            .unwrap_or(display_name);
+        let display_name = as_c_str_w(&OsString::from(display_name));
+        let description = as_c_str_w(&OsString::from(description));
 
         // Notes on AppContainer Profiles and race conditions:
         // On AppContainer initial creation, the original call will wait to return until Windows
@@ -58,11 +80,16 @@ impl AppContainer {
         // However, we lessen the need for the extreme complex setup by creating a global, OS-wide lock.
         // This only runs at sandbox creation time, so the impact on performance should remain minimal.
         if app_container_policy.reuse_existing {
-            let _init_lock = super::os_lock::OsLock::acquire(&app_container_policy.name)?;
             let os_name = OsString::from(&app_container_policy.name);
-            match create_profile(&os_name, &display_name)? {
+            // Fast path: once the shared profile exists, its SID derives from the name alone,
+            // so repeated launches skip the lock and the profile creation call.
+            if let ExistingProfile::Ready(sid) = find_existing_ready_profile(&os_name)? {
+                return Ok(Self::from_existing_profile(sid));
+            }
+            let _init_lock = super::os_lock::OsLock::acquire(&app_container_policy.name)?;
+            match create_profile(&os_name, &display_name, &description)? {
                 CreateProfileResult::Created(sid) => {
-                    return Ok(Self::from_created_profile(sid, os_name.clone(), true));
+                    return Ok(Self::from_created_profile(sid, os_name.clone(), false));
                 }
                 CreateProfileResult::PossiblyPending => {
                     for _ in 0..MAX_DISCOVERY_ATTEMPTS {
@@ -92,25 +119,39 @@ impl AppContainer {
         loop {
             let uid = super::rand::random_string_name(&app_container_policy.name)?;
             let os_name = OsString::from(uid);
-            match create_profile(&os_name, &display_name)? {
+            match create_profile(&os_name, &display_name, &description)? {
                 CreateProfileResult::Created(sid) => {
-                    return Ok(Self::from_created_profile(sid, os_name, false));
+                    return Ok(Self::from_created_profile(
+                        sid,
+                        os_name,
+                        app_container_policy.delete_on_exit,
+                    ));
                 }
                 CreateProfileResult::PossiblyPending => continue,
             }
         }
     }
 
-    fn from_created_profile(sid: Security::PSID, os_name: OsString, reuse_existing: bool) -> Self {
+    fn from_created_profile(sid: Security::PSID, os_name: OsString, delete: bool) -> Self {
         // Returned SID is released via FreeSid, so wrap as RawSid.
         let sid: Rc<Box<dyn Sid>> = Rc::new(Box::new(RawSid::new(sid)));
         Self {
             sid: Some(sid.clone()),
             acl: Some(SecurityAttributesWithAcl::default(sid)),
-            drop_remove: if reuse_existing { None } else { Some(os_name) },
+            drop_remove: if delete {
+                Some(ProfileRemover { name: os_name })
+            } else {
+                None
+            },
         }
     }
 
+    /// Hand over the profile deletion to the caller, so the profile lives as long as the
+    /// launched process rather than this structure.
+    pub fn take_profile_remover(&mut self) -> Option<ProfileRemover> {
+        self.drop_remove.take()
+    }
+
     fn from_existing_profile(sid: Rc<Box<dyn Sid>>) -> Self {
         Self {
             sid: Some(sid),
@@ -228,6 +269,7 @@ enum ExistingSid {
 fn create_profile(
     os_name: &OsString,
     display_name: &[u16],
+    description: &[u16],
 ) -> Result<CreateProfileResult, WindowsSandboxError> {
     // May want to add in WinAppContainerCapability capabilities.
     // See:
@@ -236,7 +278,7 @@ fn create_profile(
         Security::Isolation::CreateAppContainerProfile(
             windows::core::PCWSTR(as_c_str_w(os_name).as_ptr()),
             windows::core::PCWSTR(display_name.as_ptr()),
-            windows::core::PCWSTR(display_name.as_ptr()),
+            windows::core::PCWSTR(description.as_ptr()),
             None,
         )
     } {
@@ -335,20 +377,143 @@ fn appcontainer_folder_path_for_sid<T: Sid>(sid: &T) -> Result<OsString, Windows
     }
 }
 
+fn delete_profile(name: &OsString) -> Result<(), WindowsSandboxError> {
+    unsafe {
+        Security::Isolation::DeleteAppContainerProfile(windows::core::PCWSTR(
+            as_c_str_w(name).as_ptr(),
+        ))
+    }
+    .map_err(WindowsSandboxError::setup)
+}
+
+/// Registry key, under the current user's local settings, that maps each AppContainer SID
+/// to its profile name (the "Moniker" value).
+const PROFILE_MAPPINGS_KEY: &str =
+    "Software\\Microsoft\\Windows\\CurrentVersion\\AppContainer\\Mappings";
+
+/// List the names of all AppContainer profiles for the current user.
+fn list_profile_names() -> Result<Vec<OsString>, WindowsSandboxError> {
+    let mut key = Registry::HKEY::default();
+    let status = unsafe {
+        Registry::RegOpenKeyExW(
+            Registry::HKEY_CURRENT_USER_LOCAL_SETTINGS,
+            windows::core::PCWSTR(as_c_str_w(&OsString::from(PROFILE_MAPPINGS_KEY)).as_ptr()),
+            None,
+            Registry::KEY_READ,
+            &mut key,
+        )
+    };
+    if status == Foundation::ERROR_FILE_NOT_FOUND {
+        // No profiles have been created yet.
+        return Ok(Vec::new());
+    }
+    status.ok().map_err(WindowsSandboxError::setup)?;
+
+    let mut names = Vec::new();
+    let mut index = 0;
+    let result = loop {
+        // Subkeys are SID strings, well under this length.
+        let mut sid_buf = [0u16; 256];
+        let mut sid_len = sid_buf.len() as u32;
+        let status = unsafe {
+            Registry::RegEnumKeyExW(
+                key,
+                index,
+                Some(windows::core::PWSTR(sid_buf.as_mut_ptr())),
+                &mut sid_len,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+        if status == Foundation::ERROR_NO_MORE_ITEMS {
+            break Ok(names);
+        }
+        if let Err(e) = status.ok() {
+            break Err(WindowsSandboxError::setup(e));
+        }
+        index += 1;
+
+        let mut moniker = [0u16; 512];
+        let mut moniker_size = (moniker.len() * 2) as u32;
+        let status = unsafe {
+            Registry::RegGetValueW(
+                key,
+                windows::core::PCWSTR(sid_buf.as_ptr()),
+                windows::core::w!("Moniker"),
+                Registry::RRF_RT_REG_SZ,
+                None,
+                Some(moniker.as_mut_ptr() as *mut c_void),
+                Some(&mut moniker_size),
+            )
+        };
+        if status.is_ok() {
+            names.push(OsString::from(super::conv::c_str_w_as_str(&moniker)));
+        }
+    };
+    unsafe {
+        let _ = Registry::RegCloseKey(key);
+    }
+    result
+}
+
+/// True if the profile name looks like a per-launch profile for the application:
+/// the application name, a dash, and the random suffix.
+fn is_launch_profile_name(application_name: &str, profile: &str) -> bool {
+    match profile
+        .strip_prefix(application_name)
+        .and_then(|rest| rest.strip_prefix('-'))
+    {
+        Some(suffix) => {
+            suffix.len() == super::rand::RANDOM_NAME_LEN
+                && suffix
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        }
+        None => false,
+    }
+}
+
+/// Delete the per-launch AppContainer profiles for the application that a crashed or killed
+/// parent left behind.  The shared profile is kept.  Only call this when no sandboxes for the
+/// application are running, such as at startup, since their profiles would be deleted too.
+///
+/// Returns the number of profiles deleted.
+pub fn cleanup_stale_profiles(application_name: &str) -> Result<usize, WindowsSandboxError> {
+    let _lock = super::os_lock::OsLock::acquire(application_name)?;
+    let mut count = 0;
+    for name in list_profile_names()? {
+        let is_stale = name
+            .to_str()
+            .is_some_and(|n| is_launch_profile_name(application_name, n));
+        if is_stale && delete_profile(&name).is_ok() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
 impl Drop for AppContainer {
     fn drop(&mut self) {
         self.acl.take();
         self.sid.take();
-        match self.drop_remove.take() {
-            None => (),
-            Some(s) => {
-                // Need to remove the Profile.
-                let _ = unsafe {
-                    Security::Isolation::DeleteAppContainerProfile(windows::core::PCWSTR(
-                        as_c_str_w(&s).as_ptr(),
-                    ))
-                };
-            }
-        }
+        // Removes the profile, if the launch didn't take ownership of it.
+        self.drop_remove.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launch_profile_names() {
+        assert!(is_launch_profile_name("app", "app-0123456789abcdeF"));
+        assert!(is_launch_profile_name("app", "app--_cdefghijklmnop"));
+        assert!(!is_launch_profile_name("app", "app"));
+        assert!(!is_launch_profile_name("app", "app-short"));
+        assert!(!is_launch_profile_name("app", "other-0123456789abcdef"));
+        assert!(!is_launch_profile_name("app", "app-0123456789abcde!"));
     }
 }
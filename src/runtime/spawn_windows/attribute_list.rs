@@ -103,7 +103,9 @@ impl ThreadAttribute for ThreadAttributeChildProcessRestriction {
 }
 
 /// The process being created is not allowed to create child processes.
-/// Only effective within AppContainer sandboxes.
+/// Only effective within AppContainer sandboxes.  Without an AppContainer, the job object's
+/// active process limit of 1 still stops the child from starting sub-processes.
+/// Ref: PROCESS_CREATION_CHILD_PROCESS_RESTRICTED (CHILD_PROCESS_POLICY_PROHIBIT)
 pub const NO_CHILD_PROCESS_RESTRICTION: ThreadAttributeChildProcessRestriction = 1;
 
 // See https://learn.microsoft.com/en-us/windows/win32/api/processthreadsapi/nf-processthreadsapi-updateprocthreadattribute
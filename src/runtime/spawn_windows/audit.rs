@@ -0,0 +1,262 @@
+// SPDX-License-Identifier: MIT
+
+//! Report the accesses the sandbox denied the child.
+//!
+//! Windows records denied object and network accesses as audit failures in the Security
+//! event log, which the event log fills from the security auditing ETW provider.  This
+//! subscribes to those events for the child's process ID while the child runs.
+//!
+//! The Security log only holds these events when the host's audit policy enables
+//! failure auditing for "Object Access" (and "Filtering Platform Connection" for network
+//! denials), and reading the log requires administrator rights or membership in the
+//! "Event Log Readers" group.  Events arrive asynchronously, so a denial right before
+//! the child exits may show up shortly after.
+
+use std::{
+    ffi::c_void,
+    sync::{Arc, Mutex},
+};
+
+use windows::{
+    Win32::System::EventLog::{
+        EVT_HANDLE, EVT_SUBSCRIBE_NOTIFY_ACTION, EvtClose, EvtRender, EvtRenderEventXml,
+        EvtSubscribe, EvtSubscribeActionDeliver, EvtSubscribeToFutureEvents,
+    },
+    core::PCWSTR,
+};
+
+use crate::{logging, runtime::spawn::SandboxDenial};
+
+use super::{conv::as_c_str_w, error::WindowsSandboxError};
+
+/// Security log event for a handle request to an object, such as a file or registry key.
+const EVENT_OBJECT_HANDLE: u32 = 4656;
+/// Security log event for an attempted access to an object.
+const EVENT_OBJECT_ACCESS: u32 = 4663;
+/// Security log event for a privileged service call.
+const EVENT_PRIVILEGED_SERVICE: u32 = 4673;
+/// Security log event for a connection blocked by the Windows Filtering Platform.
+const EVENT_CONNECTION_BLOCKED: u32 = 5157;
+
+/// The "Audit Failure" keyword bit.
+const KEYWORD_AUDIT_FAILURE: u64 = 0x0010_0000_0000_0000;
+
+/// Collects the denials for a single process, until dropped.
+pub struct DenialAudit {
+    subscription: EVT_HANDLE,
+    // The callback context points into this; it must outlive the subscription.
+    denials: Arc<Mutex<Vec<SandboxDenial>>>,
+}
+
+// The subscription handle is only closed in drop, and the denials are behind a lock.
+unsafe impl Send for DenialAudit {}
+unsafe impl Sync for DenialAudit {}
+
+impl DenialAudit {
+    /// Subscribe to the audit failures for the process.  Start this before the process
+    /// resumes, so no denial is missed.
+    pub fn start(pid: u32) -> Result<Self, WindowsSandboxError> {
+        let denials = Arc::new(Mutex::new(Vec::new()));
+        let query = as_c_str_w(std::ffi::OsStr::new(&denial_query(pid)));
+        let channel = as_c_str_w(std::ffi::OsStr::new("Security"));
+        let subscription = unsafe {
+            EvtSubscribe(
+                None,
+                None,
+                PCWSTR(channel.as_ptr()),
+                PCWSTR(query.as_ptr()),
+                None,
+                Some(Arc::as_ptr(&denials) as *const c_void),
+                Some(on_event),
+                EvtSubscribeToFutureEvents.0,
+            )
+        }
+        .map_err(|e| {
+            WindowsSandboxError::setup_message(&format!(
+                "could not subscribe to the Security event log for denial auditing: {}",
+                e.message()
+            ))
+        })?;
+        logging::debug!("auditing denials for process {}", pid);
+        Ok(DenialAudit {
+            subscription,
+            denials,
+        })
+    }
+
+    /// The denials reported so far.
+    pub fn denials(&self) -> Vec<SandboxDenial> {
+        match self.denials.lock() {
+            Ok(d) => d.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl Drop for DenialAudit {
+    fn drop(&mut self) {
+        // Closing waits for any running callback, so the context stays valid until then.
+        let _ = unsafe { EvtClose(self.subscription) };
+    }
+}
+
+/// Build the XPath query for the audit failures that name the process.
+/// Object access events write the process ID in hex, while the filtering platform
+/// writes it in decimal.
+fn denial_query(pid: u32) -> String {
+    format!(
+        "*[(System[band(Keywords,{keyword}) and \
+         (EventID={handle} or EventID={access} or EventID={privileged})] \
+         and EventData[Data[@Name='ProcessId']='0x{pid:x}']) \
+         or (System[EventID={blocked}] and EventData[Data[@Name='ProcessID']='{pid}'])]",
+        keyword = KEYWORD_AUDIT_FAILURE,
+        handle = EVENT_OBJECT_HANDLE,
+        access = EVENT_OBJECT_ACCESS,
+        privileged = EVENT_PRIVILEGED_SERVICE,
+        blocked = EVENT_CONNECTION_BLOCKED,
+        pid = pid,
+    )
+}
+
+unsafe extern "system" fn on_event(
+    action: EVT_SUBSCRIBE_NOTIFY_ACTION,
+    context: *const c_void,
+    event: EVT_HANDLE,
+) -> u32 {
+    if action != EvtSubscribeActionDeliver || context.is_null() {
+        return 0;
+    }
+    let denials = unsafe { &*(context as *const Mutex<Vec<SandboxDenial>>) };
+    if let Some(xml) = unsafe { render_xml(event) } {
+        if let Some(denial) = parse_denial(&xml) {
+            logging::debug!("sandbox denied {} on {}", denial.access, denial.resource);
+            if let Ok(mut d) = denials.lock() {
+                d.push(denial);
+            }
+        }
+    }
+    0
+}
+
+/// Render the event as its XML text.
+unsafe fn render_xml(event: EVT_HANDLE) -> Option<String> {
+    let mut used = 0u32;
+    let mut count = 0u32;
+    // The first call only reports the needed size, in bytes.
+    let _ = unsafe {
+        EvtRender(
+            None,
+            event,
+            EvtRenderEventXml.0,
+            0,
+            None,
+            &mut used,
+            &mut count,
+        )
+    };
+    if used == 0 {
+        return None;
+    }
+    let mut buffer: Vec<u16> = vec![0; (used as usize).div_ceil(2)];
+    unsafe {
+        EvtRender(
+            None,
+            event,
+            EvtRenderEventXml.0,
+            used,
+            Some(buffer.as_mut_ptr() as *mut c_void),
+            &mut used,
+            &mut count,
+        )
+    }
+    .ok()?;
+    let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    Some(String::from_utf16_lossy(&buffer[..len]))
+}
+
+/// Pull the denial details out of the event XML.
+fn parse_denial(xml: &str) -> Option<SandboxDenial> {
+    let code: i64 = element_text(xml, "EventID")?.trim().parse().ok()?;
+    let resource = data_value(xml, "ObjectName")
+        .or_else(|| data_value(xml, "Service"))
+        .or_else(|| {
+            let addr = data_value(xml, "DestAddress")?;
+            let port = data_value(xml, "DestPort").unwrap_or_default();
+            Some(format!("{}:{}", addr, port))
+        })
+        .unwrap_or_default();
+    let access = data_value(xml, "AccessList")
+        .or_else(|| data_value(xml, "PrivilegeList"))
+        .or_else(|| data_value(xml, "Direction").map(|_| "connect".to_string()))
+        .unwrap_or_default();
+    Some(SandboxDenial {
+        resource,
+        access: access.split_whitespace().collect::<Vec<_>>().join(" "),
+        code,
+    })
+}
+
+/// The text of the first `<name ...>text</name>` element.
+fn element_text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}", name))?;
+    let rest = &xml[start..];
+    let open_end = rest.find('>')?;
+    let body = &rest[open_end + 1..];
+    let close = body.find(&format!("</{}>", name))?;
+    Some(&body[..close])
+}
+
+/// The text of the `<Data Name='name'>text</Data>` element.
+fn data_value(xml: &str, name: &str) -> Option<String> {
+    let start = xml
+        .find(&format!("<Data Name='{}'>", name))
+        .or_else(|| xml.find(&format!("<Data Name=\"{}\">", name)))?;
+    let body = &xml[start..];
+    let body = &body[body.find('>')? + 1..];
+    let value = &body[..body.find("</Data>")?];
+    let value = value.trim();
+    if value.is_empty() || value == "-" {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OBJECT_EVENT: &str = "<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>\
+        <System><EventID>4656</EventID></System><EventData>\
+        <Data Name='ObjectName'>C:\\Users\\me\\secret.txt</Data>\
+        <Data Name='AccessList'>%%4416\r\n\t\t\t\t%%4417</Data>\
+        <Data Name='ProcessId'>0x1f4</Data></EventData></Event>";
+
+    const NETWORK_EVENT: &str = "<Event><System><EventID>5157</EventID></System><EventData>\
+        <Data Name=\"ProcessID\">500</Data><Data Name=\"Direction\">%%14593</Data>\
+        <Data Name=\"DestAddress\">10.0.0.1</Data><Data Name=\"DestPort\">443</Data>\
+        </EventData></Event>";
+
+    #[test]
+    fn parse_object_denial() {
+        let d = parse_denial(OBJECT_EVENT).unwrap();
+        assert_eq!(d.code, 4656);
+        assert_eq!(d.resource, "C:\\Users\\me\\secret.txt");
+        assert_eq!(d.access, "%%4416 %%4417");
+    }
+
+    #[test]
+    fn parse_network_denial() {
+        let d = parse_denial(NETWORK_EVENT).unwrap();
+        assert_eq!(d.code, 5157);
+        assert_eq!(d.resource, "10.0.0.1:443");
+        assert_eq!(d.access, "connect");
+    }
+
+    #[test]
+    fn query_names_process() {
+        let q = denial_query(500);
+        assert!(q.contains("'0x1f4'"));
+        assert!(q.contains("'500'"));
+    }
+}
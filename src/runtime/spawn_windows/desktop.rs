@@ -6,6 +6,9 @@
 //! clipboard, window messages), prevent the child from showing windows to
 //! the interactive user or receiving input from other apps.
 //!
+//! Callers may instead name a desktop they created themselves, in which case the
+//! child runs there and the sandbox leaves its lifetime to the caller.
+//!
 //! In some circumstances, the execution context prohibits the generation of the
 //! window station.  In this case, the sandbox just doesn't use it, and it means
 //! the child will also be prohibited from using the UI.
@@ -16,6 +19,7 @@ use std::sync::Mutex;
 
 use windows::Win32::{Foundation, Security, System::StationsAndDesktops, UI::WindowsAndMessaging};
 
+use crate::logging;
 use crate::restrictions;
 
 use super::conv;
@@ -86,42 +90,45 @@ impl UiIsolate {
         let require_di = match restr.windows.desktop_isolate {
             restrictions::windows::DesktopIsolateMode::Disabled => {
                 return Ok(Self {
-                        desktop: DesktopIsolate {
-                            name: None,
-                            desktop: None,
-                        },
-                        station: WindowStationIsolate {
-                            name: None,
-                            station: None,
-                        },
-                        desktop_path: None,
-                    });
-            }
-            restrictions::windows::DesktopIsolateMode::Enabled => {
-                false
+                    desktop: DesktopIsolate {
+                        name: None,
+                        desktop: None,
+                    },
+                    station: WindowStationIsolate {
+                        name: None,
+                        station: None,
+                    },
+                    desktop_path: None,
+                });
             }
-            restrictions::windows::DesktopIsolateMode::Required => {
-                true
+            restrictions::windows::DesktopIsolateMode::Existing(ref name) => {
+                // The caller owns the station and desktop, so there's nothing to close.
+                return Ok(Self {
+                    desktop: DesktopIsolate {
+                        name: None,
+                        desktop: None,
+                    },
+                    station: WindowStationIsolate {
+                        name: None,
+                        station: None,
+                    },
+                    desktop_path: Some(conv::as_c_str_w(OsStr::new(name))),
+                });
             }
+            restrictions::windows::DesktopIsolateMode::Enabled => false,
+            restrictions::windows::DesktopIsolateMode::Required => true,
         };
         let (name, app_sid) = match &restr.windows.app_container {
-            restrictions::windows::AppContainerMode::Enabled(acp) => {
-                (
-                    randomized_desktop_name(&acp.name)?,
-                    app_sid.ok_or_else(|| {
-                        WindowsSandboxError::setup_message("missing AppContainer SID")
-                    })?,
-                )
-            }
-            restrictions::windows::AppContainerMode::Disabled => {
-                (
-                    randomized_desktop_name(&format!(
-                        "gracklezero-desktop-{}",
-                        std::process::id()
-                    ))?,
-                    current_logon_sid()?,
-                )
-            }
+            restrictions::windows::AppContainerMode::Enabled(acp) => (
+                randomized_desktop_name(&acp.name)?,
+                app_sid.ok_or_else(|| {
+                    WindowsSandboxError::setup_message("missing AppContainer SID")
+                })?,
+            ),
+            restrictions::windows::AppContainerMode::Disabled => (
+                randomized_desktop_name(&format!("gracklezero-desktop-{}", std::process::id()))?,
+                current_logon_sid()?,
+            ),
         };
 
         // This operation mutates process-wide UI state (window station switching),
@@ -243,9 +250,8 @@ impl DesktopIsolate {
             Err(e) => {
                 if Self::is_desktop_creation_denied(&e) {
                     // This is expected in some contexts, so return None to indicate that the desktop could not be created.
-                    // TODO use a proper logging mechanism here.
-                    println!(
-                        "Warning: failed to create desktop: {} (0x{:x})",
+                    logging::warning!(
+                        "failed to create desktop: {} (0x{:x})",
                         e.message(),
                         e.code().0,
                     );
@@ -2,9 +2,9 @@
 
 //! Windows based errors.
 
-use std::sync::PoisonError;
+use std::{ffi, path::PathBuf, sync::PoisonError};
 
-use crate::runtime::error::SandboxError;
+use crate::runtime::error::{JailMechanism, SandboxError};
 
 /// Allows handling Windows errors and library errors in the same code.
 #[derive(Debug)]
@@ -21,6 +21,22 @@ impl WindowsSandboxError {
     pub fn setup(e: windows::core::Error) -> Self {
         WindowsSandboxError::Setup(e)
     }
+
+    /// Name the jail mechanism behind a Windows error, for the launch diagnostics.
+    pub fn in_mechanism(self, mechanism: JailMechanism) -> Self {
+        match self {
+            Self::Setup(e) => Self::Sandbox(SandboxError::jail(Some(mechanism), e)),
+            e => e,
+        }
+    }
+
+    /// Report a Windows error as the failure to create the process for the executable.
+    pub fn in_exec(self, path: &ffi::OsStr) -> Self {
+        match self {
+            Self::Setup(e) => Self::Sandbox(SandboxError::exec(PathBuf::from(path), e.into())),
+            e => e,
+        }
+    }
 }
 
 impl From<windows::core::Error> for WindowsSandboxError {
@@ -33,9 +49,8 @@ impl Into<SandboxError> for WindowsSandboxError {
     fn into(self) -> SandboxError {
         match self {
             Self::Sandbox(s) => s,
-            Self::Setup(e) => {
-                SandboxError::JailSetup(format!("problem setting up the process: {:?}", e))
-            }
+            // Keeps the Windows error code as the source.
+            Self::Setup(e) => SandboxError::jail(None, e),
         }
     }
 }
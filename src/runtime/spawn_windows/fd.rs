@@ -1,16 +1,21 @@
 //! Handle the "file descriptor" style passing from the parent to the child.
 
 use std::fs::File;
-use std::os::windows::io::FromRawHandle;
+use std::os::windows::io::{FromRawHandle, IntoRawHandle, RawHandle};
 use windows_result::HRESULT;
 use windows_sys::Win32::System::Console;
 
+use super::overlapped::{OverlappedPipe, create_pipe};
+use crate::runtime::stream::ChildWriter;
+
 use windows::Win32::{
     Foundation::{
-        CloseHandle, DUPLICATE_SAME_ACCESS, DuplicateHandle, FALSE, HANDLE, HANDLE_FLAG_INHERIT,
-        HANDLE_FLAGS, INVALID_HANDLE_VALUE, SetHandleInformation,
+        CloseHandle, DUPLICATE_SAME_ACCESS, DuplicateHandle, FALSE, GENERIC_READ, GENERIC_WRITE,
+        HANDLE, HANDLE_FLAG_INHERIT, HANDLE_FLAGS, INVALID_HANDLE_VALUE, SetHandleInformation,
+        TRUE,
     },
     Security,
+    Storage::FileSystem,
     System::{Pipes, Threading::GetCurrentProcess},
 };
 
@@ -23,20 +28,33 @@ pub struct WinFdSet {
 
 impl WinFdSet {
     pub fn new(stdio: StdIoSet, others: Vec<WinFd>) -> windows::core::Result<Self> {
+        // Anonymous pipes can't skip blocking, so non-blocking pipes use named pipes.
+        let pipe = |fd: u32, direction: StreamDirection| -> windows::core::Result<WinFd> {
+            if stdio.nonblocking {
+                let mut ret = WinFd::new_overlapped(fd, direction, None)?;
+                ret.set_nonblocking();
+                Ok(ret)
+            } else {
+                WinFd::new(fd, direction)
+            }
+        };
         let stdin = match stdio.stdin {
-            StdIo::Pipe => StdIoFd::Pipe(WinFd::new(0, StreamDirection::ToChild)?),
-            StdIo::None => StdIoFd::None,
-            StdIo::PassThrough => StdIoFd::Pipe(WinFd::from_std(0)?),
+            StdIo::Pipe => StdIoFd::Pipe(pipe(0, StreamDirection::ToChild)?),
+            StdIo::None => StdIoFd::Null(WinFd::null(0)?),
+            StdIo::PassThrough => WinFd::pass_through(0)?,
+            StdIo::File(f) => StdIoFd::File(WinFd::from_file(0, StreamDirection::ToChild, f)?),
         };
         let stdout = match stdio.stdout {
-            StdIo::Pipe => StdIoFd::Pipe(WinFd::new(1, StreamDirection::FromChild)?),
-            StdIo::None => StdIoFd::None,
-            StdIo::PassThrough => StdIoFd::Pipe(WinFd::from_std(1)?),
+            StdIo::Pipe => StdIoFd::Pipe(pipe(1, StreamDirection::FromChild)?),
+            StdIo::None => StdIoFd::Null(WinFd::null(1)?),
+            StdIo::PassThrough => WinFd::pass_through(1)?,
+            StdIo::File(f) => StdIoFd::File(WinFd::from_file(1, StreamDirection::FromChild, f)?),
         };
         let stderr = match stdio.stderr {
-            StdIo::Pipe => StdIoFd::Pipe(WinFd::new(2, StreamDirection::FromChild)?),
-            StdIo::None => StdIoFd::None,
-            StdIo::PassThrough => StdIoFd::Pipe(WinFd::from_std(2)?),
+            StdIo::Pipe => StdIoFd::Pipe(pipe(2, StreamDirection::FromChild)?),
+            StdIo::None => StdIoFd::Null(WinFd::null(2)?),
+            StdIo::PassThrough => WinFd::pass_through(2)?,
+            StdIo::File(f) => StdIoFd::File(WinFd::from_file(2, StreamDirection::FromChild, f)?),
         };
         Ok(WinFdSet {
             stdin,
@@ -45,6 +63,32 @@ impl WinFdSet {
             others,
         })
     }
+
+    /// Give the FD a duplicate of the target's child handle, so the child writes both to
+    /// the same stream.  A merged standard stream replaces its placeholder.
+    pub fn merge(&mut self, fd: u32, target: u32) -> windows::core::Result<()> {
+        let target_fd = match target {
+            0 => Some(self.stdin.win_fd()),
+            1 => Some(self.stdout.win_fd()),
+            2 => Some(self.stderr.win_fd()),
+            _ => self.others.iter().find(|f| f.fd == target),
+        };
+        let merged = match target_fd {
+            Some(t) => t.duplicate_child(fd)?,
+            None => {
+                return Err(windows::core::Error::new(
+                    HRESULT(0i32),
+                    "merge target is not in the fd set",
+                ));
+            }
+        };
+        match fd {
+            1 => self.stdout = StdIoFd::File(merged),
+            2 => self.stderr = StdIoFd::File(merged),
+            _ => self.others.push(merged),
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -57,6 +101,8 @@ pub enum StreamDirection {
 pub struct WinFd {
     fd: u32,
     parent_handle: Option<HANDLE>,
+    // The parent's end, when it is an overlapped named pipe rather than a plain handle.
+    parent_pipe: Option<OverlappedPipe>,
     child_handle: Option<HANDLE>,
     direction: StreamDirection,
 }
@@ -71,17 +117,28 @@ pub struct StdIoSet {
     pub stdin: StdIo,
     pub stdout: StdIo,
     pub stderr: StdIo,
+    pub nonblocking: bool,
 }
 
 pub enum StdIo {
-    None,        // don't use this fd
+    None,        // attach the NUL device
     PassThrough, // reuse the parent's handle
     Pipe,        // use a pipe.
+    File(File),  // redirect to a file opened by the parent.
 }
 
 pub enum StdIoFd {
-    None,        // don't use this fd
+    Null(WinFd), // the NUL device; the parent has no stream for it.
     Pipe(WinFd), // use a pipe.
+    File(WinFd), // a file opened by the parent; the parent has no stream for it.
+}
+
+impl StdIoFd {
+    fn win_fd(&self) -> &WinFd {
+        match self {
+            StdIoFd::Null(v) | StdIoFd::Pipe(v) | StdIoFd::File(v) => v,
+        }
+    }
 }
 
 const DEFAULT_BUFFER_SIZE: u32 = 0; // use default buffer size
@@ -117,6 +174,7 @@ impl WinFd {
                     fd,
                     direction,
                     parent_handle: Some(write),
+                    parent_pipe: None,
                     child_handle: Some(read),
                 }
             }
@@ -127,13 +185,52 @@ impl WinFd {
                     fd,
                     direction,
                     parent_handle: Some(read),
+                    parent_pipe: None,
                     child_handle: Some(write),
                 }
             }
         })
     }
 
-    fn from_std(fd: u32) -> windows::core::Result<Self> {
+    /// Create a named pipe for a non-standard FD.  The parent's end uses overlapped I/O,
+    /// so its reads and writes fail with `TimedOut` after the timeout.
+    pub fn new_overlapped(
+        fd: u32,
+        direction: StreamDirection,
+        timeout: Option<std::time::Duration>,
+    ) -> windows::core::Result<Self> {
+        let parent_reads = matches!(direction, StreamDirection::FromChild);
+        let (parent, child) = create_pipe(parent_reads, timeout)?;
+        allow_inheritable(child)?;
+        Ok(Self {
+            fd,
+            direction,
+            parent_handle: None,
+            parent_pipe: Some(parent),
+            child_handle: Some(child),
+        })
+    }
+
+    /// Fail the parent's reads and writes that can't finish right away, rather than
+    /// waiting.  Only applies to the overlapped named pipes.
+    pub fn set_nonblocking(&mut self) {
+        if let Some(pipe) = self.parent_pipe.as_mut() {
+            pipe.set_nonblocking();
+        }
+    }
+
+    /// Reuse the parent's standard handle in the child.  Services, GUI applications, and
+    /// some CI agents run without a console, so there's no handle to share; the child
+    /// gets the NUL device instead.
+    fn pass_through(fd: u32) -> windows::core::Result<StdIoFd> {
+        Ok(match Self::from_std(fd)? {
+            Some(v) => StdIoFd::Pipe(v),
+            None => StdIoFd::Null(Self::null(fd)?),
+        })
+    }
+
+    /// Duplicate the parent's standard handle, or None if the parent doesn't have one.
+    fn from_std(fd: u32) -> windows::core::Result<Option<Self>> {
         let (direction, std_handle) = match fd {
             0 => (StreamDirection::ToChild, Console::STD_INPUT_HANDLE),
             1 => (StreamDirection::FromChild, Console::STD_OUTPUT_HANDLE),
@@ -154,9 +251,12 @@ impl WinFd {
         let null: *mut std::ffi::c_void =
             core::ptr::null::<*mut std::ffi::c_void>() as *mut std::ffi::c_void;
         // Some environments don't have a console.
-        if parent == null || parent == INVALID_HANDLE_VALUE.0 {
+        if parent == INVALID_HANDLE_VALUE.0 {
             return Err(windows::core::Error::from_thread());
         }
+        if parent == null {
+            return Ok(None);
+        }
         let mut child = HANDLE::default();
         unsafe {
             DuplicateHandle(
@@ -169,10 +269,93 @@ impl WinFd {
                 DUPLICATE_SAME_ACCESS, // options
             )?
         };
-        Ok(Self {
+        Ok(Some(Self {
             fd,
             direction,
             parent_handle: None, // This is a pass-through FD, so the parent process will not access it.
+            parent_pipe: None,
+            child_handle: Some(child),
+        }))
+    }
+
+    /// Open the NUL device as an inheritable handle for the child.
+    /// Like `/dev/null`, reads return end-of-file and writes are discarded, so
+    /// programs that write to an unused std handle don't fail.
+    pub fn null(fd: u32) -> windows::core::Result<Self> {
+        let sa = Security::SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<Security::SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: std::ptr::null_mut(),
+            bInheritHandle: TRUE, // only ever given to the child.
+        };
+        let direction = match fd {
+            0 => StreamDirection::ToChild,
+            _ => StreamDirection::FromChild,
+        };
+        let child = unsafe {
+            FileSystem::CreateFileW(
+                windows::core::w!("NUL"),
+                (GENERIC_READ | GENERIC_WRITE).0,
+                FileSystem::FILE_SHARE_READ | FileSystem::FILE_SHARE_WRITE,
+                Some(&sa),
+                FileSystem::OPEN_EXISTING,
+                FileSystem::FILE_ATTRIBUTE_NORMAL,
+                None,
+            )?
+        };
+        Ok(Self {
+            fd,
+            direction,
+            parent_handle: None, // Nothing to read or write from the parent side.
+            parent_pipe: None,
+            child_handle: Some(child),
+        })
+    }
+
+    /// Hand a file opened by the parent to the child.
+    pub fn from_file(
+        fd: u32,
+        direction: StreamDirection,
+        file: File,
+    ) -> windows::core::Result<Self> {
+        let child = HANDLE(file.into_raw_handle());
+        allow_inheritable(child)?;
+        Ok(Self {
+            fd,
+            direction,
+            parent_handle: None, // The child owns the file.
+            parent_pipe: None,
+            child_handle: Some(child),
+        })
+    }
+
+    /// Duplicate the child's handle as an inheritable handle for another FD.
+    fn duplicate_child(&self, fd: u32) -> windows::core::Result<Self> {
+        let source = match self.child_handle {
+            Some(h) => h,
+            None => {
+                return Err(windows::core::Error::new(
+                    HRESULT(0i32),
+                    "merge target has no child handle",
+                ));
+            }
+        };
+        let mut child = HANDLE::default();
+        unsafe {
+            DuplicateHandle(
+                GetCurrentProcess(),
+                source,
+                GetCurrentProcess(),
+                &mut child,
+                0,
+                true, // the child inherits it.
+                DUPLICATE_SAME_ACCESS,
+            )?
+        };
+        Ok(Self {
+            fd,
+            direction: StreamDirection::FromChild,
+            parent_handle: None, // The parent reads the target's stream instead.
+            parent_pipe: None,
             child_handle: Some(child),
         })
     }
@@ -195,8 +378,14 @@ impl WinFd {
         self.child_handle
     }
 
-    // Takes the parent handle as a stream reader.
-    pub fn as_reader(&mut self) -> Option<Box<dyn std::io::Read>> {
+    // Takes the parent handle as a stream reader, along with its raw handle.
+    pub fn as_reader(&mut self) -> Option<(Box<dyn std::io::Read>, RawHandle)> {
+        if let StreamDirection::FromChild = self.direction
+            && let Some(pipe) = self.parent_pipe.take()
+        {
+            let raw = pipe.raw_handle();
+            return Some((Box::new(pipe), raw));
+        }
         let handle = match self.parent_handle.take() {
             None => {
                 return None;
@@ -205,14 +394,21 @@ impl WinFd {
         };
         match self.direction {
             StreamDirection::ToChild => None,
-            StreamDirection::FromChild => {
-                Some(Box::new(unsafe { File::from_raw_handle(handle.0) }))
-            }
+            StreamDirection::FromChild => Some((
+                Box::new(unsafe { File::from_raw_handle(handle.0) }),
+                handle.0,
+            )),
         }
     }
 
     // Takes the parent handle as a stream writer.
-    pub fn as_writer(&mut self) -> Option<Box<dyn std::io::Write>> {
+    pub fn as_writer(&mut self) -> Option<ChildWriter> {
+        if let StreamDirection::ToChild = self.direction
+            && let Some(pipe) = self.parent_pipe.take()
+        {
+            let raw = pipe.raw_handle();
+            return Some(ChildWriter::new(Box::new(pipe), raw));
+        }
         let handle = match self.parent_handle.take() {
             None => {
                 return None;
@@ -221,7 +417,10 @@ impl WinFd {
         };
         match self.direction {
             StreamDirection::FromChild => None,
-            StreamDirection::ToChild => Some(Box::new(unsafe { File::from_raw_handle(handle.0) })),
+            StreamDirection::ToChild => {
+                let file = unsafe { File::from_raw_handle(handle.0) };
+                Some(ChildWriter::new(Box::new(file), handle.0))
+            }
         }
     }
 }
@@ -1,6 +1,5 @@
 // SPDX-License-Identifier: MIT
 use std::{
-    collections::HashMap,
     ffi, mem,
     path::PathBuf,
     sync::{
@@ -9,35 +8,56 @@ use std::{
     },
 };
 use windows::Win32::{
-    Foundation::HANDLE,
+    Foundation::{self, HANDLE},
     System::{JobObjects, Threading},
 };
 
+use crate::logging;
 use crate::restrictions;
+use crate::runtime::EnvMap;
+use crate::runtime::error::JailMechanism;
 
-use super::appcontainer::AppContainer;
+use super::appcontainer::{AppContainer, ProfileRemover};
 use super::attribute_list::{
     NO_CHILD_PROCESS_RESTRICTION, ThreadAttribute, ThreadAttributeChildProcessRestriction,
     ThreadAttributeHandles, ThreadAttributeList, ThreadAttributeMitigationPolicy,
     ThreadAttributeMitigationPolicyFlag, new_appcontainer_attribute, policy_flags,
 };
+use super::audit::DenialAudit;
 use super::conv::{as_c_str_w, c_str_w_as_str};
 use super::desktop::UiIsolate;
 use super::error::WindowsSandboxError;
+use super::monitor::SANDBOX_KILL_CODE;
 
 #[derive(Clone)]
 pub struct ProcessInfo {
     pub process: HANDLE,
+    pub process_id: u32,
     pub thread: HANDLE,
     pub job: HANDLE,
     // Keep UI isolation objects alive while the process state is held by callers.
     // Dropping these too early can tear down the child desktop/window station
     // during startup.
     _ui_isolate: Arc<UiIsolate>,
+    // Deletes a per-launch AppContainer profile once the process is no longer tracked.
+    _profile: Arc<Option<ProfileRemover>>,
+    // Collects the denied accesses, if requested.
+    pub audit: Arc<Option<DenialAudit>>,
 }
 
 static LAUNCH_SEQ: AtomicU64 = AtomicU64::new(1);
 
+/// End the suspended process that failed to launch, along with its job, and close their
+/// handles.
+unsafe fn abandon_suspended(pi: &Threading::PROCESS_INFORMATION, job: HANDLE) {
+    unsafe {
+        let _ = JobObjects::TerminateJobObject(job, SANDBOX_KILL_CODE);
+        let _ = Foundation::CloseHandle(pi.hThread);
+        let _ = Foundation::CloseHandle(pi.hProcess);
+        let _ = Foundation::CloseHandle(job);
+    }
+}
+
 /// Spawn the executable in a restricted mode.
 /// Make sure to pass the handles through the `prepare_inheritance_allowlist` function.
 /// Construct the cmdline argument with the `launch_quote::quote_arguments` function.
@@ -48,7 +68,7 @@ static LAUNCH_SEQ: AtomicU64 = AtomicU64::new(1);
 pub fn launch_restricted<'a, 'b, 'c, 'd>(
     exe: &'a ffi::OsStr,
     cmdline: &'b Vec<u16>,
-    env: HashMap<ffi::OsString, ffi::OsString>,
+    env: EnvMap,
     stdin: Option<HANDLE>,
     stdout: Option<HANDLE>,
     stderr: Option<HANDLE>,
@@ -69,14 +89,14 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
 
         // ---------------------------
         // Prepare the AppContainer.
-        let appcontainer = match AppContainer::new(restr) {
+        let mut appcontainer = match AppContainer::new(restr) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!(
+                logging::error!(
                     "[launch {launch_id}] launch_restricted: AppContainer::new failed: {:?}",
                     e
                 );
-                return Err(e);
+                return Err(e.in_mechanism(JailMechanism::AppContainer));
             }
         };
 
@@ -104,11 +124,11 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
         let attributes = match ThreadAttributeList::new(attributes) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!(
+                logging::error!(
                     "[launch {launch_id}] launch_restricted: ThreadAttributeList::new failed: {:?}",
                     e
                 );
-                return Err(e);
+                return Err(e.in_mechanism(JailMechanism::ProcessAttributes));
             }
         };
 
@@ -116,11 +136,11 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
         let ui_isolate = match UiIsolate::initialize(restr, appcontainer.sid()) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!(
+                logging::error!(
                     "[launch {launch_id}] launch_restricted: UiIsolate::initialize failed: {:?}",
                     e
                 );
-                return Err(e);
+                return Err(e.in_mechanism(JailMechanism::Desktop));
             }
         };
 
@@ -161,7 +181,7 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
         let mut cwd = match app_container_cwd(&appcontainer, launch_id) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!(
+                logging::error!(
                     "[launch {launch_id}] launch_restricted: app_container_cwd failed: {:?}",
                     e
                 );
@@ -171,7 +191,7 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
         let env = match with_default_environ(&appcontainer, env) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!(
+                logging::error!(
                     "[launch {launch_id}] launch_restricted: with_default_environ failed: {:?}",
                     e
                 );
@@ -197,11 +217,11 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
             &si_ex.StartupInfo,
             &mut pi,
         ) {
-            eprintln!(
+            logging::error!(
                 "[launch {launch_id}] launch_restricted: process creation failed: {:?}",
                 e
             );
-            return Err(e);
+            return Err(e.in_exec(exe));
         }
 
         // ---------------------------
@@ -209,20 +229,33 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
         let job = match JobObjects::CreateJobObjectW(None, windows::core::PCWSTR::null()) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!(
+                logging::error!(
                     "[launch {launch_id}] launch_restricted: CreateJobObjectW failed: {:?}",
                     e
                 );
-                return Err(e.into());
+                return Err(WindowsSandboxError::from(e).in_mechanism(JailMechanism::JobObject));
             }
         };
 
         let mut basic: JobObjects::JOBOBJECT_BASIC_LIMIT_INFORMATION = mem::zeroed();
         basic.LimitFlags = JobObjects::JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE
             | JobObjects::JOB_OBJECT_LIMIT_ACTIVE_PROCESS;
+        // Backs up the child process policy attribute: the job refuses any second process.
         basic.ActiveProcessLimit = 1;
 
+        let limits = &restr.resource_limits;
+        if let Some(max_cpu_time) = limits.max_cpu_time {
+            // Measured in 100-nanosecond ticks.
+            basic.LimitFlags |= JobObjects::JOB_OBJECT_LIMIT_JOB_TIME;
+            basic.PerJobUserTimeLimit =
+                (max_cpu_time.as_nanos() / 100).min(i64::MAX as u128) as i64;
+        }
+
         let mut ext: JobObjects::JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+        if let Some(max_memory) = limits.max_memory {
+            basic.LimitFlags |= JobObjects::JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+            ext.ProcessMemoryLimit = max_memory.min(usize::MAX as u64) as usize;
+        }
         ext.BasicLimitInformation = basic;
 
         if let Err(e) = JobObjects::SetInformationJobObject(
@@ -231,19 +264,48 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
             &mut ext as *mut _ as *mut _,
             mem::size_of::<JobObjects::JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
         ) {
-            eprintln!(
+            logging::error!(
                 "[launch {launch_id}] launch_restricted: SetInformationJobObject failed: {:?}",
                 e
             );
-            return Err(e.into());
+            return Err(WindowsSandboxError::from(e).in_mechanism(JailMechanism::JobObject));
+        }
+
+        if let Some(cpu_rate_percent) = limits.cpu_rate_percent {
+            // The rate is the share of processor cycles, in 1/100th of a percent.
+            let mut rate: JobObjects::JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = mem::zeroed();
+            rate.ControlFlags = JobObjects::JOB_OBJECT_CPU_RATE_CONTROL_ENABLE
+                | JobObjects::JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+            rate.Anonymous.CpuRate = cpu_rate_percent.clamp(1, 100) * 100;
+            if let Err(e) = JobObjects::SetInformationJobObject(
+                job,
+                JobObjects::JobObjectCpuRateControlInformation,
+                &mut rate as *mut _ as *mut _,
+                mem::size_of::<JobObjects::JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as u32,
+            ) {
+                logging::error!(
+                    "[launch {launch_id}] launch_restricted: SetInformationJobObject (cpu rate) failed: {:?}",
+                    e
+                );
+                return Err(WindowsSandboxError::from(e).in_mechanism(JailMechanism::JobObject));
+            }
         }
 
         if let Err(e) = JobObjects::AssignProcessToJobObject(job, pi.hProcess) {
-            eprintln!(
+            logging::error!(
                 "[launch {launch_id}] launch_restricted: AssignProcessToJobObject failed: {:?}",
                 e
             );
-            return Err(e.into());
+            return Err(WindowsSandboxError::from(e).in_mechanism(JailMechanism::JobObject));
+        }
+
+        // Pin the process to the requested processors before it runs any code.
+        if let Err(e) = super::placement::apply_cpu_placement(pi.hProcess, &restr.cpu_placement) {
+            logging::error!(
+                "[launch {launch_id}] launch_restricted: apply_cpu_placement failed: {:?}",
+                e
+            );
+            return Err(e);
         }
 
         // TODO inject ntdll patching + inline syscall trampoline.
@@ -253,17 +315,39 @@ pub fn launch_restricted<'a, 'b, 'c, 'd>(
         //   3. creating a remote thread to execute the trampoline (CreateRemoteThread(pi.hProcess, NULL, 0, LoadLibraryW, addr, 0, NULL))
         //   4. Wait for the loader thread to finish.  This should do something like exposing a named event or a completion protocol.
 
+        // Subscribe before the process runs any code, so no denial is missed.
+        let audit = if restr.windows.audit_denials {
+            match DenialAudit::start(pi.dwProcessId) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    logging::error!(
+                        "[launch {launch_id}] launch_restricted: DenialAudit::start failed: {:?}",
+                        e
+                    );
+                    // The process never ran; don't leave it suspended in its job.
+                    abandon_suspended(&pi, job);
+                    return Err(e);
+                }
+            }
+        } else {
+            None
+        };
+
         // ---------------------------
         // Resume thread to allow the process to start, and clean up
         Threading::ResumeThread(pi.hThread);
+        logging::debug!("[launch {launch_id}] started process {}", pi.dwProcessId);
 
         // The other structures will drop their handles when this function returns.
 
         Ok(ProcessInfo {
             process: pi.hProcess,
+            process_id: pi.dwProcessId,
             thread: pi.hThread,
             job,
             _ui_isolate: Arc::new(ui_isolate),
+            _profile: Arc::new(appcontainer.take_profile_remover()),
+            audit: Arc::new(audit),
         })
     }
 }
@@ -289,9 +373,10 @@ fn app_container_cwd(app: &AppContainer, launch_id: u64) -> Result<Vec<u16>, Win
                         ));
                     }
                     Err(_) => {
-                        eprintln!(
+                        logging::error!(
                             "[launch {launch_id}] app_container_cwd create_dir_all failed path={:?} err={}",
-                            dir_path, create_err
+                            dir_path,
+                            create_err
                         );
                         return Err(WindowsSandboxError::setup_message(&format!(
                             "failed to create app container temp folder: {}",
@@ -346,38 +431,34 @@ fn ensure_valid_launch_cwd(
 
 fn with_default_environ(
     app: &AppContainer,
-    mut environ: HashMap<ffi::OsString, ffi::OsString>,
+    environ: EnvMap,
 ) -> Result<Vec<u16>, WindowsSandboxError> {
+    // Windows refuses duplicate names that only differ by case, so fold any map built
+    // with case-sensitive names.  The map iterates in sorted order, so this is deterministic.
+    let mut environ = if environ.is_case_insensitive() {
+        environ
+    } else {
+        let mut folded = EnvMap::case_insensitive();
+        folded.extend(environ);
+        folded
+    };
     let system_root = std::env::var_os("SYSTEMROOT").unwrap_or_else(|| ffi::OsString::new());
 
     // If SYSTEMROOT is not set, add it from the current process's environment.
-    if !environ
-        .iter()
-        .any(|(k, _)| k.to_string_lossy().to_uppercase() == "SYSTEMROOT")
-    {
-        environ.insert(ffi::OsString::from("SystemRoot"), system_root.clone());
-    }
+    environ.insert_default("SystemRoot", system_root.clone());
     // ... same for winroot.
-    if !environ
-        .iter()
-        .any(|(k, _)| k.to_string_lossy().to_uppercase() == "WINDIR")
-    {
-        environ.insert(
-            ffi::OsString::from("Windir"),
-            std::env::var_os("WINDIR").unwrap_or_else(|| ffi::OsString::new()),
-        );
-    }
+    environ.insert_default(
+        "Windir",
+        std::env::var_os("WINDIR").unwrap_or_else(|| ffi::OsString::new()),
+    );
 
     // Use a minimal path, if not given.
-    if !environ
-        .iter()
-        .any(|(k, _)| k.to_string_lossy().to_uppercase() == "PATH")
-    {
+    if !environ.contains_key("Path") {
         let mut path = ffi::OsString::from(&system_root);
         path.push(";");
         path.push(&system_root);
         path.push("\\System32");
-        environ.insert(ffi::OsString::from("Path"), path);
+        environ.insert("Path", path);
     }
 
     // Force the AppContainer profile folders or user version.
@@ -394,9 +475,9 @@ fn with_default_environ(
             (f, t)
         }
     };
-    environ.insert("LOCALAPPDATA".into(), app_folder.clone().into());
-    environ.insert("TEMP".into(), tmp_folder.clone().into());
-    environ.insert("TMP".into(), tmp_folder.into());
+    environ.insert("LOCALAPPDATA", app_folder.clone());
+    environ.insert("TEMP", tmp_folder.clone());
+    environ.insert("TMP", tmp_folder);
 
     // Windows requires a hidden drive-current-directory entry when a CWD is passed
     // to CreateProcess*. Without this, process creation can intermittently fail
@@ -408,11 +489,12 @@ fn with_default_environ(
                 let drive = (drive as char).to_ascii_uppercase();
                 let mut drive_key = ffi::OsString::new();
                 drive_key.push(format!("={}:", drive).as_str());
-                environ.insert(drive_key, app_folder.clone().into());
+                environ.insert(drive_key, app_folder.clone());
             }
         }
     }
 
+    // The map guarantees that no two keys differ only by case.
     super::launch_quote::encode_env_strings(
         environ
             .into_iter()
@@ -429,10 +511,13 @@ fn add_std_handle(
 ) -> Result<Vec<HANDLE>, WindowsSandboxError> {
     match handle {
         Some(h) => {
+            // Console handles go through conhost, which needs win32k.  Pipes and the NUL
+            // device don't.
             if matches!(
                 restr.windows.disable_win32k_system_calls,
                 restrictions::windows::AlwaysMode::AlwaysOn
-            ) {
+            ) && is_console_handle(h)
+            {
                 return Err(WindowsSandboxError::setup_message(
                     "cannot use CLI std* handles with win32k system call restriction",
                 ));
@@ -444,20 +529,26 @@ fn add_std_handle(
     }
 }
 
+/// True if the handle refers to a console, rather than a file or pipe.
+fn is_console_handle(h: HANDLE) -> bool {
+    let mut mode = 0;
+    unsafe { windows_sys::Win32::System::Console::GetConsoleMode(h.0, &mut mode) != 0 }
+}
+
 struct MitigationPolicies {
     policy: ThreadAttributeMitigationPolicyFlag,
     policy2: ThreadAttributeMitigationPolicyFlag,
 }
 
 #[derive(Clone, Copy, Debug)]
-struct WindowsVersion {
-    major: u32,
-    minor: u32,
-    build: u32,
+pub(super) struct WindowsVersion {
+    pub(super) major: u32,
+    pub(super) minor: u32,
+    pub(super) build: u32,
 }
 
 impl WindowsVersion {
-    fn is_at_least(self, major: u32, minor: u32, build: u32) -> bool {
+    pub(super) fn is_at_least(self, major: u32, minor: u32, build: u32) -> bool {
         if self.major != major {
             return self.major > major;
         }
@@ -468,7 +559,7 @@ impl WindowsVersion {
     }
 }
 
-fn current_windows_version() -> Option<WindowsVersion> {
+pub(super) fn current_windows_version() -> Option<WindowsVersion> {
     let mut info = windows::Win32::System::SystemInformation::OSVERSIONINFOW::default();
     info.dwOSVersionInfoSize =
         std::mem::size_of::<windows::Win32::System::SystemInformation::OSVERSIONINFOW>() as u32;
@@ -2,22 +2,43 @@
 
 use std::{
     collections::HashMap,
-    ffi::{OsStr, OsString},
+    ffi::OsString,
+    os::windows::ffi::OsStringExt as _,
     path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use windows::Win32::Foundation::HANDLE;
+use windows::{
+    Win32::{
+        Foundation::{HANDLE, MAX_PATH},
+        Storage::FileSystem,
+    },
+    core::PCWSTR,
+};
 
 use crate::{
-    FdSet,
+    FdSet, logging,
+    restrictions::windows::AppContainerMode,
     runtime::{
-        error::SandboxError,
-        spawn::{Child, ExitCode, LaunchEnv, OsTermination},
+        deps::{Dependency, DependencyReport},
+        error::{LaunchDiagnostics, LaunchPhase, SandboxError},
+        lifecycle::LifecycleObserver,
+        limit::OutputGuards,
+        plan::SandboxPlan,
+        probe::refuse_privileged_parent,
+        registry,
+        spawn::{
+            Child, ExecResolution, ExitStatus, LaunchEnv, LaunchReport, ResourceUsage,
+            SandboxDenial,
+        },
         spawn_windows::{
+            conv::as_c_str_w,
             fd::{StdIo, StdIoFd, StdIoSet, StreamDirection, WinFd, WinFdSet},
             jail, launch_quote,
-            monitor::ProcessState,
+            monitor::{ProcessState, SANDBOX_KILL_CODE},
         },
+        stream::{ChildReader, ChildWriter},
     },
 };
 
@@ -27,54 +48,184 @@ pub struct WindowsChild {
     stdout: Option<StdIoFd>,
     stderr: Option<StdIoFd>,
     others: HashMap<u32, WinFd>,
+    channels: HashMap<String, u32>,
+    /// Limits and log files for the output streams.
+    output: OutputGuards,
+    kill_on_drop: bool,
+    observer: Option<Arc<dyn LifecycleObserver>>,
+    report: LaunchReport,
 }
 
-const LAUNCH_HANDLE_ENV: &str = "SANDBOX_HANDLES";
-
 /// Handle the child process launching.
 pub fn launch_child(env: LaunchEnv) -> Result<WindowsChild, SandboxError> {
-    let cmd = get_full_path_name(&env.cmd)?; // must be a real path, not a relative location.
-    let args = launch_quote::quote_arguments(OsString::from("command.com").as_os_str(), &env.args)?; // Use a fake command name.
-    let (fds, handles, env_handles) = create_fds(env.fds)?;
+    let mut diagnostics = LaunchDiagnostics::new(&env.cmd);
+    launch_phases(env, &mut diagnostics).map_err(|e| diagnostics.attach(e))
+}
 
+/// Launch the child, keeping the diagnostics up to date with the phase reached.
+fn launch_phases(
+    env: LaunchEnv,
+    diagnostics: &mut LaunchDiagnostics,
+) -> Result<WindowsChild, SandboxError> {
+    env.fds.validate()?;
+    refuse_privileged_parent(&env)?;
+    let started = Instant::now();
+    diagnostics.phase = LaunchPhase::Resolve;
+    let cmd = resolve_cmd(&env)?;
+    diagnostics.resolved = Some(cmd.clone());
+    // DLLs aren't analyzed yet, so only the executable is checked.  It stays open, without
+    // write or delete sharing, until the process is created from it.
+    diagnostics.phase = LaunchPhase::Verify;
+    let verified = match &env.hash_allowlist {
+        Some(allowlist) if allowlist.verifies_libraries() => {
+            return Err(SandboxError::JailNotSupported(
+                "the hash allowlist can't verify DLLs on Windows".to_string(),
+            ));
+        }
+        Some(allowlist) => Some(allowlist.open_verified_locked(&cmd)?),
+        None => None,
+    };
+    let resolved = Instant::now();
+    if let Some(observer) = &env.observer {
+        observer.on_resolved(&cmd);
+    }
+    // Use a fake command name, unless the caller picked one.
+    let arg0 = env
+        .arg0
+        .clone()
+        .unwrap_or_else(|| OsString::from("command.com"));
+    diagnostics.phase = LaunchPhase::Validate;
+    let args = launch_quote::quote_arguments(arg0.as_os_str(), &env.args)?;
     // The generated AppContainer must have read access to this cwd.
     let cwd = get_full_path_name(&env.cwd)?; // Must be a real path, not a relative location.
-    println!(
-        "Running [{}] [{}] in [{}]",
-        cmd.to_str().unwrap(),
-        String::from_utf16(args.as_slice()).unwrap(),
-        cwd.to_str().unwrap()
+    let channels = env.fds.channels();
+    let channels_env = env.fds.channels_env();
+    diagnostics.phase = LaunchPhase::FdSetup;
+    let output = OutputGuards::open(&env.fds)?;
+    let fd_started = Instant::now();
+    let (fds, handles, env_handles) = create_fds(env.fds)?;
+    let fd_done = Instant::now();
+
+    logging::debug!(
+        "running {:?} [{}] in {:?}",
+        cmd,
+        String::from_utf16_lossy(args.as_slice()),
+        cwd
     );
 
     let mut environ = env.env;
-    environ.insert(OsString::from(LAUNCH_HANDLE_ENV), env_handles);
+    environ.insert(crate::child::SANDBOX_HANDLES_ENV, env_handles);
+    if let Some(v) = channels_env {
+        environ.insert(crate::child::SANDBOX_CHANNELS_ENV, v);
+    }
 
+    diagnostics.phase = LaunchPhase::Fork;
+    let fork_started = Instant::now();
     let child = jail::launch_restricted(
         cmd.as_os_str(),
         &args,
         environ,
         match &fds.stdin {
-            StdIoFd::None => None,
-            StdIoFd::Pipe(v) => v.child(),
+            StdIoFd::Null(v) | StdIoFd::Pipe(v) | StdIoFd::File(v) => v.child(),
         },
         match &fds.stdout {
-            StdIoFd::None => None,
-            StdIoFd::Pipe(v) => v.child(),
+            StdIoFd::Null(v) | StdIoFd::Pipe(v) | StdIoFd::File(v) => v.child(),
         },
         match &fds.stderr {
-            StdIoFd::None => None,
-            StdIoFd::Pipe(v) => v.child(),
+            StdIoFd::Null(v) | StdIoFd::Pipe(v) | StdIoFd::File(v) => v.child(),
         },
         handles.as_slice(),
         &env.restrictions,
     )
-    .map_err(|e| SandboxError::JailSetup(format!("problem launching process: {:?}", e)))?;
+    .map_err(Into::<SandboxError>::into)?;
+    drop(verified);
+    let report = LaunchReport {
+        resolve: Some(resolved - started),
+        fd_setup: Some(fd_done - fd_started),
+        fork: Some(fork_started.elapsed()),
+        placement: env.restrictions.cpu_placement.pinned(),
+        ..LaunchReport::default()
+    };
 
-    Ok(WindowsChild::new(child, fds))
+    // The process was created suspended inside its job, and resumed before returning.
+    if let Some(observer) = &env.observer {
+        observer.on_forked(child.process_id);
+        observer.on_restricted(child.process_id);
+        observer.on_exec(child.process_id);
+    }
+
+    let child = WindowsChild::new(
+        child,
+        fds,
+        channels,
+        output,
+        env.kill_on_drop,
+        env.observer,
+        report,
+    );
+    registry::register(&child.state);
+    Ok(child)
+}
+
+/// Work out the rules the sandbox would apply to the launch, without launching anything.
+pub(crate) fn plan(env: &LaunchEnv) -> Result<SandboxPlan, SandboxError> {
+    env.fds.validate()?;
+    let (app_container, capabilities) = match &env.restrictions.windows.app_container {
+        AppContainerMode::Enabled(acp) => (Some(acp.name.clone()), acp.capabilities.clone()),
+        AppContainerMode::Disabled => (None, Vec::new()),
+    };
+    Ok(SandboxPlan {
+        exec: resolve_cmd(env)?,
+        interpreter: None,
+        cwd: get_full_path_name(&env.cwd)?,
+        readable_paths: Vec::new(),
+        writable_paths: Vec::new(),
+        allowed_syscalls: Vec::new(),
+        syscall_violation_kills: false,
+        tcp_connect_ports: Vec::new(),
+        tcp_bind_ports: Vec::new(),
+        max_open_files: None,
+        resource_limits: env.restrictions.resource_limits.clone(),
+        cpu_placement: env.restrictions.cpu_placement.clone(),
+        app_container,
+        capabilities,
+    })
+}
+
+/// Resolve the executable, which is the only file the report lists, as the DLL imports
+/// aren't analyzed yet.
+pub(crate) fn dependencies(env: &LaunchEnv) -> Result<DependencyReport, SandboxError> {
+    let exec = resolve_cmd(env)?;
+    Ok(DependencyReport {
+        dependencies: vec![Dependency::from_path(&exec, true)],
+        exec,
+        interpreter: None,
+        extra_libraries: Vec::new(),
+        readable_paths: Vec::new(),
+        writable_paths: Vec::new(),
+    })
+}
+
+/// The full path to the executable for the command.
+fn resolve_cmd(env: &LaunchEnv) -> Result<PathBuf, SandboxError> {
+    let cmd = match env.resolution {
+        ExecResolution::ParentPath => env.cmd.clone(),
+        _ => env.resolution.resolve(&env.cmd)?,
+    };
+    // Must be a real path, not a relative location.
+    get_full_path_name(&cmd).map_err(|e| SandboxError::resolution(&env.cmd, e))
 }
 
 impl WindowsChild {
-    fn new(proc: jail::ProcessInfo, fds: WinFdSet) -> Self {
+    fn new(
+        proc: jail::ProcessInfo,
+        fds: WinFdSet,
+        channels: HashMap<String, u32>,
+        output: OutputGuards,
+        kill_on_drop: bool,
+        observer: Option<Arc<dyn LifecycleObserver>>,
+        report: LaunchReport,
+    ) -> Self {
         let mut others = HashMap::new();
         for fd in fds.others {
             others.insert(fd.fd(), fd);
@@ -86,33 +237,56 @@ impl WindowsChild {
             stdout: Some(fds.stdout),
             stderr: Some(fds.stderr),
             others,
+            channels,
+            output,
+            kill_on_drop,
+            observer,
+            report,
         }
     }
 
     pub(crate) fn state(&self) -> ProcessState {
         self.state.clone()
     }
+
+    /// The observer to tell how the child ended.
+    pub(crate) fn observer(&self) -> Option<Arc<dyn LifecycleObserver>> {
+        self.observer.clone()
+    }
+}
+
+impl Drop for WindowsChild {
+    fn drop(&mut self) {
+        if self.kill_on_drop {
+            // Closing the job also ends the process, but without waiting for it to exit.
+            if let Err(e) = self.state.terminate(SANDBOX_KILL_CODE) {
+                logging::warning!("could not stop child on drop: {}", e);
+                return;
+            }
+            let _ = self.state.wait(None);
+        }
+    }
 }
 
 impl Child for WindowsChild {
     fn terminate(&self) -> Result<(), std::io::Error> {
-        self.state.terminate(255)
+        self.state.terminate(SANDBOX_KILL_CODE)
     }
 
-    fn take_stream_from_child(&mut self, fd: u32) -> Option<Box<dyn std::io::Read>> {
-        match fd {
+    fn take_stream_from_child(&mut self, fd: u32) -> Option<ChildReader> {
+        let (stream, raw) = match fd {
             0 => None, // stdin is a parent writer, not a reader.
             1 => match self.stdout.take() {
                 None => None,
                 Some(s) => match s {
-                    StdIoFd::None => None,
+                    StdIoFd::Null(_) | StdIoFd::File(_) => None,
                     StdIoFd::Pipe(mut v) => v.as_reader(),
                 },
             },
             2 => match self.stderr.take() {
                 None => None,
                 Some(s) => match s {
-                    StdIoFd::None => None,
+                    StdIoFd::Null(_) | StdIoFd::File(_) => None,
                     StdIoFd::Pipe(mut v) => v.as_reader(),
                 },
             },
@@ -120,15 +294,21 @@ impl Child for WindowsChild {
                 None => None,
                 Some(mut v) => v.as_reader(),
             },
-        }
+        }?;
+        let state = self.state.clone();
+        let terminate = Box::new(move || state.terminate(SANDBOX_KILL_CODE));
+        Some(ChildReader::new(
+            self.output.wrap(fd, stream, terminate),
+            raw,
+        ))
     }
 
-    fn take_stream_to_child(&mut self, fd: u32) -> Option<Box<dyn std::io::Write>> {
+    fn take_stream_to_child(&mut self, fd: u32) -> Option<ChildWriter> {
         match fd {
             0 => match self.stdin.take() {
                 None => None,
                 Some(s) => match s {
-                    StdIoFd::None => None,
+                    StdIoFd::Null(_) | StdIoFd::File(_) => None,
                     StdIoFd::Pipe(mut v) => v.as_writer(),
                 },
             },
@@ -141,86 +321,195 @@ impl Child for WindowsChild {
         }
     }
 
-    fn exit_status(&self) -> ExitCode {
-        match self.state.exit_code() {
-            Ok(v) => v,
-            Err(_) => ExitCode::OsError(OsTermination {
-                message: "Failed to get exit code".to_string(),
-                code: -1,
-                subcode: None,
-            }),
-        }
+    fn channel_fd(&self, name: &str) -> Option<u32> {
+        self.channels.get(name).copied()
+    }
+
+    fn exit_status(&self) -> Result<Option<ExitStatus>, std::io::Error> {
+        self.state.exit_status()
+    }
+
+    fn id(&self) -> u32 {
+        self.state.process_id()
+    }
+
+    fn process_handle(&self) -> Option<std::os::windows::io::BorrowedHandle<'_>> {
+        // The state keeps the handle open until the child is dropped.
+        let handle = self.state.process_handle();
+        Some(unsafe { std::os::windows::io::BorrowedHandle::borrow_raw(handle.0) })
+    }
+
+    fn wait(&self) -> Result<ExitStatus, std::io::Error> {
+        self.state
+            .wait(None)?
+            .ok_or_else(|| std::io::Error::other("process still running after the wait"))
+    }
+
+    fn wait_timeout(&self, timeout: Duration) -> Result<Option<ExitStatus>, std::io::Error> {
+        self.state.wait(Some(timeout))
+    }
+
+    fn launch_report(&self) -> Option<LaunchReport> {
+        Some(self.report.clone())
+    }
+
+    fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.state.resource_usage()
+    }
+
+    fn denials(&self) -> Vec<SandboxDenial> {
+        self.state.denials()
     }
 }
 
+fn terminal_unsupported() -> SandboxError {
+    SandboxError::JailSetup("windows does not support pseudo-terminal fds".to_string())
+}
+
+/// Keeps the Windows error code, as an I/O error.
+fn fd_setup_error(fd: Option<u32>) -> impl FnOnce(windows::core::Error) -> SandboxError {
+    move |e| SandboxError::fd_setup(fd, e)
+}
+
 fn create_fds(src: FdSet) -> Result<(WinFdSet, Vec<HANDLE>, OsString), SandboxError> {
     let mut stdin = StdIo::None;
     let mut stdout = StdIo::None;
     let mut stderr = StdIo::None;
     let mut others = vec![];
+    let mut merges = vec![];
+    let io_timeout = src.io_timeout();
+    let nonblocking = src.nonblocking();
 
     for fd in src.modes() {
         match fd.fd {
             0 => {
                 stdin = match fd.mode {
-                    crate::FdMode::FromChild => {
+                    crate::FdMode::FromChild
+                    | crate::FdMode::ToFile(_)
+                    | crate::FdMode::AppendFile(_) => {
                         return Err(SandboxError::JailSetup(
                             "stdio marked as read from child".to_string(),
                         ));
                     }
-                    crate::FdMode::Null => StdIo::None,
+                    crate::FdMode::Null | crate::FdMode::NullDevice => StdIo::None,
                     crate::FdMode::KeepInChild => StdIo::PassThrough,
                     crate::FdMode::ToChild => StdIo::Pipe,
+                    crate::FdMode::FromFile(_) | crate::FdMode::Inherit(_) => {
+                        StdIo::File(open_redirect(fd.fd, &fd.mode)?)
+                    }
+                    crate::FdMode::MergeInto(_) => {
+                        return Err(SandboxError::JailSetup(
+                            "stdin cannot merge into another fd".to_string(),
+                        ));
+                    }
+                    crate::FdMode::Terminal => return Err(terminal_unsupported()),
                 };
             }
             1 => {
                 stdout = match fd.mode {
                     crate::FdMode::FromChild => StdIo::Pipe,
-                    crate::FdMode::Null => StdIo::None,
+                    crate::FdMode::MergeInto(target) => {
+                        // Stays on NUL until the merge replaces it.
+                        merges.push((fd.fd, target));
+                        StdIo::None
+                    }
+                    crate::FdMode::Null | crate::FdMode::NullDevice => StdIo::None,
                     crate::FdMode::KeepInChild => StdIo::PassThrough,
-                    crate::FdMode::ToChild => {
+                    crate::FdMode::ToFile(_)
+                    | crate::FdMode::AppendFile(_)
+                    | crate::FdMode::Inherit(_) => StdIo::File(open_redirect(fd.fd, &fd.mode)?),
+                    crate::FdMode::ToChild | crate::FdMode::FromFile(_) => {
                         return Err(SandboxError::JailSetup(
                             "stdout marked as write to child".to_string(),
                         ));
                     }
+                    crate::FdMode::Terminal => return Err(terminal_unsupported()),
                 }
             }
             2 => {
                 stderr = match fd.mode {
                     crate::FdMode::FromChild => StdIo::Pipe,
-                    crate::FdMode::Null => StdIo::None,
+                    crate::FdMode::MergeInto(target) => {
+                        // Stays on NUL until the merge replaces it.
+                        merges.push((fd.fd, target));
+                        StdIo::None
+                    }
+                    crate::FdMode::Null | crate::FdMode::NullDevice => StdIo::None,
                     crate::FdMode::KeepInChild => StdIo::PassThrough,
-                    crate::FdMode::ToChild => {
+                    crate::FdMode::ToFile(_)
+                    | crate::FdMode::AppendFile(_)
+                    | crate::FdMode::Inherit(_) => StdIo::File(open_redirect(fd.fd, &fd.mode)?),
+                    crate::FdMode::ToChild | crate::FdMode::FromFile(_) => {
                         return Err(SandboxError::JailSetup(
                             "stdout marked as write to child".to_string(),
                         ));
                     }
+                    crate::FdMode::Terminal => return Err(terminal_unsupported()),
                 }
             }
             _ => match fd.mode {
                 crate::FdMode::Null => (),
+                crate::FdMode::MergeInto(target) => merges.push((fd.fd, target)),
+                crate::FdMode::NullDevice => {
+                    others.push(WinFd::null(fd.fd).map_err(fd_setup_error(Some(fd.fd)))?);
+                }
                 crate::FdMode::KeepInChild => {
                     return Err(SandboxError::JailSetup(
                         "windows cannot pass-through arbitrary handles".to_string(),
                     ));
                 }
+                crate::FdMode::Terminal => return Err(terminal_unsupported()),
                 crate::FdMode::ToChild => {
-                    others.push(WinFd::new(fd.fd, StreamDirection::ToChild).map_err(|e| {
-                        SandboxError::JailSetup(format!("problem setting up fd: {:?}", e))
-                    })?);
+                    others.push(
+                        WinFd::new_overlapped(fd.fd, StreamDirection::ToChild, io_timeout)
+                            .map_err(fd_setup_error(Some(fd.fd)))?,
+                    );
                 }
                 crate::FdMode::FromChild => {
-                    others.push(WinFd::new(fd.fd, StreamDirection::FromChild).map_err(|e| {
-                        SandboxError::JailSetup(format!("problem setting up fd: {:?}", e))
-                    })?);
+                    others.push(
+                        WinFd::new_overlapped(fd.fd, StreamDirection::FromChild, io_timeout)
+                            .map_err(fd_setup_error(Some(fd.fd)))?,
+                    );
+                }
+                crate::FdMode::FromFile(_)
+                | crate::FdMode::ToFile(_)
+                | crate::FdMode::AppendFile(_)
+                | crate::FdMode::Inherit(_) => {
+                    let direction = match fd.mode {
+                        crate::FdMode::FromFile(_) => StreamDirection::ToChild,
+                        _ => StreamDirection::FromChild,
+                    };
+                    others.push(
+                        WinFd::from_file(fd.fd, direction, open_redirect(fd.fd, &fd.mode)?)
+                            .map_err(fd_setup_error(Some(fd.fd)))?,
+                    );
                 }
             },
         };
     }
 
+    let mut set = WinFdSet::new(
+        StdIoSet {
+            stdin,
+            stdout,
+            stderr,
+            nonblocking,
+        },
+        others,
+    )
+    .map_err(fd_setup_error(None))?;
+    if nonblocking {
+        for fd in set.others.iter_mut() {
+            fd.set_nonblocking();
+        }
+    }
+    for (fd, target) in merges {
+        set.merge(fd, target).map_err(fd_setup_error(Some(fd)))?;
+    }
+
     let mut handles = vec![];
     let mut env_handles = OsString::new();
-    for fd in &others {
+    for fd in &set.others {
         match fd.child() {
             None => (),
             Some(v) => {
@@ -234,35 +523,109 @@ fn create_fds(src: FdSet) -> Result<(WinFdSet, Vec<HANDLE>, OsString), SandboxEr
             }
         }
     }
-    Ok((
-        WinFdSet::new(
-            StdIoSet {
-                stdin,
-                stdout,
-                stderr,
-            },
-            others,
-        )
-        .map_err(|e| SandboxError::JailSetup(format!("problem setting up fd: {:?}", e)))?,
-        handles,
-        env_handles,
-    ))
+    Ok((set, handles, env_handles))
+}
+
+/// Open the file for a file redirection mode.
+fn open_redirect(fd: u32, mode: &crate::FdMode) -> Result<std::fs::File, SandboxError> {
+    let file = mode
+        .open_file()
+        .map_err(|e| SandboxError::fd_setup(Some(fd), e))?;
+    file.ok_or_else(|| {
+        SandboxError::JailSetup(format!("fd mode {:?} is not a file redirection", mode))
+    })
 }
 
-// Get the canonical Win32 path, not the extended-length path that canonicalize() generates.
+/// Get the absolute Win32 path, keeping UNC (`\\server\share`) paths intact.  Unlike
+/// `canonicalize()`, this returns the canonical Win32 form rather than the `\\?\`
+/// extended-length form, unless the path is too long for the Win32 form.
 fn get_full_path_name(path: &PathBuf) -> Result<PathBuf, std::io::Error> {
-    // The "correct" way is to use GetFullPathNameW.  That's messy.
-    // Future people may do it the right way.
-    // For now, strip off the \\?\ that the extended-length path adds.
-    let path = path.canonicalize()?;
-    let path = path.as_os_str().as_encoded_bytes();
-    if &path[0..4] == br"\\?\" as &[u8] {
-        Ok(PathBuf::from(unsafe {
-            OsStr::from_encoded_bytes_unchecked(&path[4..])
-        }))
-    } else {
-        Ok(PathBuf::from(unsafe {
-            OsStr::from_encoded_bytes_unchecked(path)
-        }))
+    let name = as_c_str_w(path.as_os_str());
+    let mut buffer: Vec<u16> = vec![0; MAX_PATH as usize];
+    loop {
+        // Returns the length without the terminating null on success, or the required
+        // buffer size including the null if the buffer is too small.
+        let len = unsafe {
+            FileSystem::GetFullPathNameW(PCWSTR(name.as_ptr()), Some(buffer.as_mut_slice()), None)
+        } as usize;
+        if len == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if len < buffer.len() {
+            buffer.truncate(len);
+            break;
+        }
+        buffer.resize(len, 0);
+    }
+    let full = PathBuf::from(OsString::from_wide(&extended_length(buffer)));
+    // Match the canonicalize() behavior of reporting missing files up front.
+    std::fs::metadata(&full)?;
+    Ok(full)
+}
+
+/// Add the `\\?\` prefix, or `\\?\UNC\` for a UNC path, to an absolute path that
+/// doesn't fit in MAX_PATH, so the Win32 calls don't truncate it.  Shorter paths, and
+/// paths that already use a device prefix, are returned as they are.
+fn extended_length(path: Vec<u16>) -> Vec<u16> {
+    const SEP: u16 = b'\\' as u16;
+    if path.len() < MAX_PATH as usize {
+        return path;
+    }
+    let (prefix, rest) = match path.as_slice() {
+        // Already `\\?\` or `\\.\`.
+        [SEP, SEP, mark, SEP, ..] if *mark == b'?' as u16 || *mark == b'.' as u16 => {
+            return path;
+        }
+        // `\\server\share` becomes `\\?\UNC\server\share`.
+        [SEP, SEP, ..] => (r"\\?\UNC", &path[1..]),
+        [_, colon, SEP, ..] if *colon == b':' as u16 => (r"\\?\", &path[..]),
+        _ => return path,
+    };
+    let mut ret: Vec<u16> = prefix.encode_utf16().collect();
+    ret.extend_from_slice(rest);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extended_length, get_full_path_name};
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn short_paths_unchanged() {
+        for path in [r"C:\Windows", r"\\server\share\file", r"\\?\C:\Windows"] {
+            assert_eq!(extended_length(wide(path)), wide(path));
+        }
+    }
+
+    #[test]
+    fn long_paths_prefixed() {
+        let tail = "d\\".repeat(150);
+        assert_eq!(
+            extended_length(wide(&format!(r"C:\{tail}"))),
+            wide(&format!(r"\\?\C:\{tail}"))
+        );
+        assert_eq!(
+            extended_length(wide(&format!(r"\\server\share\{tail}"))),
+            wide(&format!(r"\\?\UNC\server\share\{tail}"))
+        );
+        let verbatim = format!(r"\\?\C:\{tail}");
+        assert_eq!(extended_length(wide(&verbatim)), wide(&verbatim));
+    }
+
+    #[test]
+    fn long_directory_resolves() {
+        let dir = tempfile::tempdir().expect("temp dir failed");
+        let mut long = dir.path().to_path_buf();
+        while long.as_os_str().len() <= 300 {
+            long.push("a-long-directory-name");
+        }
+        std::fs::create_dir_all(&long).expect("create failed");
+        let full = get_full_path_name(&long).expect("long path should resolve");
+        assert!(full.as_os_str().to_string_lossy().starts_with(r"\\?\"));
+        assert!(full.is_dir());
     }
 }
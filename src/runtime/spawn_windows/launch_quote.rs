@@ -38,10 +38,8 @@ pub fn encode_env_strings(env: &[(OsString, OsString)]) -> Result<Vec<u16>, Sand
         a_key.cmp(&b_key)
     });
 
-    // println!("DEBUG Environment for child process:");
     let mut block: Vec<u16> = Vec::new();
     for (k, v) in pairs {
-        // println!("  {:?}={:?}", k, v);
         let k = enforce_no_zero(k)?;
         let v = enforce_no_zero(v)?;
         block.extend(k.encode_wide());
@@ -1,18 +1,23 @@
 // SPDX-License-Identifier: MIT
 
-use crate::runtime::spawn::{ExitCode, OsTermination};
+use crate::logging;
+use crate::runtime::spawn::{ExitStatus, ResourceUsage, SandboxDenial};
 
 use super::jail::ProcessInfo;
 use std::{
+    os::windows::io::{AsRawHandle as _, BorrowedHandle, OwnedHandle},
     ptr::null,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 use windows::{
     Win32::{
-        Foundation::{self, CloseHandle, HANDLE},
+        Foundation::{self, CloseHandle, HANDLE, WAIT_OBJECT_0, WAIT_TIMEOUT},
         System::{
-            Diagnostics, JobObjects::TerminateJobObject, LibraryLoader,
-            Threading::GetExitCodeProcess,
+            Diagnostics,
+            JobObjects::{self, TerminateJobObject},
+            LibraryLoader,
+            Threading::{GetExitCodeProcess, INFINITE, WaitForSingleObject},
         },
     },
     core,
@@ -38,6 +43,16 @@ impl ProcessState {
         }
     }
 
+    /// The OS process id.
+    pub fn process_id(&self) -> u32 {
+        self.info.process_id
+    }
+
+    /// The process handle, owned by this state.
+    pub fn process_handle(&self) -> HANDLE {
+        self.info.process
+    }
+
     /// Terminate the process.
     /// This will only send the termination once.
     /// Need to investigate whether situations may arise where it may be necessary to run this
@@ -48,6 +63,7 @@ impl ProcessState {
             .lock()
             .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "lock poisoned"))?;
         if !(*guard).terminated {
+            logging::debug!("terminating job with exit code {}", exit_code);
             self.inner_terminate(exit_code)
                 .map_err(|e| std::io::Error::new(std::io::ErrorKind::Interrupted, e))?;
             (*guard).terminated = true;
@@ -64,123 +80,213 @@ impl ProcessState {
         }
     }
 
-    /// Get the exit code for the process, or None if it hasn't exited yet.
-    pub fn exit_code(&self) -> Result<ExitCode, std::io::Error> {
-        let mut guard = self
+    /// Another reference to the process, which doesn't end it when dropped.
+    pub fn process_ref(&self) -> Result<ProcessRef, std::io::Error> {
+        let dup = |h: HANDLE| unsafe { BorrowedHandle::borrow_raw(h.0) }.try_clone_to_owned();
+        Ok(ProcessRef {
+            process: dup(self.info.process)?,
+            job: dup(self.info.job)?,
+        })
+    }
+
+    /// The accesses denied the process so far; empty unless auditing was requested.
+    pub fn denials(&self) -> Vec<SandboxDenial> {
+        match self.info.audit.as_ref() {
+            Some(audit) => audit.denials(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get how the process ended, or None if it is still running.
+    pub fn exit_status(&self) -> Result<Option<ExitStatus>, std::io::Error> {
+        let terminated = self
             .mutable
             .lock()
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "lock poisoned"))?;
-        match &(*guard).exit_code {
-            Some(c) => Ok(c.clone()),
-            None => {
-                let code = self
-                    .inner_exit_code()
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Interrupted, e))?;
-                match &code {
-                    // Don't capture the Running state.
-                    ExitCode::Running => (),
-                    _ => {
-                        (*guard).exit_code = Some(code.clone());
-                    }
-                }
-                Ok(code)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "lock poisoned"))?
+            .terminated;
+        Ok(self.raw_exit_code()?.map(|code| {
+            if terminated && code == SANDBOX_KILL_CODE {
+                ExitStatus::SandboxKilled
+            } else if is_ntstatus_failure(code) {
+                // Keep the same bits, so the code reads as the NTSTATUS value in hex.
+                ExitStatus::Signaled(code as i32)
+            } else {
+                ExitStatus::Code(code as i32)
             }
-        }
+        }))
     }
 
-    fn inner_exit_code(&self) -> core::Result<ExitCode> {
+    /// The job's resource accounting, or None while the process runs or if the query
+    /// fails.
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        if self.raw_exit_code().ok()?.is_none() {
+            return None;
+        }
+        let mut basic = JobObjects::JOBOBJECT_BASIC_ACCOUNTING_INFORMATION::default();
+        let mut ext = JobObjects::JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
         unsafe {
-            let mut code = 0u32;
-            GetExitCodeProcess(self.info.process, &mut code)?;
-            Ok(Self::from_code(code))
+            JobObjects::QueryInformationJobObject(
+                Some(self.info.job),
+                JobObjects::JobObjectBasicAccountingInformation,
+                &mut basic as *mut _ as *mut _,
+                std::mem::size_of_val(&basic) as u32,
+                None,
+            )
+            .ok()?;
+            JobObjects::QueryInformationJobObject(
+                Some(self.info.job),
+                JobObjects::JobObjectExtendedLimitInformation,
+                &mut ext as *mut _ as *mut _,
+                std::mem::size_of_val(&ext) as u32,
+                None,
+            )
+            .ok()?;
         }
+        // The job reports times in 100 nanosecond units.
+        let duration = |t: i64| Duration::from_nanos(t.max(0) as u64 * 100);
+        Some(ResourceUsage {
+            user_cpu: duration(basic.TotalUserTime),
+            sys_cpu: duration(basic.TotalKernelTime),
+            max_rss: ext.PeakProcessMemoryUsed as u64,
+            page_faults: basic.TotalPageFaultCount as u64,
+        })
     }
 
-    fn from_code(code: u32) -> ExitCode {
-        let mut buffer: Vec<u16> = vec![0; FORMAT_MESSAGE_BUFFER_SIZE];
-        let m_null: *mut ::core::ffi::c_void = null::<()>() as *mut ::core::ffi::c_void;
-
-        if code > i32::MAX as u32 {
-            // Try ntdll.dll, as that stores these larger exit codes.
-            unsafe {
-                // Try ntdll.dll first (direct NTSTATUS lookup)
-                let module: Foundation::HMODULE = match LibraryLoader::GetModuleHandleW(
-                    core::PCWSTR(windows::core::w!("ntdll.dll").as_ptr()),
-                ) {
-                    Ok(m) => m,
-                    Err(_) => Foundation::HMODULE(m_null),
-                };
-
-                if module.0 != m_null {
-                    let len = Diagnostics::Debug::FormatMessageW(
-                        Diagnostics::Debug::FORMAT_MESSAGE_FROM_HMODULE
-                            | Diagnostics::Debug::FORMAT_MESSAGE_IGNORE_INSERTS,
-                        Some(module.0 as *const ::core::ffi::c_void),
-                        code,
-                        0,
-                        core::PWSTR(buffer.as_mut_ptr()),
-                        FORMAT_MESSAGE_BUFFER_SIZE as u32,
-                        None,
-                    );
-                    if len > 0 {
-                        return ExitCode::OsError(OsTermination {
-                            message: String::from_utf16_lossy(&buffer[..len as usize])
-                                .trim()
-                                .to_string(),
-                            code: code as i64,
-                            subcode: None,
-                        });
-                    }
-                }
-            }
-            return ExitCode::OsError(OsTermination {
-                message: format!("Process failed with OS error code 0x{:X}", code),
-                code: code as i64,
-                subcode: None,
-            });
+    /// Stop the process if it's still running, and get how it ended.
+    pub fn kill(&self) -> Result<ExitStatus, std::io::Error> {
+        if self.raw_exit_code()?.is_none() {
+            self.terminate(SANDBOX_KILL_CODE)?;
+            self.wait(None)?;
         }
+        self.exit_status()?
+            .ok_or_else(|| std::io::Error::other("process still running after termination"))
+    }
 
-        // Everything else deals with a 32 bit exit code.
-
-        let icode = code as i32;
-        if icode == Foundation::STILL_ACTIVE.0 {
-            return ExitCode::Running;
+    /// The unsigned value from GetExitCodeProcess, or None while the process runs.
+    fn raw_exit_code(&self) -> Result<Option<u32>, std::io::Error> {
+        let mut guard = self
+            .mutable
+            .lock()
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "lock poisoned"))?;
+        if let Some(code) = (*guard).exit_code {
+            return Ok(Some(code));
         }
-        if icode < Foundation::STILL_ACTIVE.0 {
-            return ExitCode::Exited(icode);
+        let mut code = 0u32;
+        unsafe { GetExitCodeProcess(self.info.process, &mut code) }
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Interrupted, e))?;
+        // A process that really exits with 259 looks like it's still running; the handle
+        // is only signaled once it has exited, so check it without waiting.
+        if code == Foundation::STILL_ACTIVE.0 as u32
+            && unsafe { WaitForSingleObject(self.info.process, 0) } != WAIT_OBJECT_0
+        {
+            return Ok(None);
         }
+        if is_ntstatus_failure(code) {
+            logging::debug!("process ended with {}", ntstatus_message(code));
+        }
+        (*guard).exit_code = Some(code);
+        Ok(Some(code))
+    }
 
-        // Try NTSTATUS as a Win32 error
-        let win32_err = unsafe { Foundation::RtlNtStatusToDosError(Foundation::NTSTATUS(icode)) };
-
-        let len = unsafe {
-            Diagnostics::Debug::FormatMessageW(
-                Diagnostics::Debug::FORMAT_MESSAGE_FROM_SYSTEM
-                    | Diagnostics::Debug::FORMAT_MESSAGE_IGNORE_INSERTS,
-                None,
-                win32_err,
-                0,
-                core::PWSTR(buffer.as_mut_ptr()),
-                FORMAT_MESSAGE_BUFFER_SIZE as u32,
-                None,
-            )
+    /// Wait for the process to exit, up to the timeout.  Returns None if the timeout passed
+    /// first.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<Option<ExitStatus>, std::io::Error> {
+        let wait_ms = match timeout {
+            // INFINITE is u32::MAX, so cap just below it.
+            Some(t) => t.as_millis().min((INFINITE - 1) as u128) as u32,
+            None => INFINITE,
         };
-        if len > 0 {
-            return ExitCode::OsError(OsTermination {
-                message: String::from_utf16_lossy(&buffer[..len as usize])
-                    .trim()
-                    .to_string(),
-                code: icode as i64,
-                subcode: Some(win32_err as i64),
-            });
+        // The lock isn't held while waiting, so another thread can still terminate the
+        // process.
+        let waited = unsafe { WaitForSingleObject(self.info.process, wait_ms) };
+        if waited == WAIT_TIMEOUT {
+            return Ok(None);
         }
+        if waited != WAIT_OBJECT_0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        self.exit_status()
+    }
+}
+
+/// Exit code used when the sandbox kills the process.
+pub const SANDBOX_KILL_CODE: u32 = 255;
+
+/// Is the exit code an NTSTATUS failure, rather than a code the program chose?
+///
+/// NTSTATUS values set the high bit for warnings (0x8xxxxxxx) and errors (0xCxxxxxxx), so
+/// crash codes such as 0xC0000005 (STATUS_ACCESS_VIOLATION) or 0x80000003
+/// (STATUS_BREAKPOINT) are never confused with an exit code the program chose, which
+/// would show up as a negative number when cast to an `i32`.
+fn is_ntstatus_failure(code: u32) -> bool {
+    code & 0x8000_0000 != 0
+}
+
+/// Describe the NTSTATUS failure code, for the log.
+fn ntstatus_message(code: u32) -> String {
+    let win32_err = unsafe { Foundation::RtlNtStatusToDosError(Foundation::NTSTATUS(code as i32)) };
+    // ERROR_MR_MID_NOT_FOUND means there is no Win32 equivalent.
+    let has_win32 = !matches!(win32_err, 0 | 317);
+    ntdll_message(code)
+        .or_else(|| has_win32.then(|| system_message(win32_err)).flatten())
+        .unwrap_or_else(|| format!("Process failed with NTSTATUS 0x{:08X}", code))
+}
+
+/// Look up the NTSTATUS message text, which ntdll.dll stores.
+fn ntdll_message(code: u32) -> Option<String> {
+    let m_null: *mut ::core::ffi::c_void = null::<()>() as *mut ::core::ffi::c_void;
+    let module: Foundation::HMODULE = unsafe {
+        LibraryLoader::GetModuleHandleW(core::PCWSTR(windows::core::w!("ntdll.dll").as_ptr()))
+    }
+    .ok()?;
+    if module.0 == m_null {
+        return None;
+    }
+    let mut buffer: Vec<u16> = vec![0; FORMAT_MESSAGE_BUFFER_SIZE];
+    let len = unsafe {
+        Diagnostics::Debug::FormatMessageW(
+            Diagnostics::Debug::FORMAT_MESSAGE_FROM_HMODULE
+                | Diagnostics::Debug::FORMAT_MESSAGE_IGNORE_INSERTS,
+            Some(module.0 as *const ::core::ffi::c_void),
+            code,
+            0,
+            core::PWSTR(buffer.as_mut_ptr()),
+            FORMAT_MESSAGE_BUFFER_SIZE as u32,
+            None,
+        )
+    };
+    trimmed_message(&buffer, len)
+}
 
-        // If all else fails, return the raw code.
-        return ExitCode::OsError(OsTermination {
-            message: format!("Unknown error: 0x{:08X}", icode),
-            code: icode as i64,
-            subcode: Some(win32_err as i64),
-        });
+/// Look up the Win32 error message text.
+fn system_message(win32_err: u32) -> Option<String> {
+    let mut buffer: Vec<u16> = vec![0; FORMAT_MESSAGE_BUFFER_SIZE];
+    let len = unsafe {
+        Diagnostics::Debug::FormatMessageW(
+            Diagnostics::Debug::FORMAT_MESSAGE_FROM_SYSTEM
+                | Diagnostics::Debug::FORMAT_MESSAGE_IGNORE_INSERTS,
+            None,
+            win32_err,
+            0,
+            core::PWSTR(buffer.as_mut_ptr()),
+            FORMAT_MESSAGE_BUFFER_SIZE as u32,
+            None,
+        )
+    };
+    trimmed_message(&buffer, len)
+}
+
+fn trimmed_message(buffer: &[u16], len: u32) -> Option<String> {
+    if len == 0 {
+        return None;
+    }
+    let message = String::from_utf16_lossy(&buffer[..(len as usize).min(buffer.len())])
+        .trim()
+        .to_string();
+    if message.is_empty() {
+        None
+    } else {
+        Some(message)
     }
 }
 
@@ -192,7 +298,7 @@ impl Drop for ProcessState {
         // Note: ignoring errors inside the drop.
 
         // Ensure it's been killed.
-        let _ = self.terminate(255);
+        let _ = self.terminate(SANDBOX_KILL_CODE);
 
         // Close off handles.
         unsafe {
@@ -212,7 +318,27 @@ impl Drop for ProcessState {
     }
 }
 
+/// Duplicates of the process and job handles, to check on and end the process without
+/// the `ProcessState` and its drop behavior.
+pub struct ProcessRef {
+    process: OwnedHandle,
+    job: OwnedHandle,
+}
+
+impl ProcessRef {
+    /// Is the process still running?
+    pub fn is_running(&self) -> bool {
+        unsafe { WaitForSingleObject(HANDLE(self.process.as_raw_handle()), 0) == WAIT_TIMEOUT }
+    }
+
+    /// Terminate everything in the process's job.
+    pub fn terminate(&self) -> Result<(), std::io::Error> {
+        unsafe { TerminateJobObject(HANDLE(self.job.as_raw_handle()), SANDBOX_KILL_CODE) }
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Interrupted, e))
+    }
+}
+
 struct MutableProcessState {
     terminated: bool,
-    exit_code: Option<ExitCode>,
+    exit_code: Option<u32>,
 }
@@ -0,0 +1,245 @@
+// SPDX-License-Identifier: MIT
+
+//! Named pipes opened for overlapped I/O.
+//!
+//! Anonymous pipes only support blocking reads and writes, so a child that stops
+//! talking wedges the handler.  The parent's end of a named pipe opened with
+//! `FILE_FLAG_OVERLAPPED` can instead wait on an event with a timeout, and cancel the
+//! pending operation when the timeout expires.  The child's end stays a plain,
+//! synchronous handle.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use windows::Win32::{
+    Foundation::{
+        CloseHandle, ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_NO_DATA, ERROR_OPERATION_ABORTED,
+        FALSE, GENERIC_READ, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0,
+        WAIT_TIMEOUT, WIN32_ERROR,
+    },
+    Security,
+    Storage::FileSystem,
+    System::{IO, Pipes, Threading},
+};
+
+use super::conv::as_c_str_w;
+
+static PIPE_SEQ: AtomicU64 = AtomicU64::new(1);
+
+/// Create a connected named pipe.  Returns the parent's overlapped end and the child's
+/// synchronous end.  Neither handle is inheritable.
+///
+/// `parent_reads` is true when the data flows from the child to the parent.
+pub fn create_pipe(
+    parent_reads: bool,
+    timeout: Option<Duration>,
+) -> windows::core::Result<(OverlappedPipe, HANDLE)> {
+    // The random part keeps other processes from guessing the name and squatting on it;
+    // FILE_FLAG_FIRST_PIPE_INSTANCE makes the creation fail if one did anyway.
+    let suffix = super::rand::random_hex_str().map_err(|_| windows::core::Error::from_thread())?;
+    let name = format!(
+        "\\\\.\\pipe\\gracklezero-{}-{}-{}",
+        std::process::id(),
+        PIPE_SEQ.fetch_add(1, Ordering::Relaxed),
+        suffix
+    );
+    let name = as_c_str_w(std::ffi::OsStr::new(&name));
+
+    let sa = Security::SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<Security::SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: std::ptr::null_mut(),
+        bInheritHandle: FALSE,
+    };
+    let (server_access, client_access) = if parent_reads {
+        (FileSystem::PIPE_ACCESS_INBOUND, GENERIC_WRITE)
+    } else {
+        (FileSystem::PIPE_ACCESS_OUTBOUND, GENERIC_READ)
+    };
+
+    let server = unsafe {
+        Pipes::CreateNamedPipeW(
+            windows::core::PCWSTR(name.as_ptr()),
+            server_access
+                | FileSystem::FILE_FLAG_OVERLAPPED
+                | FileSystem::FILE_FLAG_FIRST_PIPE_INSTANCE,
+            Pipes::PIPE_TYPE_BYTE | Pipes::PIPE_WAIT | Pipes::PIPE_REJECT_REMOTE_CLIENTS,
+            1, // only the child's end may connect.
+            0, // default output buffer size
+            0, // default input buffer size
+            0, // default timeout
+            Some(&sa),
+        )
+    };
+    if server.is_invalid() || server == INVALID_HANDLE_VALUE {
+        return Err(windows::core::Error::from_thread());
+    }
+    let pipe = OverlappedPipe::new(server, timeout).inspect_err(|_| unsafe {
+        let _ = CloseHandle(server);
+    })?;
+
+    // Connecting right away means no other client can take the single instance.  If one
+    // already did, this fails with ERROR_PIPE_BUSY instead of leaking the data.
+    let client = unsafe {
+        FileSystem::CreateFileW(
+            windows::core::PCWSTR(name.as_ptr()),
+            client_access.0,
+            FileSystem::FILE_SHARE_MODE(0),
+            Some(&sa),
+            FileSystem::OPEN_EXISTING,
+            FileSystem::FILE_ATTRIBUTE_NORMAL,
+            None,
+        )?
+    };
+    Ok((pipe, client))
+}
+
+/// The parent's end of a named pipe, with optional timeouts on each read and write.
+pub struct OverlappedPipe {
+    handle: HANDLE,
+    event: HANDLE,
+    timeout: Option<Duration>,
+    // Don't wait at all; an operation that can't finish right away is cancelled.
+    nonblocking: bool,
+}
+
+// The handles are owned by this structure and only used through `&mut self`.
+unsafe impl Send for OverlappedPipe {}
+
+impl OverlappedPipe {
+    fn new(handle: HANDLE, timeout: Option<Duration>) -> windows::core::Result<Self> {
+        // Manual reset, as GetOverlappedResult expects.
+        let event =
+            unsafe { Threading::CreateEventW(None, true, false, windows::core::PCWSTR::null())? };
+        Ok(Self {
+            handle,
+            event,
+            timeout,
+            nonblocking: false,
+        })
+    }
+
+    /// Fail reads and writes that can't finish right away with `WouldBlock`.
+    pub fn set_nonblocking(&mut self) {
+        self.nonblocking = true;
+    }
+
+    /// The pipe's handle, which stays owned by this.
+    pub fn raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.handle.0
+    }
+
+    /// Wait for the pending operation to finish, or cancel it once the timeout passes.
+    fn complete(&self, overlapped: &IO::OVERLAPPED) -> std::io::Result<usize> {
+        let wait_ms = match self.timeout {
+            _ if self.nonblocking => 0,
+            // INFINITE is u32::MAX, so cap just below it.
+            Some(t) => t.as_millis().min((Threading::INFINITE - 1) as u128) as u32,
+            None => Threading::INFINITE,
+        };
+        let waited = unsafe { Threading::WaitForSingleObject(self.event, wait_ms) };
+        let mut transferred = 0u32;
+        if waited == WAIT_TIMEOUT {
+            unsafe {
+                let _ = IO::CancelIoEx(self.handle, Some(overlapped));
+            }
+            // The operation may have finished just before the cancel; keep that data.
+            return match unsafe {
+                IO::GetOverlappedResult(self.handle, overlapped, &mut transferred, true)
+            } {
+                Ok(()) => Ok(transferred as usize),
+                Err(e)
+                    if self.nonblocking
+                        && WIN32_ERROR::from_error(&e) == Some(ERROR_OPERATION_ABORTED) =>
+                {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::WouldBlock,
+                        "pipe operation would block",
+                    ))
+                }
+                Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_OPERATION_ABORTED) => Err(
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, "pipe operation timed out"),
+                ),
+                Err(e) => Err(to_io_error(e)),
+            };
+        }
+        if waited != WAIT_OBJECT_0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        unsafe { IO::GetOverlappedResult(self.handle, overlapped, &mut transferred, false) }
+            .map_err(to_io_error)?;
+        Ok(transferred as usize)
+    }
+}
+
+impl std::io::Read for OverlappedPipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize);
+        let mut overlapped = IO::OVERLAPPED {
+            hEvent: self.event,
+            ..Default::default()
+        };
+        let started = unsafe {
+            FileSystem::ReadFile(
+                self.handle,
+                Some(&mut buf[..len]),
+                None,
+                Some(&mut overlapped),
+            )
+        };
+        let result = match started {
+            Ok(()) => self.complete(&overlapped),
+            Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_IO_PENDING) => {
+                self.complete(&overlapped)
+            }
+            Err(e) => Err(to_io_error(e)),
+        };
+        match result {
+            // The child closed its end.
+            Err(e) if e.raw_os_error() == Some(ERROR_BROKEN_PIPE.0 as i32) => Ok(0),
+            r => r,
+        }
+    }
+}
+
+impl std::io::Write for OverlappedPipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize);
+        let mut overlapped = IO::OVERLAPPED {
+            hEvent: self.event,
+            ..Default::default()
+        };
+        let started = unsafe {
+            FileSystem::WriteFile(self.handle, Some(&buf[..len]), None, Some(&mut overlapped))
+        };
+        match started {
+            Ok(()) => self.complete(&overlapped),
+            Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_IO_PENDING) => {
+                self.complete(&overlapped)
+            }
+            Err(e) if WIN32_ERROR::from_error(&e) == Some(ERROR_NO_DATA) => Err(
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "child closed the pipe"),
+            ),
+            Err(e) => Err(to_io_error(e)),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for OverlappedPipe {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.handle);
+            let _ = CloseHandle(self.event);
+        }
+    }
+}
+
+fn to_io_error(e: windows::core::Error) -> std::io::Error {
+    match WIN32_ERROR::from_error(&e) {
+        Some(code) => std::io::Error::from_raw_os_error(code.0 as i32),
+        None => std::io::Error::other(e),
+    }
+}
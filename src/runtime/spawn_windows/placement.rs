@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: MIT
+
+//! Pin the child process to a set of processors using CPU Sets.
+//!
+//! CPU Sets are processor-group aware, so this works on machines with more
+//! than 64 logical processors.  Logical processor indices in the placement are
+//! counted across all processor groups, in group order, which matches the
+//! numbering Linux uses.
+//!
+//! Ref: https://learn.microsoft.com/en-us/windows/win32/procthread/cpu-sets
+
+use windows::Win32::{
+    Foundation::HANDLE,
+    System::{SystemInformation, Threading},
+};
+
+use crate::restrictions::CpuPlacement;
+
+use super::error::WindowsSandboxError;
+
+/// A single processor known to the CPU Set API.
+struct CpuSetEntry {
+    id: u32,
+    group: u16,
+    logical_index: u8,
+    numa_node: u8,
+}
+
+/// Apply the placement to the (suspended) child process.
+pub fn apply_cpu_placement(
+    process: HANDLE,
+    placement: &CpuPlacement,
+) -> Result<(), WindowsSandboxError> {
+    if let CpuPlacement::Any = placement {
+        return Ok(());
+    }
+    let ids = cpu_set_ids(&system_cpu_sets()?, placement)?;
+    unsafe { Threading::SetProcessDefaultCpuSets(process, Some(ids.as_slice())) }
+        .ok()
+        .map_err(WindowsSandboxError::setup)
+}
+
+/// Select the CPU Set IDs matching the placement.
+fn cpu_set_ids(
+    entries: &[CpuSetEntry],
+    placement: &CpuPlacement,
+) -> Result<Vec<u32>, WindowsSandboxError> {
+    let ids: Vec<u32> = match placement {
+        CpuPlacement::Any => entries.iter().map(|e| e.id).collect(),
+        CpuPlacement::Cpus(cpus) => {
+            let mut ids = Vec::with_capacity(cpus.len());
+            for cpu in cpus {
+                match entries.get(*cpu) {
+                    Some(e) => ids.push(e.id),
+                    None => {
+                        return Err(WindowsSandboxError::setup_message(&format!(
+                            "cpu {cpu} does not exist on this system"
+                        )));
+                    }
+                }
+            }
+            ids
+        }
+        CpuPlacement::NumaNode(node) => entries
+            .iter()
+            .filter(|e| e.numa_node as usize == *node)
+            .map(|e| e.id)
+            .collect(),
+    };
+    if ids.is_empty() {
+        return Err(WindowsSandboxError::setup_message(
+            "cpu placement does not contain any CPUs",
+        ));
+    }
+    Ok(ids)
+}
+
+/// Enumerate all the processors, ordered by processor group then logical index.
+fn system_cpu_sets() -> Result<Vec<CpuSetEntry>, WindowsSandboxError> {
+    let mut needed: u32 = 0;
+    // First call asks for the required size in bytes.
+    let _ =
+        unsafe { SystemInformation::GetSystemCpuSetInformation(None, 0, &mut needed, None, None) };
+    if needed == 0 {
+        return Err(WindowsSandboxError::setup_message(
+            "CPU set information query returned empty size",
+        ));
+    }
+
+    // Back the buffer with u64 values to keep the structure alignment.
+    let mut buf = vec![0u64; (needed as usize).div_ceil(size_of::<u64>())];
+    unsafe {
+        SystemInformation::GetSystemCpuSetInformation(
+            Some(buf.as_mut_ptr() as *mut SystemInformation::SYSTEM_CPU_SET_INFORMATION),
+            needed,
+            &mut needed,
+            None,
+            None,
+        )
+    }
+    .ok()
+    .map_err(WindowsSandboxError::setup)?;
+
+    let mut ret = Vec::new();
+    let base = buf.as_ptr() as *const u8;
+    let mut offset = 0usize;
+    while offset < needed as usize {
+        // Entries are variable sized; each reports its own size.
+        let info =
+            unsafe { &*(base.add(offset) as *const SystemInformation::SYSTEM_CPU_SET_INFORMATION) };
+        if info.Size == 0 {
+            break;
+        }
+        if info.Type == SystemInformation::CpuSetInformation {
+            let cpu = unsafe { &info.Anonymous.CpuSet };
+            ret.push(CpuSetEntry {
+                id: cpu.Id,
+                group: cpu.Group,
+                logical_index: cpu.LogicalProcessorIndex,
+                numa_node: cpu.NumaNodeIndex,
+            });
+        }
+        offset += info.Size as usize;
+    }
+    ret.sort_by_key(|e| (e.group, e.logical_index));
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<CpuSetEntry> {
+        vec![
+            CpuSetEntry {
+                id: 256,
+                group: 0,
+                logical_index: 0,
+                numa_node: 0,
+            },
+            CpuSetEntry {
+                id: 257,
+                group: 0,
+                logical_index: 1,
+                numa_node: 0,
+            },
+            CpuSetEntry {
+                id: 320,
+                group: 1,
+                logical_index: 0,
+                numa_node: 1,
+            },
+        ]
+    }
+
+    #[test]
+    fn cpus_across_groups() {
+        let ids = cpu_set_ids(&entries(), &CpuPlacement::Cpus(vec![0, 2])).unwrap();
+        assert_eq!(ids, vec![256, 320]);
+    }
+
+    #[test]
+    fn numa_node() {
+        let ids = cpu_set_ids(&entries(), &CpuPlacement::NumaNode(0)).unwrap();
+        assert_eq!(ids, vec![256, 257]);
+    }
+
+    #[test]
+    fn missing_cpu() {
+        assert!(cpu_set_ids(&entries(), &CpuPlacement::Cpus(vec![3])).is_err());
+        assert!(cpu_set_ids(&entries(), &CpuPlacement::NumaNode(4)).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: MIT
+
+//! Check what the host's Windows version supports.
+
+use super::{jail::current_windows_version, process_token::ProcessToken};
+use crate::logging;
+use crate::runtime::probe::HostCapabilities;
+
+pub(crate) fn probe() -> HostCapabilities {
+    let version = current_windows_version();
+    HostCapabilities {
+        os: "windows".to_string(),
+        os_version: version
+            .map(|v| format!("{}.{}.{}", v.major, v.minor, v.build))
+            .unwrap_or_default(),
+        landlock_abi: None,
+        seccomp: false,
+        user_namespaces: false,
+        // AppContainers arrived with Windows 8.
+        app_container: version.is_some_and(|v| v.is_at_least(6, 2, 0)),
+        privileged: is_privileged(),
+    }
+}
+
+/// An elevated Administrator may debug other processes and take ownership of files, which
+/// the restricted token can't fully take away.
+pub(crate) fn is_privileged() -> bool {
+    match ProcessToken::current_process().and_then(|t| t.is_elevated()) {
+        Ok(elevated) => elevated,
+        Err(e) => {
+            logging::warning!("could not check the process elevation: {:?}", e);
+            false
+        }
+    }
+}
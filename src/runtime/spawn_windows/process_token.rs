@@ -107,6 +107,25 @@ impl ProcessToken {
         }
     }
 
+    /// Does the token belong to an elevated Administrator?
+    pub fn is_elevated(&self) -> Result<bool, WindowsSandboxError> {
+        let token = self.token.ok_or_else(|| {
+            WindowsSandboxError::setup_message("process token handle is not available")
+        })?;
+        let mut elevation = Security::TOKEN_ELEVATION::default();
+        let mut needed: u32 = 0;
+        unsafe {
+            Security::GetTokenInformation(
+                token,
+                Security::TokenElevation,
+                Some(&mut elevation as *mut _ as *mut _),
+                std::mem::size_of::<Security::TOKEN_ELEVATION>() as u32,
+                &mut needed,
+            )?;
+        }
+        Ok(elevation.TokenIsElevated != 0)
+    }
+
     pub fn handle(&self) -> Option<HANDLE> {
         self.token
     }
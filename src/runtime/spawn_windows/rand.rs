@@ -4,11 +4,14 @@ use windows::Win32::Security::Cryptography;
 
 use crate::runtime::spawn_windows::error::WindowsSandboxError;
 
+/// Length of the random part of generated names.
+pub const RANDOM_NAME_LEN: usize = 16;
+
 const ENCODING: &[u8; 64] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ-_";
 
 /// Generate 16 characters of random alphanumeric (0-9, a-z, A-Z, _, -) values.
 pub fn random_hex_str() -> Result<String, WindowsSandboxError> {
-    let mut bytes = [0u8; 16];
+    let mut bytes = [0u8; RANDOM_NAME_LEN];
     let status = unsafe {
         Cryptography::BCryptGenRandom(
             None, // use a default algorithm
@@ -22,7 +25,7 @@ pub fn random_hex_str() -> Result<String, WindowsSandboxError> {
             status.0 as u32
         )));
     }
-    let mut suffix = String::with_capacity(16);
+    let mut suffix = String::with_capacity(RANDOM_NAME_LEN);
     for b in bytes {
         // Only use 6 bits of the random data.
         suffix.push(ENCODING[(b & 0x3f) as usize] as char);
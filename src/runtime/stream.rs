@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT
+
+//! The parent's ends of the child's streams, as taken from a `Child`.
+
+use std::io::{Read, Write};
+
+#[cfg(unix)]
+pub(crate) type RawStream = std::os::fd::RawFd;
+
+#[cfg(windows)]
+pub(crate) type RawStream = std::os::windows::io::RawHandle;
+
+/// The parent's end of a stream that receives from the child, as returned by
+/// `Child::take_stream_from_child`.
+///
+/// Besides reading, it exposes the stream's descriptor (`AsRawFd`) or handle
+/// (`AsRawHandle`), so it can be registered with an event loop such as mio.  Reading goes
+/// through the stream's output limit and log file, so read through this rather than the
+/// raw descriptor.  The descriptor stays open until this is dropped, or until a read past
+/// a `LimitAction::CloseStream` limit closes it.
+pub struct ChildReader {
+    inner: Box<dyn Read>,
+    raw: RawStream,
+}
+
+impl ChildReader {
+    pub(crate) fn new(inner: Box<dyn Read>, raw: RawStream) -> Self {
+        ChildReader { inner, raw }
+    }
+}
+
+impl Read for ChildReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for ChildReader {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.raw
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for ChildReader {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.raw
+    }
+}
+
+/// The parent's end of a stream that sends to the child, as returned by
+/// `Child::take_stream_to_child`.
+///
+/// Besides writing, it exposes the stream's descriptor (`AsRawFd`) or handle
+/// (`AsRawHandle`), so it can be registered with an event loop such as mio.  The
+/// descriptor stays open until this is dropped.
+pub struct ChildWriter {
+    inner: Box<dyn Write + Send>,
+    raw: RawStream,
+}
+
+impl ChildWriter {
+    pub(crate) fn new(inner: Box<dyn Write + Send>, raw: RawStream) -> Self {
+        ChildWriter { inner, raw }
+    }
+
+    /// The stream without its raw descriptor, so it can move to another thread.
+    pub(crate) fn into_inner(self) -> Box<dyn Write + Send> {
+        self.inner
+    }
+}
+
+impl Write for ChildWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for ChildWriter {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.raw
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for ChildWriter {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.raw
+    }
+}
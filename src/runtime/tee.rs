@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: MIT
+
+//! Copy the child's output to a log file while the handler reads it.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use crate::logging;
+
+/// A log file that receives a copy of everything the handler reads from a child's output
+/// stream.  Add it with `FdSet::with_tee`.
+///
+/// The copy is made as the handler reads, so output the handler never reads doesn't
+/// reach the file.  The parent opens the file, so the child never needs access to its
+/// path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdTee {
+    path: PathBuf,
+    append: bool,
+}
+
+impl FdTee {
+    /// Add to the end of the file, creating it if it doesn't exist.
+    pub fn append(path: PathBuf) -> Self {
+        FdTee { path, append: true }
+    }
+
+    /// Create the file, or truncate it if it exists.
+    pub fn create(path: PathBuf) -> Self {
+        FdTee {
+            path,
+            append: false,
+        }
+    }
+
+    /// The log file's path.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    fn open(&self) -> Result<File, std::io::Error> {
+        let file = if self.append {
+            File::options().append(true).create(true).open(&self.path)
+        } else {
+            File::create(&self.path)
+        };
+        file.map_err(|e| {
+            std::io::Error::new(e.kind(), format!("could not open {:?}: {}", self.path, e))
+        })
+    }
+}
+
+/// Open the log files for a launch, keyed by the FD they copy.
+pub(crate) fn open_tees(tees: &HashMap<u32, FdTee>) -> Result<HashMap<u32, File>, std::io::Error> {
+    let mut ret = HashMap::new();
+    for (fd, tee) in tees {
+        ret.insert(*fd, tee.open()?);
+    }
+    Ok(ret)
+}
+
+/// Copies the stream into its log file, if it has one.
+pub(crate) struct TeeReader<R> {
+    inner: R,
+    /// None if there is no log, or once writing to it failed.
+    log: Option<File>,
+}
+
+impl<R> TeeReader<R> {
+    pub(crate) fn new(inner: R, log: Option<File>) -> Self {
+        TeeReader { inner, log }
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        if let Some(log) = self.log.as_mut() {
+            // A failing log shouldn't cost the handler its data, so stop copying instead.
+            if let Err(e) = log.write_all(&buf[..count]) {
+                logging::warning!("stopped copying child output to its log: {}", e);
+                self.log = None;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_what_is_read() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        let path = dir.path().join("out.log");
+        std::fs::write(&path, b"old;").unwrap();
+        let tees = HashMap::from([(1, FdTee::append(path.clone()))]);
+        let mut logs = open_tees(&tees).unwrap();
+        let mut reader = TeeReader::new(&b"hello"[..], logs.remove(&1));
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).unwrap();
+        drop(reader);
+        assert_eq!(got, b"hello");
+        assert_eq!(std::fs::read(&path).unwrap(), b"old;hello");
+    }
+}
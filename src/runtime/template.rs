@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: MIT
+
+//! Prepare a launch once, then spawn it many times.
+
+use std::ffi::OsString;
+
+use super::{CommHandler, LaunchEnv, RunResult, error::SandboxError};
+
+/// A launch environment with the expensive setup already performed.
+///
+/// On Linux, preparing the template resolves the executable, discovers its
+/// dependencies, converts the arguments and environment, and builds the Landlock and
+/// seccomp rules.  Each spawn then only creates the FD pipes and forks.  On other
+/// operating systems, each spawn performs the full launch.
+///
+/// ```no_run
+/// use gracklezero::{LaunchEnv, compat_restrictions, runtime::SandboxTemplate};
+/// # use gracklezero::{Child, CommHandler};
+/// # struct Handler;
+/// # impl CommHandler for Handler {
+/// #     type Output = ();
+/// #     type Error = std::io::Error;
+/// #     fn handle(&mut self, _child: Box<dyn Child>) -> Result<(), std::io::Error> { Ok(()) }
+/// # }
+///
+/// let template = SandboxTemplate::prepare(LaunchEnv::new(
+///     "the-child".into(),
+///     compat_restrictions!("app"),
+/// ))
+/// .expect("prepare failed");
+/// for i in 0..100 {
+///     template.spawn(&[i.to_string().into()], Handler).expect("spawn failed");
+/// }
+/// ```
+pub struct SandboxTemplate {
+    #[cfg(target_os = "linux")]
+    prepared: super::spawn_linux::PreparedLaunch,
+
+    #[cfg(not(target_os = "linux"))]
+    env: LaunchEnv,
+}
+
+impl SandboxTemplate {
+    /// Perform the launch setup that is shared between all the spawned children.
+    #[cfg(target_os = "linux")]
+    pub fn prepare(env: LaunchEnv) -> Result<Self, SandboxError> {
+        Ok(SandboxTemplate {
+            prepared: super::spawn_linux::PreparedLaunch::new(env)?,
+        })
+    }
+
+    /// Perform the launch setup that is shared between all the spawned children.
+    #[cfg(not(target_os = "linux"))]
+    pub fn prepare(env: LaunchEnv) -> Result<Self, SandboxError> {
+        Ok(SandboxTemplate { env })
+    }
+
+    /// Launch a child from the template, with the arguments appended to the template's
+    /// arguments, and pass it to the handler.  Works like `sandbox_child`.
+    #[cfg(target_os = "linux")]
+    pub fn spawn<CH: CommHandler>(
+        &self,
+        args: &[OsString],
+        handler: CH,
+    ) -> Result<RunResult<CH::Output, CH::Error>, SandboxError> {
+        super::run_child(super::OwnedChild::new(self.prepared.launch(args)?), handler)
+    }
+
+    /// Launch a child from the template, with the arguments appended to the template's
+    /// arguments, and pass it to the handler.  Works like `sandbox_child`.
+    #[cfg(not(target_os = "linux"))]
+    pub fn spawn<CH: CommHandler>(
+        &self,
+        args: &[OsString],
+        handler: CH,
+    ) -> Result<RunResult<CH::Output, CH::Error>, SandboxError> {
+        let mut env = self.env.clone();
+        env.args.extend(args.iter().cloned());
+        super::sandbox_child(env, handler)
+    }
+}
@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: MIT
+
+//! Pseudo-terminals for the child.
+//!
+//! An FD marked `FdMode::Terminal` attaches to a pseudo-terminal instead of a pipe, so
+//! the child sees a real terminal, and programs that check `isatty` or draw full-screen
+//! output behave as they would for a user.  Every `Terminal` FD in the set shares the one
+//! terminal, which also becomes the child's controlling terminal.
+//!
+//! The parent holds the terminal's other side.  Writes to the FD 0 stream arrive as typed
+//! input, and the stream for the first other terminal FD in the set reads everything the
+//! child writes to any of its terminal FDs, including the echoed input.
+//!
+//! Changing the terminal size through `Terminal::resize` makes the kernel send `SIGWINCH`
+//! to the child, so full-screen programs redraw.  `Terminal::forward_resizes` keeps the
+//! child's terminal the same size as the parent's own.
+//!
+//! Pseudo-terminals are currently only supported on Linux.
+
+use std::{
+    fs::File,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+/// The (columns, rows) of a new terminal, when the parent has no terminal to copy.
+pub(crate) const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// How often a `ResizeForwarder` checks the parent's terminal size.
+const FORWARD_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The parent's side of a child's pseudo-terminal.
+///
+/// Clones share the same terminal, so one can move to another thread while the handler
+/// keeps the child.
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    master: Arc<File>,
+}
+
+impl Terminal {
+    pub(crate) fn new(master: File) -> Self {
+        Terminal {
+            master: Arc::new(master),
+        }
+    }
+
+    /// Set the terminal size, in character cells.
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<(), std::io::Error> {
+        set_size(&self.master, cols, rows)
+    }
+
+    /// The terminal's current (columns, rows).
+    pub fn size(&self) -> Result<(u16, u16), std::io::Error> {
+        get_size(&self.master)
+    }
+
+    /// Copy the parent's terminal size to the child's terminal now, and again whenever it
+    /// changes, until the returned forwarder is dropped.
+    ///
+    /// This checks the parent's size a few times a second, rather than handling
+    /// `SIGWINCH`, so it never replaces a signal handler the embedding program installed.
+    /// Fails with `std::io::ErrorKind::NotFound` if the parent has no terminal.
+    pub fn forward_resizes(&self) -> Result<ResizeForwarder, std::io::Error> {
+        let (cols, rows) = parent_size().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "the parent process has no terminal",
+            )
+        })?;
+        self.resize(cols, rows)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let terminal = self.clone();
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut last = (cols, rows);
+            while !stopped.load(Ordering::Relaxed) {
+                std::thread::sleep(FORWARD_INTERVAL);
+                match parent_size() {
+                    Some(size) if size != last => {
+                        if terminal.resize(size.0, size.1).is_err() {
+                            break;
+                        }
+                        last = size;
+                    }
+                    _ => {}
+                }
+            }
+        });
+        Ok(ResizeForwarder {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Copies the parent's terminal size to the child's terminal, until dropped.
+pub struct ResizeForwarder {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ResizeForwarder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The (columns, rows) of the parent's own terminal, from whichever standard stream is
+/// attached to one.
+#[cfg(target_os = "linux")]
+pub(crate) fn parent_size() -> Option<(u16, u16)> {
+    get_size(&std::io::stdout())
+        .or_else(|_| get_size(&std::io::stdin()))
+        .or_else(|_| get_size(&std::io::stderr()))
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn parent_size() -> Option<(u16, u16)> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn get_size<F: std::os::fd::AsRawFd>(fd: &F) -> Result<(u16, u16), std::io::Error> {
+    let mut size: nix::libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { nix::libc::ioctl(fd.as_raw_fd(), nix::libc::TIOCGWINSZ, &mut size) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((size.ws_col, size.ws_row))
+}
+
+#[cfg(target_os = "linux")]
+fn set_size<F: std::os::fd::AsRawFd>(fd: &F, cols: u16, rows: u16) -> Result<(), std::io::Error> {
+    let size = winsize(cols, rows);
+    if unsafe { nix::libc::ioctl(fd.as_raw_fd(), nix::libc::TIOCSWINSZ, &size) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn winsize(cols: u16, rows: u16) -> nix::libc::winsize {
+    nix::libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_size(_fd: &File) -> Result<(u16, u16), std::io::Error> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_size(_fd: &File, _cols: u16, _rows: u16) -> Result<(), std::io::Error> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "pseudo-terminals are not supported on this OS",
+    )
+}
+
+/// Reads the child's terminal output.
+///
+/// Once the child side of the terminal closes, Linux fails reads with `EIO` rather than
+/// reporting the end of the stream; this reports the end instead.
+#[cfg(target_os = "linux")]
+pub(crate) struct TerminalReader(pub(crate) File);
+
+#[cfg(target_os = "linux")]
+impl std::io::Read for TerminalReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(nix::libc::EIO) => Ok(0),
+            r => r,
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_round_trip() {
+        let pty = nix::pty::openpty(Some(&winsize(80, 24)), None).expect("openpty failed");
+        let terminal = Terminal::new(File::from(pty.master));
+        assert_eq!(terminal.size().unwrap(), (80, 24));
+        terminal.resize(132, 43).unwrap();
+        assert_eq!(terminal.size().unwrap(), (132, 43));
+        // The child side sees the same size.
+        assert_eq!(get_size(&pty.slave).unwrap(), (132, 43));
+    }
+}
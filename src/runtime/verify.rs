@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: MIT
+
+//! Check the files a launch runs against known SHA-256 digests, so a binary replaced on
+//! disk is refused even when it sits at the expected path.
+
+use std::{collections::HashSet, fs::File, io::Read, path::Path};
+
+use sha2::{Digest as _, Sha256};
+
+use crate::runtime::error::SandboxError;
+
+/// The SHA-256 digests of the executables, and optionally the shared libraries, a launch
+/// may run.  Set it as `LaunchEnv::hash_allowlist`.
+///
+/// The launcher hashes the resolved executable just before each launch, and refuses to
+/// start the child when the digest isn't in the list.  For a script, both the script and
+/// its interpreter must be listed.  With `with_libraries`, every shared library the
+/// executable loads, and the `LaunchEnv::extra_libraries`, must be listed too; this is
+/// only checked on Linux.  Windows doesn't yet analyze DLLs, so a launch there with
+/// `with_libraries` fails rather than run unchecked libraries.
+///
+/// On Linux, the executable is opened once, and the child runs the same open file that
+/// was hashed, so replacing it after the check has no effect.  The script, for a script,
+/// and the shared libraries are still hashed by path, so they have a short window between
+/// the check and their use; that check works best when the files aren't writable by
+/// whoever might replace them.  On Windows, the hashed executable stays open, without
+/// sharing write or delete access, until the process is created, so it can't be changed
+/// or replaced in between.
+///
+/// ```
+/// use gracklezero::runtime::HashAllowlist;
+///
+/// let allowlist = HashAllowlist::new()
+///     .allow_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+///     .expect("valid digest");
+/// assert!(!allowlist.verifies_libraries());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashAllowlist {
+    digests: HashSet<[u8; 32]>,
+    libraries: bool,
+}
+
+impl HashAllowlist {
+    /// An empty list, which allows nothing to run.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow files with the digest.
+    pub fn allow(mut self, digest: [u8; 32]) -> Self {
+        self.digests.insert(digest);
+        self
+    }
+
+    /// Allow files with the digest, written as 64 hexadecimal digits, as `sha256sum`
+    /// prints it.
+    pub fn allow_hex(self, digest: &str) -> Result<Self, SandboxError> {
+        match parse_hex(digest.trim()) {
+            Some(digest) => Ok(self.allow(digest)),
+            None => Err(SandboxError::InvalidDigest(digest.to_string())),
+        }
+    }
+
+    /// Allow files with the same contents as this one, as it is now.
+    pub fn allow_file(self, path: &Path) -> Result<Self, SandboxError> {
+        let digest = sha256_file(path)?;
+        Ok(self.allow(digest))
+    }
+
+    /// Require the executable's shared libraries to be listed, too.
+    pub fn with_libraries(mut self) -> Self {
+        self.libraries = true;
+        self
+    }
+
+    /// Must the shared libraries be listed?
+    pub fn verifies_libraries(&self) -> bool {
+        self.libraries
+    }
+
+    /// Is the digest listed?
+    pub fn contains(&self, digest: &[u8; 32]) -> bool {
+        self.digests.contains(digest)
+    }
+
+    /// Hash the file, and fail if the digest isn't listed.
+    pub fn verify(&self, path: &Path) -> Result<(), SandboxError> {
+        self.open_verified(path).map(|_| ())
+    }
+
+    /// Open the file and hash it, and fail if the digest isn't listed.  The returned file
+    /// is the one that was hashed, so running it avoids a race with the file being
+    /// replaced.
+    pub(crate) fn open_verified(&self, path: &Path) -> Result<File, SandboxError> {
+        self.check_opened(path, File::open(path)?)
+    }
+
+    /// Open the file so no one else can write, rename or delete it while it's open, then
+    /// hash it, and fail if the digest isn't listed.  Keeping the returned file open keeps
+    /// the hashed contents in place.
+    #[cfg(target_os = "windows")]
+    pub(crate) fn open_verified_locked(&self, path: &Path) -> Result<File, SandboxError> {
+        use std::os::windows::fs::OpenOptionsExt as _;
+        use windows::Win32::Storage::FileSystem::FILE_SHARE_READ;
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .share_mode(FILE_SHARE_READ.0)
+            .open(path)?;
+        self.check_opened(path, file)
+    }
+
+    /// Hash the opened file, and hand it back if the digest is listed.
+    fn check_opened(&self, path: &Path, file: File) -> Result<File, SandboxError> {
+        let digest = sha256_reader(&file)?;
+        if self.contains(&digest) {
+            Ok(file)
+        } else {
+            Err(SandboxError::UntrustedFile(format!(
+                "{} has SHA-256 digest {}, which is not in the allowlist",
+                path.display(),
+                to_hex(&digest)
+            )))
+        }
+    }
+}
+
+/// The SHA-256 digest of the file's contents.
+pub fn sha256_file(path: &Path) -> std::io::Result<[u8; 32]> {
+    sha256_reader(File::open(path)?)
+}
+
+/// The SHA-256 digest of everything left to read.
+fn sha256_reader(mut file: impl Read) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let count = file.read(&mut buf)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buf[..count]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut ret = [0u8; 32];
+    for (i, b) in ret.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(ret)
+}
+
+fn to_hex(digest: &[u8; 32]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trip() {
+        let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let digest = parse_hex(hex).expect("parse failed");
+        assert_eq!(to_hex(&digest), hex);
+        assert_eq!(parse_hex(&hex.to_uppercase()), Some(digest));
+        assert_eq!(parse_hex(&hex[1..]), None);
+        assert_eq!(parse_hex(&hex.replace('e', "g")), None);
+    }
+
+    #[test]
+    fn verify_file() {
+        let file = tempfile::NamedTempFile::new().expect("created a temp file");
+        // The digest of the empty file.
+        let allowlist = HashAllowlist::new()
+            .allow_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+            .expect("valid digest");
+        allowlist
+            .verify(file.path())
+            .expect("empty file not allowed");
+
+        std::fs::write(file.path(), b"changed").expect("write failed");
+        let err = allowlist
+            .verify(file.path())
+            .expect_err("changed file allowed");
+        assert!(matches!(err, SandboxError::UntrustedFile(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn bad_hex() {
+        let err = HashAllowlist::new()
+            .allow_hex("e3b0")
+            .expect_err("short digest allowed");
+        assert!(matches!(err, SandboxError::InvalidDigest(_)), "{:?}", err);
+        let err = std::io::Error::from(err);
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn open_verified_keeps_hashed_file() {
+        let file = tempfile::NamedTempFile::new().expect("created a temp file");
+        let allowlist = HashAllowlist::new()
+            .allow_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+            .expect("valid digest");
+        let opened = allowlist
+            .open_verified(file.path())
+            .expect("empty file not allowed");
+
+        // Replacing the file leaves the opened one as it was hashed.
+        let path = file.path().to_path_buf();
+        drop(file);
+        std::fs::write(&path, b"replaced").expect("write failed");
+        assert_eq!(opened.metadata().expect("stat failed").len(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: MIT
+
+//! Shell-style name patterns.
+
+/// Simple shell-style matching, supporting `*` and `?`.
+pub(crate) fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| wildcard_match(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && wildcard_match(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && wildcard_match(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match(b"libnss_*.so.?", b"libnss_files.so.2"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(!wildcard_match(b"libnss_*.so.?", b"libnss_files.so.12"));
+        assert!(!wildcard_match(b"lib?.so", b"lib.so"));
+    }
+}
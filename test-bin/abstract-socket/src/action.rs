@@ -0,0 +1,20 @@
+// SPDX-License-Identifier: MIT
+
+use super::debug::debug;
+
+#[cfg(target_os = "linux")]
+pub(crate) fn perform(name: String) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixStream};
+
+    debug(format!("connecting to abstract socket {}", name));
+    let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let _ = UnixStream::connect_addr(&addr).unwrap();
+    debug(format!("connected to abstract socket {}", name));
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn perform(name: String) {
+    debug(format!("abstract socket {} not supported on this OS", name));
+    panic!("abstract sockets are only supported on Linux");
+}
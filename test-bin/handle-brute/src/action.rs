@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: MIT
+
+use super::debug::debug;
+
+pub(crate) fn perform(max: String) {
+    let max: usize = max.parse().unwrap();
+    debug(format!("searching for leaked handles up to {}", max));
+    for value in first_handle()..=max {
+        if is_open(value) {
+            debug(format!("found open handle {}", value));
+            return;
+        }
+    }
+    panic!("no leaked handles found");
+}
+
+#[cfg(unix)]
+fn first_handle() -> usize {
+    // Skip stdin, stdout, and stderr.
+    3
+}
+
+#[cfg(unix)]
+fn is_open(value: usize) -> bool {
+    use std::os::fd::FromRawFd;
+
+    let file = unsafe { std::fs::File::from_raw_fd(value as i32) };
+    let ret = file.metadata().is_ok();
+    // Don't close a descriptor this doesn't own.
+    std::mem::forget(file);
+    ret
+}
+
+#[cfg(windows)]
+fn first_handle() -> usize {
+    // Handle values are multiples of 4.
+    4
+}
+
+#[cfg(windows)]
+fn is_open(value: usize) -> bool {
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+
+    if value % 4 != 0 {
+        return false;
+    }
+    // The standard stream handles are expected to be open.
+    for std_handle in [
+        std::io::stdin().as_raw_handle(),
+        std::io::stdout().as_raw_handle(),
+        std::io::stderr().as_raw_handle(),
+    ] {
+        if std_handle as usize == value {
+            return false;
+        }
+    }
+    let file = unsafe { std::fs::File::from_raw_handle(value as _) };
+    let ret = file.metadata().is_ok();
+    // Don't close a handle this doesn't own.
+    std::mem::forget(file);
+    ret
+}
@@ -0,0 +1,9 @@
+// SPDX-License-Identifier: MIT
+
+use super::debug::debug;
+
+pub(crate) fn perform(path: String) {
+    debug(format!("probing {}", path));
+    let data = std::fs::read(&path).unwrap();
+    debug(format!("read {} bytes from {}", data.len(), path));
+}
@@ -0,0 +1,21 @@
+// SPDX-License-Identifier: MIT
+
+use super::debug::debug;
+
+/// The contents of the file the parent tries to keep away from the child.
+const SECRET: &str = "secret";
+
+const ATTEMPTS: usize = 10_000;
+
+pub(crate) fn perform(link: String) {
+    debug(format!("racing reads through {}", link));
+    for _ in 0..ATTEMPTS {
+        if let Ok(contents) = std::fs::read_to_string(&link) {
+            if contents.trim() == SECRET {
+                debug(format!("read the secret through {}", link));
+                return;
+            }
+        }
+    }
+    panic!("never read the secret through {}", link);
+}
@@ -5,8 +5,11 @@
 use std::{path::PathBuf, sync::mpsc, thread};
 
 use gracklezero::{
-    FdSet, LaunchEnv, Restrictions, compat_restrictions, restrictions,
-    runtime::{ExitCode, error::SandboxError},
+    FdMode, FdSet, LaunchEnv, Restrictions, compat_restrictions, restrictions,
+    runtime::{
+        ExitStatus, RunResult,
+        error::{LaunchPhase, SandboxError},
+    },
     sandbox_child,
 };
 use tempfile::NamedTempFile;
@@ -32,12 +35,11 @@ fn not_exist() {
     let (h, m) = simple_handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: path,
             args: util::str_as_args("not used"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: util::std_fd(),
-            restrictions: compat_restrictions!("noop"),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(path, compat_restrictions!("noop"))
         },
         h,
     );
@@ -47,10 +49,10 @@ fn not_exist() {
             panic!("incorrectly returned from sandbox_child without an error");
         }
         Err(e) => {
+            assert_eq!(e.diagnostics().map(|d| d.phase), Some(LaunchPhase::Resolve));
             match e {
-                // Some library code error; that's the expected result.  The
-                // actual error is OS dependent.
-                SandboxError::Io(_) => (),
+                // The command can't be found; the OS error behind it is OS dependent.
+                SandboxError::Resolution { .. } => (),
                 e => {
                     panic!("Invalid generated error: {:?}", e);
                 }
@@ -76,7 +78,10 @@ fn simple_c() {
 fn run_simple_c(
     name: &String,
     restr: Restrictions,
-) -> (Result<ExitCode, SandboxError>, TestMonitor) {
+) -> (
+    Result<RunResult<(), std::io::Error>, SandboxError>,
+    TestMonitor,
+) {
     println!(
         "Running with restrictions {} + (always disable win32k disabled due to native hook issues)",
         &name
@@ -91,12 +96,11 @@ fn run_simple_c(
     let (h, m) = simple_handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("simple-c"),
             args: util::str_as_args("not used"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: FdSet::basic(&[]),
-            restrictions: restr,
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(util::require_exec("simple-c"), restr)
         },
         h,
     );
@@ -144,16 +148,16 @@ fn simple_c_parallel() {
                 errors.push(format!("{}: did not run successfully: {:?}", name, e));
             }
         };
-        match &m.exit_code() {
-            ExitCode::Exited(c) => {
-                if *c != 0 {
-                    errors.push(format!("{}: exited with {}, expected 0", name, *c));
+        match m.exit_status() {
+            Some(ExitStatus::Code(c)) => {
+                if c != 0 {
+                    errors.push(format!("{}: exited with {}, expected 0", name, c));
                 }
             }
-            ExitCode::OsError(term) => {
-                errors.push(format!("{}: terminated due to {:?}", name, term));
+            Some(status) => {
+                errors.push(format!("{}: terminated with {:?}", name, status));
             }
-            ExitCode::Running => {
+            None => {
                 errors.push(format!("{}: still running after timeout", name));
             }
         }
@@ -173,12 +177,11 @@ fn simple_rust() {
         let (h, m) = simple_handler::new();
         sandbox_child(
             LaunchEnv {
-                cmd: util::require_exec("simple-rust"),
                 args: util::str_as_args("not used"),
-                cwd: PathBuf::from("."),
                 env: util::env_backtrace(),
                 fds: FdSet::basic(&[]),
-                restrictions: restr.1,
+                allow_privileged_parent: true,
+                ..LaunchEnv::new(util::require_exec("simple-rust"), restr.1)
             },
             h,
         )
@@ -247,12 +250,11 @@ fn run_cfg_on(kind: &str, wrapper: fn(restrictions::Restrictions) -> restriction
         let (h, m) = simple_handler::new();
         sandbox_child(
             LaunchEnv {
-                cmd: exec.clone(),
                 args: util::str_as_args("not used"),
-                cwd: PathBuf::from("."),
                 env: util::env_backtrace(),
                 fds: FdSet::basic(&[]),
-                restrictions: cfg,
+                allow_privileged_parent: true,
+                ..LaunchEnv::new(exec.clone(), cfg)
             },
             h,
         )
@@ -314,12 +316,11 @@ fn run_cfg_off_ok(
     let (h, m) = simple_handler::new();
     let _ = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("simple-c"),
             args: util::str_as_args("not used"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: FdSet::basic(&[]),
-            restrictions: restr,
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(util::require_exec("simple-c"), restr)
         },
         h,
     )
@@ -336,12 +337,11 @@ fn run_cfg_off_fail(
     let (h, m) = simple_handler::new();
     let err = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("simple-c"),
             args: util::str_as_args("not used"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: FdSet::basic(&[]),
-            restrictions: restr,
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(util::require_exec("simple-c"), restr)
         },
         h,
     )
@@ -353,3 +353,29 @@ fn run_cfg_off_fail(
         err,
     );
 }
+
+/// A conflicting FdSet fails before the child starts.
+#[test]
+fn invalid_fd_set() {
+    let (h, m) = simple_handler::new();
+    let err = sandbox_child(
+        LaunchEnv {
+            args: util::str_as_args("not used"),
+            env: util::env_backtrace(),
+            fds: FdSet::basic(&[FdMode::FromChild]),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(
+                util::require_exec("simple-c"),
+                compat_restrictions!("invalid fd set"),
+            )
+        },
+        h,
+    )
+    .expect_err("did not exit with error");
+    m.assert_never_started();
+    assert!(
+        matches!(err, SandboxError::InvalidFdSet(_)),
+        "Unexpected error: {:?}",
+        err,
+    );
+}
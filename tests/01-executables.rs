@@ -3,7 +3,6 @@
 //! All the test executables that include interaction.
 
 use std::io::Write;
-use std::path::PathBuf;
 
 use gracklezero::{LaunchEnv, compat_restrictions, sandbox_child};
 
@@ -18,12 +17,11 @@ fn noop() {
     let (h, m) = handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("noop"),
             args: util::str_as_args("not used"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: util::std_fd(),
-            restrictions: compat_restrictions!(APP_NAME),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(util::require_exec("noop"), compat_restrictions!(APP_NAME))
         },
         h,
     );
@@ -42,18 +40,73 @@ fn file_read() {
     let (h, m) = handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("file-read"),
             args: util::path_as_args(file.path()),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: util::std_fd(),
-            restrictions: compat_restrictions!(APP_NAME),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(
+                util::require_exec("file-read"),
+                compat_restrictions!(APP_NAME),
+            )
         },
         h,
     );
     m.assert(res, Expected::blocked());
 }
 
+/// Read from a file that the launch environment explicitly allows.
+#[cfg(target_os = "linux")]
+#[test]
+fn file_read_allowed() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "contents").unwrap();
+
+    let (h, m) = handler::new();
+    let res = sandbox_child(
+        LaunchEnv {
+            args: util::path_as_args(file.path()),
+            env: util::env_backtrace(),
+            fds: util::std_fd(),
+            readable_paths: vec![file.path().to_path_buf()],
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(
+                util::require_exec("file-read"),
+                compat_restrictions!(APP_NAME),
+            )
+        },
+        h,
+    );
+    m.assert(res, Expected::succeeds());
+}
+
+/// Read from a file inside the working directory, when the launch environment
+/// grants read access to the working directory.
+#[cfg(target_os = "linux")]
+#[test]
+fn file_read_cwd_granted() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.txt");
+    std::fs::write(&path, "contents\n").unwrap();
+
+    let (h, m) = handler::new();
+    let res = sandbox_child(
+        LaunchEnv {
+            args: util::path_as_args(&path),
+            cwd: dir.path().to_path_buf(),
+            env: util::env_backtrace(),
+            fds: util::std_fd(),
+            grant_cwd_read: true,
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(
+                std::fs::canonicalize(util::require_exec("file-read")).unwrap(),
+                compat_restrictions!(APP_NAME),
+            )
+        },
+        h,
+    );
+    m.assert(res, Expected::succeeds());
+}
+
 /// Execute itself.
 /// Because the executable can read its own file (necessary in order to
 /// have the 'fork' follow by an 'exec'), have the executable turn around and
@@ -63,12 +116,14 @@ fn exec_self() {
     let (h, m) = handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("exec-self"),
             args: util::str_as_args("not used"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: util::std_fd(),
-            restrictions: compat_restrictions!(APP_NAME),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(
+                util::require_exec("exec-self"),
+                compat_restrictions!(APP_NAME),
+            )
         },
         h,
     );
@@ -81,15 +136,16 @@ fn clipboard() {
     let (h, m) = handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("clipboard"),
-
             // Argument is the number of attempts to read.
             // Give it 3 tries in case it encounters an "in use" transient error.
             args: util::str_as_args("3"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: util::std_fd(),
-            restrictions: compat_restrictions!(APP_NAME),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(
+                util::require_exec("clipboard"),
+                compat_restrictions!(APP_NAME),
+            )
         },
         h,
     );
@@ -104,16 +160,14 @@ fn cpuid() {
     let (h, m) = handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("cpuid"),
-
             // This can pass in many different arguments.
             // Explicitly leaving out 'o', because that's easy to find out
             // without any special privileges.
             args: util::str_as_args("sumicdhq"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: util::std_fd(),
-            restrictions: compat_restrictions!(APP_NAME),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(util::require_exec("cpuid"), compat_restrictions!(APP_NAME))
         },
         h,
     );
@@ -130,12 +184,11 @@ fn tcpip() {
     let (h, m) = handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("tcpip"),
             args: util::string_as_args(&addr),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: util::std_fd(),
-            restrictions: compat_restrictions!(APP_NAME),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(util::require_exec("tcpip"), compat_restrictions!(APP_NAME))
         },
         h,
     );
@@ -154,12 +207,11 @@ fn gui() {
     let (h, m) = handler::new();
     let res = sandbox_child(
         LaunchEnv {
-            cmd: util::require_exec("gui"),
             args: util::str_as_args("app"),
-            cwd: PathBuf::from("."),
             env: util::env_backtrace(),
             fds: util::std_fd(),
-            restrictions: compat_restrictions!(APP_NAME),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(util::require_exec("gui"), compat_restrictions!(APP_NAME))
         },
         h,
     );
@@ -0,0 +1,30 @@
+// SPDX-License-Identifier: MIT
+
+//! Run the sandbox escape self-test suite against the current host.
+//! Requires the `escapetest` feature and the compiled `test-bin` programs.
+
+#![cfg(feature = "escapetest")]
+
+use std::path::Path;
+
+use gracklezero::{
+    compat_restrictions,
+    escapetest::{run_escape_tests, test_bin_locator},
+};
+
+mod common;
+use common::gen_r::APP_NAME;
+
+#[test]
+fn escape_suite() {
+    let report = run_escape_tests(
+        test_bin_locator(Path::new("test-bin")),
+        &compat_restrictions!(APP_NAME),
+    );
+    print!("{}", report);
+    assert!(
+        report.all_contained_or_skipped(),
+        "a probe escaped the sandbox or failed:\n{}",
+        report
+    );
+}
@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: MIT
+
+//! Run the `grackle` command line tool.
+//! Requires the `cli` feature.
+
+#![cfg(all(feature = "cli", target_os = "linux"))]
+
+use std::process::{Command, Stdio};
+
+use tempfile::NamedTempFile;
+
+fn grackle() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_grackle"))
+}
+
+/// `cat` a file, with and without a policy that allows reading it.
+#[test]
+fn run_with_policy() {
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"from the cli").expect("write failed");
+    let mut policy = tempfile::Builder::new()
+        .suffix(".toml")
+        .tempfile()
+        .expect("created a temp file");
+    std::io::Write::write_all(
+        &mut policy,
+        format!("[filesystem]\nread = [{:?}]\n", input.path()).as_bytes(),
+    )
+    .expect("write failed");
+
+    let output = grackle()
+        .args(["run", "--allow-privileged"])
+        .arg("--policy")
+        .arg(policy.path())
+        .arg("--")
+        .arg("cat")
+        .arg(input.path())
+        .stdin(Stdio::null())
+        .output()
+        .expect("grackle failed");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"from the cli");
+
+    let output = grackle()
+        .args(["run", "--allow-privileged", "--", "cat"])
+        .arg(input.path())
+        .stdin(Stdio::null())
+        .output()
+        .expect("grackle failed");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+/// Report the host's capabilities as JSON.
+#[test]
+fn probe_json() {
+    let output = grackle()
+        .args(["probe", "--json"])
+        .output()
+        .expect("grackle failed");
+    assert_eq!(output.status.code(), Some(0));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).expect("not JSON");
+    assert_eq!(json["os"], "linux");
+    assert_eq!(json["seccomp"], true);
+}
+
+/// List what `cat` loads, and fail on a missing library.
+#[test]
+fn deps_report() {
+    let output = grackle()
+        .args(["deps", "--json", "cat"])
+        .output()
+        .expect("grackle failed");
+    assert_eq!(output.status.code(), Some(0));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).expect("not JSON");
+    assert!(json["dependencies"].as_array().expect("not a list").len() > 1);
+
+    let output = grackle()
+        .args(["deps", "--library", "/does/not/exist/libplugin.so", "cat"])
+        .output()
+        .expect("grackle failed");
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("libplugin.so => not found"), "{}", stdout);
+}
+
+/// grackle fails with its own exit code when the command can't be found.
+#[test]
+fn run_missing_command() {
+    let output = grackle()
+        .args(["run", "--allow-privileged", "--", "/not-a-command"])
+        .stdin(Stdio::null())
+        .output()
+        .expect("grackle failed");
+    assert_eq!(output.status.code(), Some(125));
+}
+
+/// grackle refuses to run a command while privileged, unless told to.
+#[test]
+fn run_privileged_parent() {
+    let run = |extra: &[&str]| {
+        grackle()
+            .arg("run")
+            .args(extra)
+            .args(["--", "true"])
+            .stdin(Stdio::null())
+            .output()
+            .expect("grackle failed")
+    };
+    let output = run(&[]);
+    if gracklezero::runtime::probe().privileged {
+        assert_eq!(output.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("allow_privileged_parent"), "{}", stderr);
+    } else {
+        assert_eq!(output.status.code(), Some(0));
+    }
+    assert_eq!(run(&["--allow-privileged"]).status.code(), Some(0));
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT
+
+//! Terminate every tracked child at once.
+//! The registry is process-wide, so these run in their own test binary, away from the
+//! other tests' children.
+
+#![cfg(target_os = "linux")]
+
+use gracklezero::{
+    Child as _, LaunchEnv, compat_restrictions,
+    runtime::{ExitStatus, OwnedChild, registry, spawn},
+};
+
+const APP_NAME: &str = "gracklezero-integration-tests";
+
+/// Launch `cat`, which runs until its STDIN closes.  The `OwnedChild` holds STDIN open.
+fn blocked_cat() -> OwnedChild {
+    let env = LaunchEnv {
+        kill_on_drop: false,
+        allow_privileged_parent: true,
+        ..LaunchEnv::new(
+            which::which("cat").expect("cat not found"),
+            compat_restrictions!(APP_NAME),
+        )
+    };
+    spawn(env).expect("spawn failed")
+}
+
+/// Children end with `terminate_all`, and when a thread panics once the panic hook is
+/// installed.
+#[test]
+fn terminate_tracked_children() {
+    let mut untracked = blocked_cat();
+    registry::install_shutdown_hooks();
+    assert!(registry::is_enabled());
+
+    let child = blocked_cat();
+    assert_eq!(registry::live_count(), 1);
+    assert_eq!(gracklezero::terminate_all(), 1);
+    assert_eq!(
+        child.finish().expect("finish failed"),
+        ExitStatus::SandboxKilled
+    );
+    assert_eq!(registry::live_count(), 0);
+
+    // Without the panic hook, a panic leaves the children running.
+    let child = blocked_cat();
+    let panicked = std::thread::spawn(|| panic!("worker failed")).join();
+    assert!(panicked.is_err());
+    assert_eq!(child.exit_status().expect("exit status failed"), None);
+    assert_eq!(gracklezero::terminate_all(), 1);
+    child.finish().expect("finish failed");
+
+    registry::install_panic_hook();
+    let child = blocked_cat();
+    let panicked = std::thread::spawn(|| panic!("supervisor failed")).join();
+    assert!(panicked.is_err());
+    assert_eq!(
+        child.finish().expect("finish failed"),
+        ExitStatus::SandboxKilled
+    );
+
+    // Launched before the registry was enabled.
+    assert_eq!(untracked.exit_status().expect("exit status failed"), None);
+    // Closing its STDIN lets it exit on its own.
+    drop(untracked.take_stream_to_child(0));
+    untracked.wait().expect("wait failed");
+    assert_eq!(
+        untracked.finish().expect("finish failed"),
+        ExitStatus::Code(0)
+    );
+}
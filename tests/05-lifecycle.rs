@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: MIT
+
+//! Launch children and follow them through to their exit, without a handler.
+
+use gracklezero::{
+    Child as _, FdMode, FdSet, LaunchEnv, compat_restrictions,
+    runtime::{ExitStatus, SandboxTemplate},
+};
+
+mod common;
+use common::{simple_handler, util};
+
+/// Launch the simple-c program without a handler, and wait on it directly.
+#[test]
+fn simple_c_owned() {
+    let child = gracklezero::runtime::spawn(LaunchEnv {
+        env: util::env_backtrace(),
+        fds: FdSet::basic(&[]),
+        args: util::str_as_args("not used"),
+        ..util::launch_env(util::require_exec("simple-c"))
+    })
+    .expect("spawn failed");
+    assert_eq!(child.wait().expect("wait failed"), ExitStatus::Code(0));
+    assert_eq!(child.finish().expect("finish failed"), ExitStatus::Code(0));
+}
+
+/// Prepare the simple-c program once, then run it several times from the template.
+#[test]
+fn simple_c_template() {
+    let template = SandboxTemplate::prepare(LaunchEnv {
+        args: util::str_as_args("not used"),
+        env: util::env_backtrace(),
+        fds: FdSet::basic(&[]),
+        allow_privileged_parent: true,
+        ..LaunchEnv::new(util::require_exec("simple-c"), compat_restrictions!("noop"))
+    })
+    .expect("template should prepare");
+    for _ in 0..3 {
+        let (h, m) = simple_handler::new();
+        template
+            .spawn(&[], h)
+            .expect("should have ran successfully");
+        m.assert_exited_with(0);
+    }
+}
+
+/// Start a zygote for the simple-c program, then run it several times through the zygote.
+#[cfg(target_os = "linux")]
+#[test]
+fn simple_c_zygote() {
+    let zygote = gracklezero::runtime::Zygote::start(LaunchEnv {
+        args: util::str_as_args("not used"),
+        env: util::env_backtrace(),
+        fds: FdSet::basic(&[]),
+        allow_privileged_parent: true,
+        ..LaunchEnv::new(util::require_exec("simple-c"), compat_restrictions!("noop"))
+    })
+    .expect("zygote should start");
+    for _ in 0..3 {
+        let (h, m) = simple_handler::new();
+        zygote.spawn(&[], h).expect("should have ran successfully");
+        m.assert_exited_with(0);
+    }
+}
+
+/// Run cat through a zygote, from one file into another, and check that the zygote put
+/// itself in its baseline jail before opening them.
+#[cfg(target_os = "linux")]
+#[test]
+fn zygote_is_jailed() {
+    let dir = tempfile::tempdir().expect("temp dir failed");
+    let (input, output) = (dir.path().join("in.txt"), dir.path().join("out.txt"));
+    std::fs::write(&input, "through the zygote").expect("write failed");
+    let zygote = gracklezero::runtime::Zygote::start(LaunchEnv {
+        fds: FdSet::basic(&[
+            FdMode::FromFile(input.clone()),
+            FdMode::ToFile(output.clone()),
+            FdMode::NullDevice,
+        ]),
+        ..util::tool_env("cat")
+    })
+    .expect("zygote should start");
+
+    let status = std::fs::read_to_string(format!("/proc/{}/status", zygote.id()))
+        .expect("read status failed");
+    assert!(status.lines().any(|l| l == "NoNewPrivs:\t1"), "{status}");
+    assert!(status.lines().any(|l| l == "Seccomp:\t2"), "{status}");
+
+    let (h, m) = simple_handler::new();
+    zygote.spawn(&[], h).expect("should have ran successfully");
+    m.assert_exited_with(0);
+    assert_eq!(
+        std::fs::read_to_string(&output).expect("read failed"),
+        "through the zygote"
+    );
+}
+
+/// Drain STDOUT and STDERR together, and see the exit, from one thread.
+#[cfg(target_os = "linux")]
+#[test]
+fn stream_selector() {
+    use gracklezero::runtime::{Selected, StreamSelector};
+    use std::io::Write;
+
+    let mut child = gracklezero::runtime::spawn(LaunchEnv {
+        fds: FdSet::std(),
+        args: vec!["-".into(), "/not-a-file".into()],
+        ..util::tool_env("cat")
+    })
+    .expect("spawn failed");
+    let mut stdin = child.take_stream_to_child(0).expect("no stdin");
+    stdin.write_all(b"out").expect("write failed");
+    drop(stdin);
+    let mut selector = StreamSelector::from_child(&mut child, &[1, 2]).expect("no selector");
+    let (mut out, mut err, mut exited) = (Vec::new(), Vec::new(), false);
+    let mut buf = [0u8; 64];
+    while !selector.is_empty() {
+        let ready = selector
+            .wait(Some(std::time::Duration::from_secs(10)))
+            .expect("wait failed");
+        assert!(!ready.is_empty(), "timed out");
+        for sel in ready {
+            match sel {
+                Selected::Readable(fd) => {
+                    let count = selector.read(fd, &mut buf).expect("read failed");
+                    match fd {
+                        1 => out.extend_from_slice(&buf[..count]),
+                        _ => err.extend_from_slice(&buf[..count]),
+                    }
+                }
+                Selected::Exited => exited = true,
+            }
+        }
+    }
+    assert_eq!(out, b"out");
+    assert!(String::from_utf8_lossy(&err).contains("/not-a-file"));
+    assert!(exited);
+    assert_eq!(child.finish().expect("finish failed"), ExitStatus::Code(1));
+}
+
+/// Run more children than the group's limit; the extra one waits for a free place.
+#[cfg(target_os = "linux")]
+#[test]
+fn child_group() {
+    use gracklezero::runtime::{ChildGroup, ChildId, GroupEvent};
+    use std::{collections::HashMap, io::Write};
+
+    fn feed(group: &mut ChildGroup, id: ChildId, data: &[u8]) {
+        let child = group.child(id).expect("child not in the group");
+        let mut stdin = child.take_stream_to_child(0).expect("no stdin");
+        stdin.write_all(data).expect("write failed");
+    }
+
+    let env = || LaunchEnv {
+        fds: FdSet::std(),
+        ..util::tool_env("cat")
+    };
+    let mut group = ChildGroup::new(1).expect("no group");
+    let first = group.spawn(env(), &[1]).expect("spawn failed");
+    let second = group.spawn(env(), &[1]).expect("spawn failed");
+    assert_eq!((group.running(), group.queued()), (1, 1));
+    feed(&mut group, first, b"one");
+
+    let mut out: HashMap<ChildId, Vec<u8>> = HashMap::new();
+    let mut exited = Vec::new();
+    let mut buf = [0u8; 64];
+    while !group.is_empty() {
+        let events = group
+            .wait(Some(std::time::Duration::from_secs(10)))
+            .expect("wait failed");
+        assert!(!events.is_empty(), "timed out");
+        for event in events {
+            match event {
+                GroupEvent::Started(id) => {
+                    assert_eq!(id, second);
+                    assert_eq!(exited, vec![first]);
+                    feed(&mut group, id, b"two");
+                }
+                GroupEvent::Readable(id, fd) => {
+                    let count = group.read(id, fd, &mut buf).expect("read failed");
+                    out.entry(id).or_default().extend_from_slice(&buf[..count]);
+                }
+                GroupEvent::Exited(id, status) => {
+                    assert_eq!(status, ExitStatus::Code(0));
+                    exited.push(id);
+                }
+                GroupEvent::LaunchFailed(_, e) => panic!("launch failed: {}", e),
+            }
+        }
+    }
+    assert_eq!(exited, vec![first, second]);
+    assert_eq!(out[&first], b"one");
+    assert_eq!(out[&second], b"two");
+}
+
+/// The taken streams expose their descriptors, so an event loop can poll them.
+#[cfg(target_os = "linux")]
+#[test]
+fn stream_raw_fds() {
+    use std::io::{Read, Write};
+    use std::os::fd::AsRawFd;
+
+    let mut child = gracklezero::runtime::spawn(LaunchEnv {
+        fds: FdSet::std(),
+        ..util::tool_env("cat")
+    })
+    .expect("spawn failed");
+    let mut stdin = child.take_stream_to_child(0).expect("no stdin");
+    let mut stdout = child.take_stream_from_child(1).expect("no stdout");
+    assert_ne!(stdin.as_raw_fd(), stdout.as_raw_fd());
+    stdin.write_all(b"ping").expect("write failed");
+    let mut pollfd = nix::libc::pollfd {
+        fd: stdout.as_raw_fd(),
+        events: nix::libc::POLLIN,
+        revents: 0,
+    };
+    assert_eq!(unsafe { nix::libc::poll(&mut pollfd, 1, 5000) }, 1);
+    let mut got = [0u8; 4];
+    stdout.read_exact(&mut got).expect("read failed");
+    assert_eq!(&got, b"ping");
+    drop(stdin);
+    assert_eq!(child.wait().expect("wait failed"), ExitStatus::Code(0));
+}
+
+/// Pipe through two `cat` children, with the data passing straight between them.
+#[cfg(target_os = "linux")]
+#[test]
+fn pipeline_cat() {
+    use std::io::{Read, Write};
+
+    let env = LaunchEnv {
+        fds: FdSet::std(),
+        ..util::tool_env("cat")
+    };
+    let mut pipeline =
+        gracklezero::runtime::pipeline(&[env.clone(), env]).expect("pipeline failed");
+    assert_eq!(pipeline.len(), 2);
+    let mut stdin = pipeline.take_stdin().expect("no stdin");
+    let mut stdout = pipeline.take_stdout().expect("no stdout");
+    assert!(
+        pipeline
+            .child(0)
+            .expect("no first child")
+            .take_stream_from_child(1)
+            .is_none()
+    );
+    stdin.write_all(b"piped").expect("write failed");
+    drop(stdin);
+    let mut got = String::new();
+    stdout.read_to_string(&mut got).expect("read failed");
+    assert_eq!(got, "piped");
+    let statuses = pipeline.wait().expect("wait failed");
+    assert_eq!(statuses, [ExitStatus::Code(0), ExitStatus::Code(0)]);
+}
+
+/// Run through the `std::process::Command` style calls.
+#[cfg(target_os = "linux")]
+#[test]
+fn command_output() {
+    use gracklezero::runtime::{GrackleCommand, Stdio};
+
+    let output = GrackleCommand::from(util::tool_env("cat"))
+        .args(["-", "/not-a-file"])
+        .stdin(Stdio::null())
+        .output()
+        .expect("output failed");
+    assert_eq!(output.status, ExitStatus::Code(1));
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+
+    let status = GrackleCommand::from(util::tool_env("cat"))
+        .stdin(Stdio::null())
+        .status()
+        .expect("status failed");
+    assert!(status.success());
+
+    let mut child = GrackleCommand::from(util::tool_env("cat"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("spawn failed");
+    assert!(child.take_stream_to_child(0).is_some());
+    assert!(child.take_stream_from_child(1).is_some());
+    assert!(child.take_stream_from_child(2).is_none());
+}
+
+/// Echo through `cat` with the async streams, and await its exit.
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+#[test]
+fn cat_async() {
+    use std::{future::poll_fn, pin::Pin};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .expect("runtime should build");
+    runtime.block_on(async {
+        let mut child = gracklezero::runtime::asynch::sandbox_child_async(LaunchEnv {
+            fds: FdSet::std(),
+            ..util::tool_env("cat")
+        })
+        .expect("spawn failed");
+        let mut stdin = child.take_stream_to_child(0).expect("no stdin");
+        let mut stdout = child.take_stream_from_child(1).expect("no stdout");
+        let sent = poll_fn(|cx| Pin::new(&mut stdin).poll_write(cx, b"hello"))
+            .await
+            .expect("write failed");
+        assert_eq!(sent, 5);
+        poll_fn(|cx| Pin::new(&mut stdin).poll_shutdown(cx))
+            .await
+            .expect("shutdown failed");
+        let mut got = Vec::new();
+        loop {
+            let mut buf = [0u8; 64];
+            let mut read = ReadBuf::new(&mut buf);
+            poll_fn(|cx| Pin::new(&mut stdout).poll_read(cx, &mut read))
+                .await
+                .expect("read failed");
+            if read.filled().is_empty() {
+                break;
+            }
+            got.extend_from_slice(read.filled());
+        }
+        assert_eq!(got, b"hello");
+        assert_eq!(
+            child.wait().await.expect("wait failed"),
+            ExitStatus::Code(0)
+        );
+    });
+}
+
+/// Echo through `cat` with the `futures::io` streams, and await its exit.
+#[cfg(all(feature = "futures-io", target_os = "linux"))]
+#[test]
+fn cat_futures_io() {
+    use futures_io::{AsyncRead, AsyncWrite};
+    use std::{future::poll_fn, pin::Pin};
+
+    async_io::block_on(async {
+        let mut child = gracklezero::runtime::futures_io::sandbox_child_async(LaunchEnv {
+            fds: FdSet::std(),
+            ..util::tool_env("cat")
+        })
+        .expect("spawn failed");
+        let mut stdin = child.take_stream_to_child(0).expect("no stdin");
+        let mut stdout = child.take_stream_from_child(1).expect("no stdout");
+        let sent = poll_fn(|cx| Pin::new(&mut stdin).poll_write(cx, b"hello"))
+            .await
+            .expect("write failed");
+        assert_eq!(sent, 5);
+        poll_fn(|cx| Pin::new(&mut stdin).poll_close(cx))
+            .await
+            .expect("close failed");
+        let mut got = Vec::new();
+        loop {
+            let mut buf = [0u8; 64];
+            let count = poll_fn(|cx| Pin::new(&mut stdout).poll_read(cx, &mut buf))
+                .await
+                .expect("read failed");
+            if count == 0 {
+                break;
+            }
+            got.extend_from_slice(&buf[..count]);
+        }
+        assert_eq!(got, b"hello");
+        assert_eq!(
+            child.wait().await.expect("wait failed"),
+            ExitStatus::Code(0)
+        );
+    });
+}
+
+/// The child sees the overridden `argv[0]`, rather than the fixed name.
+#[cfg(target_os = "linux")]
+#[test]
+fn arg0_override() {
+    // With `-c` and no other arguments, the shell's `$0` is its own `argv[0]`.
+    let output = gracklezero::runtime::run(LaunchEnv {
+        args: vec!["-c".into(), "echo $0".into()],
+        arg0: Some("renamed".into()),
+        ..util::tool_env("sh")
+    })
+    .expect("run failed");
+    assert_eq!(output.status, ExitStatus::Code(0));
+    assert_eq!(output.stdout, b"renamed\n");
+
+    let output = gracklezero::runtime::run(LaunchEnv {
+        args: vec!["-c".into(), "echo $0".into()],
+        ..util::tool_env("sh")
+    })
+    .expect("run failed");
+    assert_eq!(output.stdout, b"sandboxed\n");
+}
+
+/// The child learns which extra FDs it was given.
+#[cfg(target_os = "linux")]
+#[test]
+fn streams_listed() {
+    let env = util::tool_env("sh");
+    let output = gracklezero::runtime::run(LaunchEnv {
+        args: vec!["-c".into(), "printf %s \"$SANDBOX_FDS\"".into()],
+        fds: env.fds.clone().with_fd(3, FdMode::NullDevice),
+        ..env
+    })
+    .expect("run failed");
+    assert_eq!(output.status, ExitStatus::Code(0));
+    assert_eq!(output.stdout, b"3;");
+}
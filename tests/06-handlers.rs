@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: MIT
+
+//! The `CommHandler` implementations, combinators, and the results they return.
+
+use gracklezero::{
+    Child, CommHandler, FdMode, FdSet, LaunchEnv, compat_restrictions,
+    runtime::{ExitStatus, RunResult, SandboxTemplate, error::SandboxError},
+    sandbox_child,
+};
+use tempfile::NamedTempFile;
+
+mod common;
+use common::util;
+
+/// A handler's own error type reaches the caller intact.
+#[test]
+fn handler_error_survives() {
+    #[derive(Debug, PartialEq)]
+    struct BadReply(u8);
+
+    impl std::fmt::Display for BadReply {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "bad reply {}", self.0)
+        }
+    }
+
+    impl std::error::Error for BadReply {}
+
+    impl From<BadReply> for SandboxError {
+        fn from(e: BadReply) -> Self {
+            SandboxError::handler(e)
+        }
+    }
+
+    struct FailingHandler;
+
+    impl CommHandler for FailingHandler {
+        type Output = ();
+        type Error = BadReply;
+
+        fn handle(&mut self, _child: Box<dyn Child>) -> Result<(), BadReply> {
+            Err(BadReply(7))
+        }
+    }
+
+    let result = sandbox_child(
+        LaunchEnv {
+            args: util::str_as_args("not used"),
+            env: util::env_backtrace(),
+            fds: FdSet::basic(&[]),
+            allow_privileged_parent: true,
+            ..LaunchEnv::new(util::require_exec("simple-c"), compat_restrictions!("noop"))
+        },
+        FailingHandler,
+    )
+    .expect("launch failed");
+    assert_eq!(result.handler, Err(BadReply(7)));
+    assert!(!result.success());
+    match result.into_result() {
+        Err(SandboxError::Handler(e)) => {
+            assert_eq!(e.downcast_ref::<BadReply>(), Some(&BadReply(7)))
+        }
+        r => panic!("unexpected result: {:?}", r.map(|(_, status)| status)),
+    }
+}
+
+/// One handler, and its state, serves several launches.
+#[test]
+fn handler_reuse() {
+    struct CountingHandler(u32);
+
+    impl CommHandler for CountingHandler {
+        type Output = u32;
+        type Error = std::io::Error;
+
+        fn handle(&mut self, child: Box<dyn Child>) -> Result<u32, std::io::Error> {
+            child.wait()?;
+            self.0 += 1;
+            Ok(self.0)
+        }
+    }
+
+    let template = SandboxTemplate::prepare(LaunchEnv {
+        args: util::str_as_args("not used"),
+        env: util::env_backtrace(),
+        fds: FdSet::basic(&[]),
+        allow_privileged_parent: true,
+        ..LaunchEnv::new(util::require_exec("simple-c"), compat_restrictions!("noop"))
+    })
+    .expect("template should prepare");
+    let mut handler = CountingHandler(0);
+    for expected in 1..=2 {
+        let (count, status) = template
+            .spawn(&[], &mut handler)
+            .and_then(RunResult::into_result)
+            .expect("spawn failed");
+        assert_eq!(count, expected);
+        assert_eq!(status, ExitStatus::Code(0));
+    }
+    assert_eq!(handler.0, 2);
+}
+
+/// Collect what `cat` prints from a file, with the built-in handler.
+#[cfg(target_os = "linux")]
+#[test]
+fn run_collects_output() {
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"collected").expect("write failed");
+    let output = gracklezero::runtime::run(LaunchEnv {
+        fds: FdSet::std().with_fd(0, FdMode::FromFile(input.path().to_path_buf())),
+        ..util::tool_env("cat")
+    })
+    .expect("run failed");
+    assert_eq!(output.status, ExitStatus::Code(0));
+    assert_eq!(output.stdout, b"collected");
+    assert!(output.stderr.is_empty());
+}
+
+/// Split what `cat` prints into lines, with the built-in handler.
+#[cfg(target_os = "linux")]
+#[test]
+fn line_handler() {
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"one\r\ntwo\nthree").expect("write failed");
+    let mut lines = Vec::new();
+    let (_, status) = sandbox_child(
+        LaunchEnv {
+            args: vec!["-".into(), "/not-a-file".into()],
+            fds: FdSet::std().with_fd(0, FdMode::FromFile(input.path().to_path_buf())),
+            ..util::tool_env("cat")
+        },
+        gracklezero::runtime::LineHandler::new(|fd, line: &str| lines.push((fd, line.to_string()))),
+    )
+    .and_then(RunResult::into_result)
+    .expect("launch failed");
+    assert_eq!(status, ExitStatus::Code(1));
+    let stdout: Vec<&str> = lines
+        .iter()
+        .filter(|l| l.0 == 1)
+        .map(|l| l.1.as_str())
+        .collect();
+    assert_eq!(stdout, vec!["one", "two", "three"]);
+    assert_eq!(lines.iter().filter(|l| l.0 == 2).count(), 1);
+}
+
+/// Kill a child whose handler runs past its timeout, and convert a handler's output.
+#[cfg(target_os = "linux")]
+#[test]
+fn handler_combinators() {
+    use gracklezero::runtime::{CollectingHandler, map_output, with_timeout};
+    use std::time::{Duration, Instant};
+
+    struct StuckHandler;
+
+    impl CommHandler for StuckHandler {
+        type Output = ();
+        type Error = std::io::Error;
+
+        fn handle(&mut self, mut child: Box<dyn Child>) -> Result<(), std::io::Error> {
+            // Holding STDIN open keeps `cat` running.
+            let _stdin = child.take_stream_to_child(0);
+            child.wait()?;
+            Ok(())
+        }
+    }
+
+    let env = LaunchEnv {
+        fds: FdSet::std(),
+        ..util::tool_env("cat")
+    };
+    let started = Instant::now();
+    let result = sandbox_child(
+        env.clone(),
+        with_timeout(StuckHandler, Duration::from_millis(200)),
+    )
+    .expect("launch failed");
+    match result.handler {
+        Err(SandboxError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+        r => panic!("expected a timeout, found {:?}", r),
+    }
+    // The handler's failure doesn't hide how the child ended.
+    assert_eq!(result.exit, ExitStatus::SandboxKilled);
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let handler = map_output(CollectingHandler, |(stdout, _)| stdout.len());
+    let (len, status) = sandbox_child(env, with_timeout(handler, Duration::from_secs(10)))
+        .and_then(RunResult::into_result)
+        .expect("launch failed");
+    assert_eq!(len, 0);
+    assert_eq!(status, ExitStatus::Code(0));
+}
+
+/// Log each line `cat` writes to STDERR, and keep what it writes to STDOUT.
+#[cfg(all(feature = "log", target_os = "linux"))]
+#[test]
+fn stderr_log_handler() {
+    use std::sync::Mutex;
+
+    /// Keeps the records for the test's target.
+    struct Captured(Mutex<Vec<(log::Level, String, String)>>);
+
+    impl log::Log for Captured {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "grackle_test"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                let child = record.key_values().get("child".into());
+                self.0.lock().unwrap().push((
+                    record.level(),
+                    record.args().to_string(),
+                    child.map(|v| v.to_string()).unwrap_or_default(),
+                ));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURED: Captured = Captured(Mutex::new(Vec::new()));
+    log::set_logger(&CAPTURED).expect("no other logger");
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"kept").expect("write failed");
+    let (stdout, status) = sandbox_child(
+        LaunchEnv {
+            args: vec!["-".into(), "/not-a-file".into()],
+            fds: FdSet::std().with_fd(0, FdMode::FromFile(input.path().to_path_buf())),
+            ..util::tool_env("cat")
+        },
+        gracklezero::runtime::StderrLogHandler::new()
+            .with_target("grackle_test")
+            .with_level(log::Level::Info)
+            .with_name("cat"),
+    )
+    .and_then(RunResult::into_result)
+    .expect("sandbox_child failed");
+    assert_eq!(status, ExitStatus::Code(1));
+    assert_eq!(stdout, b"kept");
+    let captured = CAPTURED.0.lock().unwrap();
+    assert_eq!(captured.len(), 1, "{:?}", captured);
+    assert_eq!(captured[0].0, log::Level::Info);
+    assert!(captured[0].1.contains("/not-a-file"), "{:?}", captured);
+    assert_eq!(captured[0].2, "cat");
+}
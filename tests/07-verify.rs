@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: MIT
+
+//! Check the host, the executable, and the sandbox rules before launching.
+
+use std::path::PathBuf;
+
+use gracklezero::{
+    Child as _, DependencySearch, LaunchEnv, SandboxPolicy,
+    runtime::{ExitStatus, LaunchBuilder, error::SandboxError},
+};
+use tempfile::NamedTempFile;
+
+mod common;
+use common::util;
+
+/// Work out the rules for running `cat`, without running it.
+#[cfg(target_os = "linux")]
+#[test]
+fn plan_without_launch() {
+    let env = util::tool_env("cat");
+    let plan = gracklezero::runtime::plan(&LaunchEnv {
+        readable_paths: vec![std::env::temp_dir()],
+        ..env.clone()
+    })
+    .expect("plan failed");
+    assert_eq!(plan.exec, env.cmd);
+    assert_eq!(plan.interpreter, None);
+    assert!(plan.readable_paths.contains(&std::env::temp_dir()));
+    assert!(plan.readable_paths.len() > 2, "{:?}", plan.readable_paths);
+    assert!(plan.allowed_syscalls.iter().any(|c| c == "read"));
+    assert!(!plan.allowed_syscalls.iter().any(|c| c == "ptrace"));
+}
+
+/// Review the rules for running `cat`, then run the launch that was reviewed.
+#[cfg(target_os = "linux")]
+#[test]
+fn builder_plan_then_run() {
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"planned").expect("write failed");
+    let planned = LaunchBuilder::from(util::tool_env("cat"))
+        .arg(input.path())
+        .readable_path(input.path())
+        .plan()
+        .expect("plan failed");
+    assert!(
+        planned
+            .plan()
+            .readable_paths
+            .contains(&input.path().to_path_buf())
+    );
+    let mut child = planned.spawn().expect("spawn failed");
+    let mut out = Vec::new();
+    std::io::Read::read_to_end(
+        &mut child.take_stream_from_child(1).expect("no stdout"),
+        &mut out,
+    )
+    .expect("read failed");
+    child.wait().expect("wait failed");
+    assert_eq!(child.finish().expect("finish failed"), ExitStatus::Code(0));
+    assert_eq!(out, b"planned");
+}
+
+/// Keep the rules for running `cat` as JSON.
+#[cfg(all(feature = "serde", target_os = "linux"))]
+#[test]
+fn plan_as_json() {
+    let env = util::tool_env("cat");
+    let plan = gracklezero::runtime::plan(&env).expect("plan failed");
+    let json = serde_json::to_value(&plan).expect("serialize failed");
+    assert_eq!(json["exec"], env.cmd.to_str().expect("not UTF-8"));
+    assert_eq!(json["cpu_placement"], "Any");
+    assert!(
+        json["allowed_syscalls"]
+            .as_array()
+            .expect("not a list")
+            .len()
+            > 10
+    );
+    assert!(json["readable_paths"].as_array().expect("not a list").len() > 1);
+}
+
+/// `cat` can only read the file under the preset that allows it.
+#[cfg(target_os = "linux")]
+#[test]
+fn policy_presets() {
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"preset").expect("write failed");
+    let cat = |policy: SandboxPolicy| {
+        gracklezero::runtime::run(
+            LaunchEnv {
+                args: vec![input.path().into()],
+                ..util::tool_env("cat")
+            }
+            .with_policy(&policy),
+        )
+        .expect("run failed")
+    };
+    let output = cat(SandboxPolicy::read_data([input.path()]));
+    assert_eq!(output.status, ExitStatus::Code(0));
+    assert_eq!(output.stdout, b"preset");
+    let output = cat(SandboxPolicy::strict());
+    assert_ne!(output.status, ExitStatus::Code(0));
+    assert!(output.stdout.is_empty());
+}
+
+/// Copy a file into a directory the policy lets the child write to.
+#[cfg(target_os = "linux")]
+#[test]
+fn policy_allows_writes() {
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"copied").expect("write failed");
+    let out_dir = tempfile::tempdir().expect("created a temp dir");
+    let out = out_dir.path().join("out");
+    let mut policy = SandboxPolicy::new();
+    policy.filesystem.read.push(input.path().to_path_buf());
+    policy.filesystem.write.push(out_dir.path().to_path_buf());
+    let output = gracklezero::runtime::run(
+        LaunchEnv {
+            args: vec![input.path().into(), out.clone().into()],
+            ..util::tool_env("cp")
+        }
+        .with_policy(&policy),
+    )
+    .expect("run failed");
+    assert_eq!(
+        output.status,
+        ExitStatus::Code(0),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read(&out).expect("no copy"), b"copied");
+}
+
+/// Run `cat` as described by a TOML profile.
+#[cfg(all(feature = "profile", target_os = "linux"))]
+#[test]
+fn launch_from_profile() {
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"profiled").expect("write failed");
+    let mut profile = tempfile::Builder::new()
+        .suffix(".toml")
+        .tempfile()
+        .expect("created a temp file");
+    let cat = which::which("cat").expect("cat not found");
+    std::io::Write::write_all(
+        &mut profile,
+        format!(
+            "cmd = {:?}\nargs = [{:?}]\nname = \"test\"\nallow_privileged_parent = true\n\n\
+             [policy.filesystem]\nread = [{:?}]\n",
+            cat,
+            input.path(),
+            input.path()
+        )
+        .as_bytes(),
+    )
+    .expect("write failed");
+    let env = LaunchEnv::from_profile(profile.path()).expect("load failed");
+    let output = gracklezero::runtime::run(env).expect("run failed");
+    assert_eq!(output.status, ExitStatus::Code(0));
+    assert_eq!(output.stdout, b"profiled");
+}
+
+/// The tests launch sandboxes, so the host must support them.
+#[cfg(target_os = "linux")]
+#[test]
+fn probe_host() {
+    let host = gracklezero::runtime::probe();
+    assert_eq!(host.os, "linux");
+    assert!(host.landlock_abi.is_some_and(|abi| abi >= 1), "{}", host);
+    assert!(host.seccomp);
+    assert!(host.can_sandbox());
+}
+
+/// Find what `cat` loads, with a missing extra library that would fail the launch.
+#[cfg(target_os = "linux")]
+#[test]
+fn dependency_report() {
+    let report = gracklezero::runtime::deps::dependencies(&LaunchEnv {
+        extra_libraries: vec![PathBuf::from("/does/not/exist/libplugin.so")],
+        ..util::tool_env("cat")
+    })
+    .expect("dependencies failed");
+    assert!(report.dependencies.len() > 1);
+    assert!(report.dependencies.iter().all(|d| d.exists()));
+    let missing: Vec<_> = report.missing().collect();
+    assert_eq!(missing.len(), 1);
+    assert_eq!(missing[0].name(), "libplugin.so");
+    assert!(report.readable_paths.contains(&report.exec));
+
+    let deps =
+        gracklezero::runtime::deps::find_dependencies(&report.exec, &DependencySearch::default());
+    assert_eq!(deps[0].name(), "cat");
+    assert_eq!(deps[0].needed(), report.dependencies[0].needed());
+}
+
+/// Only run `true` when it, and with `with_libraries` its libraries, match the allowlist.
+#[cfg(target_os = "linux")]
+#[test]
+fn hash_allowlist() {
+    use gracklezero::runtime::HashAllowlist;
+
+    let env = util::tool_env("true");
+    let exec = env.cmd.clone();
+    let launch = |allowlist: HashAllowlist| {
+        gracklezero::runtime::run(LaunchEnv {
+            hash_allowlist: Some(allowlist),
+            ..env.clone()
+        })
+    };
+    let allowlist = HashAllowlist::new().allow_file(&exec).expect("hash failed");
+    let output = launch(allowlist.clone()).expect("run failed");
+    assert_eq!(output.status, ExitStatus::Code(0));
+
+    let other = HashAllowlist::new().allow([0u8; 32]);
+    let err = launch(other).expect_err("run passed");
+    assert!(matches!(err, SandboxError::UntrustedFile(_)), "{:?}", err);
+
+    // The libraries aren't listed yet.
+    let err = launch(allowlist.clone().with_libraries()).expect_err("run passed");
+    assert!(matches!(err, SandboxError::UntrustedFile(_)), "{:?}", err);
+
+    let report = gracklezero::runtime::deps::dependencies(&env).expect("dependencies failed");
+    let allowlist = report
+        .dependencies
+        .iter()
+        .fold(allowlist.with_libraries(), |a, d| {
+            a.allow_file(d.best_path()).expect("hash failed")
+        });
+    let output = launch(allowlist).expect("run failed");
+    assert_eq!(output.status, ExitStatus::Code(0));
+}
+
+/// A privileged parent only launches when the launch allows it.
+#[cfg(target_os = "linux")]
+#[test]
+fn privileged_parent() {
+    let env = util::tool_env("true");
+    let result = gracklezero::runtime::run(LaunchEnv {
+        allow_privileged_parent: false,
+        ..env.clone()
+    });
+    if gracklezero::runtime::probe().privileged {
+        let err = result.expect_err("run passed");
+        assert!(matches!(err, SandboxError::PrivilegedParent), "{:?}", err);
+    } else {
+        assert_eq!(result.expect("run failed").status, ExitStatus::Code(0));
+    }
+    let output = gracklezero::runtime::run(env).expect("run failed");
+    assert_eq!(output.status, ExitStatus::Code(0));
+}
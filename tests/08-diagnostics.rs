@@ -0,0 +1,117 @@
+// SPDX-License-Identifier: MIT
+
+//! Observe the phases of a launch, and what's reported when one fails.
+
+use gracklezero::{
+    Child as _, FdMode, FdSet, LaunchEnv,
+    restrictions::CpuPlacement,
+    runtime::{
+        ExitStatus,
+        error::{LaunchPhase, SandboxError},
+    },
+};
+use tempfile::NamedTempFile;
+
+mod common;
+use common::util;
+
+/// Record each phase of a `cat` run, in order.
+#[cfg(target_os = "linux")]
+#[test]
+fn lifecycle_observer() {
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl gracklezero::runtime::LifecycleObserver for Recorder {
+        fn on_resolved(&self, exec: &std::path::Path) {
+            assert!(exec.is_absolute());
+            self.0.lock().unwrap().push("resolved".into());
+        }
+        fn on_forked(&self, _pid: u32) {
+            self.0.lock().unwrap().push("forked".into());
+        }
+        fn on_restricted(&self, _pid: u32) {
+            self.0.lock().unwrap().push("restricted".into());
+        }
+        fn on_exec(&self, _pid: u32) {
+            self.0.lock().unwrap().push("exec".into());
+        }
+        fn on_exit(&self, _pid: u32, status: ExitStatus) {
+            self.0.lock().unwrap().push(format!("exit {:?}", status));
+        }
+        fn on_killed(&self, _pid: u32) {
+            self.0.lock().unwrap().push("killed".into());
+        }
+    }
+
+    let mut input = NamedTempFile::new().expect("created a temp file");
+    std::io::Write::write_all(&mut input, b"observed").expect("write failed");
+    let recorder = Arc::new(Recorder::default());
+    let output = gracklezero::runtime::run(LaunchEnv {
+        fds: FdSet::std().with_fd(0, FdMode::FromFile(input.path().to_path_buf())),
+        observer: Some(recorder.clone()),
+        ..util::tool_env("cat")
+    })
+    .expect("run failed");
+    assert_eq!(output.stdout, b"observed");
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        ["resolved", "forked", "restricted", "exec", "exit Code(0)"]
+    );
+}
+
+/// Time each phase of launching `cat`.
+#[cfg(target_os = "linux")]
+#[test]
+fn launch_report() {
+    let mut child = gracklezero::runtime::spawn(util::tool_env("cat")).expect("spawn failed");
+    drop(child.take_stream_to_child(0));
+    let report = child.launch_report().expect("no launch report");
+    child.wait().expect("wait failed");
+    assert_eq!(child.finish().expect("finish failed"), ExitStatus::Code(0));
+    assert!(report.resolve.is_some());
+    assert!(report.dependencies.is_some());
+    assert!(report.fd_setup.is_some());
+    assert!(report.fork.is_some());
+    let restrict = report.restrict.expect("restrict not reported");
+    assert!(report.exec.expect("exec not reported") >= restrict);
+    assert_eq!(report.placement, None);
+}
+
+/// Record the processors the child was pinned to.
+#[cfg(target_os = "linux")]
+#[test]
+fn launch_report_placement() {
+    let mut env = util::tool_env("cat");
+    env.restrictions.cpu_placement = CpuPlacement::Cpus(vec![0]);
+    let mut child = gracklezero::runtime::spawn(env).expect("spawn failed");
+    drop(child.take_stream_to_child(0));
+    let report = child.launch_report().expect("no launch report");
+    child.wait().expect("wait failed");
+    assert_eq!(child.finish().expect("finish failed"), ExitStatus::Code(0));
+    assert_eq!(report.placement, Some(CpuPlacement::Cpus(vec![0])));
+}
+
+/// Report the errno when the child can't exec an executable file that isn't a program.
+#[cfg(target_os = "linux")]
+#[test]
+fn exec_failure_keeps_errno() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let exec = dir.path().join("not-a-program");
+    std::fs::write(&exec, "not a program\n").expect("write failed");
+    std::fs::set_permissions(&exec, std::fs::Permissions::from_mode(0o755)).expect("chmod failed");
+    let err = gracklezero::runtime::run(util::launch_env(exec.clone())).expect_err("run passed");
+    match &err {
+        SandboxError::Exec { path, .. } => assert_eq!(path, &exec),
+        e => panic!("unexpected error: {:?}", e),
+    }
+    assert_eq!(err.raw_os_error(), Some(nix::libc::ENOEXEC));
+    let diagnostics = err.diagnostics().expect("no diagnostics");
+    assert_eq!(diagnostics.phase, LaunchPhase::Exec);
+    assert_eq!(diagnostics.resolved.as_ref(), Some(&exec));
+    assert!(std::error::Error::source(&err).is_some());
+}
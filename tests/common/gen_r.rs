@@ -43,7 +43,6 @@ pub fn generate_restrictions<'a>() -> Vec<(String, Restrictions)> {
             "slr-bottom_up_randomization-defer",
             windows::defer_aslr_bottom_up_randomization,
         ),
-
         // Note: explicitly omit win32k disable, due to issues with
         // native auto-run hooks like virus scanners that can trigger
         // executables to unexpectedly fail.
@@ -60,8 +59,14 @@ pub fn generate_restrictions<'a>() -> Vec<(String, Restrictions)> {
         ret.push((format!("app-{}", &b.0), b.1(app.clone())));
         ret.push((format!("desk-{}", &b.0), b.1(desk.clone())));
     }
-    ret.push(("compat".to_string(), create_compat_restrictions(&APP_NAME.to_string())));
-    ret.push(("strict".to_string(), create_strict_restrictions(&APP_NAME.to_string())));
+    ret.push((
+        "compat".to_string(),
+        create_compat_restrictions(&APP_NAME.to_string()),
+    ));
+    ret.push((
+        "strict".to_string(),
+        create_strict_restrictions(&APP_NAME.to_string()),
+    ));
 
     ret
 }
@@ -107,8 +112,11 @@ fn app_container_restrictions() -> Restrictions {
     let mut r = base_restrictions();
     r.windows.app_container = windows::AppContainerMode::Enabled(windows::AppContainer {
         name: APP_NAME.to_string(),
+        display_name: None,
+        description: None,
         capabilities: Vec::new(),
         reuse_existing: true,
+        delete_on_exit: true,
     });
     r
 }
@@ -120,6 +128,8 @@ fn base_restrictions() -> Restrictions {
             max_open_files: 20,
             secomp_kill: false,
             dev_null_accessible: true,
+            tcp_connect_ports: Vec::new(),
+            tcp_bind_ports: Vec::new(),
         },
         windows: windows::WindowsRestrictions {
             app_container: windows::AppContainerMode::Disabled,
@@ -152,6 +162,9 @@ fn base_restrictions() -> Restrictions {
             cet_binary_load_blocking: windows::CETBinaryLoadBlocking::AlwaysOff,
             cet_dynamic_apis_out_of_proc_only: windows::AlwaysMode::AlwaysOff,
             disable_fsctl_system_call: windows::AlwaysMode::AlwaysOff,
+            audit_denials: false,
         },
+        cpu_placement: CpuPlacement::Any,
+        resource_limits: ResourceLimits::default(),
     }
 }
@@ -1,10 +1,10 @@
 // SPDX-License-Identifier: MIT
 
 //! CommHandler implementation for the tests.
-use std::{io::ErrorKind, thread, time::Duration};
+use std::{io::ErrorKind, time::Duration};
 
 use super::state::{ExecutionState, HandlerCheck};
-use gracklezero::{Child, CommHandler, runtime::spawn::ExitCode};
+use gracklezero::{Child, CommHandler, runtime::spawn::ExitStatus};
 
 /// Create the CommHandler and HandlerCheck for the test execution.
 #[allow(unused)]
@@ -20,7 +20,10 @@ pub struct TestHandler {
 }
 
 impl CommHandler for TestHandler {
-    fn handle(self, mut child: Box<dyn Child>) -> Result<(), std::io::Error> {
+    type Output = ();
+    type Error = std::io::Error;
+
+    fn handle(&mut self, mut child: Box<dyn Child>) -> Result<(), std::io::Error> {
         let ret = self.run_process(&mut child);
         match &ret {
             Ok(_) => {
@@ -33,14 +36,14 @@ impl CommHandler for TestHandler {
 
         // There are sometimes timing issues here - where the child sent the exit
         // message but still hasn't finished.
-        if !self.state.set_exit_code(child.exit_status())? {
+        if !self.state.set_exit_status(child.exit_status()?)? {
             // The child may have completed protocol I/O but not fully exited yet.
             // Give it a short grace period before forcefully terminating.
-            for _ in 0..50 {
-                thread::sleep(Duration::from_millis(10));
-                if self.state.set_exit_code(child.exit_status())? {
-                    return ret;
-                }
+            if self
+                .state
+                .set_exit_status(child.wait_timeout(Duration::from_millis(500))?)?
+            {
+                return ret;
             }
             println!("Child is still running, terminating");
             match child.terminate() {
@@ -51,7 +54,7 @@ impl CommHandler for TestHandler {
                     println!("Termination failed: {}", e);
                 }
             }
-            if !self.state.set_exit_code(child.exit_status())? {
+            if !self.state.set_exit_status(child.exit_status()?)? {
                 println!("Child is still running after termination");
             } else {
                 println!("Child exited after termination");
@@ -67,23 +70,20 @@ impl TestHandler {
         println!("Starting communication with child");
         self.state.mark_handle_started()?;
         println!("Initial exit status check");
-        match child.exit_status() {
-            ExitCode::OsError(s) => {
+        match child.exit_status()? {
+            Some(ExitStatus::Code(code)) => {
                 return Err(std::io::Error::new(
                     ErrorKind::BrokenPipe,
-                    format!(
-                        "child exited before communication: {} (0x{:X})",
-                        s.message, s.code
-                    ),
+                    format!("child exited before communication with code {}", code),
                 ));
             }
-            ExitCode::Exited(code) => {
+            Some(status) => {
                 return Err(std::io::Error::new(
                     ErrorKind::BrokenPipe,
-                    format!("child exited before communication with code {}", code),
+                    format!("child exited before communication: {:?}", status),
                 ));
             }
-            ExitCode::Running => {}
+            None => {}
         }
         println!("Getting to-child stream");
         let mut out = match child.take_stream_to_child(0) {
@@ -5,10 +5,10 @@
 #[allow(unused)]
 use std::{
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
-use gracklezero::{Child, CommHandler, runtime::spawn::ExitCode};
+use gracklezero::{Child, CommHandler, runtime::spawn::ExitStatus};
 
 /// Create the CommHandler and HandlerCheck for the test execution.
 #[allow(unused)]
@@ -16,13 +16,12 @@ pub fn new() -> (TestHandler, TestMonitor) {
     let state = TestState {
         state: Arc::new(Mutex::new(InnerTestState {
             started: false,
-            exit_code: ExitCode::Running,
+            exit_status: None,
         })),
     };
     (
         TestHandler {
             timeout: Duration::from_secs(10),
-            sleep: Duration::from_millis(200),
             state: state.clone(),
         },
         TestMonitor { state },
@@ -46,68 +45,46 @@ impl TestMonitor {
     }
 
     #[allow(unused)]
-    pub fn exit_code(&self) -> ExitCode {
-        self.state.exit_code().clone()
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.state.exit_status()
     }
 
     #[allow(unused)]
-    pub fn assert_failed(&self, err: &str) {
-        assert_eq!(self.state.started(), true);
-        match &self.state.exit_code() {
-            ExitCode::OsError(term) => {
-                assert_eq!(format!("{:?}", term), err);
-            }
-            ExitCode::Exited(c) => {
-                panic!("exited with code {}", c)
-            }
-            ExitCode::Running => {
-                panic!("still running after timeout");
-            }
+    pub fn assert_signaled(&self, signal: i32) {
+        assert!(self.state.started());
+        match self.state.exit_status() {
+            Some(ExitStatus::Signaled(s)) => assert_eq!(s, signal),
+            Some(status) => panic!("ended with {:?}", status),
+            None => panic!("still running after timeout"),
         }
     }
 
     #[allow(unused)]
     pub fn assert_exited_with(&self, code: i32) {
-        assert_eq!(self.state.started(), true);
-        match &self.state.exit_code() {
-            ExitCode::Exited(c) => {
-                assert_eq!(*c, code);
-            }
-            ExitCode::OsError(term) => {
-                panic!("terminated due to {:?}", term)
-            }
-            ExitCode::Running => {
-                panic!("still running after timeout");
-            }
+        assert!(self.state.started());
+        match self.state.exit_status() {
+            Some(ExitStatus::Code(c)) => assert_eq!(c, code),
+            Some(status) => panic!("terminated with {:?}", status),
+            None => panic!("still running after timeout"),
         }
     }
 }
 
 pub struct TestHandler {
-    sleep: Duration,
     timeout: Duration,
     state: TestState,
 }
 
 impl CommHandler for TestHandler {
-    fn handle(self, child: Box<dyn Child>) -> Result<(), std::io::Error> {
+    type Output = ();
+    type Error = std::io::Error;
+
+    fn handle(&mut self, child: Box<dyn Child>) -> Result<(), std::io::Error> {
         self.state.set_started();
 
         // Wait until timeout or exit.
-        let expires = Instant::now() + self.timeout;
-        loop {
-            let exit = child.exit_status();
-            match exit {
-                ExitCode::Running => (),
-                x => {
-                    self.state.set_exit_code(x);
-                    break;
-                }
-            }
-            if Instant::now() >= expires {
-                break;
-            }
-            std::thread::sleep(self.sleep);
+        if let Some(status) = child.wait_timeout(self.timeout)? {
+            self.state.set_exit_status(status);
         }
         Ok(())
     }
@@ -116,7 +93,7 @@ impl CommHandler for TestHandler {
 #[derive(Clone)]
 struct InnerTestState {
     started: bool,
-    exit_code: ExitCode,
+    exit_status: Option<ExitStatus>,
 }
 
 #[derive(Clone)]
@@ -129,8 +106,8 @@ impl TestState {
         self.update(|s| s.started)
     }
 
-    pub fn exit_code(&self) -> ExitCode {
-        self.update(|s| s.exit_code.clone())
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.update(|s| s.exit_status)
     }
 
     pub fn set_started(&self) {
@@ -139,9 +116,9 @@ impl TestState {
         });
     }
 
-    pub fn set_exit_code(&self, code: ExitCode) {
+    pub fn set_exit_status(&self, status: ExitStatus) {
         self.update(|s| {
-            s.exit_code = code;
+            s.exit_status = Some(status);
         });
     }
 
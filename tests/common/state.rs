@@ -4,7 +4,10 @@
 #[allow(unused)]
 use std::sync::{Arc, Mutex};
 
-use gracklezero::runtime::{error, spawn::ExitCode};
+use gracklezero::runtime::{
+    error,
+    spawn::{ExitStatus, RunResult},
+};
 
 #[allow(unused)]
 #[derive(Debug)]
@@ -66,7 +69,7 @@ impl ExecutionState {
     pub fn new() -> Self {
         ExecutionState {
             state: Arc::new(Mutex::new(InnerExecutionState {
-                exit_code: None,
+                exit_status: None,
                 handle_started: false,
                 sent_init: false,
                 read_start: false,
@@ -109,15 +112,13 @@ impl ExecutionState {
         })
     }
 
-    pub fn set_exit_code(&self, code: ExitCode) -> Result<bool, std::io::Error> {
-        println!("Setting exit code: {:?}", code);
+    /// Record the child's exit status, or None while it's running.  Returns true if the
+    /// child exited.
+    pub fn set_exit_status(&self, status: Option<ExitStatus>) -> Result<bool, std::io::Error> {
+        println!("Setting exit status: {:?}", status);
         self.update(|c| {
-            let ret = match code {
-                ExitCode::Running => false,
-                _ => true,
-            };
-            c.exit_code = Some(code);
-            ret
+            c.exit_status = Some(status);
+            status.is_some()
         })
     }
 
@@ -142,24 +143,39 @@ pub struct HandlerCheck {
 impl HandlerCheck {
     /// Assert that the handler's actual state meets the test's expectations.
     #[allow(unused)]
-    pub fn assert(&self, res: Result<ExitCode, error::SandboxError>, expected: Expected) {
+    pub fn assert<E: Into<error::SandboxError>>(
+        &self,
+        res: Result<RunResult<(), E>, error::SandboxError>,
+        expected: Expected,
+    ) {
         let guard = self.state.lock().expect("lock poisoned");
-        guard.ensure(expected, res);
+        guard.ensure(
+            expected,
+            res.and_then(|r| r.into_result()).map(|(_, status)| status),
+        );
     }
 
     /// Return true if the handler's actual state meets the test's expectations.
     #[allow(unused)]
-    pub fn is_success(&self, res: Result<ExitCode, error::SandboxError>, expected: Expected) -> bool {
+    pub fn is_success<E: Into<error::SandboxError>>(
+        &self,
+        res: Result<RunResult<(), E>, error::SandboxError>,
+        expected: Expected,
+    ) -> bool {
         let guard = self.state.lock().expect("lock poisoned");
-        guard.is_success(expected, res)
+        guard.is_success(
+            expected,
+            res.and_then(|r| r.into_result()).map(|(_, status)| status),
+        )
     }
 }
 
 #[derive(Debug)]
 /// Contains the test communication process.
 struct InnerExecutionState {
-    /// exit_code contains the exit code at the current execution point in the process.
-    exit_code: Option<ExitCode>,
+    /// exit_status contains the exit status at the current execution point in the process,
+    /// once it was checked.
+    exit_status: Option<Option<ExitStatus>>,
 
     // started is true after the handler has begun running.
     handle_started: bool,
@@ -177,7 +193,7 @@ struct InnerExecutionState {
 impl InnerExecutionState {
     /// Check the result for issues
     #[allow(unused)]
-    fn is_success(&self, expected: Expected, res: Result<ExitCode, error::SandboxError>) -> bool {
+    fn is_success(&self, expected: Expected, res: Result<ExitStatus, error::SandboxError>) -> bool {
         let mut success = true;
         if self.handle_started != expected.handle_started
             || self.sent_init != expected.sent_init
@@ -198,42 +214,33 @@ impl InnerExecutionState {
             }
         }
 
-        match &self.exit_code {
+        match &self.exit_status {
             None => {
                 // The "check for exit code" was never called.
                 // The self.state vs. expected state handles the success checks.
             }
-            Some(c) => match c {
-                ExitCode::Running => {
-                    // The child process hasn't exited yet.
-                    // This means a bug with the test or the runtime.
-                    success = false;
-                    println!("The child did not stop (and is most likely still running)");
-                }
-                ExitCode::OsError(s) => {
-                    // Due to OS differences, this can be the equivalent of "never started".
-                    // ... but, we'll count the "code" as one of the expected exit codes.
-                    let icode = s.code as i32;
-                    if !expected.exit_code.contains(&icode) {
-                        if success {
-                            // Didn't report the status above.
-                            println!("Expected: {:?}", expected);
-                            println!("  Actual: {:?} ({:?})", self, s);
-                        }
-                        success = false;
-                    }
-                }
-                ExitCode::Exited(c) => {
-                    if !expected.exit_code.contains(&c) {
-                        if success {
-                            // Didn't report the status above.
-                            println!("Expected: {:?}", expected);
-                            println!("  Actual: {:?}", self);
-                        }
-                        success = false;
+            Some(None) => {
+                // The child process hasn't exited yet.
+                // This means a bug with the test or the runtime.
+                success = false;
+                println!("The child did not stop (and is most likely still running)");
+            }
+            Some(Some(status)) => {
+                // Due to OS differences, a signal can be the equivalent of "never started".
+                // ... but, we'll count the signal as one of the expected exit codes.
+                let code = match status {
+                    ExitStatus::Code(c) | ExitStatus::Signaled(c) => Some(*c),
+                    ExitStatus::SandboxKilled => None,
+                };
+                if !code.is_some_and(|c| expected.exit_code.contains(&c)) {
+                    if success {
+                        // Didn't report the status above.
+                        println!("Expected: {:?}", expected);
+                        println!("  Actual: {:?}", self);
                     }
+                    success = false;
                 }
-            },
+            }
         }
 
         success
@@ -241,7 +248,7 @@ impl InnerExecutionState {
 
     /// Ensure the expected matches the actual.  Order matters.
     #[allow(unused)]
-    fn ensure(&self, expected: Expected, res: Result<ExitCode, error::SandboxError>) {
+    fn ensure(&self, expected: Expected, res: Result<ExitStatus, error::SandboxError>) {
         assert!(self.is_success(expected, res), "Execution State mismatch");
     }
 }
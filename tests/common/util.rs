@@ -4,12 +4,13 @@
 
 #[allow(unused)]
 use std::{
-    collections::HashMap,
     ffi::OsString,
     path::{Path, PathBuf},
 };
 
-use gracklezero::{FdMode, FdSet};
+use gracklezero::{EnvMap, FdMode, FdSet, LaunchEnv};
+
+use super::gen_r::generate_restrictions;
 
 /// Convert the path value into an array of arguments.
 #[allow(unused)]
@@ -18,6 +19,7 @@ pub fn path_as_args(path: &Path) -> Vec<OsString> {
 }
 
 /// Convert the string value into an array of arguments.
+#[allow(unused)]
 pub fn str_as_args(s: &str) -> Vec<OsString> {
     vec![OsString::from(s)]
 }
@@ -33,22 +35,27 @@ pub fn string_as_args(s: &String) -> Vec<OsString> {
 ///     stdin (0): a stream that writes to the child.
 ///    stdout (1): a stream that reads from the child.
 ///    stderr (2): pipe from the child directly into the executing test's stderr.
+#[allow(unused)]
 pub fn std_fd() -> FdSet {
     FdSet::basic(&[FdMode::ToChild, FdMode::FromChild, FdMode::KeepInChild])
 }
 
+#[allow(unused)]
 #[cfg(target_os = "windows")]
 const EXEC_SUFFIX: &str = ".exe";
 
+#[allow(unused)]
 #[cfg(not(target_os = "windows"))]
 const EXEC_SUFFIX: &str = "";
 
 /// Find the executable for the given test program.
+#[allow(unused)]
 pub fn require_exec(exec_name: &str) -> PathBuf {
     find_exec(exec_name).expect("Failed to find the executable")
 }
 
 /// Find the executable for the given test program.
+#[allow(unused)]
 pub fn find_exec(exec_name: &str) -> Option<PathBuf> {
     // Find the 'tests' directory off the root.
     let test_dir = Path::new("test-bin");
@@ -99,8 +106,26 @@ pub fn find_exec(exec_name: &str) -> Option<PathBuf> {
 }
 
 /// Create an environment that tells the executed rust program to include the backtrace.
-pub fn env_backtrace() -> HashMap<OsString, OsString> {
-    let mut env = HashMap::new();
-    env.insert(OsString::from("RUST_BACKTRACE"), OsString::from("1"));
+#[allow(unused)]
+pub fn env_backtrace() -> EnvMap {
+    let mut env = EnvMap::new();
+    env.insert("RUST_BACKTRACE", "1");
     env
 }
+
+/// Create the launch for the command, with the least restrictive of the generated
+/// restrictions.  The tests often run as root inside containers, so it allows a
+/// privileged parent.
+#[allow(unused)]
+pub fn launch_env(cmd: PathBuf) -> LaunchEnv {
+    LaunchEnv {
+        allow_privileged_parent: true,
+        ..LaunchEnv::new(cmd, generate_restrictions().remove(0).1)
+    }
+}
+
+/// Create the launch for a system tool, such as `cat`, found on the parent's `PATH`.
+#[allow(unused)]
+pub fn tool_env(name: &str) -> LaunchEnv {
+    launch_env(which::which(name).unwrap_or_else(|_| panic!("{} not found", name)))
+}